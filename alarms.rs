@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::clock;
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::events::{Bus, Event};
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::Celsius;
+
+const HISTORY_FILE: &str = "alarm_history.json";
+// alarms raised and cleared, oldest dropped first; at a few hundred bytes each this is well
+// under one SPIFFS block
+const MAX_HISTORY: usize = 100;
+
+struct Alarm {
+    message: String,
+    since: Instant,
+    // the command source it was acknowledged from
+    acknowledged: Option<String>,
+    // raised in maintenance mode, or active when it started: not sounding
+    suppressed: bool,
+    // AlarmRaised went out, so AlarmCleared does too
+    announced: bool,
+}
+
+// what a sensor alarm was raised on, for the history
+#[derive(Clone, Debug)]
+pub struct Detail {
+    pub sensor: String,
+    pub threshold: Celsius,
+    // None while the sensor doesn't answer
+    pub value: Option<Celsius>,
+}
+
+// one alarm from raise to clear; end is None while it is active. Times are unix seconds,
+// boot-relative before the clock was set
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Episode {
+    pub id: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<Celsius>,
+    // the reading furthest past the threshold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak: Option<Celsius>,
+    pub start: u64,
+    pub end: Option<u64>,
+    // still active when the device restarted, so when it ended isn't known
+    #[serde(default)]
+    pub interrupted: bool,
+    // raised in maintenance mode, and ended with it at the latest
+    #[serde(default)]
+    pub maintenance: bool,
+    // "cli", "mqtt", "api", ... for an acknowledged alarm
+    pub acknowledged_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_at: Option<u64>,
+}
+
+impl Episode {
+    fn update_peak(&mut self, value: Celsius) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let distance = |celsius: Celsius| (celsius - threshold).abs();
+        if self.peak.map_or(true, |peak| distance(value) > distance(peak)) {
+            self.peak = Some(value);
+        }
+    }
+}
+
+// currently active alarm conditions, keyed by a stable id such as "program_done"; changes
+// go out on the event bus and are kept in a history on storage
+pub struct Alarms {
+    active: Mutex<BTreeMap<String, Alarm>>,
+    history: Mutex<Vec<Episode>>,
+    // the history changed since it was last written; it is saved with the counters, so an
+    // alarm flapping every cycle costs one write per flush rather than two per cycle
+    dirty: AtomicBool,
+    // until the first sampling cycle after boot has finished, an alarm that was acknowledged
+    // when the device restarted comes back acknowledged; one that only returns later sounds
+    carry_over: AtomicBool,
+    suppressed: AtomicBool,
+    events: Arc<Bus>,
+}
+
+impl Alarms {
+    pub fn new(events: Arc<Bus>) -> Self {
+        let mut history: Vec<Episode> = storage::read_json(HISTORY_FILE).unwrap_or_default();
+        // one still active comes back as a new episode if its condition still holds
+        for episode in history.iter_mut().filter(|episode| episode.end.is_none()) {
+            episode.interrupted = true;
+        }
+        Self {
+            active: Mutex::new(BTreeMap::new()),
+            history: Mutex::new(history),
+            dirty: AtomicBool::new(false),
+            carry_over: AtomicBool::new(true),
+            suppressed: AtomicBool::new(false),
+            events,
+        }
+    }
+
+    // in maintenance mode, which silences the alarms already active too; they all end with it
+    // and come back, announced, if their condition still holds
+    pub fn set_suppressed(&self, suppressed: bool) {
+        if self.suppressed.swap(suppressed, Ordering::Relaxed) == suppressed {
+            return;
+        }
+        if suppressed {
+            for alarm in self.active.lock().unwrap().values_mut() {
+                alarm.suppressed = true;
+            }
+            return;
+        }
+        let ids: Vec<String> = {
+            let active = self.active.lock().unwrap();
+            active.iter().filter(|(_, alarm)| alarm.suppressed).map(|(id, _)| id.clone()).collect()
+        };
+        for id in ids {
+            self.clear(&id);
+        }
+    }
+
+    // raising an alarm that is already active keeps its start time and acknowledgement
+    pub fn raise(&self, id: &str, message: String) {
+        self.raise_with(id, message, None);
+    }
+
+    pub fn raise_with(&self, id: &str, message: String, detail: Option<Detail>) {
+        let mut active = self.active.lock().unwrap();
+        match active.get_mut(id) {
+            Some(alarm) => {
+                alarm.message = message;
+                drop(active);
+                // the peak is saved with the end, not on every reading
+                if let Some(value) = detail.and_then(|detail| detail.value) {
+                    let mut history = self.history.lock().unwrap();
+                    if let Some(episode) = open_episode(&mut history, id) {
+                        episode.update_peak(value);
+                    }
+                }
+            }
+            None => {
+                let suppressed = self.suppressed.load(Ordering::Relaxed);
+                // acknowledged before a restart and still holding right after it: it stays
+                // acknowledged
+                let acknowledged = {
+                    let history = self.history.lock().unwrap();
+                    history
+                        .iter()
+                        .rev()
+                        .find(|episode| episode.id == id)
+                        .filter(|episode| episode.interrupted && self.carry_over.load(Ordering::Relaxed))
+                        .and_then(|episode| episode.acknowledged_by.clone())
+                };
+                active.insert(
+                    id.to_string(),
+                    Alarm {
+                        message: message.clone(),
+                        since: Instant::now(),
+                        acknowledged: acknowledged.clone(),
+                        suppressed,
+                        announced: !suppressed,
+                    },
+                );
+                drop(active);
+                let mut episode = Episode {
+                    id: id.to_string(),
+                    message: message.clone(),
+                    sensor: detail.as_ref().map(|detail| detail.sensor.clone()),
+                    threshold: detail.as_ref().map(|detail| detail.threshold),
+                    peak: None,
+                    start: clock::now_unix(),
+                    end: None,
+                    interrupted: false,
+                    maintenance: suppressed,
+                    acknowledged_at: acknowledged.as_ref().map(|_| clock::now_unix()),
+                    acknowledged_by: acknowledged,
+                };
+                if let Some(value) = detail.and_then(|detail| detail.value) {
+                    episode.update_peak(value);
+                }
+                self.record(|history| history.push(episode));
+                if !suppressed {
+                    self.events.publish(Event::AlarmRaised { id: id.to_string(), message });
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self, id: &str) {
+        let removed = self.active.lock().unwrap().remove(id);
+        if let Some(alarm) = removed {
+            self.record(|history| {
+                if let Some(episode) = open_episode(history, id) {
+                    episode.end = Some(clock::now_unix());
+                }
+            });
+            if alarm.announced {
+                self.events.publish(Event::AlarmCleared { id: id.to_string() });
+            }
+        }
+    }
+
+    // `by` is where the acknowledgement came from, a command source; acknowledging again keeps
+    // the first
+    pub fn acknowledge(&self, id: &str, by: &str) -> bool {
+        let mut active = self.active.lock().unwrap();
+        let Some(alarm) = active.get_mut(id) else {
+            return false;
+        };
+        if alarm.acknowledged.is_some() {
+            return true;
+        }
+        alarm.acknowledged = Some(by.to_string());
+        let suppressed = alarm.suppressed;
+        drop(active);
+        self.record(|history| {
+            if let Some(episode) = open_episode(history, id) {
+                episode.acknowledged_by = Some(by.to_string());
+                episode.acknowledged_at = Some(clock::now_unix());
+            }
+        });
+        if !suppressed {
+            self.events.publish(Event::AlarmAcknowledged { id: id.to_string(), by: by.to_string() });
+        }
+        true
+    }
+
+    // after the first sampling cycle, which raises whatever still holds from before the restart
+    pub fn end_carry_over(&self) {
+        self.carry_over.store(false, Ordering::Relaxed);
+    }
+
+    // changes the history, saved by the next flush
+    fn record(&self, change: impl FnOnce(&mut Vec<Episode>)) {
+        let mut history = self.history.lock().unwrap();
+        change(&mut history);
+        if history.len() > MAX_HISTORY {
+            let excess = history.len() - MAX_HISTORY;
+            history.drain(..excess);
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn flush(&self) {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let history = self.history.lock().unwrap();
+        if let Err(error) = storage::write_json(HISTORY_FILE, &*history) {
+            log::warn!("failed to save the alarm history: {}", error);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // newest first
+    pub fn history_json(&self, limit: usize) -> Value {
+        let history = self.history.lock().unwrap();
+        json!(history.iter().rev().take(limit).collect::<Vec<_>>())
+    }
+
+    // a specific alarm, or any alarm without an id
+    pub fn is_active(&self, id: Option<&str>) -> bool {
+        let active = self.active.lock().unwrap();
+        match id {
+            Some(id) => active.contains_key(id),
+            None => !active.is_empty(),
+        }
+    }
+
+    // the buzzer sounds while any alarm is active, not yet acknowledged and not silenced by
+    // maintenance mode
+    pub fn sounding(&self) -> bool {
+        self.active.lock().unwrap().values().any(|alarm| alarm.acknowledged.is_none() && !alarm.suppressed)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let active = self.active.lock().unwrap();
+        Value::Array(
+            active
+                .iter()
+                .map(|(id, alarm)| {
+                    json!({
+                        "id": id,
+                        "message": alarm.message,
+                        "active_secs": alarm.since.elapsed().as_secs(),
+                        "acknowledged": alarm.acknowledged.is_some(),
+                        "acknowledged_by": alarm.acknowledged,
+                        "maintenance": alarm.suppressed,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+// tracks how long a value has been outside a band, for alarms that tolerate brief excursions
+#[derive(Default)]
+pub struct BandWatch {
+    outside_since: Option<Instant>,
+}
+
+impl BandWatch {
+    // true once the value has been outside low..=high for at least `delay`; a missing value
+    // (sensor gone) counts as outside
+    pub fn update<T: PartialOrd>(&mut self, value: Option<T>, low: T, high: T, delay: Duration) -> bool {
+        if value.is_some_and(|value| (low..=high).contains(&value)) {
+            self.outside_since = None;
+            return false;
+        }
+        self.outside_since.get_or_insert_with(Instant::now).elapsed() >= delay
+    }
+
+    pub fn reset(&mut self) {
+        self.outside_since = None;
+    }
+}
+
+fn open_episode<'a>(history: &'a mut [Episode], id: &str) -> Option<&'a mut Episode> {
+    history.iter_mut().rev().find(|episode| episode.id == id && episode.end.is_none() && !episode.interrupted)
+}
+
+pub fn sensor_alarm_id(sensor: &str) -> String {
+    format!("sensor_{}", sensor)
+}
+
+// evaluates the alarm_low/alarm_high thresholds configured per sensor in the registry
+#[derive(Default)]
+pub struct SensorAlarms {
+    watches: BTreeMap<String, BandWatch>,
+}
+
+impl SensorAlarms {
+    pub fn update(&mut self, context: &Context, readings: &[Reading]) {
+        let registry = context.registry.lock().unwrap();
+        let contacts = context.contacts.lock().unwrap();
+        for (sensor, info) in registry.sensors() {
+            if info.alarm_low.is_none() && info.alarm_high.is_none() {
+                self.watches.remove(sensor);
+                context.alarms.clear(&sensor_alarm_id(sensor));
+                continue;
+            }
+            let watch = self.watches.entry(sensor.clone()).or_default();
+
+            // an open door is expected to warm things up: don't start counting until it's been
+            // closed for its grace period, an alarm that is already active stays active
+            if contacts.suppresses(sensor) {
+                watch.reset();
+                continue;
+            }
+
+            let low = info.alarm_low.unwrap_or(Celsius(f32::NEG_INFINITY));
+            let high = info.alarm_high.unwrap_or(Celsius(f32::INFINITY));
+            let delay = Duration::from_secs(u64::from(info.alarm_delay_minutes.unwrap_or(0)) * 60);
+            let celsius = readings.iter().find(|reading| &reading.sensor == sensor).map(|reading| reading.celsius);
+            let id = sensor_alarm_id(sensor);
+            if watch.update(celsius, low, high, delay) {
+                let name = info.name.as_deref().unwrap_or(sensor);
+                let (message, threshold) = match (celsius, info.alarm_low, info.alarm_high) {
+                    (None, _, _) => (format!("{} is not responding", name), info.alarm_low.or(info.alarm_high)),
+                    (Some(celsius), Some(low), _) if celsius < low => (format!("{} at {:.1}, below {}", name, celsius, low), Some(low)),
+                    (Some(celsius), _, _) => (format!("{} at {:.1}, above {}", name, celsius, high), Some(high)),
+                };
+                let detail = threshold.map(|threshold| Detail { sensor: sensor.clone(), threshold, value: celsius });
+                context.alarms.raise_with(&id, message, detail);
+            } else {
+                context.alarms.clear(&id);
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Acknowledge {
+    id: String,
+}
+
+fn active_example() -> Value {
+    json!([{
+        "id": "",
+        "message": "",
+        "active_secs": 0,
+        "acknowledged": false,
+        "acknowledged_by": "",
+        "maintenance": false,
+    }])
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let list_context = context.clone();
+    http::route(server, "/api/alarms", Method::Get, Api::json(active_example), move |request| {
+        http::write_json(request, &list_context.alarms.to_json())
+    })?;
+
+    // every alarm raised of the last 100, newest first, ?limit= for fewer
+    let history_context = context.clone();
+    let history = Api::json(|| {
+        json!([Episode {
+            id: String::new(),
+            message: String::new(),
+            sensor: Some(String::new()),
+            threshold: Some(Celsius(0.0)),
+            peak: Some(Celsius(0.0)),
+            start: 0,
+            end: Some(0),
+            interrupted: false,
+            maintenance: false,
+            acknowledged_by: Some(String::new()),
+            acknowledged_at: Some(0),
+        }])
+    });
+    http::route(server, "/api/alarms/history", Method::Get, history.query(&[("limit", "integer")]), move |request| {
+        let limit = http::query_param(request.uri(), "limit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(MAX_HISTORY);
+        http::write_json(request, &history_context.alarms.history_json(limit))
+    })?;
+
+    // silences the buzzer for an alarm; it stays listed until its condition clears
+    let ack_context = context;
+    let ack = Api::json(active_example).body(|| json!({ "id": "" }));
+    http::route(server, "/api/alarms/ack", Method::Post, ack, move |mut request| {
+        let acknowledge = match http::read_json::<Acknowledge>(&mut request, 256)? {
+            Ok(acknowledge) => acknowledge,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let args = Map::from_iter([("id".to_string(), json!(acknowledge.id))]);
+        match commands::run(&ack_context, "ack", &args, Source::Api) {
+            Ok(alarms) => http::write_json(request, &alarms),
+            Err(error) => http::write_error(request, 404, &error),
+        }
+    })?;
+
+    Ok(())
+}
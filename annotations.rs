@@ -0,0 +1,102 @@
+use std::io;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::context::Context;
+use crate::history::{self, Record};
+use crate::http;
+use crate::openapi::Api;
+
+const MAX_TEXT_LEN: usize = 200;
+const DEFAULT_LIMIT: usize = 50;
+
+#[derive(Deserialize)]
+pub struct Annotation {
+    pub text: String,
+    // defaults to now, lets a note be added after the fact
+    pub t: Option<u64>,
+}
+
+pub fn add(context: &Context, annotation: Annotation, source: &str) -> io::Result<Record> {
+    let text: String = annotation.text.trim().chars().take(MAX_TEXT_LEN).collect();
+    if text.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "annotation text is empty"));
+    }
+    let record = Record::Annotation {
+        t: annotation.t.unwrap_or_else(clock::now_unix),
+        text,
+        source: source.to_string(),
+    };
+    let mut history = context.history.lock().unwrap();
+    match annotation.t {
+        Some(_) => history.insert(&record)?,
+        None => history.append(&record)?,
+    }
+    Ok(record)
+}
+
+// MQTT payloads are either plain text or the same JSON as the REST API
+pub fn from_mqtt(context: &Context, payload: &[u8]) {
+    let annotation = serde_json::from_slice::<Annotation>(payload).unwrap_or_else(|_| Annotation {
+        text: String::from_utf8_lossy(payload).into_owned(),
+        t: None,
+    });
+    if let Err(error) = add(context, annotation, "mqtt") {
+        log::warn!("ignored MQTT annotation: {}", error);
+    }
+}
+
+fn record_example() -> Value {
+    json!(Record::Annotation { t: 0, text: String::new(), source: String::new() })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let add_context = context.clone();
+    let add_api = Api::json(record_example).body(|| json!({ "text": "", "t": 0 }));
+    http::route(server, "/api/annotations", Method::Post, add_api, move |mut request| {
+        let annotation = match http::read_json::<Annotation>(&mut request, 1024)? {
+            Ok(annotation) => annotation,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        match add(&add_context, annotation, "api") {
+            Ok(record) => http::write_json(request, &json!(record)),
+            Err(error) if error.kind() == io::ErrorKind::InvalidInput => http::write_error(request, 400, &error.to_string()),
+            Err(error) => http::write_error(request, 500, &error.to_string()),
+        }
+    })?;
+
+    // the most recent annotations, newest last; ?since=<unix> and ?limit=
+    let list_context = context;
+    let list = Api::json(|| json!([record_example()])).query(&[("since", "integer"), ("limit", "integer")]);
+    http::route(server, "/api/annotations", Method::Get, list, move |request| {
+        let since = http::query_param(request.uri(), "since")
+            .and_then(|since| since.parse().ok())
+            .unwrap_or(0);
+        let limit = http::query_param(request.uri(), "limit")
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(DEFAULT_LIMIT);
+        let mut annotations = Vec::new();
+        let result = history::scan_paged(&list_context.history, since, |record| {
+            if matches!(record, Record::Annotation { .. }) {
+                annotations.push(record);
+                if annotations.len() > limit {
+                    annotations.remove(0);
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        });
+        if let Err(error) = result {
+            return http::write_error(request, 500, &error.to_string());
+        }
+        http::write_json(request, &json!(annotations))
+    })?;
+
+    Ok(())
+}
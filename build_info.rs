@@ -0,0 +1,95 @@
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::config;
+use crate::wifi;
+
+// filled in by build.rs, with fallbacks so the firmware still builds without it
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = match option_env!("GIT_HASH") {
+    Some(hash) => hash,
+    None => "unknown",
+};
+pub const BUILD_TIMESTAMP: &str = match option_env!("BUILD_TIMESTAMP") {
+    Some(timestamp) => timestamp,
+    None => "0",
+};
+pub const FEATURES: &str = match option_env!("BUILD_FEATURES") {
+    Some(features) => features,
+    None => "",
+};
+
+pub fn features() -> impl Iterator<Item = &'static str> {
+    FEATURES.split(',').filter(|feature| !feature.is_empty())
+}
+
+// abbreviated tag such as "0.1.0-3f2a9c1", short enough for MQTT discovery's sw_version
+pub fn version_tag() -> String {
+    format!("{}-{}", VERSION, GIT_HASH)
+}
+
+pub fn device_name(node_id: &str) -> &str {
+    if config::DEVICE_NAME.is_empty() {
+        node_id
+    } else {
+        config::DEVICE_NAME
+    }
+}
+
+pub fn mac_text() -> String {
+    match wifi::mac() {
+        Ok(mac) => mac.map(|byte| format!("{:02x}", byte)).join(":"),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+pub fn info_json(node_id: &str) -> Value {
+    json!({
+        "node": node_id,
+        "name": device_name(node_id),
+        "mac": mac_text(),
+        "version": version_tag(),
+        "git_hash": GIT_HASH,
+        "build_timestamp": BUILD_TIMESTAMP.parse::<u64>().unwrap_or(0),
+        "features": features().collect::<Vec<_>>(),
+    })
+}
+
+// host and port of a broker URL, without any user:password@ in it
+fn host_of(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.split('/').next().unwrap_or(rest);
+    rest.rsplit_once('@').map_or(rest, |(_, host)| host)
+}
+
+// the lines printed on the serial console at boot: who the device is and how it was built and
+// configured; `sensors` is how many the last 1-Wire search found
+pub fn banner(node_id: &str, sensors: usize) -> Vec<String> {
+    let built = BUILD_TIMESTAMP.parse::<u64>().ok().filter(|&unix| unix > 0);
+    let features: Vec<&str> = features().collect();
+    let or_off = |value: &str| if value.is_empty() { "off".to_string() } else { value.to_string() };
+    let wifi = if config::WIFI_SSID.is_empty() {
+        "off, serial only".to_string()
+    } else {
+        format!("{} (power save {}, prefer {})", config::WIFI_SSID, config::WIFI_POWER_SAVE, config::NET_PREFER)
+    };
+    vec![
+        format!("==== temp {} ====", version_tag()),
+        format!("device   {} ({}), MAC {}", device_name(node_id), node_id, mac_text()),
+        format!(
+            "build    {}, features: {}",
+            built.map_or_else(|| "unknown".to_string(), clock::format_iso8601),
+            if features.is_empty() { "none".to_string() } else { features.join(",") }
+        ),
+        format!("wifi     {}", wifi),
+        format!("mqtt     {}", or_off(host_of(config::MQTT_URL))),
+        format!("influx   {}", or_off(host_of(config::INFLUX_URL))),
+        format!(
+            "role     {}, profile {}, sampling every {} ms",
+            config::NODE_ROLE,
+            if config::PROFILE.is_empty() { "monitor" } else { config::PROFILE },
+            config::sample_interval_ms()
+        ),
+        format!("sensors  {} found by the last search", sensors),
+    ]
+}
@@ -0,0 +1,134 @@
+use std::error::Error;
+use std::sync::{Mutex, TryLockError};
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::io::{Read, Write};
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::http::Method;
+use serde_json::{json, Value};
+
+// the response is read to the end so the connection can be kept, beyond this it is dropped
+const MAX_BODY: usize = 2048;
+// servers close idle keep-alive connections after a while, reconnecting beats a failed request
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+struct Open {
+    connection: EspHttpConnection,
+    // scheme, host and port
+    origin: String,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct Stats {
+    requests: u64,
+    // new connections, each with a TLS handshake for https
+    connections: u64,
+    reused: u64,
+    failures: u64,
+}
+
+// the outbound HTTP(S) connection every client of the firmware shares (InfluxDB so far). A TLS
+// handshake holds some 40 KB of heap while it runs, so requests take turns on one kept-alive
+// connection instead of each opening its own, and the next request to the same host skips it
+#[derive(Default)]
+pub struct HttpClient {
+    // held for a whole request
+    open: Mutex<Option<Open>>,
+    stats: Mutex<Stats>,
+}
+
+fn origin(url: &str) -> &str {
+    let host_start = url.find("://").map_or(0, |index| index + 3);
+    url[host_start..].find('/').map_or(url, |index| &url[..host_start + index])
+}
+
+fn exchange(
+    connection: &mut EspHttpConnection,
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, Box<dyn Error>> {
+    connection.initiate_request(method, url, headers)?;
+    connection.write_all(body)?;
+    connection.initiate_response()?;
+    let status = connection.status();
+    let mut body = Vec::new();
+    let mut buffer = [0u8; 256];
+    loop {
+        let read = connection.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        let room = MAX_BODY - body.len();
+        body.extend_from_slice(&buffer[..read.min(room)]);
+    }
+    Ok(Response { status, body })
+}
+
+impl HttpClient {
+    // waits for a request of another client to finish; any status is Ok, only transport errors
+    // (which also close the connection) are Err
+    pub fn request(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Response, Box<dyn Error>> {
+        let mut open = self.open.lock().unwrap();
+        let origin = origin(url);
+        let reusable = open.as_ref().is_some_and(|open| open.origin == origin && open.last_used.elapsed() < IDLE_TIMEOUT);
+        if !reusable {
+            // the old connection's TLS state is freed before the next handshake allocates its own
+            *open = None;
+            let connection = EspHttpConnection::new(&Configuration {
+                crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+                ..Default::default()
+            })?;
+            *open = Some(Open { connection, origin: origin.to_string(), last_used: Instant::now() });
+        }
+        let mut stats = self.stats.lock().unwrap();
+        stats.requests += 1;
+        if reusable {
+            stats.reused += 1;
+        } else {
+            stats.connections += 1;
+        }
+        drop(stats);
+
+        let current = open.as_mut().unwrap();
+        let result = exchange(&mut current.connection, method, url, headers, body);
+        current.last_used = Instant::now();
+        if result.is_err() {
+            *open = None;
+            self.stats.lock().unwrap().failures += 1;
+        }
+        result
+    }
+
+    // whether a kept-alive connection to the origin of `url` is open; one in use right now counts
+    pub fn connected(&self, url: &str) -> bool {
+        match self.open.try_lock() {
+            Ok(open) => open.as_ref().is_some_and(|open| open.origin == origin(url) && open.last_used.elapsed() < IDLE_TIMEOUT),
+            Err(TryLockError::WouldBlock) => true,
+            Err(TryLockError::Poisoned(_)) => false,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let stats = self.stats.lock().unwrap();
+        json!({
+            "requests": stats.requests,
+            "connections": stats.connections,
+            "reused": stats.reused,
+            "failures": stats.failures,
+        })
+    }
+}
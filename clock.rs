@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// anything earlier means SNTP hasn't set the clock yet (it starts at 1970 after boot)
+const SYNCED_AFTER: u64 = 1_700_000_000;
+
+// what set the clock last: "sntp", "rtc" or "none"
+static SOURCE: Mutex<&str> = Mutex::new("none");
+
+// the time as schedules, compliance and degree days see it; they take a SimulatedClock to be
+// run through days of time in a test or a preview instead of waiting for SNTP and real days.
+// Nothing here touches esp-idf (setting the clock is in systime.rs), so this and the modules
+// that only use it build and test on the host
+pub trait Clock: Send + Sync {
+    fn now_unix(&self) -> u64;
+
+    fn is_synced(&self) -> bool {
+        is_plausible(self.now_unix())
+    }
+}
+
+// the system time, as SNTP, the RTC or the GPS set it
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix(&self) -> u64 {
+        now_unix()
+    }
+}
+
+// a clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct SimulatedClock {
+    now: AtomicU64,
+}
+
+impl SimulatedClock {
+    pub fn at(unix: u64) -> Self {
+        SimulatedClock { now: AtomicU64::new(unix) }
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.now.fetch_add(secs, Ordering::Relaxed);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now_unix(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+pub fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn is_synced() -> bool {
+    now_unix() >= SYNCED_AFTER
+}
+
+// a time that can't be right (an RTC that lost it reads 2000) is refused
+pub fn is_plausible(unix: u64) -> bool {
+    unix >= SYNCED_AFTER
+}
+
+pub fn set_source(source: &'static str) {
+    *SOURCE.lock().unwrap() = source;
+}
+
+pub fn source() -> &'static str {
+    *SOURCE.lock().unwrap()
+}
+
+// days since 1970-01-01 to a civil date, after Howard Hinnant's algorithm
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+// the inverse of civil_from_days
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let day_of_year = (153 * month_index + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// UTC timestamp as "2024-06-20T13:45:00Z"
+pub fn format_iso8601(unix: u64) -> String {
+    let (year, month, day) = civil_from_days((unix / 86_400) as i64);
+    let secs = unix % 86_400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
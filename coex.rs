@@ -0,0 +1,86 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+// a BLE scan is at most this long, a bus pass waiting for one to end gives up after it
+const SCAN_WAIT: Duration = Duration::from_secs(6);
+
+#[derive(Debug, Default)]
+struct Counts {
+    passes: u64,
+    reads: u64,
+    failures: u64,
+}
+
+impl Counts {
+    fn to_json(&self) -> Value {
+        let rate = if self.reads == 0 { 0.0 } else { self.failures as f64 / self.reads as f64 };
+        json!({ "passes": self.passes, "reads": self.reads, "failures": self.failures, "failure_rate": rate })
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    bus_busy: bool,
+    scanning: bool,
+    // bus passes finished, a scan waits for the next one so it starts right after sampling
+    passes_done: u64,
+    scanned_after: u64,
+    // bus passes that ran while a scan was going on anyway, and the others
+    overlapped: Counts,
+    clear: Counts,
+}
+
+// keeps BLE scans and 1-Wire bus passes apart: the BLE controller's interrupts on the same
+// core stretch the bit-banged 1-Wire slots into CRC errors and missing sensors. Scans start
+// right after a pass, when the bus is idle for most of the sample interval, and a pass waits
+// for a running scan to end.
+#[derive(Default)]
+pub struct Coexistence {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Coexistence {
+    // true when it couldn't wait for a scan to end
+    pub fn bus_begin(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let (mut state, timeout) = self.changed.wait_timeout_while(state, SCAN_WAIT, |state| state.scanning).unwrap();
+        state.bus_busy = true;
+        timeout.timed_out()
+    }
+
+    // `reads` sensors read of which `failures` didn't answer or failed their CRC
+    pub fn bus_end(&self, overlapped: bool, reads: usize, failures: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.bus_busy = false;
+        state.passes_done += 1;
+        let counts = if overlapped { &mut state.overlapped } else { &mut state.clear };
+        counts.passes += 1;
+        counts.reads += reads as u64;
+        counts.failures += failures as u64;
+        self.changed.notify_all();
+    }
+
+    // blocks until a bus pass has finished since the last scan and the bus is idle
+    pub fn scan_begin(&self) {
+        let state = self.state.lock().unwrap();
+        let mut state = self
+            .changed
+            .wait_while(state, |state| state.bus_busy || state.passes_done == state.scanned_after)
+            .unwrap();
+        state.scanning = true;
+        state.scanned_after = state.passes_done;
+    }
+
+    pub fn scan_end(&self) {
+        self.state.lock().unwrap().scanning = false;
+        self.changed.notify_all();
+    }
+
+    pub fn to_json(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        json!({ "during_scans": state.overlapped.to_json(), "without_scans": state.clear.to_json() })
+    }
+}
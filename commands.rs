@@ -0,0 +1,498 @@
+use std::io::{self, BufRead};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::{esp, esp_vfs_dev_uart_use_driver, uart_driver_install, EspError, CONFIG_ESP_CONSOLE_UART_NUM};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::annotations::{self, Annotation};
+use crate::build_info;
+use crate::clock;
+use crate::context::Context;
+use crate::counters;
+use crate::http;
+use crate::led;
+use crate::openapi::Api;
+use crate::output;
+use crate::ow_trace;
+use crate::presets;
+use crate::profiles;
+use crate::service;
+use crate::wifi;
+
+// every device command, defined once and reachable over the serial console, the remote console,
+// the MQTT command topic and POST /api/commands
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub args: &'static [Arg],
+    handler: fn(&Context, &Map<String, Value>, Source) -> Result<Value, String>,
+}
+
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub required: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Text,
+    Number,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Source {
+    Cli,
+    Mqtt,
+    Api,
+    Remote,
+    Grpc,
+}
+
+impl Source {
+    fn name(self) -> &'static str {
+        match self {
+            Source::Cli => "cli",
+            Source::Mqtt => "mqtt",
+            Source::Api => "api",
+            Source::Remote => "remote",
+            Source::Grpc => "grpc",
+        }
+    }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "ack",
+        description: "silence the buzzer for an alarm",
+        args: &[Arg { name: "id", kind: Kind::Text, required: true }],
+        handler: ack,
+    },
+    Command {
+        name: "annotate",
+        description: "add a note to the history",
+        args: &[
+            Arg { name: "text", kind: Kind::Text, required: true },
+            Arg { name: "t", kind: Kind::Number, required: false },
+        ],
+        handler: annotate,
+    },
+    Command {
+        name: "stop_program",
+        description: "stop the running temperature program",
+        args: &[],
+        handler: stop_program,
+    },
+    Command {
+        name: "compliance_reset",
+        description: "start a new compliance audit period",
+        args: &[],
+        handler: compliance_reset,
+    },
+    Command {
+        name: "drift_reset",
+        description: "forget the drift bias of a sensor, or of all sensors",
+        args: &[Arg { name: "sensor", kind: Kind::Text, required: false }],
+        handler: drift_reset,
+    },
+    Command {
+        name: "soak",
+        description: "hammer the 1-Wire bus for a number of minutes (60 by default, 0 stops)",
+        args: &[Arg { name: "minutes", kind: Kind::Number, required: false }],
+        handler: soak,
+    },
+    Command {
+        name: "ow_trace",
+        description: "record the 1-Wire pin for a number of seconds (60 by default, 0 stops) for /api/debug/ow-trace",
+        args: &[Arg { name: "seconds", kind: Kind::Number, required: false }],
+        handler: start_ow_trace,
+    },
+    Command {
+        name: "scan",
+        description: "search the 1-Wire bus for sensors on the next cycle",
+        args: &[],
+        handler: scan,
+    },
+    Command {
+        name: "selftest",
+        description: "summarize the device state, health, sensors and bus errors",
+        args: &[],
+        handler: selftest,
+    },
+    Command {
+        name: "alarms",
+        description: "list the active alarms",
+        args: &[],
+        handler: list_alarms,
+    },
+    Command {
+        name: "maintenance",
+        description: "enter maintenance mode for a number of minutes (30 by default, 0 leaves it)",
+        args: &[Arg { name: "minutes", kind: Kind::Number, required: false }],
+        handler: maintenance,
+    },
+    Command {
+        name: "identify",
+        description: "blink the status LED for a number of seconds (10 by default) and show who this is",
+        args: &[Arg { name: "seconds", kind: Kind::Number, required: false }],
+        handler: identify,
+    },
+    Command {
+        name: "profile",
+        description: "switch to a configuration profile",
+        args: &[Arg { name: "profile", kind: Kind::Text, required: true }],
+        handler: profile,
+    },
+    Command {
+        name: "preset",
+        description: "apply a built-in preset: home_heating, aquarium, greenhouse or freezer",
+        args: &[Arg { name: "preset", kind: Kind::Text, required: true }],
+        handler: preset,
+    },
+    Command {
+        name: "schedule_preview",
+        description: "walk the output schedule through the next hours (24 by default) from now or t",
+        args: &[
+            Arg { name: "hours", kind: Kind::Number, required: false },
+            Arg { name: "t", kind: Kind::Number, required: false },
+        ],
+        handler: schedule_preview,
+    },
+    Command {
+        name: "power_save",
+        description: "switch Wi-Fi power save until the next restart: none, min or max",
+        args: &[Arg { name: "mode", kind: Kind::Text, required: true }],
+        handler: power_save,
+    },
+    Command {
+        name: "reboot",
+        description: "restart the device",
+        args: &[],
+        handler: reboot,
+    },
+];
+
+fn text<'a>(args: &'a Map<String, Value>, name: &str) -> Option<&'a str> {
+    args.get(name).and_then(Value::as_str)
+}
+
+fn ack(context: &Context, args: &Map<String, Value>, source: Source) -> Result<Value, String> {
+    let id = text(args, "id").unwrap_or_default();
+    if !context.alarms.acknowledge(id, source.name()) {
+        return Err("no such alarm".to_string());
+    }
+    Ok(context.alarms.to_json())
+}
+
+fn annotate(context: &Context, args: &Map<String, Value>, source: Source) -> Result<Value, String> {
+    let annotation = Annotation {
+        text: text(args, "text").unwrap_or_default().to_string(),
+        t: args.get("t").and_then(Value::as_u64),
+    };
+    let record = annotations::add(context, annotation, source.name()).map_err(|error| error.to_string())?;
+    Ok(json!(record))
+}
+
+fn stop_program(context: &Context, _: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    let mut state = context.program.lock().unwrap();
+    state.run = None;
+    state.save();
+    Ok(json!({ "stopped": true }))
+}
+
+fn compliance_reset(context: &Context, _: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    context.compliance.lock().unwrap().reset(clock::now_unix());
+    Ok(json!({ "reset": true }))
+}
+
+fn drift_reset(context: &Context, args: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    let sensor = text(args, "sensor");
+    context.drift.lock().unwrap().reset(sensor);
+    Ok(json!({ "reset": sensor.unwrap_or("all") }))
+}
+
+fn soak(context: &Context, args: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    let minutes = args.get("minutes").and_then(Value::as_f64).unwrap_or(60.0);
+    if !(0.0..=7.0 * 24.0 * 60.0).contains(&minutes) {
+        return Err("minutes must be between 0 and a week".to_string());
+    }
+    let mut soak = context.soak.lock().unwrap();
+    soak.start(Duration::from_secs_f64(minutes * 60.0));
+    Ok(soak.to_json())
+}
+
+fn start_ow_trace(_: &Context, args: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    let seconds = args.get("seconds").and_then(Value::as_f64).unwrap_or(60.0);
+    if !(0.0..=ow_trace::MAX_SECS).contains(&seconds) {
+        return Err(format!("seconds must be between 0 and {}", ow_trace::MAX_SECS));
+    }
+    ow_trace::start(Duration::from_secs_f64(seconds));
+    Ok(ow_trace::to_json())
+}
+
+fn scan(context: &Context, _: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    context.rescan.store(true, Ordering::Relaxed);
+    Ok(json!({ "scan": "next cycle" }))
+}
+
+fn selftest(context: &Context, _: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    let alarms = context.alarms.to_json();
+    Ok(json!({
+        "state": context.lifecycle.state(),
+        "uptime_secs": context.lifecycle.uptime().as_secs(),
+        "healthy": context.health.ok(),
+        "outputs": context.health.to_json(),
+        "sensors": context.latest_readings().len(),
+        "alarms": alarms.as_array().map_or(0, Vec::len),
+        "one_wire": context.coex.to_json(),
+        "sensor_supply": context.rail.lock().unwrap().to_json(),
+        "soak_active": context.soak.lock().unwrap().active(),
+    }))
+}
+
+fn list_alarms(context: &Context, _: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    Ok(context.alarms.to_json())
+}
+
+fn maintenance(context: &Context, args: &Map<String, Value>, source: Source) -> Result<Value, String> {
+    let minutes = args.get("minutes").and_then(Value::as_f64).unwrap_or(service::DEFAULT_MINUTES as f64);
+    if !(0.0..=service::MAX_MINUTES as f64).contains(&minutes) {
+        return Err(format!("minutes must be between 0 and {}", service::MAX_MINUTES));
+    }
+    if minutes == 0.0 {
+        service::leave(context);
+    } else {
+        service::enter(context, minutes.ceil() as u64, source.name());
+    }
+    Ok(context.service.to_json())
+}
+
+fn identify(context: &Context, args: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    let seconds = args.get("seconds").and_then(Value::as_f64).unwrap_or(10.0);
+    if !(0.0..=300.0).contains(&seconds) {
+        return Err("seconds must be between 0 and 300".to_string());
+    }
+    led::identify(Duration::from_secs_f64(seconds));
+    let mut identity = build_info::info_json(&context.node_id);
+    identity["blinking_secs"] = json!(seconds);
+    Ok(identity)
+}
+
+fn profile(context: &Context, args: &Map<String, Value>, source: Source) -> Result<Value, String> {
+    let name = args.get("profile").and_then(Value::as_str).unwrap_or_default();
+    profiles::switch(context, name, source.name())?;
+    Ok(json!(*context.profiles.lock().unwrap()))
+}
+
+fn preset(context: &Context, args: &Map<String, Value>, source: Source) -> Result<Value, String> {
+    let name = args.get("preset").and_then(Value::as_str).unwrap_or_default();
+    presets::apply(context, name, source.name())?;
+    Ok(presets::to_json(context))
+}
+
+fn power_save(_: &Context, args: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    wifi::change_power_save(text(args, "mode").unwrap_or_default())?;
+    Ok(json!({ "power_save": wifi::power_save() }))
+}
+
+fn schedule_preview(context: &Context, args: &Map<String, Value>, _: Source) -> Result<Value, String> {
+    // numbers from the serial console are floats
+    let hours = args.get("hours").and_then(Value::as_f64).unwrap_or(24.0);
+    if !(1.0..=7.0 * 24.0).contains(&hours) {
+        return Err("hours must be between 1 and a week".to_string());
+    }
+    let from = args.get("t").and_then(Value::as_f64).map_or_else(clock::now_unix, |t| t as u64);
+    Ok(context.schedule.lock().unwrap().preview(from, hours as u64))
+}
+
+fn reboot(context: &Context, _: &Map<String, Value>, source: Source) -> Result<Value, String> {
+    log::warn!("reboot requested over {}", source.name());
+    counters::flush(context);
+    // give the reply a moment to go out
+    thread::spawn(|| {
+        thread::sleep(Duration::from_secs(1));
+        esp_idf_hal::reset::restart();
+    });
+    Ok(json!({ "rebooting": true }))
+}
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+// the command, once the arguments match its schema
+pub fn check(name: &str, args: &Map<String, Value>) -> Result<&'static Command, String> {
+    let command = find(name).ok_or_else(|| format!("unknown command {}", name))?;
+    for (key, _) in args {
+        if !command.args.iter().any(|arg| arg.name == key) {
+            return Err(format!("{} takes no argument {}", name, key));
+        }
+    }
+    for arg in command.args {
+        match (args.get(arg.name), arg.kind) {
+            (None, _) if arg.required => return Err(format!("{} needs {}", name, arg.name)),
+            (None, _) | (Some(Value::String(_)), Kind::Text) | (Some(Value::Number(_)), Kind::Number) => {}
+            (Some(_), Kind::Text) => return Err(format!("{} must be text", arg.name)),
+            (Some(_), Kind::Number) => return Err(format!("{} must be a number", arg.name)),
+        }
+    }
+    Ok(command)
+}
+
+pub fn run(context: &Context, name: &str, args: &Map<String, Value>, source: Source) -> Result<Value, String> {
+    let command = check(name, args)?;
+    (command.handler)(context, args, source)
+}
+
+pub fn schema_json() -> Value {
+    Value::Array(
+        COMMANDS
+            .iter()
+            .map(|command| {
+                let args: Vec<_> = command
+                    .args
+                    .iter()
+                    .map(|arg| {
+                        let kind = if arg.kind == Kind::Text { "text" } else { "number" };
+                        json!({ "name": arg.name, "kind": kind, "required": arg.required })
+                    })
+                    .collect();
+                json!({ "command": command.name, "description": command.description, "args": args })
+            })
+            .collect(),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct Invocation {
+    pub command: String,
+    #[serde(default)]
+    pub args: Map<String, Value>,
+}
+
+// the MQTT form, answered on temp/<node>/command/result
+pub fn run_json(context: &Context, payload: &[u8], source: Source) -> Value {
+    let result = serde_json::from_slice::<Invocation>(payload)
+        .map_err(|error| error.to_string())
+        .and_then(|invocation| run(context, &invocation.command, &invocation.args, source));
+    match result {
+        Ok(result) => json!({ "ok": true, "result": result }),
+        Err(error) => json!({ "ok": false, "error": error }),
+    }
+}
+
+// console form: the arguments in schema order, a text argument with nothing required after
+// it takes the rest of the line, e.g. "annotate defrost started" or "ack program_done"
+fn parse_line(line: &str) -> Result<(&'static Command, Map<String, Value>), String> {
+    let line = line.trim();
+    let (name, mut rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let command = find(name).ok_or_else(|| format!("unknown command {}, try help", name))?;
+    let mut args = Map::new();
+    for (index, arg) in command.args.iter().enumerate() {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let rest_of_line = command.args[index + 1..].iter().all(|later| !later.required);
+        let value = if arg.kind == Kind::Text && rest_of_line {
+            std::mem::take(&mut rest)
+        } else {
+            let (value, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            rest = remainder;
+            value
+        };
+        let value = match arg.kind {
+            Kind::Text => json!(value),
+            Kind::Number => json!(value.parse::<f64>().map_err(|_| format!("{} must be a number", arg.name))?),
+        };
+        args.insert(arg.name.to_string(), value);
+    }
+    Ok((command, args))
+}
+
+// one console line answered as text, the same on the serial and the remote console
+pub fn answer(context: &Context, line: &str, source: Source) -> String {
+    if line.trim() == "help" {
+        let lines: Vec<_> = COMMANDS
+            .iter()
+            .map(|command| {
+                let args: Vec<_> = command.args.iter().map(|arg| arg.name).collect();
+                format!("{} {}: {}", command.name, args.join(" "), command.description)
+            })
+            .collect();
+        return lines.join("\n");
+    }
+    match parse_line(line).and_then(|(command, args)| run(context, command.name, &args, source)) {
+        Ok(result) => result.to_string(),
+        Err(error) => format!("error: {}", error),
+    }
+}
+
+// reads from the console UART block only once its driver backs stdin, without it they return
+// at once with nothing
+fn install_console_driver() -> Result<(), EspError> {
+    let uart = CONFIG_ESP_CONSOLE_UART_NUM as i32;
+    esp!(unsafe { uart_driver_install(uart, 512, 0, 0, std::ptr::null_mut(), 0) })?;
+    unsafe { esp_vfs_dev_uart_use_driver(uart) };
+    Ok(())
+}
+
+// reads commands from the serial console, one per line
+pub fn start_console(context: Arc<Context>) {
+    if let Err(error) = install_console_driver() {
+        log::warn!("no console, the UART driver failed to install: {}", error);
+        return;
+    }
+    let spawned = thread::Builder::new()
+        .name("console".into())
+        .stack_size(6 * 1024)
+        .spawn(move || {
+            let mut out = output::serial(context.clone());
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    // e.g. a line that isn't UTF-8; waits a moment so a failing read can't spin
+                    Err(error) => {
+                        log::warn!("console read failed: {}", error);
+                        thread::sleep(Duration::from_secs(1));
+                        continue;
+                    }
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                writeln!(out, "{}", answer(&context, &line, Source::Cli));
+            }
+            log::warn!("console input closed");
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the console: {}", error);
+    }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    http::route(server, "/api/commands", Method::Get, Api::json(schema_json), |request| {
+        http::write_json(request, &schema_json())
+    })?;
+
+    // {"command": "ack", "args": {"id": "program_done"}}; what it answers depends on the command
+    let invoke = Api::json(|| Value::Null).body(|| json!({ "command": "", "args": {} }));
+    http::route(server, "/api/commands", Method::Post, invoke, move |mut request| {
+        let invocation = match http::read_json::<Invocation>(&mut request, 1024)? {
+            Ok(invocation) => invocation,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        match run(&context, &invocation.command, &invocation.args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}
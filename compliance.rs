@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clock::{self, Clock};
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::Celsius;
+
+const STATE_FILE: &str = "compliance.json";
+// oldest closed excursions are dropped beyond this, the summary counters keep counting
+const MAX_EXCURSIONS: usize = 200;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub low: Celsius,
+    pub high: Celsius,
+    // sensors subject to the band, all sensors when empty
+    pub sensors: Vec<String>,
+}
+
+impl Default for Settings {
+    // the usual 2-8 °C band for chilled food and pharmaceuticals
+    fn default() -> Self {
+        Self {
+            low: Celsius(2.0),
+            high: Celsius(8.0),
+            sensors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Excursion {
+    pub sensor: String,
+    pub start: u64,
+    // None while still outside the band
+    pub end: Option<u64>,
+    // the reading furthest outside the band
+    pub peak: Celsius,
+    // false if the clock wasn't SNTP-synced at the start, so the timestamps are boot-relative
+    pub time_valid: bool,
+}
+
+impl Excursion {
+    fn duration_secs(&self, now: u64) -> u64 {
+        self.end.unwrap_or(now).saturating_sub(self.start)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct SensorStats {
+    first: u64,
+    last: u64,
+    samples: u64,
+    min: Celsius,
+    max: Celsius,
+    sum: f64,
+    excursions: u64,
+    // closed excursions only, open ones are added when reporting
+    seconds_outside: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Compliance {
+    pub settings: Settings,
+    // start of the audit period, set when the log is reset
+    period_start: u64,
+    stats: BTreeMap<String, SensorStats>,
+    excursions: Vec<Excursion>,
+    // the statistics moved since the last save; written with the counters, excursion changes
+    // right away
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl Compliance {
+    pub fn load() -> Self {
+        storage::read_json(STATE_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&mut self) {
+        self.dirty = false;
+        if let Err(error) = storage::write_json(STATE_FILE, self) {
+            log::warn!("failed to save compliance log: {}", error);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+
+    pub fn period_start(&self) -> u64 {
+        self.period_start
+    }
+
+    fn applies_to(&self, sensor: &str) -> bool {
+        self.settings.sensors.is_empty() || self.settings.sensors.iter().any(|monitored| monitored == sensor)
+    }
+
+    pub fn reset(&mut self, now: u64) {
+        self.period_start = now;
+        self.stats.clear();
+        self.excursions.clear();
+        self.save();
+    }
+
+    pub fn update(&mut self, readings: &[Reading], clock: &dyn Clock) {
+        let now = clock.now_unix();
+        if self.period_start == 0 {
+            self.period_start = now;
+        }
+        let (low, high) = (self.settings.low, self.settings.high);
+        let mut changed = false;
+
+        let monitored: Vec<&Reading> = readings.iter().filter(|reading| self.applies_to(&reading.sensor)).collect();
+        for reading in monitored {
+            let celsius = reading.celsius;
+            let stats = self.stats.entry(reading.sensor.clone()).or_insert_with(|| SensorStats {
+                first: now,
+                min: celsius,
+                max: celsius,
+                ..Default::default()
+            });
+            stats.last = now;
+            stats.samples += 1;
+            stats.min = stats.min.min(celsius);
+            stats.max = stats.max.max(celsius);
+            stats.sum += f64::from(celsius.0);
+
+            let outside = celsius < low || celsius > high;
+            let open = self
+                .excursions
+                .iter()
+                .rposition(|excursion| excursion.sensor == reading.sensor && excursion.end.is_none());
+            match (open, outside) {
+                (Some(index), true) => {
+                    // keep whichever reading is further from the band
+                    let distance = |value: Celsius| if value < low { low - value } else { value - high };
+                    let excursion = &mut self.excursions[index];
+                    if distance(celsius) > distance(excursion.peak) {
+                        excursion.peak = celsius;
+                    }
+                }
+                (Some(index), false) => {
+                    let excursion = &mut self.excursions[index];
+                    excursion.end = Some(now);
+                    stats.seconds_outside += excursion.duration_secs(now);
+                    changed = true;
+                }
+                (None, true) => {
+                    stats.excursions += 1;
+                    self.excursions.push(Excursion {
+                        sensor: reading.sensor.clone(),
+                        start: now,
+                        end: None,
+                        peak: celsius,
+                        time_valid: clock.is_synced(),
+                    });
+                    changed = true;
+                }
+                (None, false) => {}
+            }
+        }
+
+        // drop the oldest closed excursions, open ones are always kept
+        while self.excursions.len() > MAX_EXCURSIONS {
+            match self.excursions.iter().position(|excursion| excursion.end.is_some()) {
+                Some(oldest) => {
+                    self.excursions.remove(oldest);
+                }
+                None => break,
+            }
+        }
+
+        if changed {
+            self.save();
+        } else {
+            self.dirty = true;
+        }
+    }
+
+    pub fn report_json(&self, node_id: &str, now: u64) -> serde_json::Value {
+        let sensors: Vec<_> = self
+            .stats
+            .iter()
+            .map(|(sensor, stats)| {
+                let open_secs: u64 = self
+                    .excursions
+                    .iter()
+                    .filter(|excursion| &excursion.sensor == sensor && excursion.end.is_none())
+                    .map(|excursion| excursion.duration_secs(now))
+                    .sum();
+                json!({
+                    "sensor": sensor,
+                    "first": stats.first,
+                    "last": stats.last,
+                    "samples": stats.samples,
+                    "min": stats.min,
+                    "max": stats.max,
+                    "mean": if stats.samples > 0 { stats.sum / stats.samples as f64 } else { 0.0 },
+                    "excursions": stats.excursions,
+                    "seconds_outside": stats.seconds_outside + open_secs,
+                })
+            })
+            .collect();
+        let excursions: Vec<_> = self
+            .excursions
+            .iter()
+            .map(|excursion| {
+                json!({
+                    "sensor": excursion.sensor,
+                    "start": excursion.start,
+                    "end": excursion.end,
+                    "duration_secs": excursion.duration_secs(now),
+                    "peak": excursion.peak,
+                    "time_valid": excursion.time_valid,
+                })
+            })
+            .collect();
+        json!({
+            "node": node_id,
+            "generated": now,
+            "period_start": self.period_start,
+            "band": { "low": self.settings.low, "high": self.settings.high },
+            "sensors": sensors,
+            "excursions": excursions,
+        })
+    }
+
+    // a report with one sensor and one excursion, for the API description
+    pub fn report_example() -> serde_json::Value {
+        let mut compliance = Compliance::default();
+        compliance.stats.insert(String::new(), SensorStats::default());
+        let excursion = Excursion { sensor: String::new(), start: 0, end: Some(0), peak: Celsius(0.0), time_valid: true };
+        compliance.excursions.push(excursion);
+        compliance.report_json("", 0)
+    }
+
+    pub fn report_csv(&self, node_id: &str, now: u64) -> String {
+        let time = |unix: u64| clock::format_iso8601(unix);
+        let mut csv = String::new();
+        let _ = writeln!(csv, "# cold-chain report for {} generated {}", node_id, time(now));
+        let _ = writeln!(
+            csv,
+            "# period from {}, band {:.1}..{:.1}",
+            time(self.period_start),
+            self.settings.low,
+            self.settings.high
+        );
+        let _ = writeln!(csv, "sensor,start,end,duration_secs,peak_celsius,time_valid");
+        for excursion in &self.excursions {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{:.2},{}",
+                excursion.sensor,
+                time(excursion.start),
+                excursion.end.map(time).unwrap_or_default(),
+                excursion.duration_secs(now),
+                excursion.peak.0,
+                excursion.time_valid
+            );
+        }
+        csv
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use esp_idf_hal::io::Write;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde_json::{json, Map};
+use sha2::{Digest, Sha512};
+
+use crate::clock;
+use crate::commands::{self, Source};
+use crate::compliance::{Compliance, Settings};
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::roughtime;
+use crate::signing;
+
+// /api/compliance: the band, resets and signed reports of the log kept in compliance.rs
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| json!({ "settings": Settings::default(), "period_start": 0 }));
+    http::route(server, "/api/compliance", Method::Get, status, move |request| {
+        let compliance = status_context.compliance.lock().unwrap();
+        let body = json!({ "settings": compliance.settings, "period_start": compliance.period_start() });
+        drop(compliance);
+        http::write_json(request, &body)
+    })?;
+
+    let settings_context = context.clone();
+    http::route(server, "/api/compliance", Method::Post, Api::settings::<Settings>(), move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 1024)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let mut compliance = settings_context.compliance.lock().unwrap();
+        compliance.settings = settings;
+        compliance.save();
+        let body = json!(compliance.settings);
+        drop(compliance);
+        settings_context.events.publish(Event::ConfigChanged("compliance"));
+        http::write_json(request, &body)
+    })?;
+
+    // starts a new audit period, e.g. per shipment
+    let reset_context = context.clone();
+    http::route(server, "/api/compliance/reset", Method::Post, Api::json(|| json!({ "reset": true })), move |request| {
+        match commands::run(&reset_context, "compliance_reset", &Map::new(), Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 500, &error),
+        }
+    })?;
+
+    // the signature over the report and the key to check it with are sent as X-Signature and
+    // X-Public-Key; ?format=csv reports repeat them in a trailing comment that isn't signed.
+    // with Roughtime configured the SHA-512 of the report is the nonce of a Roughtime request,
+    // the server's signed response proves the report existed at that time (X-Roughtime-*)
+    let report_context = context.clone();
+    let report = Api::json(Compliance::report_example).query(&[("format", "string")]);
+    http::route(server, "/api/compliance/report", Method::Get, report, move |request| {
+        let now = clock::now_unix();
+        let compliance = report_context.compliance.lock().unwrap();
+        let csv = http::query_param(request.uri(), "format") == Some("csv");
+        let (content_type, mut body) = if csv {
+            ("text/csv", compliance.report_csv(&report_context.node_id, now))
+        } else {
+            ("application/json", compliance.report_json(&report_context.node_id, now).to_string())
+        };
+        drop(compliance);
+
+        let signature = report_context.device_key.sign_hex(body.as_bytes());
+        let public_key = report_context.device_key.public_key_hex();
+        let attestation = if roughtime::enabled() {
+            let mut nonce = [0u8; 64];
+            nonce.copy_from_slice(&Sha512::digest(body.as_bytes()));
+            roughtime::attest(nonce)
+                .inspect_err(|error| log::warn!("roughtime attestation failed: {}", error))
+                .ok()
+        } else {
+            None
+        };
+        if csv {
+            // the trailing comments are not part of the signed data
+            body.push_str(&format!("# ed25519 signature {} public key {}\n", signature, public_key));
+            if let Some(attestation) = &attestation {
+                body.push_str(&format!(
+                    "# roughtime {} midpoint {} radius {} response {}\n",
+                    attestation.server,
+                    attestation.midpoint,
+                    attestation.radius,
+                    signing::to_hex(&attestation.response)
+                ));
+            }
+        }
+
+        let mut headers = vec![
+            ("Content-Type", content_type.to_string()),
+            ("X-Signature", signature),
+            ("X-Public-Key", public_key),
+        ];
+        if let Some(attestation) = attestation {
+            headers.push(("X-Roughtime-Server", attestation.server.to_string()));
+            headers.push(("X-Roughtime-Midpoint", attestation.midpoint.to_string()));
+            headers.push(("X-Roughtime-Radius", attestation.radius.to_string()));
+            headers.push(("X-Roughtime-Response", signing::to_hex(&attestation.response)));
+        }
+        let headers: Vec<_> = headers.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        let mut response = request.into_response(200, None, &headers)?;
+        response.write_all(body.as_bytes())?;
+        Ok::<(), esp_idf_svc::io::EspIOError>(())
+    })?;
+
+    let key_context = context;
+    let key = Api::json(|| json!({ "algorithm": "", "public_key": "" }));
+    http::route(server, "/api/compliance/key", Method::Get, key, move |request| {
+        let public_key = key_context.device_key.public_key_hex();
+        http::write_json(request, &json!({ "algorithm": "ed25519", "public_key": public_key }))
+    })?;
+
+    Ok(())
+}
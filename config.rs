@@ -0,0 +1,218 @@
+// compile-time settings, e.g. `WIFI_SSID=... WIFI_PASSWORD=... MQTT_URL=mqtt://broker:1883 cargo build`
+// an empty WIFI_SSID builds a serial-only firmware
+pub const WIFI_SSID: &str = match option_env!("WIFI_SSID") {
+    Some(ssid) => ssid,
+    None => "",
+};
+pub const WIFI_PASSWORD: &str = match option_env!("WIFI_PASSWORD") {
+    Some(password) => password,
+    None => "",
+};
+pub const MQTT_URL: &str = match option_env!("MQTT_URL") {
+    Some(url) => url,
+    None => "",
+};
+// Wi-Fi power save (WIFI_POWER_SAVE=none|min|max): "none" keeps the radio listening all the
+// time, "min" (the ESP-IDF default) wakes for every DTIM beacon, "max" only every
+// WIFI_LISTEN_INTERVAL beacons, trading command and publish latency for current draw
+pub const WIFI_POWER_SAVE: &str = match option_env!("WIFI_POWER_SAVE") {
+    Some(mode) => power_save_mode(mode),
+    None => "min",
+};
+// in beacon intervals (about 100 ms each) for "max", 0 for the ESP-IDF default of 3
+pub const WIFI_LISTEN_INTERVAL: u32 = match option_env!("WIFI_LISTEN_INTERVAL") {
+    Some(interval) => parse_u32(interval),
+    None => 0,
+};
+// address family tried first where the firmware picks one (NET_PREFER=v4|v6). With "v6" the
+// station also comes up without a DHCP lease, on its IPv6 link-local address alone, for
+// installs without a router
+pub const NET_PREFER: &str = match option_env!("NET_PREFER") {
+    Some(family) => family,
+    None => "v4",
+};
+// InfluxDB v2 write endpoint including org, bucket and precision=s, and its API token
+pub const INFLUX_URL: &str = match option_env!("INFLUX_URL") {
+    Some(url) => url,
+    None => "",
+};
+pub const INFLUX_TOKEN: &str = match option_env!("INFLUX_TOKEN") {
+    Some(token) => token,
+    None => "",
+};
+
+// remote console: a WebSocket on wss://<device>/console that takes the serial console's
+// commands. It needs a shared secret every session starts with and the PEM certificate and
+// key to serve TLS with (CONSOLE_CERT="$(cat cert.pem)"); without all three it stays off
+pub const CONSOLE_TOKEN: &str = match option_env!("CONSOLE_TOKEN") {
+    Some(token) => token,
+    None => "",
+};
+pub const CONSOLE_CERT: &str = match option_env!("CONSOLE_CERT") {
+    Some(cert) => cert,
+    None => "",
+};
+pub const CONSOLE_KEY: &str = match option_env!("CONSOLE_KEY") {
+    Some(key) => key,
+    None => "",
+};
+
+// gRPC-Web service of proto/temp.proto on its own port (GRPC_PORT=50051), 0 to leave it off
+pub const GRPC_PORT: u32 = match option_env!("GRPC_PORT") {
+    Some(port) => parse_u32(port),
+    None => 0,
+};
+
+// long polls of /api/temps (?wait=<secs>) on a port of their own (LONG_POLL_PORT=8081), 0 to
+// leave them off
+pub const LONG_POLL_PORT: u32 = match option_env!("LONG_POLL_PORT") {
+    Some(port) => parse_u32(port),
+    None => 8081,
+};
+
+// read-only Modbus TCP of the readings (MODBUS_PORT=502), 0 to leave it off; the register map
+// is at /api/register-map
+pub const MODBUS_PORT: u32 = match option_env!("MODBUS_PORT") {
+    Some(port) => parse_u32(port),
+    None => 0,
+};
+
+// prefix for all MQTT topics published by this device, e.g. temp/<node>/state
+pub const MQTT_TOPIC_PREFIX: &str = "temp";
+pub const MQTT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+pub const SAMPLE_INTERVAL_MS: u32 = 30_000;
+// time one sampling cycle may take before optional work is skipped (CYCLE_BUDGET_MS=...),
+// 0 for three quarters of the sample interval
+pub const CYCLE_BUDGET_MS: u32 = match option_env!("CYCLE_BUDGET_MS") {
+    Some(budget) => parse_u32(budget),
+    None => 0,
+};
+// for battery installs (DEEP_SLEEP_SECS=...): after each reading has gone out the chip deep
+// sleeps this long instead of waiting out the sample interval, 0 to stay awake
+pub const DEEP_SLEEP_SECS: u64 = match option_env!("DEEP_SLEEP_SECS") {
+    Some(secs) => parse_u32(secs) as u64,
+    None => 0,
+};
+// full 1-Wire ROM search this often (RESCAN_MINUTES=...); in between, the sensors found last
+// time are read directly
+pub const RESCAN_MINUTES: u32 = match option_env!("RESCAN_MINUTES") {
+    Some(minutes) => parse_u32(minutes),
+    None => 10,
+};
+// readings are written to the history log this often
+pub const HISTORY_INTERVAL_SECS: u64 = 300;
+// a scheduled Wi-Fi connect that failed is tried again after this long
+pub const WIFI_RETRY_SECS: u64 = 60;
+// SIGN_HISTORY=1 chain-hashes and signs every full history segment with the device key
+pub const SIGN_HISTORY: bool = option_env!("SIGN_HISTORY").is_some();
+
+// Roughtime server ("host:port") and its long-term Ed25519 public key in hex; with both set
+// compliance reports carry a signed timestamp from the server
+pub const ROUGHTIME_SERVER: &str = match option_env!("ROUGHTIME_SERVER") {
+    Some(server) => server,
+    None => "",
+};
+pub const ROUGHTIME_PUBLIC_KEY: &str = match option_env!("ROUGHTIME_PUBLIC_KEY") {
+    Some(key) => key,
+    None => "",
+};
+
+// the supply voltage through a divider on GPIO34: VIN_DIVIDER is VIN over the pin voltage in
+// thousandths (2000 for two equal resistors), 0 without a divider; a drop below VIN_DIP_MV is
+// logged as a dip
+pub const VIN_DIVIDER: u32 = match option_env!("VIN_DIVIDER") {
+    Some(ratio) => parse_u32(ratio),
+    None => 0,
+};
+pub const VIN_DIP_MV: u32 = match option_env!("VIN_DIP_MV") {
+    Some(millivolts) => parse_u32(millivolts),
+    None => 4500,
+};
+
+// a 74HC4051 between GPIO4 and this many sensor cables (2 to 8), its select lines S0..S2 on
+// GPIO5, GPIO13 and GPIO14; 0 without one
+pub const MUX_CHANNELS: u32 = match option_env!("MUX_CHANNELS") {
+    Some(channels) => parse_u32(channels),
+    None => 0,
+};
+
+// an INA219 or INA3221 on the I2C bus measures the current into the sensor supply through
+// shunts of SHUNT_MILLIOHMS (100 on the usual breakout boards); RAIL_MAX_MA and RAIL_MIN_MA are
+// fixed limits for a short and an open line, 0 to go by the draw learnt after boot alone
+pub const SHUNT_MILLIOHMS: u32 = match option_env!("SHUNT_MILLIOHMS") {
+    Some(milliohms) => parse_u32(milliohms),
+    None => 100,
+};
+pub const RAIL_MAX_MA: u32 = match option_env!("RAIL_MAX_MA") {
+    Some(milliamps) => parse_u32(milliamps),
+    None => 0,
+};
+pub const RAIL_MIN_MA: u32 = match option_env!("RAIL_MIN_MA") {
+    Some(milliamps) => parse_u32(milliamps),
+    None => 0,
+};
+
+// a GPS module on UART2 (its TX to GPIO16) at this baud rate, usually 9600; 0 without one
+pub const GPS_BAUD: u32 = match option_env!("GPS_BAUD") {
+    Some(baud) => parse_u32(baud),
+    None => 0,
+};
+
+// "gateway" nodes subscribe to their peers' state and show them on their dashboard
+pub const NODE_ROLE: &str = match option_env!("NODE_ROLE") {
+    Some(role) => role,
+    None => "node",
+};
+
+pub fn is_gateway() -> bool {
+    NODE_ROLE == "gateway"
+}
+
+// what the device is called in the boot banner, /api/info and the identify command, e.g. where
+// it is installed (DEVICE_NAME="Cold store 3"); the node id when empty
+pub const DEVICE_NAME: &str = match option_env!("DEVICE_NAME") {
+    Some(name) => name,
+    None => "",
+};
+
+// controller profile driving the heat (GPIO26) and cool/humidifier (GPIO27) outputs:
+// "fermentation", "sous_vide", "incubator", "heating" or empty for a plain monitor
+pub const PROFILE: &str = match option_env!("PROFILE") {
+    Some(profile) => profile,
+    None => "",
+};
+
+// sous-vide heater gains, output is the heater duty cycle (0..1) per °C of error
+pub const PID_KP: f32 = 0.4;
+pub const PID_KI: f32 = 0.0008;
+pub const PID_KD: f32 = 20.0;
+
+// closed-loop PID control needs faster sampling than monitoring does
+pub fn sample_interval_ms() -> u32 {
+    match PROFILE {
+        "sous_vide" => 2_000,
+        "incubator" => 5_000,
+        _ => SAMPLE_INTERVAL_MS,
+    }
+}
+
+// option_env! only gives strings, this turns numeric settings into constants at compile time
+const fn power_save_mode(mode: &'static str) -> &'static str {
+    match mode.as_bytes() {
+        b"none" | b"min" | b"max" => mode,
+        _ => panic!("WIFI_POWER_SAVE is none, min or max"),
+    }
+}
+
+const fn parse_u32(text: &str) -> u32 {
+    let bytes = text.as_bytes();
+    let mut value = 0u32;
+    let mut index = 0;
+    while index < bytes.len() {
+        assert!(bytes[index].is_ascii_digit(), "expected a number");
+        value = value * 10 + (bytes[index] - b'0') as u32;
+        index += 1;
+    }
+    value
+}
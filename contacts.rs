@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::v2::InputPin;
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clock;
+use crate::context::Context;
+use crate::events::Event;
+use crate::history::Record;
+use crate::http;
+use crate::openapi::Api;
+use crate::storage;
+
+const SETTINGS_FILE: &str = "contacts.json";
+const POLL_MS: u32 = 50;
+// consecutive equal samples before a change is accepted, reed switches bounce
+const DEBOUNCE_SAMPLES: u8 = 4;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContactSettings {
+    pub name: String,
+    // sensors affected by this contact, e.g. the probes inside that fridge; all when empty
+    pub sensors: Vec<String>,
+    // alarms of those sensors stay suppressed this long after the contact closes again
+    pub grace_minutes: u32,
+}
+
+impl Default for ContactSettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            sensors: Vec::new(),
+            grace_minutes: 10,
+        }
+    }
+}
+
+struct ContactState {
+    open: bool,
+    changed: Option<Instant>,
+}
+
+// settings and live state of the digital inputs, in the order the pins were passed to `start`
+pub struct Contacts {
+    settings: Vec<ContactSettings>,
+    state: Vec<ContactState>,
+}
+
+impl Contacts {
+    pub fn load() -> Self {
+        Self {
+            settings: storage::read_json(SETTINGS_FILE).unwrap_or_default(),
+            state: Vec::new(),
+        }
+    }
+
+    fn settings(&self, index: usize) -> ContactSettings {
+        self.settings.get(index).cloned().unwrap_or_else(|| ContactSettings {
+            name: format!("contact{}", index + 1),
+            ..Default::default()
+        })
+    }
+
+    // true while a contact covering `sensor` is open or only recently closed
+    pub fn suppresses(&self, sensor: &str) -> bool {
+        self.state.iter().enumerate().any(|(index, state)| {
+            let settings = self.settings(index);
+            let covers = settings.sensors.is_empty() || settings.sensors.iter().any(|covered| covered == sensor);
+            let grace = Duration::from_secs(u64::from(settings.grace_minutes) * 60);
+            covers && (state.open || state.changed.is_some_and(|changed| changed.elapsed() < grace))
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!((0..self.state.len().max(self.settings.len()))
+            .map(|index| {
+                let state = self.state.get(index);
+                json!({
+                    "settings": self.settings(index),
+                    "open": state.map(|state| state.open),
+                    "changed_secs": state.and_then(|state| state.changed).map(|changed| changed.elapsed().as_secs()),
+                })
+            })
+            .collect::<Vec<_>>())
+    }
+}
+
+// polls the inputs in the background; switches pull the input low when closed (door shut)
+pub fn start<P: InputPin + Send + 'static>(context: Arc<Context>, pins: Vec<P>) {
+    thread::Builder::new()
+        .name("contacts".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut stable: Vec<bool> = pins.iter().map(|pin| pin.is_high().unwrap_or(false)).collect();
+            let mut pending = vec![0u8; pins.len()];
+            context.contacts.lock().unwrap().state = stable
+                .iter()
+                .map(|&open| ContactState { open, changed: None })
+                .collect();
+
+            loop {
+                for (index, pin) in pins.iter().enumerate() {
+                    let Ok(open) = pin.is_high() else {
+                        continue;
+                    };
+                    if open == stable[index] {
+                        pending[index] = 0;
+                        continue;
+                    }
+                    pending[index] += 1;
+                    if pending[index] < DEBOUNCE_SAMPLES {
+                        continue;
+                    }
+                    pending[index] = 0;
+                    stable[index] = open;
+
+                    let mut contacts = context.contacts.lock().unwrap();
+                    contacts.state[index] = ContactState {
+                        open,
+                        changed: Some(Instant::now()),
+                    };
+                    let name = contacts.settings(index).name;
+                    drop(contacts);
+
+                    let record = Record::Contact {
+                        t: clock::now_unix(),
+                        name,
+                        open,
+                    };
+                    if let Err(error) = context.history.lock().unwrap().append(&record) {
+                        log::warn!("failed to log contact change: {}", error);
+                    }
+                }
+                FreeRtos::delay_ms(POLL_MS);
+            }
+        })
+        .expect("failed to start the contact inputs");
+}
+
+fn status_example() -> serde_json::Value {
+    json!([{ "settings": ContactSettings::default(), "open": false, "changed_secs": 0 }])
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/contacts", Method::Get, Api::json(status_example), move |request| {
+        let body = status_context.contacts.lock().unwrap().to_json();
+        http::write_json(request, &body)
+    })?;
+
+    // a list with one entry per input, in pin order
+    let settings_context = context;
+    let settings = Api::json(status_example).body(|| json!([ContactSettings::default()]));
+    http::route(server, "/api/contacts", Method::Post, settings, move |mut request| {
+        let settings = match http::read_json::<Vec<ContactSettings>>(&mut request, 2048)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        if let Err(error) = storage::write_json(SETTINGS_FILE, &settings) {
+            return http::write_error(request, 500, &error.to_string());
+        }
+        let mut contacts = settings_context.contacts.lock().unwrap();
+        contacts.settings = settings;
+        let body = contacts.to_json();
+        drop(contacts);
+        settings_context.events.publish(Event::ConfigChanged("contacts"));
+        http::write_json(request, &body)
+    })?;
+
+    Ok(())
+}
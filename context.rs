@@ -0,0 +1,166 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+use crate::alarms::Alarms;
+use crate::client::HttpClient;
+use crate::coex::Coexistence;
+use crate::compliance::Compliance;
+use crate::config;
+use crate::contacts::Contacts;
+use crate::counters::Counters;
+use crate::cycle::CycleStats;
+use crate::degree_days::DegreeDays;
+use crate::derived;
+use crate::display::Extremes;
+use crate::drift::Drift;
+use crate::events::Bus;
+use crate::export::Exporters;
+use crate::expressions;
+use crate::fleet::Fleet;
+use crate::gps::Gps;
+use crate::health::Health;
+use crate::heating::HeatingState;
+use crate::history::History;
+use crate::incubator::IncubatorState;
+use crate::lifecycle::Lifecycle;
+use crate::maintenance::Maintenance;
+use crate::mqtt::RoundTrip;
+use crate::peers::Peers;
+use crate::power::Power;
+use crate::presets::Presets;
+use crate::profiles::Profiles;
+use crate::program::ProgramState;
+use crate::rail::Rail;
+use crate::readings::{Reading, ReadingsCache};
+use crate::redundancy;
+use crate::registry::Registry;
+use crate::rules::Rules;
+use crate::schedule;
+use crate::scripting::Script;
+use crate::service::ServiceMode;
+use crate::shadow::Restored;
+use crate::signing::DeviceKey;
+use crate::soak::Soak;
+use crate::tilt::TiltReading;
+use crate::topology::Discovery;
+use crate::trend::Trends;
+use crate::twin::Twin;
+
+// state shared between the sampling loop, the HTTP handlers and the MQTT callback
+pub struct Context {
+    pub node_id: String,
+    pub gateway: bool,
+    pub readings: ReadingsCache,
+    pub peers: Peers,
+    pub registry: Mutex<Registry>,
+    pub program: Mutex<ProgramState>,
+    pub alarms: Alarms,
+    pub hydrometer: Mutex<Option<TiltReading>>,
+    pub incubator: Mutex<IncubatorState>,
+    pub compliance: Mutex<Compliance>,
+    pub device_key: DeviceKey,
+    pub contacts: Mutex<Contacts>,
+    pub history: Mutex<History>,
+    pub heating: Mutex<HeatingState>,
+    pub derived: Mutex<derived::Settings>,
+    pub degree_days: Mutex<DegreeDays>,
+    pub trends: Mutex<Trends>,
+    pub redundancy: Mutex<redundancy::Settings>,
+    pub drift: Mutex<Drift>,
+    pub topology: Mutex<Discovery>,
+    pub cycle_stats: Mutex<CycleStats>,
+    pub events: Arc<Bus>,
+    pub lifecycle: Lifecycle,
+    pub health: Health,
+    pub exporters: Exporters,
+    pub extremes: Mutex<Extremes>,
+    pub soak: Mutex<Soak>,
+    pub schedule: Mutex<schedule::Settings>,
+    pub round_trip: Mutex<RoundTrip>,
+    pub coex: Coexistence,
+    pub rail: Mutex<Rail>,
+    // set by the scan command, the sampling loop searches the bus on its next cycle
+    pub rescan: AtomicBool,
+    pub fleet: Mutex<Fleet>,
+    pub twin: Mutex<Twin>,
+    // set while the readings are the ones from before a reset
+    pub restored: Mutex<Option<Restored>>,
+    pub expressions: Mutex<expressions::Settings>,
+    pub script: Mutex<Script>,
+    pub rules: Mutex<Rules>,
+    pub profiles: Mutex<Profiles>,
+    pub presets: Mutex<Presets>,
+    pub counters: Counters,
+    pub power: Mutex<Power>,
+    pub gps: Mutex<Gps>,
+    pub http_client: HttpClient,
+    pub maintenance: Maintenance,
+    // maintenance mode for probe swaps and cleaning, not to be confused with the storage
+    // housekeeping above
+    pub service: ServiceMode,
+}
+
+impl Context {
+    pub fn new(node_id: String, gateway: bool, device_key: DeviceKey, counters: Counters) -> Arc<Self> {
+        let history = History::new(config::SIGN_HISTORY.then(|| device_key.clone()));
+        let events = Arc::new(Bus::default());
+        Arc::new(Self {
+            node_id,
+            gateway,
+            readings: ReadingsCache::default(),
+            peers: Peers::default(),
+            registry: Mutex::new(Registry::load()),
+            program: Mutex::new(ProgramState::load()),
+            alarms: Alarms::new(events.clone()),
+            incubator: Mutex::new(IncubatorState::load()),
+            compliance: Mutex::new(Compliance::load()),
+            device_key,
+            contacts: Mutex::new(Contacts::load()),
+            history: Mutex::new(history),
+            heating: Mutex::new(HeatingState::load()),
+            derived: Mutex::new(derived::Settings::load()),
+            degree_days: Mutex::new(DegreeDays::load()),
+            trends: Mutex::new(Trends::default()),
+            redundancy: Mutex::new(redundancy::Settings::load()),
+            drift: Mutex::new(Drift::load()),
+            topology: Mutex::new(Discovery::default()),
+            cycle_stats: Mutex::new(CycleStats::default()),
+            events,
+            lifecycle: Lifecycle::default(),
+            health: Health::default(),
+            exporters: Exporters::default(),
+            extremes: Mutex::new(Extremes::default()),
+            soak: Mutex::new(Soak::default()),
+            schedule: Mutex::new(schedule::Settings::load()),
+            round_trip: Mutex::new(RoundTrip::default()),
+            coex: Coexistence::default(),
+            rail: Mutex::new(Rail::default()),
+            rescan: AtomicBool::new(false),
+            fleet: Mutex::new(Fleet::load()),
+            twin: Mutex::new(Twin::load()),
+            restored: Mutex::new(None),
+            expressions: Mutex::new(expressions::Settings::load()),
+            script: Mutex::new(Script::load()),
+            rules: Mutex::new(Rules::load()),
+            profiles: Mutex::new(Profiles::load()),
+            presets: Mutex::new(Presets::load()),
+            counters,
+            power: Mutex::new(Power::load()),
+            gps: Mutex::new(Gps::default()),
+            http_client: HttpClient::default(),
+            maintenance: Maintenance::default(),
+            service: ServiceMode::default(),
+            hydrometer: Mutex::new(None),
+        })
+    }
+
+    // a copy to work on; readers that only look use readings.snapshot()
+    pub fn latest_readings(&self) -> Vec<Reading> {
+        self.readings.snapshot().readings.clone()
+    }
+
+    pub fn set_readings(&self, readings: Vec<Reading>) {
+        self.readings.publish(readings);
+        *self.restored.lock().unwrap() = None;
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::EspError;
+use serde_json::{json, Map, Value};
+
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+
+const NAMESPACE: &str = "counters";
+// an NVS page takes about 100k erases; flushing every 10 minutes is a few writes per counter an
+// hour instead of one per change, at the cost of up to that much counting lost on a power cut
+const FLUSH_INTERVAL: Duration = Duration::from_secs(600);
+
+// every persisted counter, also its NVS key (15 characters at most)
+pub const BOOTS: &str = "boots";
+pub const RUNTIME_SECS: &str = "runtime_secs";
+pub const CYCLES: &str = "cycles";
+pub const READ_FAILURES: &str = "read_failures";
+pub const BROWNOUTS: &str = "brownouts";
+// panics and watchdog resets
+pub const CRASHES: &str = "crashes";
+const NAMES: &[&str] = &[BOOTS, RUNTIME_SECS, CYCLES, READ_FAILURES, BROWNOUTS, CRASHES];
+
+struct Values {
+    current: BTreeMap<&'static str, u64>,
+    // what NVS holds, only counters that moved since are written
+    written: BTreeMap<&'static str, u64>,
+    last_flush: Instant,
+}
+
+// totals that change too often to write through (cycles, runtime, sequence numbers): they are
+// counted in memory and written to NVS together, on a timer and before a reboot
+pub struct Counters {
+    nvs: Mutex<EspNvs<NvsDefault>>,
+    values: Mutex<Values>,
+}
+
+impl Counters {
+    pub fn load(partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        let mut current = BTreeMap::new();
+        for name in NAMES {
+            current.insert(*name, nvs.get_u64(name)?.unwrap_or(0));
+        }
+        Ok(Self {
+            nvs: Mutex::new(nvs),
+            values: Mutex::new(Values {
+                written: current.clone(),
+                current,
+                last_flush: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn add(&self, name: &'static str, amount: u64) {
+        let mut values = self.values.lock().unwrap();
+        *values.current.entry(name).or_insert(0) += amount;
+    }
+
+    pub fn get(&self, name: &str) -> u64 {
+        self.values.lock().unwrap().current.get(name).copied().unwrap_or(0)
+    }
+
+    // writes the counters that changed; the runtime is booked here, so it counts up to the
+    // last flush
+    pub fn flush(&self) {
+        let mut values = self.values.lock().unwrap();
+        let elapsed = values.last_flush.elapsed().as_secs();
+        if elapsed > 0 {
+            *values.current.entry(RUNTIME_SECS).or_insert(0) += elapsed;
+            // keep the fraction of a second for next time
+            values.last_flush += Duration::from_secs(elapsed);
+        }
+        let changed: Vec<(&'static str, u64)> = values
+            .current
+            .iter()
+            .filter(|(name, value)| values.written.get(*name) != Some(*value))
+            .map(|(name, value)| (*name, *value))
+            .collect();
+        let mut nvs = self.nvs.lock().unwrap();
+        for (name, value) in changed {
+            match nvs.set_u64(name, value) {
+                Ok(()) => {
+                    values.written.insert(name, value);
+                }
+                Err(error) => log::warn!("failed to save counter {}: {}", name, error),
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let values = self.values.lock().unwrap();
+        let counters: Map<String, Value> = values.current.iter().map(|(name, value)| (name.to_string(), json!(value))).collect();
+        json!({ "counters": counters, "flushed_secs_ago": values.last_flush.elapsed().as_secs() })
+    }
+}
+
+// state the sampling loop changes every cycle, marked dirty there and written here with the
+// counters rather than each on a timer of its own
+const SAVERS: &[fn(&Context)] = &[
+    |context| context.degree_days.lock().unwrap().flush(),
+    |context| context.drift.lock().unwrap().flush(),
+    |context| context.compliance.lock().unwrap().flush(),
+    |context| context.program.lock().unwrap().flush(),
+    |context| context.alarms.flush(),
+];
+
+// the counters and every saver, on the timer and before a reboot
+pub fn flush(context: &Context) {
+    context.counters.flush();
+    for saver in SAVERS {
+        saver(context);
+    }
+}
+
+pub fn start(context: Arc<Context>) {
+    thread::Builder::new()
+        .name("counters".into())
+        .stack_size(4096)
+        .spawn(move || loop {
+            thread::sleep(FLUSH_INTERVAL);
+            flush(&context);
+        })
+        .expect("failed to start the counters thread");
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let counters = Api::json(|| {
+        let counters: Map<String, Value> = NAMES.iter().map(|name| (name.to_string(), json!(0))).collect();
+        json!({ "counters": counters, "flushed_secs_ago": 0 })
+    });
+    http::route(server, "/api/counters", Method::Get, counters, move |request| {
+        http::write_json(request, &context.counters.to_json())
+    })?;
+
+    Ok(())
+}
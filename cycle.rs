@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::config;
+
+// one pass of the sampling loop; past its budget the optional work (trend and drift analysis)
+// waits for a later cycle so sampling, alarms and control stay on schedule
+pub struct Cycle {
+    start: Instant,
+    interval_ms: u32,
+    budget: Duration,
+}
+
+impl Cycle {
+    pub fn start(interval_ms: u32) -> Self {
+        let budget = match config::CYCLE_BUDGET_MS {
+            0 => interval_ms * 3 / 4,
+            budget => budget,
+        };
+        Self {
+            start: Instant::now(),
+            interval_ms,
+            budget: Duration::from_millis(u64::from(budget)),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn over_budget(&self) -> bool {
+        self.elapsed() > self.budget
+    }
+
+    // what is left of the sample interval, so slow cycles don't push the schedule back
+    pub fn remaining_ms(&self) -> u32 {
+        let elapsed = u32::try_from(self.elapsed().as_millis()).unwrap_or(u32::MAX);
+        self.interval_ms.saturating_sub(elapsed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CycleStats {
+    cycles: u64,
+    overruns: u64,
+    // cycles that left out optional work
+    skipped: u64,
+    last_ms: u64,
+    max_ms: u64,
+}
+
+impl CycleStats {
+    pub fn record(&mut self, cycle: &Cycle, skipped: bool) {
+        let elapsed = cycle.elapsed().as_millis() as u64;
+        self.cycles += 1;
+        self.last_ms = elapsed;
+        self.max_ms = self.max_ms.max(elapsed);
+        if cycle.over_budget() {
+            self.overruns += 1;
+            log::warn!("cycle took {} ms, over its {} ms budget", elapsed, cycle.budget.as_millis());
+        }
+        if skipped {
+            self.skipped += 1;
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "cycles": self.cycles,
+            "overruns": self.overruns,
+            "skipped": self.skipped,
+            "last_ms": self.last_ms,
+            "max_ms": self.max_ms,
+        })
+    }
+}
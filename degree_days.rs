@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{self, Clock};
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::Celsius;
+
+const STATE_FILE: &str = "degree_days.json";
+// days further back than this from the clock's today are dropped
+const MAX_DAYS: u64 = 400;
+// a longer gap between samples (reboot, sensor missing) isn't integrated over
+const MAX_GAP_SECS: u64 = 3600;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // usually an outdoor sensor
+    pub sensor: Option<String>,
+    pub heating_base: Celsius,
+    pub cooling_base: Celsius,
+    // days are counted in local time
+    pub utc_offset_minutes: i32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            sensor: None,
+            heating_base: Celsius(15.5),
+            cooling_base: Celsius(18.0),
+            utc_offset_minutes: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Day {
+    pub date: String,
+    pub heating: f64,
+    pub cooling: f64,
+    // how much of the day was covered by readings, so partial days can be recognised
+    pub hours: f64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct DegreeDays {
+    pub settings: Settings,
+    days: Vec<Day>,
+    #[serde(skip)]
+    last_sample: Option<(u64, Celsius)>,
+    // the day's totals moved since the last save; written with the counters
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl DegreeDays {
+    pub fn load() -> Self {
+        storage::read_json(STATE_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&mut self) {
+        self.dirty = false;
+        if let Err(error) = storage::write_json(STATE_FILE, self) {
+            log::warn!("failed to save degree days: {}", error);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+
+    fn local_date(&self, unix: u64) -> String {
+        let local = unix as i64 + i64::from(self.settings.utc_offset_minutes) * 60;
+        clock::format_iso8601(local.max(0) as u64)[..10].to_string()
+    }
+
+    pub fn days(&self) -> &[Day] {
+        &self.days
+    }
+
+    pub fn update(&mut self, readings: &[Reading], clock: &dyn Clock) {
+        // degree days are per calendar day, which means nothing before SNTP has set the clock
+        if !clock.is_synced() {
+            return;
+        }
+        let now = clock.now_unix();
+        let Some(sensor) = &self.settings.sensor else {
+            return;
+        };
+        let Some(celsius) = readings.iter().find(|reading| &reading.sensor == sensor).map(|reading| reading.celsius) else {
+            self.last_sample = None;
+            return;
+        };
+
+        let previous = self.last_sample.replace((now, celsius));
+        let Some((last_time, last_celsius)) = previous else {
+            return;
+        };
+        let dt = now.saturating_sub(last_time);
+        if dt == 0 || dt > MAX_GAP_SECS {
+            return;
+        }
+
+        // trapezoidal mean over the interval, booked on the day the interval ends
+        let mean = last_celsius + (celsius - last_celsius) * 0.5;
+        let fraction_of_day = dt as f64 / 86_400.0;
+        let date = self.local_date(now);
+        let new_day = self.days.last().map_or(true, |day| day.date != date);
+        if new_day {
+            self.days.push(Day {
+                date,
+                heating: 0.0,
+                cooling: 0.0,
+                hours: 0.0,
+            });
+            // by date rather than count, so a device that was off for months doesn't keep them
+            let oldest = self.local_date(now.saturating_sub(MAX_DAYS * 86_400));
+            self.days.retain(|day| day.date > oldest);
+        }
+        let (heating_base, cooling_base) = (self.settings.heating_base, self.settings.cooling_base);
+        let day = self.days.last_mut().unwrap();
+        day.heating += f64::from((heating_base - mean).0.max(0.0)) * fraction_of_day;
+        day.cooling += f64::from((mean - cooling_base).0.max(0.0)) * fraction_of_day;
+        day.hours += dt as f64 / 3600.0;
+
+        // a day that just closed is saved right away
+        if new_day {
+            self.save();
+        } else {
+            self.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    // 2024-01-01T00:00:00Z
+    const MIDNIGHT: u64 = 1_704_067_200;
+
+    fn outdoor(celsius: f32) -> Vec<Reading> {
+        vec![Reading { sensor: "outdoor".to_string(), celsius: Celsius(celsius), humidity: None }]
+    }
+
+    fn degree_days() -> DegreeDays {
+        let settings = Settings { sensor: Some("outdoor".to_string()), ..Default::default() };
+        DegreeDays { settings, ..Default::default() }
+    }
+
+    // a reading every 10 minutes for `hours`
+    fn run(degree_days: &mut DegreeDays, clock: &SimulatedClock, celsius: f32, hours: u64) {
+        for _ in 0..hours * 6 {
+            degree_days.update(&outdoor(celsius), clock);
+            clock.advance(600);
+        }
+    }
+
+    #[test]
+    fn a_cold_day_counts_below_the_heating_base() {
+        let (mut degree_days, clock) = (degree_days(), SimulatedClock::at(MIDNIGHT));
+        run(&mut degree_days, &clock, 5.5, 24);
+        degree_days.update(&outdoor(5.5), &clock);
+
+        let days = degree_days.days();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].date, "2024-01-01");
+        // the first sample only starts the day: 143 ten-minute intervals of 10 degrees
+        assert!((days[0].heating - 10.0 * 143.0 / 144.0).abs() < 1e-6);
+        assert!((days[0].hours - 143.0 / 6.0).abs() < 1e-6);
+        assert_eq!(days[0].cooling, 0.0);
+        // the interval ending at midnight is booked on the new day
+        assert_eq!(days[1].date, "2024-01-02");
+        assert!((days[1].heating - 10.0 / 144.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn days_follow_the_local_date() {
+        let mut degree_days = degree_days();
+        degree_days.settings.utc_offset_minutes = -60;
+        let clock = SimulatedClock::at(MIDNIGHT);
+        run(&mut degree_days, &clock, 25.0, 1);
+        assert_eq!(degree_days.days()[0].date, "2023-12-31");
+        assert!(degree_days.days()[0].cooling > 0.0);
+    }
+
+    #[test]
+    fn gaps_and_an_unsynced_clock_are_not_counted() {
+        let (mut degree_days, clock) = (degree_days(), SimulatedClock::at(3600));
+        run(&mut degree_days, &clock, 0.0, 2);
+        assert!(degree_days.days().is_empty());
+
+        let clock = SimulatedClock::at(MIDNIGHT);
+        degree_days.update(&outdoor(0.0), &clock);
+        clock.advance(MAX_GAP_SECS + 1);
+        degree_days.update(&outdoor(0.0), &clock);
+        assert!(degree_days.days().is_empty());
+    }
+
+    #[test]
+    fn old_days_are_dropped_by_date() {
+        let (mut degree_days, clock) = (degree_days(), SimulatedClock::at(MIDNIGHT));
+        run(&mut degree_days, &clock, 10.0, 1);
+        clock.advance(10 * 86_400);
+        run(&mut degree_days, &clock, 10.0, 1);
+        assert_eq!(degree_days.days().len(), 2);
+
+        // off for more than MAX_DAYS: the next day drops both
+        clock.advance(MAX_DAYS * 86_400);
+        run(&mut degree_days, &clock, 10.0, 1);
+        let dates: Vec<&str> = degree_days.days().iter().map(|day| day.date.as_str()).collect();
+        assert_eq!(dates, [degree_days.local_date(clock.now_unix())]);
+    }
+}
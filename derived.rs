@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::{self, Api};
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::{Celsius, Fahrenheit};
+
+const SETTINGS_FILE: &str = "derived.json";
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DewPointFormula {
+    // Magnus with the Alduchov & Eskridge (1996) constants
+    Magnus,
+    // Bolton (1980), as used by the US National Weather Service
+    Noaa,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeatIndexFormula {
+    // Rothfusz regression with the NWS adjustments, Steadman's simple formula below 80 °F
+    Noaa,
+    // Steadman's simple formula only
+    Simple,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub dew_point: Option<DewPointFormula>,
+    pub heat_index: Option<HeatIndexFormula>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dew_point: Some(DewPointFormula::Magnus),
+            heat_index: Some(HeatIndexFormula::Noaa),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        storage::read_json(SETTINGS_FILE).unwrap_or_default()
+    }
+}
+
+pub fn dew_point(celsius: Celsius, humidity: f32, formula: DewPointFormula) -> Celsius {
+    let Celsius(celsius) = celsius;
+    let (a, b) = match formula {
+        DewPointFormula::Magnus => (17.625, 243.04),
+        DewPointFormula::Noaa => (17.67, 243.5),
+    };
+    let gamma = (humidity.max(0.1) / 100.0).ln() + a * celsius / (b + celsius);
+    Celsius(b * gamma / (a - gamma))
+}
+
+pub fn heat_index(celsius: Celsius, humidity: f32, formula: HeatIndexFormula) -> Celsius {
+    // the NOAA regression is fitted in Fahrenheit
+    let Fahrenheit(t) = celsius.into();
+    let rh = humidity;
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+
+    let fahrenheit = match formula {
+        HeatIndexFormula::Simple => simple,
+        HeatIndexFormula::Noaa if (simple + t) / 2.0 < 80.0 => simple,
+        HeatIndexFormula::Noaa => {
+            let mut index = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+                - 0.224_755_4 * t * rh
+                - 0.006_837_83 * t * t
+                - 0.054_817_17 * rh * rh
+                + 0.001_228_74 * t * t * rh
+                + 0.000_852_82 * t * rh * rh
+                - 0.000_001_99 * t * t * rh * rh;
+            if rh < 13.0 && (80.0..=112.0).contains(&t) {
+                index -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+            } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+                index += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+            }
+            index
+        }
+    };
+    Fahrenheit(fahrenheit).into()
+}
+
+// adds "<sensor>-dewpoint" and "<sensor>-heatindex" readings for every sensor that measures
+// humidity, so they go through alarms, history and MQTT like any other sensor
+pub fn add_virtual_sensors(settings: &Settings, readings: &mut Vec<Reading>) {
+    let mut derived = Vec::new();
+    for reading in readings.iter() {
+        let Some(humidity) = reading.humidity else {
+            continue;
+        };
+        if let Some(formula) = settings.dew_point {
+            derived.push(Reading {
+                sensor: format!("{}-dewpoint", reading.sensor),
+                celsius: dew_point(reading.celsius, humidity, formula),
+                humidity: None,
+            });
+        }
+        if let Some(formula) = settings.heat_index {
+            derived.push(Reading {
+                sensor: format!("{}-heatindex", reading.sensor),
+                celsius: heat_index(reading.celsius, humidity, formula),
+                humidity: None,
+            });
+        }
+    }
+    readings.extend(derived);
+}
+
+pub fn apply(context: &Context, settings: Settings) -> Result<(), (u16, String)> {
+    storage::write_json(SETTINGS_FILE, &settings).map_err(|error| (500, error.to_string()))?;
+    *context.derived.lock().unwrap() = settings;
+    context.events.publish(Event::ConfigChanged("derived"));
+    Ok(())
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/derived", Method::Get, Api::json(openapi::defaults::<Settings>), move |request| {
+        let settings = status_context.derived.lock().unwrap().clone();
+        http::write_json(request, &json!(settings))
+    })?;
+
+    // {"dew_point": "magnus" | "noaa" | null, "heat_index": "noaa" | "simple" | null}
+    let settings_context = context;
+    http::route(server, "/api/derived", Method::Post, Api::settings::<Settings>(), move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 256)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let body = json!(settings);
+        match apply(&settings_context, settings) {
+            Ok(()) => http::write_json(request, &body),
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::blocking::i2c::Write;
+use embedded_hal::digital::v2::InputPin;
+use esp_idf_hal::delay::FreeRtos;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::context::Context;
+use crate::events::{Event, Network, Policy, Topic};
+use crate::readings::Reading;
+use crate::service;
+use crate::ssd1306::{self, Ssd1306};
+use crate::storage;
+
+const SETTINGS_FILE: &str = "display.json";
+const POLL_MS: u32 = 50;
+const REFRESH: Duration = Duration::from_secs(1);
+// pressing the button at least this long toggles hold instead of switching the view
+const LONG_PRESS: Duration = Duration::from_secs(1);
+const HOURS: u64 = 24;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum View {
+    #[default]
+    Current,
+    MinMax,
+    Alarms,
+    Network,
+}
+
+impl View {
+    fn next(self) -> Self {
+        match self {
+            View::Current => View::MinMax,
+            View::MinMax => View::Alarms,
+            View::Alarms => View::Network,
+            View::Network => View::Current,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            View::Current => "NOW",
+            View::MinMax => "24H   MIN   MAX",
+            View::Alarms => "ALARMS",
+            View::Network => "NETWORK",
+        }
+    }
+}
+
+// the selected view survives a reboot
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Settings {
+    view: View,
+}
+
+// lowest and highest reading of every sensor over the last 24 hours, in hourly buckets so old
+// extremes fall out without keeping every sample; since boot until a day has passed
+#[derive(Debug, Default)]
+pub struct Extremes {
+    start: Option<Instant>,
+    hours: VecDeque<(u64, BTreeMap<String, (f32, f32)>)>,
+}
+
+impl Extremes {
+    pub fn update(&mut self, readings: &[Reading]) {
+        let hour = self.start.get_or_insert_with(Instant::now).elapsed().as_secs() / 3600;
+        while self.hours.front().is_some_and(|(start, _)| start + HOURS <= hour) {
+            self.hours.pop_front();
+        }
+        if self.hours.back().map(|(start, _)| *start) != Some(hour) {
+            self.hours.push_back((hour, BTreeMap::new()));
+        }
+        let (_, bucket) = self.hours.back_mut().unwrap();
+        for reading in readings {
+            let celsius = reading.celsius.0;
+            let (low, high) = bucket.entry(reading.sensor.clone()).or_insert((celsius, celsius));
+            *low = low.min(celsius);
+            *high = high.max(celsius);
+        }
+    }
+
+    pub fn get(&self) -> BTreeMap<String, (f32, f32)> {
+        let mut extremes = BTreeMap::new();
+        for (_, bucket) in &self.hours {
+            for (sensor, &(low, high)) in bucket {
+                let (all_low, all_high) = extremes.entry(sensor.clone()).or_insert((low, high));
+                *all_low = all_low.min(low);
+                *all_high = all_high.max(high);
+            }
+        }
+        extremes
+    }
+}
+
+#[derive(Default)]
+struct Links {
+    wifi: bool,
+    mqtt: bool,
+}
+
+fn sensor_name(context: &Context, sensor: &str) -> String {
+    let registry = context.registry.lock().unwrap();
+    registry.get(sensor).and_then(|info| info.name.clone()).unwrap_or_else(|| sensor.to_string())
+}
+
+fn render(context: &Context, view: View, links: &Links) -> Vec<String> {
+    let mut lines = Vec::new();
+    match view {
+        View::Current => {
+            for reading in context.latest_readings() {
+                lines.push(format!("{:<14.14}{:>6.1}°", sensor_name(context, &reading.sensor), reading.celsius.0));
+            }
+        }
+        View::MinMax => {
+            for (sensor, (low, high)) in context.extremes.lock().unwrap().get() {
+                lines.push(format!("{:<9.9}{:>5.1} {:>5.1}", sensor_name(context, &sensor), low, high));
+            }
+        }
+        View::Alarms => {
+            if let Value::Array(alarms) = context.alarms.to_json() {
+                for alarm in alarms {
+                    // acknowledged alarms without the mark
+                    let mark = if alarm["acknowledged"].as_bool().unwrap_or(false) { ' ' } else { '!' };
+                    lines.push(format!("{}{}", mark, alarm["id"].as_str().unwrap_or_default()));
+                }
+            }
+            if lines.is_empty() {
+                lines.push("NONE".to_string());
+            }
+        }
+        View::Network => {
+            let uptime = context.lifecycle.uptime().as_secs();
+            lines.push(context.node_id.clone());
+            lines.push(format!("WIFI {}", if links.wifi { "UP" } else { "DOWN" }));
+            lines.push(format!("MQTT {}", if links.mqtt { "CONNECTED" } else { "DOWN" }));
+            lines.push(format!("{:?}", context.lifecycle.state()));
+            lines.push(format!("HEALTH {}", if context.health.ok() { "OK" } else { "FAILING" }));
+            lines.push(format!("UP {}H{:02}M", uptime / 3600, uptime % 3600 / 60));
+        }
+    }
+    lines
+}
+
+// an SSD1306 on the I2C bus and a button (the BOOT button on GPIO0 works) cycling through the
+// views; a long press holds what is shown until the next long press, a very long one toggles
+// maintenance mode. Without a display the button is handed back
+pub fn start<I, E, P>(context: Arc<Context>, i2c: Arc<Mutex<I>>, button: P) -> Option<P>
+    where
+        I: Write<Error=E> + Send + 'static,
+        E: Debug,
+        P: InputPin + Send + 'static,
+{
+    let mut panel = Ssd1306::new(ssd1306::DEFAULT_ADDRESS);
+    if let Err(error) = panel.init(&mut *i2c.lock().unwrap()) {
+        log::info!("no display: {:?}", error);
+        return Some(button);
+    }
+    let links = context.events.subscribe("display", &[Topic::Network], 8, Policy::DropOldest);
+
+    let spawned = thread::Builder::new()
+        .name("display".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut settings: Settings = storage::read_json(SETTINGS_FILE).unwrap_or_default();
+            let mut state = Links::default();
+            let mut hold = false;
+            let mut pressed_at: Option<Instant> = None;
+            let mut last_draw: Option<Instant> = None;
+            loop {
+                let events = links.try_take();
+                links.handled(events.len());
+                for event in events {
+                    match event {
+                        Event::Network(Network::WifiUp) => state.wifi = true,
+                        Event::Network(Network::WifiDown) => state.wifi = false,
+                        Event::Network(Network::MqttConnected) => state.mqtt = true,
+                        Event::Network(Network::MqttDisconnected) => state.mqtt = false,
+                        _ => {}
+                    }
+                }
+
+                // active low, with the pull-up
+                let mut redraw = false;
+                match (button.is_low().unwrap_or(false), pressed_at) {
+                    (true, None) => pressed_at = Some(Instant::now()),
+                    (false, Some(since)) => {
+                        pressed_at = None;
+                        if since.elapsed() >= service::BUTTON_HOLD {
+                            service::toggle(&context, "button");
+                        } else if since.elapsed() >= LONG_PRESS {
+                            hold = !hold;
+                        } else {
+                            settings.view = settings.view.next();
+                            hold = false;
+                            if let Err(error) = storage::write_json(SETTINGS_FILE, &settings) {
+                                log::warn!("failed to save the display view: {}", error);
+                            }
+                        }
+                        redraw = true;
+                    }
+                    _ => {}
+                }
+
+                if redraw || (!hold && last_draw.map_or(true, |last| last.elapsed() >= REFRESH)) {
+                    panel.clear();
+                    // readings from before a reset are marked until they are replaced
+                    let restored = settings.view == View::Current && context.restored.lock().unwrap().is_some();
+                    let title = if restored { "LAST" } else { settings.view.title() };
+                    let flag = if context.service.active() {
+                        "MAINT"
+                    } else if hold {
+                        "HOLD"
+                    } else {
+                        ""
+                    };
+                    panel.text(0, &format!("{:<16}{:>5}", title, flag));
+                    for (line, text) in render(&context, settings.view, &state).iter().take(ssd1306::LINES - 1).enumerate() {
+                        panel.text(line + 1, text);
+                    }
+                    if let Err(error) = panel.flush(&mut *i2c.lock().unwrap()) {
+                        log::warn!("display update failed: {:?}", error);
+                    }
+                    last_draw = Some(Instant::now());
+                }
+                FreeRtos::delay_ms(POLL_MS);
+            }
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the display: {}", error);
+    }
+    None
+}
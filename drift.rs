@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+const STATE_FILE: &str = "drift.json";
+// longer gaps (reboots, a probe unplugged) restart the averaging interval instead of counting
+const MAX_GAP_SECS: f32 = 600.0;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // warn when the long-term bias against the co-located sensors exceeds this
+    pub threshold: Degrees,
+    // time constant of the bias average, so short local disturbances (a door, sun) wash out
+    pub time_constant_hours: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            threshold: Degrees(0.5),
+            time_constant_hours: 24.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Bias {
+    // sensor minus the mean of its co-located sensors, exponentially averaged
+    pub celsius: Degrees,
+    // how long it has been compared, it only warns after a quarter of the time constant
+    pub hours: f32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Drift {
+    #[serde(default)]
+    pub settings: Settings,
+    #[serde(default)]
+    biases: BTreeMap<String, Bias>,
+    #[serde(skip)]
+    last_update: Option<Instant>,
+    // the biases moved since the last save; written with the counters
+    #[serde(skip)]
+    dirty: bool,
+}
+
+pub fn drift_alarm_id(sensor: &str) -> String {
+    format!("drift_{}", sensor)
+}
+
+// sensors are co-located when they share a redundancy group or a registry zone
+fn neighbours(context: &Context) -> BTreeMap<String, BTreeSet<String>> {
+    let mut sets: Vec<Vec<String>> = context
+        .redundancy
+        .lock()
+        .unwrap()
+        .groups
+        .iter()
+        .map(|group| group.sensors.clone())
+        .collect();
+    let mut zones: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (sensor, info) in context.registry.lock().unwrap().sensors() {
+        if let Some(zone) = &info.zone {
+            zones.entry(zone.clone()).or_default().push(sensor.clone());
+        }
+    }
+    sets.extend(zones.into_values());
+
+    let mut neighbours: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for set in &sets {
+        for sensor in set {
+            let others = set.iter().filter(|other| *other != sensor).cloned();
+            neighbours.entry(sensor.clone()).or_default().extend(others);
+        }
+    }
+    neighbours
+}
+
+impl Drift {
+    pub fn load() -> Self {
+        storage::read_json(STATE_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&mut self) {
+        self.dirty = false;
+        if let Err(error) = storage::write_json(STATE_FILE, self) {
+            log::warn!("failed to save drift state: {}", error);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+
+    pub fn reset(&mut self, sensor: Option<&str>) {
+        match sensor {
+            Some(sensor) => {
+                self.biases.remove(sensor);
+            }
+            None => self.biases.clear(),
+        }
+        self.save();
+    }
+
+    pub fn biases(&self) -> &BTreeMap<String, Bias> {
+        &self.biases
+    }
+
+    // compares every sensor with the mean of its co-located sensors and raises "drift_<id>"
+    // once the averaged bias exceeds the threshold
+    pub fn update(&mut self, context: &Context, readings: &[Reading]) {
+        let now = Instant::now();
+        let elapsed = self.last_update.replace(now).map(|last| now.duration_since(last).as_secs_f32());
+        let Some(elapsed) = elapsed.filter(|elapsed| *elapsed <= MAX_GAP_SECS) else {
+            return;
+        };
+        let celsius = |sensor: &str| readings.iter().find(|reading| reading.sensor == sensor).map(|reading| reading.celsius);
+        let tau_secs = self.settings.time_constant_hours.max(0.1) * 3600.0;
+        let alpha = (elapsed / tau_secs).min(1.0);
+
+        let neighbours = neighbours(context);
+        for (sensor, others) in &neighbours {
+            let Some(value) = celsius(sensor) else {
+                continue;
+            };
+            let others: Vec<Celsius> = others.iter().filter_map(|other| celsius(other)).collect();
+            if others.is_empty() {
+                continue;
+            }
+            let reference = Celsius(others.iter().map(|other| other.0).sum::<f32>() / others.len() as f32);
+            let bias = self.biases.entry(sensor.clone()).or_default();
+            bias.celsius = bias.celsius + (value - reference - bias.celsius) * alpha;
+            bias.hours += elapsed / 3600.0;
+        }
+        // sensors taken out of their group or zone
+        self.biases.retain(|sensor, _| {
+            let kept = neighbours.contains_key(sensor);
+            if !kept {
+                context.alarms.clear(&drift_alarm_id(sensor));
+            }
+            kept
+        });
+
+        let registry = context.registry.lock().unwrap();
+        for sensor in neighbours.keys() {
+            let id = drift_alarm_id(sensor);
+            match self.biases.get(sensor) {
+                Some(bias)
+                    if bias.hours >= self.settings.time_constant_hours / 4.0
+                        && bias.celsius.abs() > self.settings.threshold =>
+                {
+                    let name = registry.get(sensor).and_then(|info| info.name.as_deref()).unwrap_or(sensor);
+                    let message = format!(
+                        "{} reads {:+.2} °C against its neighbours, possible drift, calibration needed",
+                        name, bias.celsius.0
+                    );
+                    context.alarms.raise(&id, message);
+                }
+                _ => context.alarms.clear(&id),
+            }
+        }
+        drop(registry);
+
+        self.dirty = true;
+    }
+}
+
+#[derive(Deserialize)]
+struct ResetRequest {
+    #[serde(default)]
+    sensor: Option<String>,
+}
+
+pub fn apply(context: &Context, settings: Settings) -> Result<(), (u16, String)> {
+    let mut drift = context.drift.lock().unwrap();
+    drift.settings = settings;
+    drift.save();
+    drop(drift);
+    context.events.publish(Event::ConfigChanged("drift"));
+    Ok(())
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| json!({ "settings": Settings::default(), "biases": { "": Bias::default() } }));
+    http::route(server, "/api/drift", Method::Get, status, move |request| {
+        let drift = status_context.drift.lock().unwrap();
+        let body = json!({ "settings": drift.settings, "biases": drift.biases() });
+        drop(drift);
+        http::write_json(request, &body)
+    })?;
+
+    // {"threshold": 0.5, "time_constant_hours": 24}
+    let settings_context = context.clone();
+    http::route(server, "/api/drift", Method::Post, Api::settings::<Settings>(), move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 256)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let body = json!(settings);
+        match apply(&settings_context, settings) {
+            Ok(()) => http::write_json(request, &body),
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    // after recalibrating a probe: {"sensor": "<id>"}, or {} to start over for all sensors
+    let reset_context = context;
+    let reset = Api::json(|| json!({ "reset": "" })).body(|| json!({ "sensor": "" }));
+    http::route(server, "/api/drift/reset", Method::Post, reset, move |mut request| {
+        let reset = match http::read_json::<ResetRequest>(&mut request, 256)? {
+            Ok(reset) => reset,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let args = Map::from_iter(reset.sensor.map(|sensor| ("sensor".to_string(), json!(sensor))));
+        match commands::run(&reset_context, "drift_reset", &args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}
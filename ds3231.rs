@@ -0,0 +1,99 @@
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::clock;
+use crate::units::Celsius;
+
+const ADDRESS: u8 = 0x68;
+const TIME_REGISTER: u8 = 0x00;
+const STATUS_REGISTER: u8 = 0x0f;
+const TEMPERATURE_REGISTER: u8 = 0x11;
+// set when the oscillator stopped (battery flat or missing): the time is gone
+const OSCILLATOR_STOPPED: u8 = 0x80;
+
+pub struct Ds3231;
+
+fn from_bcd(value: u8) -> u32 {
+    u32::from(value >> 4) * 10 + u32::from(value & 0x0f)
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+impl Ds3231 {
+    pub fn sensor_id(&self) -> String {
+        format!("DS3231-{:02X}", ADDRESS)
+    }
+
+    // the time in UTC, None when it was lost since it was last set
+    pub fn read_time<I, E>(&self, i2c: &mut I) -> Result<Option<u64>, E>
+        where
+            I: WriteRead<Error=E>,
+    {
+        let mut status = [0u8; 1];
+        i2c.write_read(ADDRESS, &[STATUS_REGISTER], &mut status)?;
+        let mut data = [0u8; 7];
+        i2c.write_read(ADDRESS, &[TIME_REGISTER], &mut data)?;
+        if status[0] & OSCILLATOR_STOPPED != 0 {
+            return Ok(None);
+        }
+
+        let seconds = from_bcd(data[0] & 0x7f);
+        let minutes = from_bcd(data[1] & 0x7f);
+        // set in 24 hour mode by set_time, but a module may come in 12 hour mode
+        let hours = if data[2] & 0x40 != 0 {
+            from_bcd(data[2] & 0x1f) % 12 + if data[2] & 0x20 != 0 { 12 } else { 0 }
+        } else {
+            from_bcd(data[2] & 0x3f)
+        };
+        let day = from_bcd(data[4] & 0x3f);
+        let month = from_bcd(data[5] & 0x1f);
+        let year = 2000 + from_bcd(data[6]) + if data[5] & 0x80 != 0 { 100 } else { 0 };
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 59 {
+            return Ok(None);
+        }
+        let days = clock::days_from_civil(i64::from(year), month, day);
+        let unix = days * 86_400 + i64::from(hours * 3600 + minutes * 60 + seconds);
+        Ok(u64::try_from(unix).ok().filter(|&unix| clock::is_plausible(unix)))
+    }
+
+    // also clears the oscillator stop flag, the time is good again
+    pub fn set_time<I, E>(&self, i2c: &mut I, unix: u64) -> Result<(), E>
+        where
+            I: Write<Error=E> + WriteRead<Error=E>,
+    {
+        let days = (unix / 86_400) as i64;
+        let secs = (unix % 86_400) as u32;
+        let (year, month, day) = clock::civil_from_days(days);
+        let century = if year >= 2100 { 0x80 } else { 0 };
+        // day of the week 1..7 with 1970-01-01 a Thursday, the chip only counts it up
+        let weekday = ((days + 3).rem_euclid(7) + 1) as u32;
+        i2c.write(
+            ADDRESS,
+            &[
+                TIME_REGISTER,
+                to_bcd(secs % 60),
+                to_bcd(secs / 60 % 60),
+                to_bcd(secs / 3600),
+                to_bcd(weekday),
+                to_bcd(day),
+                to_bcd(month) | century,
+                to_bcd((year % 100) as u32),
+            ],
+        )?;
+        let mut status = [0u8; 1];
+        i2c.write_read(ADDRESS, &[STATUS_REGISTER], &mut status)?;
+        i2c.write(ADDRESS, &[STATUS_REGISTER, status[0] & !OSCILLATOR_STOPPED])
+    }
+
+    // the chip's own temperature sensor, 0.25 °C steps and updated every 64 s; it sits on the
+    // board, so it reads the enclosure
+    pub fn temperature<I, E>(&self, i2c: &mut I) -> Result<Celsius, E>
+        where
+            I: WriteRead<Error=E>,
+    {
+        let mut data = [0u8; 2];
+        i2c.write_read(ADDRESS, &[TEMPERATURE_REGISTER], &mut data)?;
+        Ok(Celsius(f32::from(i16::from_be_bytes(data) >> 6) * 0.25))
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use ds18b20::{Ds18b20, SensorData};
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use one_wire_bus::{OneWire, OneWireError, OneWireResult};
+
+use crate::registry::Registry;
+use crate::units::Celsius;
+
+// the DS18B20 compares only the whole degrees of a reading with its TL/TH bytes and flags
+// itself for ALARM SEARCH at or beyond them, inclusively
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub low: i8,
+    pub high: i8,
+}
+
+impl Limits {
+    // without a threshold the limit is pushed out of the sensor's range, so it never alarms
+    fn from_thresholds(low: Option<Celsius>, high: Option<Celsius>) -> Self {
+        let whole = |celsius: f32| celsius.clamp(i8::MIN as f32, i8::MAX as f32) as i8;
+        Self {
+            // rounded outwards and a degree further, as a reading of exactly TL already alarms,
+            // so the hardware alarm never fires inside the software band
+            low: low.map_or(i8::MIN, |low| whole(low.0.floor() - 1.0)),
+            high: high.map_or(i8::MAX, |high| whole(high.0.ceil() + 1.0)),
+        }
+    }
+
+    fn of(data: &SensorData) -> Self {
+        Self {
+            low: data.alarm_temp_low,
+            high: data.alarm_temp_high,
+        }
+    }
+}
+
+// what every registered sensor's EEPROM should hold, taken once per cycle so the registry
+// isn't locked during the slow bus transactions; unregistered sensors are left as they are
+pub fn wanted(registry: &Registry) -> BTreeMap<String, Limits> {
+    registry
+        .sensors()
+        .map(|(sensor, info)| (sensor.clone(), Limits::from_thresholds(info.alarm_low, info.alarm_high)))
+        .collect()
+}
+
+// writes the limits through to the scratchpad and EEPROM when the sensor's current ones
+// (already read with the temperature) differ, and reads both back to verify them; a mismatch
+// is reported as an unexpected response. returns whether anything was written
+pub fn sync<P, E>(
+    sensor: &Ds18b20,
+    current: &SensorData,
+    wanted: Limits,
+    one_wire_bus: &mut OneWire<P>,
+    delay: &mut impl DelayUs<u16>,
+) -> OneWireResult<bool, E>
+    where
+        P: OutputPin<Error=E> + InputPin<Error=E>,
+        E: Debug
+{
+    if Limits::of(current) == wanted {
+        return Ok(false);
+    }
+    sensor.set_config(wanted.low, wanted.high, current.resolution, one_wire_bus, delay)?;
+    if Limits::of(&sensor.read_data(one_wire_bus, delay)?) != wanted {
+        return Err(OneWireError::UnexpectedResponse);
+    }
+    sensor.save_to_eeprom(one_wire_bus, delay)?;
+    // recalling overwrites the scratchpad with what actually ended up in EEPROM
+    sensor.recall_from_eeprom(one_wire_bus, delay)?;
+    if Limits::of(&sensor.read_data(one_wire_bus, delay)?) != wanted {
+        return Err(OneWireError::UnexpectedResponse);
+    }
+    Ok(true)
+}
@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+use serde_json::{json, Value};
+
+use crate::export::Measurement;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topic {
+    Readings,
+    Alarms,
+    Network,
+    Config,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    WifiUp,
+    // switched off by the schedule
+    WifiDown,
+    MqttConnected,
+    MqttDisconnected,
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    Readings(Measurement),
+    // only on changes: raising an alarm that is already active doesn't repeat it
+    AlarmRaised { id: String, message: String },
+    AlarmCleared { id: String },
+    // the first acknowledgement of an active alarm, `by` the command source it came from
+    AlarmAcknowledged { id: String, by: String },
+    Network(Network),
+    // settings of the named module were changed and saved
+    ConfigChanged(&'static str),
+}
+
+impl Event {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::Readings(_) => Topic::Readings,
+            Event::AlarmRaised { .. } | Event::AlarmCleared { .. } | Event::AlarmAcknowledged { .. } => Topic::Alarms,
+            Event::Network(_) => Topic::Network,
+            Event::ConfigChanged(_) => Topic::Config,
+        }
+    }
+}
+
+// what a full subscription gives up
+#[derive(Clone, Copy, Debug)]
+pub enum Policy {
+    // keep the newest events, for subscribers that show the current state
+    DropOldest,
+    // keep a gap-free start, for subscribers that store a series
+    DropNewest,
+}
+
+#[derive(Default)]
+struct State {
+    events: VecDeque<Event>,
+    dropped: u64,
+    handled: u64,
+    failures: u64,
+}
+
+// a bounded queue of the events on some topics, so a slow subscriber only ever delays (and
+// drops) its own events and never the publisher
+pub struct Subscription {
+    name: &'static str,
+    topics: Vec<Topic>,
+    capacity: usize,
+    policy: Policy,
+    state: Mutex<State>,
+    ready: Condvar,
+}
+
+impl Subscription {
+    fn offer(&self, event: &Event) {
+        if !self.topics.contains(&event.topic()) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.events.len() >= self.capacity {
+            state.dropped += 1;
+            match self.policy {
+                Policy::DropOldest => {
+                    state.events.pop_front();
+                }
+                Policy::DropNewest => return,
+            }
+        }
+        state.events.push_back(event.clone());
+        self.ready.notify_one();
+    }
+
+    // blocks until there is something, then takes all of it
+    pub fn take(&self) -> Vec<Event> {
+        let mut state = self.state.lock().unwrap();
+        while state.events.is_empty() {
+            state = self.ready.wait(state).unwrap();
+        }
+        state.events.drain(..).collect()
+    }
+
+    // whatever is queued, without waiting, for subscribers polling from a loop of their own
+    pub fn try_take(&self) -> Vec<Event> {
+        self.state.lock().unwrap().events.drain(..).collect()
+    }
+
+    pub fn handled(&self, count: usize) {
+        self.state.lock().unwrap().handled += count as u64;
+    }
+
+    pub fn queued(&self) -> usize {
+        self.state.lock().unwrap().events.len()
+    }
+
+    // events dropped so far, by the queue or given up on
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+
+    // events the subscriber gave up on, counted with those the queue dropped
+    pub fn discarded(&self, count: usize) {
+        self.state.lock().unwrap().dropped += count as u64;
+    }
+
+    // events that couldn't be handled go back in front of whatever arrived meanwhile, within
+    // the capacity
+    pub fn requeue(&self, events: Vec<Event>) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        for event in events.into_iter().rev() {
+            if state.events.len() >= self.capacity {
+                state.dropped += 1;
+                match self.policy {
+                    // these are older than anything queued
+                    Policy::DropOldest => continue,
+                    Policy::DropNewest => {
+                        state.events.pop_back();
+                    }
+                }
+            }
+            state.events.push_front(event);
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        let topics: Vec<_> = self.topics.iter().map(|topic| format!("{:?}", topic).to_lowercase()).collect();
+        json!({
+            "name": self.name,
+            "topics": topics,
+            "queued": state.events.len(),
+            "capacity": self.capacity,
+            "dropped": state.dropped,
+            "handled": state.handled,
+            "failures": state.failures,
+        })
+    }
+
+    // the shape of to_json, for the API description
+    pub fn example() -> Value {
+        json!({ "name": "", "topics": [""], "queued": 0, "capacity": 0, "dropped": 0, "handled": 0, "failures": 0 })
+    }
+}
+
+#[derive(Default)]
+pub struct Bus {
+    subscriptions: Mutex<Vec<Arc<Subscription>>>,
+}
+
+impl Bus {
+    pub fn subscribe(&self, name: &'static str, topics: &[Topic], capacity: usize, policy: Policy) -> Arc<Subscription> {
+        let subscription = Arc::new(Subscription {
+            name,
+            topics: topics.to_vec(),
+            capacity: capacity.max(1),
+            policy,
+            state: Mutex::new(State::default()),
+            ready: Condvar::new(),
+        });
+        self.subscriptions.lock().unwrap().push(subscription.clone());
+        subscription
+    }
+
+    // never blocks: every interested subscription queues its copy or drops by its policy
+    pub fn publish(&self, event: Event) {
+        for subscription in self.subscriptions.lock().unwrap().iter() {
+            subscription.offer(&event);
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::Array(self.subscriptions.lock().unwrap().iter().map(|subscription| subscription.to_json()).collect())
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::clock;
+use crate::context::Context;
+use crate::events::{Event, Policy, Subscription, Topic};
+use crate::gps::Location;
+use crate::history::{self, Record};
+use crate::http;
+use crate::influx;
+use crate::mqtt;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::units::Celsius;
+
+// after a failed export the events go back into the queue and the exporter waits this long,
+// doubling up to MAX_BACKOFF while the backend stays down
+const BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+// how often an exporter the schedule switched off checks whether it's back on
+const SCHEDULE_CHECK: Duration = Duration::from_secs(30);
+
+// the readings of one sampling cycle
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    pub t: u64,
+    pub readings: Vec<Reading>,
+    // with a GPS fix
+    pub location: Option<Location>,
+    // from before a software reset, until the first conversion after it
+    pub restored: bool,
+}
+
+// what an exporter returns for a batch the backend refused as such, e.g. for a malformed line:
+// it would never be accepted, so it is dropped rather than retried
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for Rejected {}
+
+// a backend readings are sent to; each one runs on its own thread behind its own event bus
+// subscription
+pub trait Exporter: Send {
+    fn name(&self) -> &'static str;
+
+    fn export(&mut self, batch: &[Measurement]) -> Result<(), Box<dyn Error>>;
+
+    // readings always, plus whatever other topics the backend wants to pass on
+    fn topics(&self) -> &'static [Topic] {
+        &[Topic::Readings]
+    }
+
+    // an event of one of the other topics
+    fn notify(&mut self, _event: &Event) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    // queued events before the policy starts dropping
+    fn capacity(&self) -> usize {
+        60
+    }
+
+    fn policy(&self) -> Policy {
+        Policy::DropOldest
+    }
+
+    // whether the backend's connection is up, for backends that keep one
+    fn connected(&self) -> Option<bool> {
+        None
+    }
+
+    // whether readings the queue couldn't hold while the schedule had it off are sent from the
+    // history log afterwards; only for backends that keep a series, not just the current state
+    fn backfills(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Activity {
+    // waiting for events
+    Idle,
+    Exporting,
+    OffBySchedule,
+    // waiting to retry what failed
+    BackingOff,
+}
+
+struct Status {
+    subscription: Arc<Subscription>,
+    activity: Activity,
+    connected: Option<bool>,
+    // unix seconds
+    last_success: Option<u64>,
+    last_error: Option<(u64, String)>,
+    exported: u64,
+    // taken from the queue, being sent
+    in_flight: usize,
+    backoff: Duration,
+}
+
+// what every exporter thread is doing, for /api/exporters and the dashboard, to tell why data
+// stopped reaching one backend
+#[derive(Default)]
+pub struct Exporters {
+    statuses: Mutex<BTreeMap<&'static str, Status>>,
+}
+
+impl Exporters {
+    fn update(&self, name: &'static str, change: impl FnOnce(&mut Status)) {
+        if let Some(status) = self.statuses.lock().unwrap().get_mut(name) {
+            change(status);
+        }
+    }
+
+    // until every exporter has sent what is queued, or given up on it for now; before a deep
+    // sleep
+    pub fn wait_idle(&self, timeout: Duration) {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            let idle = self.statuses.lock().unwrap().values().all(|status| {
+                status.activity != Activity::Exporting && (status.activity != Activity::Idle || status.subscription.queued() == 0)
+            });
+            if idle {
+                return;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let statuses = self.statuses.lock().unwrap();
+        let exporters: Map<String, Value> = statuses
+            .iter()
+            .map(|(name, status)| {
+                let state = json!({
+                    "activity": status.activity,
+                    "connected": status.connected,
+                    "last_success": status.last_success,
+                    "last_error": status.last_error.as_ref().map(|(_, error)| error),
+                    "last_error_at": status.last_error.as_ref().map(|(t, _)| t),
+                    "exported": status.exported,
+                    "in_flight": status.in_flight,
+                    "backoff_secs": (status.activity == Activity::BackingOff).then(|| status.backoff.as_secs()),
+                    "queue": status.subscription.to_json(),
+                });
+                (name.to_string(), state)
+            })
+            .collect();
+        Value::Object(exporters)
+    }
+}
+
+// every backend the firmware knows, each returning None when it isn't configured; a new
+// backend is a module with an Exporter and one line here
+type Backend = fn(&Arc<Context>) -> Result<Option<Box<dyn Exporter>>, EspError>;
+const BACKENDS: &[Backend] = &[mqtt::exporter, influx::exporter];
+
+pub fn start(context: &Arc<Context>) -> Result<(), EspError> {
+    for backend in BACKENDS {
+        if let Some(exporter) = backend(context)? {
+            spawn(context, exporter);
+        }
+    }
+    Ok(())
+}
+
+// runs the exporter on its own thread for every batch of events queued since the last one
+fn spawn(context: &Arc<Context>, mut exporter: Box<dyn Exporter>) {
+    let name = exporter.name();
+    let context = context.clone();
+    let subscription = context.events.subscribe(name, exporter.topics(), exporter.capacity(), exporter.policy());
+    let status = Status {
+        subscription: subscription.clone(),
+        activity: Activity::Idle,
+        connected: exporter.connected(),
+        last_success: None,
+        last_error: None,
+        exported: 0,
+        in_flight: 0,
+        backoff: BACKOFF,
+    };
+    context.exporters.statuses.lock().unwrap().insert(name, status);
+    let spawned = thread::Builder::new()
+        .name(name.into())
+        .stack_size(8 * 1024)
+        .spawn(move || {
+            let mut backoff = BACKOFF;
+            // the time the schedule had the exporter off, while the queue overflowed
+            let mut gap = None;
+            // ranges of that still to be sent from the history log
+            let mut pending: Vec<(u64, u64)> = Vec::new();
+            loop {
+                // off by the schedule: the events wait in the queue, and what it can't hold is
+                // sent from the history log when the exporter is back on
+                if !context.schedule.lock().unwrap().exporting(name, &clock::SystemClock) {
+                    let (off_since, dropped) = (clock::now_unix(), subscription.dropped());
+                    while !context.schedule.lock().unwrap().exporting(name, &clock::SystemClock) {
+                        context.exporters.update(name, |status| status.activity = Activity::OffBySchedule);
+                        thread::sleep(SCHEDULE_CHECK);
+                    }
+                    if exporter.backfills() && subscription.dropped() > dropped {
+                        gap = Some((off_since, clock::now_unix()));
+                    }
+                }
+                context.exporters.update(name, |status| status.activity = Activity::Idle);
+                let events = subscription.take();
+                let count = events.len();
+                context.exporters.update(name, |status| {
+                    status.activity = Activity::Exporting;
+                    status.in_flight = count;
+                });
+                let mut last_error = None;
+                let mut batch = Vec::new();
+                let mut failed = Vec::new();
+                for event in events {
+                    match event {
+                        Event::Readings(measurement) => batch.push(measurement),
+                        event => {
+                            let result = exporter.notify(&event);
+                            if let Err(error) = &result {
+                                log::warn!("{} failed to pass on {:?}: {}", name, event.topic(), error);
+                                last_error = Some(error.to_string());
+                                failed.push(event);
+                            }
+                            context.health.record(name, result);
+                        }
+                    }
+                }
+                let (mut exported, mut rejected) = (0, 0);
+                // the queue kept the newest or the oldest of the gap, the history log has the rest
+                if let Some((from, to)) = gap.take() {
+                    let first = batch.iter().map(|measurement| measurement.t).min();
+                    let last = batch.iter().map(|measurement| measurement.t).max();
+                    match first.zip(last) {
+                        Some((first, last)) => pending.extend([(from, first), (last + 1, to)]),
+                        None => pending.push((from, to)),
+                    }
+                    pending.retain(|(from, to)| from < to);
+                }
+                while let Some(&(from, to)) = pending.first() {
+                    match backfill(&context, exporter.as_mut(), from, to) {
+                        Ok(sent) => {
+                            exported += sent as u64;
+                            pending.remove(0);
+                            context.health.record(name, Ok::<(), String>(()));
+                        }
+                        Err((resume, error)) => {
+                            log::warn!("{} backfill from the history log failed: {}", name, error);
+                            last_error = Some(error.to_string());
+                            pending[0].0 = resume;
+                            context.health.record(name, Err(error));
+                            break;
+                        }
+                    }
+                }
+                if !batch.is_empty() {
+                    let result = exporter.export(&batch);
+                    match &result {
+                        Ok(()) => {
+                            context.lifecycle.milestone(name);
+                            exported += batch.len() as u64;
+                        }
+                        Err(error) if error.is::<Rejected>() => {
+                            log::warn!("{} rejected {} readings, dropping them: {}", name, batch.len(), error);
+                            last_error = Some(error.to_string());
+                            rejected = batch.len();
+                        }
+                        Err(error) => {
+                            log::warn!("{} export failed: {}", name, error);
+                            last_error = Some(error.to_string());
+                            failed.extend(batch.into_iter().map(Event::Readings));
+                        }
+                    }
+                    context.health.record(name, result);
+                }
+
+                subscription.handled(count - failed.len() - rejected);
+                subscription.discarded(rejected);
+                let now = clock::now_unix();
+                let connected = exporter.connected();
+                let ok = failed.is_empty() && pending.is_empty();
+                context.exporters.update(name, |status| {
+                    status.connected = connected;
+                    status.exported += exported;
+                    status.in_flight = 0;
+                    if ok && rejected == 0 {
+                        status.last_success = Some(now);
+                    }
+                    if let Some(error) = last_error {
+                        status.last_error = Some((now, error));
+                    }
+                    status.activity = if ok { Activity::Idle } else { Activity::BackingOff };
+                    status.backoff = backoff;
+                });
+                if ok {
+                    backoff = BACKOFF;
+                } else {
+                    subscription.requeue(failed);
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the {} exporter: {}", name, error);
+    }
+}
+
+// the readings the history log has in [from, to), sent in batches of the exporter's capacity;
+// on a failure, the time to carry on from. These are the values as logged, every
+// HISTORY_INTERVAL_SECS
+fn backfill(context: &Context, exporter: &mut dyn Exporter, from: u64, to: u64) -> Result<usize, (u64, Box<dyn Error>)> {
+    let name = exporter.name();
+    let capacity = exporter.capacity().max(1);
+    let mut batch = Vec::new();
+    let mut sent = 0;
+    let mut failure = None;
+    let mut send = |batch: &mut Vec<Measurement>| match exporter.export(batch) {
+        Ok(()) => {
+            sent += batch.len();
+            batch.clear();
+            true
+        }
+        Err(error) if error.is::<Rejected>() => {
+            log::warn!("{} rejected {} readings from the history log, dropping them: {}", name, batch.len(), error);
+            batch.clear();
+            true
+        }
+        Err(error) => {
+            failure = Some((batch[0].t, error));
+            false
+        }
+    };
+    let scanned = history::scan_paged(&context.history, from, |record| {
+        if record.timestamp() >= to {
+            return Ok(ControlFlow::Break(()));
+        }
+        // readings taken in maintenance mode never went out in the first place
+        if let Record::Readings { t, values, location, maintenance: false } = record {
+            let readings = values.into_iter().map(|(sensor, celsius)| Reading { sensor, celsius: Celsius(celsius), humidity: None }).collect();
+            batch.push(Measurement { t, readings, location, restored: false });
+            if batch.len() >= capacity && !send(&mut batch) {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    });
+    if let Err(error) = scanned {
+        return Err((from, error.into()));
+    }
+    if !batch.is_empty() {
+        send(&mut batch);
+    }
+    match failure {
+        Some(failure) => Err(failure),
+        None => Ok(sent),
+    }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let exporters = Api::json(|| {
+        let status = json!({
+            "activity": Activity::Idle,
+            "connected": true,
+            "last_success": 0,
+            "last_error": "",
+            "last_error_at": 0,
+            "exported": 0,
+            "in_flight": 0,
+            "backoff_secs": 0,
+            "queue": Subscription::example(),
+        });
+        json!({ "maintenance_mode": false, "exporters": { "": status } })
+    });
+    http::route(server, "/api/exporters", Method::Get, exporters, move |request| {
+        let body = json!({
+            "maintenance_mode": context.service.active(),
+            "exporters": context.exporters.to_json(),
+        });
+        http::write_json(request, &body)
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,377 @@
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::registry::Registry;
+use crate::storage;
+use crate::units::Celsius;
+
+const SETTINGS_FILE: &str = "expressions.json";
+// keep a pathological expression from exhausting the stack of the sampling loop: evaluating
+// and dropping recurse once per nesting level and once per operator of a chain
+const MAX_DEPTH: usize = 16;
+const MAX_OPERATORS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Min,
+    Max,
+    Avg,
+    Abs,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(f32),
+    // a sensor id, a registry name or, as a function argument, a zone
+    Name(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    depth: usize,
+    operators: usize,
+}
+
+// expression := term (("+" | "-") term)*
+// term       := factor (("*" | "/") factor)*
+// factor     := number | name | "\"" any name "\"" | "-" factor | "(" expression ")"
+//             | function "(" expression ("," expression)* ")"
+impl Parser<'_> {
+    fn skip_spaces(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_spaces();
+        self.chars.next_if_eq(&expected).is_some()
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        let mut left = self.term()?;
+        loop {
+            let op = if self.eat('+') {
+                Op::Add
+            } else if self.eat('-') {
+                Op::Sub
+            } else {
+                break;
+            };
+            left = self.binary(op, left, Self::term)?;
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut left = self.factor()?;
+        loop {
+            let op = if self.eat('*') {
+                Op::Mul
+            } else if self.eat('/') {
+                Op::Div
+            } else {
+                break;
+            };
+            left = self.binary(op, left, Self::factor)?;
+        }
+        Ok(left)
+    }
+
+    // every operator of a chain goes through here
+    fn binary(&mut self, op: Op, left: Expr, right: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+        self.operators += 1;
+        if self.operators > MAX_OPERATORS {
+            return Err(format!("more than {} operators", MAX_OPERATORS));
+        }
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right(self)?)))
+    }
+
+    // every level of nesting goes through here
+    fn factor(&mut self) -> Result<Expr, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("nested too deeply".to_string());
+        }
+        let factor = self.operand();
+        self.depth -= 1;
+        factor
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.factor()?)));
+        }
+        if self.eat('(') {
+            let inner = self.expression()?;
+            return if self.eat(')') { Ok(inner) } else { Err("missing )".to_string()) };
+        }
+        if self.eat('"') {
+            let name: String = std::iter::from_fn(|| self.chars.next_if(|&c| c != '"')).collect();
+            return if self.eat('"') { Ok(Expr::Name(name)) } else { Err("missing \"".to_string()) };
+        }
+        self.skip_spaces();
+        let word: String = std::iter::from_fn(|| self.chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_' || c == '.')).collect();
+        if word.is_empty() {
+            return Err(match self.chars.peek() {
+                Some(c) => format!("unexpected {}", c),
+                None => "unexpected end".to_string(),
+            });
+        }
+        let function = match word.as_str() {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "avg" => Some(Function::Avg),
+            "abs" => Some(Function::Abs),
+            _ => None,
+        };
+        if let Some(function) = function {
+            if self.eat('(') {
+                let mut args = vec![self.expression()?];
+                while self.eat(',') {
+                    args.push(self.expression()?);
+                }
+                if !self.eat(')') {
+                    return Err(format!("missing ) after the arguments of {}", word));
+                }
+                return Ok(Expr::Call(function, args));
+            }
+        }
+        // a leading digit makes it a number unless it is a ROM address like 28FF4A...
+        match word.parse::<f32>() {
+            Ok(number) if word.len() < 16 => Ok(Expr::Number(number)),
+            _ => Ok(Expr::Name(word)),
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser { chars: text.chars().peekable(), depth: 0, operators: 0 };
+    let expr = parser.expression()?;
+    parser.skip_spaces();
+    match parser.chars.next() {
+        None => Ok(expr),
+        Some(c) => Err(format!("unexpected {}", c)),
+    }
+}
+
+// the current values a name stands for: the sensor with that id or registry name, or every
+// sensor of the zone
+fn resolve(name: &str, readings: &[Reading], registry: &Registry) -> Vec<f32> {
+    readings
+        .iter()
+        .filter(|reading| {
+            let info = registry.get(&reading.sensor);
+            reading.sensor == name
+                || info.is_some_and(|info| info.name.as_deref() == Some(name) || info.zone.as_deref() == Some(name))
+        })
+        .map(|reading| reading.celsius.0)
+        .collect()
+}
+
+impl Expr {
+    // None when a sensor it needs is missing, or on a division by zero
+    fn eval(&self, readings: &[Reading], registry: &Registry) -> Option<f32> {
+        let value = match self {
+            Expr::Number(number) => *number,
+            // a single sensor; a zone only makes sense inside min/max/avg
+            Expr::Name(name) => match resolve(name, readings, registry)[..] {
+                [value] => value,
+                _ => return None,
+            },
+            Expr::Neg(inner) => -inner.eval(readings, registry)?,
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(readings, registry)?, right.eval(readings, registry)?);
+                match op {
+                    Op::Add => left + right,
+                    Op::Sub => left - right,
+                    Op::Mul => left * right,
+                    Op::Div if right == 0.0 => return None,
+                    Op::Div => left / right,
+                }
+            }
+            Expr::Call(function, args) => {
+                let mut values = Vec::new();
+                for arg in args {
+                    match arg {
+                        Expr::Name(name) => values.extend(resolve(name, readings, registry)),
+                        arg => values.push(arg.eval(readings, registry)?),
+                    }
+                }
+                if values.is_empty() {
+                    return None;
+                }
+                match function {
+                    Function::Min => values.iter().copied().fold(f32::INFINITY, f32::min),
+                    Function::Max => values.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+                    Function::Avg => values.iter().sum::<f32>() / values.len() as f32,
+                    Function::Abs => values[0].abs(),
+                }
+            }
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Channel {
+    pub name: String,
+    pub expression: String,
+    #[serde(skip)]
+    compiled: Option<Expr>,
+}
+
+// virtual channels computed from other sensors every cycle, e.g. {"name": "mean",
+// "expression": "(t1 + t2) / 2"}; they go through alarms, history and the exporters like any
+// sensor. Each channel can use the ones before it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub channels: Vec<Channel>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let mut settings: Self = storage::read_json(SETTINGS_FILE).unwrap_or_default();
+        if let Err(error) = settings.compile() {
+            log::warn!("expressions: {}", error);
+        }
+        settings
+    }
+
+    fn compile(&mut self) -> Result<(), String> {
+        for channel in &mut self.channels {
+            channel.compiled = Some(parse(&channel.expression).map_err(|error| format!("{}: {}", channel.name, error))?);
+        }
+        Ok(())
+    }
+}
+
+pub fn add_channels(context: &Context, readings: &mut Vec<Reading>) {
+    let settings = context.expressions.lock().unwrap();
+    let registry = context.registry.lock().unwrap();
+    for channel in &settings.channels {
+        let Some(celsius) = channel.compiled.as_ref().and_then(|expr| expr.eval(readings, &registry)) else {
+            continue;
+        };
+        readings.push(Reading {
+            sensor: channel.name.clone(),
+            celsius: Celsius(celsius),
+            humidity: None,
+        });
+    }
+}
+
+// a channel named like a sensor would shadow it in every output. The channels configured now
+// are let through: they are among the readings, and in the registry once given limits
+fn check_names(context: &Context, settings: &Settings) -> Result<(), String> {
+    let current: Vec<String> = context.expressions.lock().unwrap().channels.iter().map(|channel| channel.name.clone()).collect();
+    let readings = context.latest_readings();
+    let registry = context.registry.lock().unwrap();
+    for (index, channel) in settings.channels.iter().enumerate() {
+        if settings.channels[..index].iter().any(|other| other.name == channel.name) {
+            return Err(format!("{} is there twice", channel.name));
+        }
+        if current.contains(&channel.name) {
+            continue;
+        }
+        if registry.get(&channel.name).is_some() || readings.iter().any(|reading| reading.sensor == channel.name) {
+            return Err(format!("{} is already a sensor", channel.name));
+        }
+    }
+    Ok(())
+}
+
+pub fn apply(context: &Context, mut settings: Settings) -> Result<(), (u16, String)> {
+    check_names(context, &settings).map_err(|message| (400, message))?;
+    settings.compile().map_err(|message| (400, message))?;
+    storage::write_json(SETTINGS_FILE, &settings).map_err(|error| (500, error.to_string()))?;
+    *context.expressions.lock().unwrap() = settings;
+    context.events.publish(Event::ConfigChanged("expressions"));
+    Ok(())
+}
+
+pub fn settings_example() -> serde_json::Value {
+    json!({ "channels": [{ "name": "", "expression": "" }] })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| json!([{ "name": "", "expression": "", "celsius": 0.0 }]));
+    http::route(server, "/api/expressions", Method::Get, status, move |request| {
+        let readings = status_context.latest_readings();
+        let settings = status_context.expressions.lock().unwrap().clone();
+        let channels: Vec<_> = settings
+            .channels
+            .iter()
+            .map(|channel| {
+                let value = readings.iter().find(|reading| reading.sensor == channel.name).map(|reading| reading.celsius);
+                json!({ "name": channel.name, "expression": channel.expression, "celsius": value })
+            })
+            .collect();
+        http::write_json(request, &json!(channels))
+    })?;
+
+    // {"channels": [{"name": "spread", "expression": "max(racks) - min(racks)"}]}
+    let settings_context = context;
+    let settings = Api::json(settings_example).body(settings_example);
+    http::route(server, "/api/expressions", Method::Post, settings, move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 4096)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let body = json!(settings);
+        match apply(&settings_context, settings) {
+            Ok(()) => http::write_json(request, &body),
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_chain_up_to_the_limit() {
+        let chain = vec!["1"; MAX_OPERATORS + 1].join("+");
+        assert!(parse(&chain).is_ok());
+    }
+
+    #[test]
+    fn refuses_a_longer_chain() {
+        let chain = vec!["1"; 100_000].join("+");
+        assert_eq!(parse(&chain).unwrap_err(), format!("more than {} operators", MAX_OPERATORS));
+        let product = vec!["t1"; 100_000].join(" * ");
+        assert!(parse(&product).is_err());
+    }
+
+    #[test]
+    fn refuses_deep_nesting() {
+        let nested = format!("{}1{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert_eq!(parse(&nested).unwrap_err(), "nested too deeply");
+    }
+}
@@ -0,0 +1,38 @@
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::context::Context;
+use crate::program::{ControlOutput, Ticker};
+use crate::readings::Reading;
+use crate::thermostat::Thermostat;
+use crate::units::Degrees;
+
+// heating/cooling dead band around the program setpoint
+const BAND: Degrees = Degrees(0.3);
+
+// follows the program with separate heat and cool outputs, e.g. a heat belt and a fridge
+pub struct Controller<H, C> {
+    thermostat: Thermostat<H, C>,
+    ticker: Ticker,
+}
+
+impl<H: OutputPin, C: OutputPin> Controller<H, C> {
+    pub fn new(heat: H, cool: C) -> Self {
+        Self {
+            thermostat: Thermostat::new(heat, cool, BAND),
+            ticker: Ticker::default(),
+        }
+    }
+
+    pub fn update(&mut self, context: &Context, readings: &[Reading]) {
+        let mut state = context.program.lock().unwrap();
+        let tick = self.ticker.tick(&mut state, readings);
+        match (tick.setpoint, tick.celsius) {
+            (Some(setpoint), Some(celsius)) => {
+                self.thermostat.update(celsius, setpoint);
+            }
+            // finished, or the probe is missing: never heat or cool blindly
+            _ => self.thermostat.stop(),
+        }
+        state.output = tick.setpoint.map(|_| ControlOutput::Thermostat(self.thermostat.state()));
+    }
+}
@@ -0,0 +1,208 @@
+use std::ffi::CStr;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use esp_idf_hal::io::Write;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::{
+    esp, esp_ota_get_next_update_partition, esp_ota_get_running_partition, esp_ota_get_state_partition,
+    esp_ota_img_states_t, esp_ota_img_states_t_ESP_OTA_IMG_ABORTED, esp_ota_img_states_t_ESP_OTA_IMG_INVALID,
+    esp_ota_img_states_t_ESP_OTA_IMG_NEW, esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY,
+    esp_ota_img_states_t_ESP_OTA_IMG_VALID, esp_partition_t, esp_spiffs_info, esp_vfs_spiffs_conf_t,
+    esp_vfs_spiffs_register, nvs_get_stats, nvs_stats_t, EspError,
+};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::storage;
+
+pub const ALARM: &str = "storage_low";
+// past this the history log and settings saves start failing soon; SPIFFS also gets slow
+// when its garbage collection has little free space to work with
+const SPIFFS_WARN_PERCENT: usize = 90;
+// every counter flush and every NVS page moved by its garbage collection needs free entries
+const NVS_WARN_FREE_ENTRIES: usize = 32;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Spiffs {
+    pub total_bytes: usize,
+    pub used_bytes: usize,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Nvs {
+    pub used_entries: usize,
+    pub free_entries: usize,
+    pub total_entries: usize,
+    pub namespaces: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Ota {
+    // partition label, "factory" on the partitions.csv of this repo
+    pub running: String,
+    // "none" for a factory image, which has no OTA state
+    pub state: &'static str,
+    // the slot an update would be written to, None without OTA partitions
+    pub next: Option<String>,
+}
+
+// how full the flash partitions the firmware depends on are, None where esp-idf can't tell
+pub struct Status {
+    pub spiffs: Option<Spiffs>,
+    pub nvs: Option<Nvs>,
+    pub ota: Option<Ota>,
+}
+
+// the SPIFFS partition behind storage.rs, formatted on first boot
+pub fn mount() -> Result<(), EspError> {
+    let conf = esp_vfs_spiffs_conf_t {
+        base_path: c"/storage".as_ptr(),
+        partition_label: storage::PARTITION.as_ptr(),
+        max_files: 8,
+        format_if_mount_failed: true,
+    };
+    esp!(unsafe { esp_vfs_spiffs_register(&conf) })?;
+    storage::recover_all();
+    Ok(())
+}
+
+fn spiffs() -> Option<Spiffs> {
+    let (mut total_bytes, mut used_bytes) = (0, 0);
+    esp!(unsafe { esp_spiffs_info(storage::PARTITION.as_ptr(), &mut total_bytes, &mut used_bytes) }).ok()?;
+    Some(Spiffs { total_bytes, used_bytes })
+}
+
+fn nvs() -> Option<Nvs> {
+    let mut stats: nvs_stats_t = unsafe { std::mem::zeroed() };
+    // null is the default "nvs" partition
+    esp!(unsafe { nvs_get_stats(std::ptr::null(), &mut stats) }).ok()?;
+    Some(Nvs {
+        used_entries: stats.used_entries,
+        free_entries: stats.free_entries,
+        total_entries: stats.total_entries,
+        namespaces: stats.namespace_count,
+    })
+}
+
+fn label(partition: *const esp_partition_t) -> Option<String> {
+    if partition.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr((*partition).label.as_ptr()) }.to_string_lossy().into_owned())
+}
+
+fn ota() -> Option<Ota> {
+    let running = unsafe { esp_ota_get_running_partition() };
+    let label_running = label(running)?;
+    let mut state: esp_ota_img_states_t = 0;
+    let state = match esp!(unsafe { esp_ota_get_state_partition(running, &mut state) }) {
+        Err(_) => "none",
+        Ok(()) => match state {
+            esp_ota_img_states_t_ESP_OTA_IMG_NEW => "new",
+            esp_ota_img_states_t_ESP_OTA_IMG_PENDING_VERIFY => "pending_verify",
+            esp_ota_img_states_t_ESP_OTA_IMG_VALID => "valid",
+            esp_ota_img_states_t_ESP_OTA_IMG_INVALID => "invalid",
+            esp_ota_img_states_t_ESP_OTA_IMG_ABORTED => "aborted",
+            _ => "undefined",
+        },
+    };
+    Some(Ota {
+        running: label_running,
+        state,
+        next: label(unsafe { esp_ota_get_next_update_partition(std::ptr::null()) }),
+    })
+}
+
+pub fn status() -> Status {
+    Status { spiffs: spiffs(), nvs: nvs(), ota: ota() }
+}
+
+impl Status {
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(spiffs) = self.spiffs.filter(|spiffs| spiffs.used_bytes * 100 >= spiffs.total_bytes * SPIFFS_WARN_PERCENT) {
+            warnings.push(format!("storage {} of {} KiB used", spiffs.used_bytes / 1024, spiffs.total_bytes / 1024));
+        }
+        if let Some(nvs) = self.nvs.filter(|nvs| nvs.free_entries < NVS_WARN_FREE_ENTRIES) {
+            warnings.push(format!("NVS down to {} free entries", nvs.free_entries));
+        }
+        warnings
+    }
+
+    // Prometheus text exposition format
+    fn to_metrics(&self, low: bool) -> String {
+        let mut metrics = String::new();
+        if let Some(spiffs) = self.spiffs {
+            let _ = writeln!(metrics, "# TYPE temp_spiffs_total_bytes gauge\ntemp_spiffs_total_bytes {}", spiffs.total_bytes);
+            let _ = writeln!(metrics, "# TYPE temp_spiffs_used_bytes gauge\ntemp_spiffs_used_bytes {}", spiffs.used_bytes);
+        }
+        if let Some(nvs) = self.nvs {
+            let _ = writeln!(metrics, "# TYPE temp_nvs_entries gauge");
+            let _ = writeln!(metrics, "temp_nvs_entries{{state=\"used\"}} {}", nvs.used_entries);
+            let _ = writeln!(metrics, "temp_nvs_entries{{state=\"free\"}} {}", nvs.free_entries);
+            let _ = writeln!(metrics, "temp_nvs_entries{{state=\"total\"}} {}", nvs.total_entries);
+            let _ = writeln!(metrics, "# TYPE temp_nvs_namespaces gauge\ntemp_nvs_namespaces {}", nvs.namespaces);
+        }
+        if let Some(ota) = &self.ota {
+            let _ = writeln!(
+                metrics,
+                "# TYPE temp_ota_info gauge\ntemp_ota_info{{running=\"{}\",state=\"{}\",next=\"{}\"}} 1",
+                ota.running,
+                ota.state,
+                ota.next.as_deref().unwrap_or("")
+            );
+        }
+        let _ = writeln!(metrics, "# TYPE temp_storage_low gauge\ntemp_storage_low {}", u8::from(low));
+        metrics
+    }
+}
+
+// once per cycle: raises the storage_low alarm before a full partition breaks logging or saves
+pub fn check(context: &Context) {
+    let warnings = status().warnings();
+    if warnings.is_empty() {
+        context.alarms.clear(ALARM);
+    } else {
+        context.alarms.raise(ALARM, warnings.join(", "));
+    }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let storage = Api::json(|| {
+        json!({
+            "spiffs": Spiffs { total_bytes: 0, used_bytes: 0 },
+            "nvs": Nvs { used_entries: 0, free_entries: 0, total_entries: 0, namespaces: 0 },
+            "ota": Ota { running: String::new(), state: "", next: Some(String::new()) },
+            "warnings": [""],
+            "alarm": false,
+            "maintenance": { "gc_runs": 0, "segments_downsampled": 0, "bytes_saved": 0, "last_error": "" },
+        })
+    });
+    http::route(server, "/api/storage", Method::Get, storage, move |request| {
+        let status = status();
+        let body = json!({
+            "spiffs": status.spiffs,
+            "nvs": status.nvs,
+            "ota": status.ota,
+            "warnings": status.warnings(),
+            "alarm": context.alarms.is_active(Some(ALARM)),
+            "maintenance": context.maintenance.to_json(),
+        });
+        http::write_json(request, &body)
+    })?;
+
+    http::route(server, "/metrics", Method::Get, Api::other("text/plain"), |request| {
+        let status = status();
+        let metrics = status.to_metrics(!status.warnings().is_empty());
+        let mut response = request.into_response(200, None, &[("Content-Type", "text/plain; version=0.0.4")])?;
+        response.write_all(metrics.as_bytes())?;
+        Ok::<(), esp_idf_svc::io::EspIOError>(())
+    })?;
+
+    Ok(())
+}
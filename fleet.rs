@@ -0,0 +1,239 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+
+use crate::commands::{self, Invocation, Source};
+use crate::config;
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::storage;
+use crate::units::Celsius;
+
+const SETTINGS_FILE: &str = "fleet.json";
+// sampling faster than a 12-bit conversion plus the reads makes no sense, slower than an hour
+// leaves the alarms blind for too long
+const INTERVAL_SECS: std::ops::RangeInclusive<u32> = 2..=3600;
+
+// the tags this device answers to on the fleet topic, and what fleet messages have set
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fleet {
+    pub tags: Vec<String>,
+    // replaces the profile's sample interval
+    pub sample_interval_secs: Option<u32>,
+    // id of the last message applied; a retained message arrives again with every reconnect
+    pub applied: Option<String>,
+}
+
+impl Fleet {
+    pub fn load() -> Self {
+        storage::read_json(SETTINGS_FILE).unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if let Err(error) = storage::write_json(SETTINGS_FILE, self) {
+            log::warn!("failed to save the fleet settings: {}", error);
+        }
+    }
+
+    // saved, for fleet messages and profiles
+    pub fn set_sample_interval(&mut self, secs: u32) -> Result<(), String> {
+        if !INTERVAL_SECS.contains(&secs) {
+            return Err(format!("between {} and {}", INTERVAL_SECS.start(), INTERVAL_SECS.end()));
+        }
+        self.sample_interval_secs = Some(secs);
+        self.save();
+        Ok(())
+    }
+
+    pub fn sample_interval_ms(&self) -> u32 {
+        self.sample_interval_secs.map_or_else(config::sample_interval_ms, |secs| secs * 1000)
+    }
+}
+
+// a setting that is left alone when absent and cleared when null
+fn clearable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Thresholds {
+    // only the sensors in this zone, every sensor without it
+    pub zone: Option<String>,
+    // each one unchanged when absent, removed when null
+    #[serde(default, deserialize_with = "clearable", skip_serializing_if = "Option::is_none")]
+    pub alarm_low: Option<Option<Celsius>>,
+    #[serde(default, deserialize_with = "clearable", skip_serializing_if = "Option::is_none")]
+    pub alarm_high: Option<Option<Celsius>>,
+    #[serde(default, deserialize_with = "clearable", skip_serializing_if = "Option::is_none")]
+    pub alarm_delay_minutes: Option<Option<u32>>,
+}
+
+impl Thresholds {
+    // every limit set, for the API description
+    pub fn example() -> Self {
+        Thresholds {
+            zone: Some(String::new()),
+            alarm_low: Some(Some(Celsius(0.0))),
+            alarm_high: Some(Some(Celsius(0.0))),
+            alarm_delay_minutes: Some(Some(0)),
+        }
+    }
+}
+
+// one message on temp/all/cmd, for every device carrying any of `tags` (all devices without
+// tags), e.g. {"id": "2026-10-14.1", "tags": ["cellar"], "sample_interval_secs": 60,
+// "thresholds": {"alarm_low": 10, "alarm_high": 14}}; "alarm_high": null removes that limit
+#[derive(Deserialize)]
+struct Broadcast {
+    id: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    sample_interval_secs: Option<u32>,
+    thresholds: Option<Thresholds>,
+    command: Option<Invocation>,
+}
+
+pub fn topic() -> String {
+    format!("{}/all/cmd", config::MQTT_TOPIC_PREFIX)
+}
+
+pub fn apply_thresholds(context: &Context, thresholds: &Thresholds) -> Result<usize, String> {
+    let mut registry = context.registry.lock().unwrap();
+    // sensors that answered but were never configured get an entry too
+    let mut sensors: Vec<String> = registry.sensors().map(|(sensor, _)| sensor.clone()).collect();
+    for reading in context.latest_readings() {
+        if !sensors.contains(&reading.sensor) {
+            sensors.push(reading.sensor);
+        }
+    }
+    let mut changed = 0;
+    for sensor in sensors {
+        let mut info = registry.get(&sensor).cloned().unwrap_or_default();
+        if thresholds.zone.is_some() && info.zone != thresholds.zone {
+            continue;
+        }
+        info.alarm_low = thresholds.alarm_low.unwrap_or(info.alarm_low);
+        info.alarm_high = thresholds.alarm_high.unwrap_or(info.alarm_high);
+        info.alarm_delay_minutes = thresholds.alarm_delay_minutes.unwrap_or(info.alarm_delay_minutes);
+        registry.update(&sensor, info);
+        changed += 1;
+    }
+    registry.save().map_err(|error| error.to_string())?;
+    Ok(changed)
+}
+
+// the reply for temp/<node>/command/result, None when the message isn't for this device or
+// was handled before. Nothing applies unless every part is valid, and the id is recorded before
+// the command runs, so the retained message arriving on every reconnect never runs it twice
+pub fn handle(context: &Context, payload: &[u8]) -> Option<Value> {
+    let broadcast = match serde_json::from_slice::<Broadcast>(payload) {
+        Ok(broadcast) => broadcast,
+        Err(error) => {
+            log::warn!("ignoring fleet message: {}", error);
+            return None;
+        }
+    };
+    let mut fleet = context.fleet.lock().unwrap();
+    let addressed = broadcast.tags.is_empty() || broadcast.tags.iter().any(|tag| fleet.tags.contains(tag));
+    if !addressed || fleet.applied.as_ref() == Some(&broadcast.id) {
+        return None;
+    }
+
+    let mut result = json!({ "fleet": broadcast.id });
+    let mut invalid = false;
+    if broadcast.sample_interval_secs.is_some_and(|secs| !INTERVAL_SECS.contains(&secs)) {
+        let error = format!("between {} and {}", INTERVAL_SECS.start(), INTERVAL_SECS.end());
+        result["sample_interval_secs"] = json!({ "error": error });
+        invalid = true;
+    }
+    if let Some(Err(error)) = broadcast.command.as_ref().map(|invocation| commands::check(&invocation.command, &invocation.args)) {
+        result["command"] = json!({ "ok": false, "error": error });
+        invalid = true;
+    }
+    if invalid {
+        result["applied"] = json!(false);
+        return Some(result);
+    }
+
+    let mut applied = true;
+    if let Some(secs) = broadcast.sample_interval_secs {
+        fleet.sample_interval_secs = Some(secs);
+        result["sample_interval_secs"] = json!(secs);
+    }
+    fleet.applied = Some(broadcast.id);
+    fleet.save();
+    drop(fleet);
+
+    if let Some(thresholds) = &broadcast.thresholds {
+        result["thresholds"] = match apply_thresholds(context, thresholds) {
+            Ok(sensors) => json!({ "sensors": sensors }),
+            Err(error) => {
+                applied = false;
+                json!({ "error": error })
+            }
+        };
+        context.events.publish(Event::ConfigChanged("sensors"));
+    }
+    context.events.publish(Event::ConfigChanged("fleet"));
+    if let Some(invocation) = &broadcast.command {
+        result["command"] = match commands::run(context, &invocation.command, &invocation.args, Source::Mqtt) {
+            Ok(result) => json!({ "ok": true, "result": result }),
+            Err(error) => {
+                applied = false;
+                json!({ "ok": false, "error": error })
+            }
+        };
+    }
+    result["applied"] = json!(applied);
+    Some(result)
+}
+
+#[derive(Deserialize)]
+struct TagsUpdate {
+    tags: Vec<String>,
+}
+
+fn fleet_example() -> Value {
+    json!(Fleet { tags: vec![String::new()], sample_interval_secs: Some(0), applied: Some(String::new()) })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| json!({ "fleet": fleet_example(), "sample_interval_ms": 0 }));
+    http::route(server, "/api/fleet", Method::Get, status, move |request| {
+        let fleet = status_context.fleet.lock().unwrap();
+        let body = json!({ "fleet": *fleet, "sample_interval_ms": fleet.sample_interval_ms() });
+        drop(fleet);
+        http::write_json(request, &body)
+    })?;
+
+    // {"tags": ["cellar", "building-2"]}
+    let tags_context = context;
+    let tags = Api::json(fleet_example).body(|| json!({ "tags": [""] }));
+    http::route(server, "/api/fleet", Method::Post, tags, move |mut request| {
+        let update = match http::read_json::<TagsUpdate>(&mut request, 512)? {
+            Ok(update) => update,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let mut fleet = tags_context.fleet.lock().unwrap();
+        fleet.tags = update.tags;
+        fleet.save();
+        let body = json!(*fleet);
+        drop(fleet);
+        tags_context.events.publish(Event::ConfigChanged("fleet"));
+        http::write_json(request, &body)
+    })?;
+
+    Ok(())
+}
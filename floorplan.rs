@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::{self, Read as _, Write as _};
+
+use esp_idf_hal::io::{Read, Write};
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::EspIOError;
+use esp_idf_svc::sys::EspError;
+use serde_json::json;
+
+use crate::http;
+use crate::openapi::Api;
+use crate::storage;
+
+const IMAGE_FILE: &str = "floorplan";
+// the SPIFFS partition is small, keep plans to a reasonably compressed image
+const MAX_IMAGE_SIZE: usize = 256 * 1024;
+
+// the image type is sniffed from its contents instead of trusting the upload. Only raster
+// formats: an SVG can carry script, and this is the dashboard's origin
+fn content_type(image: &[u8]) -> Option<&'static str> {
+    if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if image.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if image.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else {
+        None
+    }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>) -> Result<(), EspError> {
+    // streamed from storage in chunks, the plan is never in memory whole
+    http::route(server, "/api/floorplan", Method::Get, Api::other("image/*"), |request| {
+        let mut buffer = [0u8; 512];
+        let file = File::open(storage::path(IMAGE_FILE));
+        let read = file.as_ref().ok().and_then(|mut file| file.read(&mut buffer).ok()).unwrap_or(0);
+        let (Ok(mut file), Some(content_type)) = (file, content_type(&buffer[..read])) else {
+            return http::write_error(request, 404, "no floor plan uploaded");
+        };
+        let headers = [
+            ("Content-Type", content_type),
+            ("Content-Security-Policy", "sandbox"),
+            ("X-Content-Type-Options", "nosniff"),
+        ];
+        let mut response = request.into_response(200, None, &headers)?;
+        let mut read = read;
+        // a read error ends the image early
+        while read > 0 {
+            response.write_all(&buffer[..read])?;
+            read = file.read(&mut buffer).unwrap_or(0);
+        }
+        Ok::<(), EspIOError>(())
+    })?;
+
+    // written to storage as it comes in; a rejected upload leaves the old plan in place
+    let upload = Api::json(|| json!({ "size": 0 })).other_body("image/*");
+    http::route(server, "/api/floorplan", Method::Post, upload, |mut request| {
+        let mut buffer = [0u8; 512];
+        let mut size = 0;
+        let mut rejected = None;
+        let written = storage::replace(IMAGE_FILE, |file| loop {
+            let read = request.read(&mut buffer).map_err(|error| io::Error::other(error.to_string()))?;
+            if read == 0 {
+                return Ok(());
+            }
+            if size == 0 && content_type(&buffer[..read]).is_none() {
+                rejected = Some((415, "expected a PNG, JPEG or GIF image"));
+            } else if size + read > MAX_IMAGE_SIZE {
+                rejected = Some((413, "floor plan image too large"));
+            }
+            if rejected.is_some() {
+                return Err(io::Error::from(io::ErrorKind::InvalidData));
+            }
+            size += read;
+            file.write_all(&buffer[..read])?;
+        });
+        match (written, rejected) {
+            (_, Some((status, message))) => http::write_error(request, status, message),
+            (Err(error), None) => http::write_error(request, 500, &error.to_string()),
+            (Ok(()), None) => http::write_json(request, &json!({ "size": size })),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::delay::BLOCK;
+use esp_idf_hal::uart::UartDriver;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::context::Context;
+use crate::systime;
+
+// NMEA sentences are at most 82 characters, anything longer is line noise
+const MAX_SENTENCE: usize = 128;
+// a fix older than this isn't used to tag readings, the logger may have moved since
+const FIX_MAX_AGE: Duration = Duration::from_secs(10);
+// the clock is only set from GPS time when it is further off than this, the sentence arrives
+// a few hundred ms after the second it gives
+const CLOCK_TOLERANCE_SECS: u64 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+// what the GPS module reported last, from the RMC (time, position) and GGA (fix quality)
+// sentences of any constellation ($GP, $GN, ...)
+#[derive(Default)]
+pub struct Gps {
+    location: Option<Location>,
+    altitude_m: Option<f32>,
+    // GGA fix quality: 0 none, 1 GPS, 2 differential, 4 and 5 RTK, 6 dead reckoning
+    quality: u8,
+    satellites: u8,
+    hdop: Option<f32>,
+    last_fix: Option<Instant>,
+    last_sentence: Option<Instant>,
+    sentences: u64,
+    bad_checksums: u64,
+}
+
+impl Gps {
+    pub fn location(&self) -> Option<Location> {
+        self.location.filter(|_| self.quality > 0 && self.last_fix.is_some_and(|at| at.elapsed() < FIX_MAX_AGE))
+    }
+
+    // a fix with every field set, for the API description
+    pub fn example() -> Value {
+        let now = Instant::now();
+        let gps = Gps {
+            location: Some(Location { lat: 0.0, lon: 0.0 }),
+            altitude_m: Some(0.0),
+            hdop: Some(0.0),
+            last_fix: Some(now),
+            last_sentence: Some(now),
+            ..Default::default()
+        };
+        gps.to_json()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "fix": self.location().is_some(),
+            "quality": self.quality,
+            "satellites": self.satellites,
+            "hdop": self.hdop,
+            "location": self.location,
+            "altitude_m": self.altitude_m,
+            "fix_age_secs": self.last_fix.map(|at| at.elapsed().as_secs()),
+            "silent_secs": self.last_sentence.map(|at| at.elapsed().as_secs()),
+            "sentences": self.sentences,
+            "bad_checksums": self.bad_checksums,
+        })
+    }
+}
+
+// the fields between "$" and "*", if the checksum after it matches
+fn fields(sentence: &str) -> Option<Vec<&str>> {
+    let (body, checksum) = sentence.strip_prefix('$')?.split_once('*')?;
+    let expected = u8::from_str_radix(checksum.trim(), 16).ok()?;
+    (body.bytes().fold(0, |sum, byte| sum ^ byte) == expected).then(|| body.split(',').collect())
+}
+
+// "4807.038" with "N" is 48° 7.038' north; `degree_digits` is 2 for latitude, 3 for longitude
+fn coordinate(value: &str, hemisphere: &str, degree_digits: usize) -> Option<f64> {
+    // get() rather than slicing: noise on the line can put a multi-byte character here
+    let degrees: f64 = value.get(..degree_digits)?.parse().ok()?;
+    let minutes: f64 = value.get(degree_digits..)?.parse().ok()?;
+    let coordinate = degrees + minutes / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(coordinate),
+        "S" | "W" => Some(-coordinate),
+        _ => None,
+    }
+}
+
+fn number(value: &str, digits: std::ops::Range<usize>) -> Option<u32> {
+    value.get(digits)?.parse().ok()
+}
+
+// "hhmmss.ss" and "ddmmyy" in UTC
+fn unix_time(time: &str, date: &str) -> Option<u64> {
+    let (hours, minutes, seconds) = (number(time, 0..2)?, number(time, 2..4)?, number(time, 4..6)?);
+    let (day, month, year) = (number(date, 0..2)?, number(date, 2..4)?, number(date, 4..6)?);
+    if hours > 23 || minutes > 59 || seconds > 60 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = clock::days_from_civil(2000 + i64::from(year), month, day);
+    u64::try_from(days * 86_400 + i64::from(hours * 3600 + minutes * 60 + seconds)).ok()
+}
+
+fn handle(context: &Context, sentence: &str) {
+    let mut gps = context.gps.lock().unwrap();
+    gps.last_sentence = Some(Instant::now());
+    let Some(fields) = fields(sentence) else {
+        gps.bad_checksums += 1;
+        return;
+    };
+    gps.sentences += 1;
+    // the talker ($GP, $GL, $GN, ...) doesn't matter, only the sentence type
+    let kind = fields[0].get(2..).unwrap_or("");
+    match (kind, fields.as_slice()) {
+        ("RMC", [_, time, status, lat, ns, lon, ew, _, _, date, ..]) => {
+            if *status != "A" {
+                return;
+            }
+            if let (Some(lat), Some(lon)) = (coordinate(lat, ns, 2), coordinate(lon, ew, 3)) {
+                gps.location = Some(Location { lat, lon });
+                gps.last_fix = Some(Instant::now());
+            }
+            drop(gps);
+            let Some(unix) = unix_time(time, date) else {
+                return;
+            };
+            // SNTP is as good and doesn't depend on the sky
+            if clock::source() != "sntp" && clock::now_unix().abs_diff(unix) > CLOCK_TOLERANCE_SECS {
+                systime::set_unix(unix, "gps");
+                log::info!("clock set from GPS to {}", clock::format_iso8601(unix));
+            }
+        }
+        ("GGA", [_, _, _, _, _, _, quality, satellites, hdop, altitude, ..]) => {
+            gps.quality = quality.parse().unwrap_or(0);
+            gps.satellites = satellites.parse().unwrap_or(0);
+            gps.hdop = hdop.parse().ok();
+            gps.altitude_m = altitude.parse().ok();
+        }
+        _ => {}
+    }
+}
+
+// reads sentences from a GPS module on a UART, see config::GPS_BAUD
+pub fn start(context: Arc<Context>, uart: UartDriver<'static>) {
+    let spawned = thread::Builder::new()
+        .name("gps".into())
+        .stack_size(4096)
+        .spawn(move || {
+            let mut line = Vec::with_capacity(MAX_SENTENCE);
+            let mut buffer = [0u8; 128];
+            loop {
+                let read = match uart.read(&mut buffer, BLOCK) {
+                    Ok(read) => read,
+                    Err(error) => {
+                        log::warn!("GPS read failed: {}", error);
+                        thread::sleep(Duration::from_secs(1));
+                        continue;
+                    }
+                };
+                for &byte in &buffer[..read] {
+                    match byte {
+                        b'\n' => {
+                            if let Ok(sentence) = std::str::from_utf8(&line) {
+                                handle(&context, sentence.trim_end());
+                            }
+                            line.clear();
+                        }
+                        // noise or a baud rate mismatch, start over with the next line
+                        _ if line.len() >= MAX_SENTENCE => line.clear(),
+                        byte => line.push(byte),
+                    }
+                }
+            }
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the GPS reader: {}", error);
+    }
+}
@@ -0,0 +1,301 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::io::Write;
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::EspIOError;
+use esp_idf_svc::sys::EspError;
+use serde_json::{Map, Value};
+
+use crate::clock;
+use crate::commands::{self, Source};
+use crate::config;
+use crate::context::Context;
+use crate::http::{self, HandlerResult};
+use crate::readings::Reading;
+use crate::twin;
+
+// the dashboard server and the remote console have the ports below
+const CTRL_PORT: u16 = 32770;
+const CONTENT_TYPE: &str = "application/grpc-web+proto";
+const MAX_REQUEST: usize = 8192;
+// this server answers one call at a time, a stream holds it up to this long
+const MAX_STREAM: Duration = Duration::from_secs(300);
+
+// the gRPC status codes used here
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+
+type Status = (u32, String);
+
+// the few bits of the protobuf wire format proto/temp.proto needs
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(out, u64::from((field << 3) | 2));
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn put_uint(out: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(out, u64::from(field << 3));
+    put_varint(out, value);
+}
+
+fn put_float(out: &mut Vec<u8>, field: u32, value: f32) {
+    put_varint(out, u64::from((field << 3) | 5));
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn get_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+// every field of a message with its number; fixed-width ones are skipped, no request has them
+fn decode(mut data: &[u8]) -> Result<Vec<(u32, Field<'_>)>, Status> {
+    let malformed = || (INVALID_ARGUMENT, "malformed message".to_string());
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let key = get_varint(&mut data).ok_or_else(malformed)?;
+        let number = (key >> 3) as u32;
+        let skip = match key & 7 {
+            0 => {
+                fields.push((number, Field::Varint(get_varint(&mut data).ok_or_else(malformed)?)));
+                0
+            }
+            1 => 8,
+            2 => {
+                let length = get_varint(&mut data).ok_or_else(malformed)? as usize;
+                let bytes = data.get(..length).ok_or_else(malformed)?;
+                fields.push((number, Field::Bytes(bytes)));
+                length
+            }
+            5 => 4,
+            _ => return Err(malformed()),
+        };
+        data = data.get(skip..).ok_or_else(malformed)?;
+    }
+    Ok(fields)
+}
+
+fn string_field(fields: &[(u32, Field)], number: u32) -> Result<String, Status> {
+    match fields.iter().rev().find(|(field, _)| *field == number) {
+        Some((_, Field::Bytes(bytes))) => {
+            String::from_utf8(bytes.to_vec()).map_err(|_| (INVALID_ARGUMENT, format!("field {} isn't UTF-8", number)))
+        }
+        _ => Ok(String::new()),
+    }
+}
+
+fn uint_field(fields: &[(u32, Field)], number: u32) -> u64 {
+    match fields.iter().rev().find(|(field, _)| *field == number) {
+        Some((_, Field::Varint(value))) => *value,
+        _ => 0,
+    }
+}
+
+fn encode_readings(readings: &[Reading]) -> Vec<u8> {
+    let mut message = Vec::new();
+    put_uint(&mut message, 1, clock::now_unix());
+    for reading in readings {
+        let mut item = Vec::new();
+        put_bytes(&mut item, 1, reading.sensor.as_bytes());
+        put_float(&mut item, 2, reading.celsius.0);
+        if let Some(humidity) = reading.humidity {
+            put_float(&mut item, 3, humidity);
+        }
+        put_bytes(&mut message, 2, &item);
+    }
+    message
+}
+
+fn encode_config(section: &str, value: &Value) -> Vec<u8> {
+    let mut message = Vec::new();
+    put_bytes(&mut message, 1, section.as_bytes());
+    put_bytes(&mut message, 2, value.to_string().as_bytes());
+    message
+}
+
+// a gRPC-Web frame: flags (0x80 for the trailers), big-endian length, payload
+fn frame(flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 5);
+    frame.push(flags);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// grpc-message is percent-encoded
+fn trailers((status, message): &Status) -> Vec<u8> {
+    let mut encoded = String::new();
+    for byte in message.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(char::from(byte)),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    frame(0x80, format!("grpc-status:{}\r\ngrpc-message:{}\r\n", status, encoded).as_bytes())
+}
+
+// the single message of a call; compressed messages aren't accepted
+fn read_message(request: &mut Request<&mut EspHttpConnection>) -> Result<Result<Vec<u8>, Status>, EspIOError> {
+    let Some(body) = http::read_body(request, MAX_REQUEST)? else {
+        return Ok(Err((RESOURCE_EXHAUSTED, format!("messages are at most {} bytes", MAX_REQUEST))));
+    };
+    if body.len() < 5 {
+        return Ok(Err((INVALID_ARGUMENT, "no message".to_string())));
+    }
+    if body[0] != 0 {
+        return Ok(Err((UNIMPLEMENTED, "compressed messages aren't supported".to_string())));
+    }
+    let length = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    match body[5..].get(..length) {
+        Some(message) => Ok(Ok(message.to_vec())),
+        None => Ok(Err((INVALID_ARGUMENT, "truncated message".to_string()))),
+    }
+}
+
+fn respond(request: Request<&mut EspHttpConnection>, result: Result<Vec<u8>, Status>) -> HandlerResult {
+    let mut response = request.into_response(200, None, &[("Content-Type", CONTENT_TYPE)])?;
+    let status = match result {
+        Ok(message) => {
+            response.write_all(&frame(0, &message))?;
+            (OK, String::new())
+        }
+        Err(status) => status,
+    };
+    response.write_all(&trailers(&status))?;
+    Ok(())
+}
+
+fn http_status((code, message): (u16, String)) -> Status {
+    match code {
+        400 => (INVALID_ARGUMENT, message),
+        404 => (NOT_FOUND, message),
+        _ => (INTERNAL, message),
+    }
+}
+
+fn get_config(context: &Context, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let section = string_field(&decode(message)?, 1)?;
+    if section.is_empty() {
+        let reported = context.twin.lock().unwrap().reported(context);
+        return Ok(encode_config("", &reported["config"]));
+    }
+    let value = twin::section(context, &section).ok_or_else(|| (NOT_FOUND, format!("{} is not a twin section", section)))?;
+    Ok(encode_config(&section, &value))
+}
+
+fn set_config(context: &Context, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let fields = decode(message)?;
+    let (section, json) = (string_field(&fields, 1)?, string_field(&fields, 2)?);
+    let value: Value = serde_json::from_str(&json).map_err(|error| (INVALID_ARGUMENT, error.to_string()))?;
+    twin::apply_section(context, &section, &value).map_err(http_status)?;
+    Ok(encode_config(&section, &twin::section(context, &section).unwrap_or_default()))
+}
+
+fn run_command(context: &Context, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let fields = decode(message)?;
+    let (name, args_json) = (string_field(&fields, 1)?, string_field(&fields, 2)?);
+    if commands::find(&name).is_none() {
+        return Err((NOT_FOUND, format!("unknown command {}", name)));
+    }
+    let args: Map<String, Value> = if args_json.trim().is_empty() {
+        Map::new()
+    } else {
+        serde_json::from_str(&args_json).map_err(|error| (INVALID_ARGUMENT, error.to_string()))?
+    };
+    let result = commands::run(context, &name, &args, Source::Grpc).map_err(|error| (INVALID_ARGUMENT, error))?;
+    let mut response = Vec::new();
+    put_bytes(&mut response, 1, result.to_string().as_bytes());
+    Ok(response)
+}
+
+fn unary(
+    server: &mut EspHttpServer<'static>,
+    context: &Arc<Context>,
+    method: &str,
+    call: fn(&Context, &[u8]) -> Result<Vec<u8>, Status>,
+) -> Result<(), EspError> {
+    let context = context.clone();
+    server.fn_handler(&format!("/temp.v1.Temp/{}", method), Method::Post, move |mut request| {
+        let result = read_message(&mut request)?.and_then(|message| call(&context, &message));
+        respond(request, result)
+    })?;
+    Ok(())
+}
+
+// proto/temp.proto as gRPC-Web on its own server, so a stream only holds up other gRPC calls
+pub fn start(context: Arc<Context>) -> Result<Option<EspHttpServer<'static>>, EspError> {
+    let Ok(port) = u16::try_from(config::GRPC_PORT) else {
+        return Ok(None);
+    };
+    if port == 0 {
+        return Ok(None);
+    }
+    let mut server = EspHttpServer::new(&Configuration {
+        http_port: port,
+        ctrl_port: CTRL_PORT,
+        ..Default::default()
+    })?;
+
+    unary(&mut server, &context, "GetConfig", get_config)?;
+    unary(&mut server, &context, "SetConfig", set_config)?;
+    unary(&mut server, &context, "RunCommand", run_command)?;
+
+    server.fn_handler("/temp.v1.Temp/StreamReadings", Method::Post, move |mut request| {
+        let max_updates = match read_message(&mut request)?.and_then(|message| decode(&message).map(|fields| uint_field(&fields, 1))) {
+            Ok(max_updates) => max_updates,
+            Err(status) => return respond(request, Err(status)),
+        };
+        let started = Instant::now();
+        let mut response = request.into_response(200, None, &[("Content-Type", CONTENT_TYPE)])?;
+        let mut snapshot = context.readings.snapshot();
+        let mut sent = 0;
+        loop {
+            // the client went away
+            if response.write_all(&frame(0, &encode_readings(&snapshot.readings))).is_err() {
+                return Ok(());
+            }
+            sent += 1;
+            let left = MAX_STREAM.saturating_sub(started.elapsed());
+            if sent == max_updates || left.is_zero() {
+                break;
+            }
+            let next = context.readings.wait_past(snapshot.version, left);
+            if next.version == snapshot.version {
+                break;
+            }
+            snapshot = next;
+        }
+        response.write_all(&trailers(&(OK, String::new())))?;
+        Ok::<(), EspIOError>(())
+    })?;
+
+    Ok(Some(server))
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde_json::{json, Map, Value};
+
+use crate::config;
+use crate::context::Context;
+use crate::gps::Gps;
+use crate::http;
+use crate::openapi::Api;
+
+#[derive(Default)]
+struct Channel {
+    writes: u64,
+    failures: u64,
+    last_error: Option<String>,
+    // set by the first failure after a success, so a channel that keeps failing shows for how long
+    failing_since: Option<Instant>,
+}
+
+// how the ways out of the device are doing: the serial console and every exporter record each
+// write here, so a full buffer or a closed socket shows up at /api/health instead of vanishing
+#[derive(Default)]
+pub struct Health {
+    channels: Mutex<BTreeMap<&'static str, Channel>>,
+}
+
+impl Health {
+    pub fn record<E: Display>(&self, channel: &'static str, result: Result<(), E>) {
+        let mut channels = self.channels.lock().unwrap();
+        let state = channels.entry(channel).or_default();
+        state.writes += 1;
+        match result {
+            Ok(()) => state.failing_since = None,
+            Err(error) => {
+                state.failures += 1;
+                state.last_error = Some(error.to_string());
+                if state.failing_since.is_none() {
+                    state.failing_since = Some(Instant::now());
+                    // the log may well go out over the channel that failed, but it's the best
+                    // there is without a network
+                    log::warn!("{} is failing: {}", channel, error);
+                }
+            }
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.channels.lock().unwrap().values().all(|channel| channel.failing_since.is_none())
+    }
+
+    pub fn to_json(&self) -> Value {
+        let channels: Map<String, Value> = self
+            .channels
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, channel)| {
+                let state = json!({
+                    "writes": channel.writes,
+                    "failures": channel.failures,
+                    "last_error": channel.last_error,
+                    "failing_secs": channel.failing_since.map(|since| since.elapsed().as_secs()),
+                });
+                (name.to_string(), state)
+            })
+            .collect();
+        json!({ "ok": self.ok(), "channels": channels })
+    }
+}
+
+// one failing channel, and the GPS as with a module configured
+fn health_example() -> Value {
+    let channel = Channel { last_error: Some(String::new()), failing_since: Some(Instant::now()), ..Default::default() };
+    let health = Health { channels: Mutex::new(BTreeMap::from([("", channel)])) };
+    let mut body = health.to_json();
+    body["gps"] = Gps::example();
+    body
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    http::route(server, "/api/health", Method::Get, Api::json(health_example), move |request| {
+        let mut body = context.health.to_json();
+        if config::GPS_BAUD > 0 {
+            body["gps"] = context.gps.lock().unwrap().to_json();
+        }
+        http::write_json(request, &body)
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::pid::Pid;
+use crate::pwm::TimeProportional;
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+const SETTINGS_FILE: &str = "heating.json";
+
+// heating curve as (outdoor °C, flow °C) points, linear in between and flat beyond the ends
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Curve {
+    pub points: Vec<(Celsius, Celsius)>,
+}
+
+impl Curve {
+    pub fn flow_setpoint(&self, outdoor: Celsius) -> Option<Celsius> {
+        let mut points = self.points.clone();
+        points.sort_by(|(a, _), (b, _)| a.0.total_cmp(&b.0));
+        let (first, last) = (points.first()?, points.last()?);
+        if outdoor <= first.0 {
+            return Some(first.1);
+        }
+        if outdoor >= last.0 {
+            return Some(last.1);
+        }
+        points.windows(2).find(|pair| outdoor <= pair[1].0).map(|pair| {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            y0 + (y1 - y0) * ((outdoor - x0).0 / (x1 - x0).0)
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub outdoor_sensor: Option<String>,
+    pub flow_sensor: Option<String>,
+    pub curve: Curve,
+    // parallel shift of the whole curve, the usual "warmer/colder" knob
+    pub shift: Degrees,
+    // outdoor temperature is damped to follow the building's thermal inertia
+    pub damping_hours: f32,
+    // no heating at all above this (damped) outdoor temperature
+    pub summer_cutoff: Celsius,
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl Default for Settings {
+    // a typical radiator curve: 70 °C flow at -10 °C outside, 30 °C at 15 °C
+    fn default() -> Self {
+        Self {
+            outdoor_sensor: None,
+            flow_sensor: None,
+            curve: Curve {
+                points: vec![(Celsius(-10.0), Celsius(70.0)), (Celsius(15.0), Celsius(30.0))],
+            },
+            shift: Degrees(0.0),
+            damping_hours: 3.0,
+            summer_cutoff: Celsius(18.0),
+            kp: 0.1,
+            ki: 0.0005,
+            kd: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct Status {
+    pub outdoor: Option<Celsius>,
+    pub damped_outdoor: Option<Celsius>,
+    pub flow_setpoint: Option<Celsius>,
+    pub flow: Option<Celsius>,
+    pub duty: f32,
+}
+
+#[derive(Default)]
+pub struct HeatingState {
+    pub settings: Settings,
+    pub status: Status,
+}
+
+impl HeatingState {
+    pub fn load() -> Self {
+        Self {
+            settings: storage::read_json(SETTINGS_FILE).unwrap_or_default(),
+            status: Status::default(),
+        }
+    }
+}
+
+// weather-compensated flow temperature control: the curve turns the (damped) outdoor
+// temperature into a flow setpoint, which the PID holds with the heater output
+pub struct Compensator {
+    pid: Pid,
+    heater: TimeProportional,
+    damped_outdoor: Option<Celsius>,
+    last_update: Option<Instant>,
+}
+
+fn find(readings: &[Reading], sensor: &Option<String>) -> Option<Celsius> {
+    let sensor = sensor.as_ref()?;
+    readings.iter().find(|reading| &reading.sensor == sensor).map(|reading| reading.celsius)
+}
+
+impl Compensator {
+    pub fn new(heater: TimeProportional) -> Self {
+        let defaults = Settings::default();
+        Self {
+            pid: Pid::new(defaults.kp, defaults.ki, defaults.kd),
+            heater,
+            damped_outdoor: None,
+            last_update: None,
+        }
+    }
+
+    pub fn update(&mut self, context: &Context, readings: &[Reading]) {
+        let dt = self.last_update.map_or(0.0, |last| last.elapsed().as_secs_f32());
+        self.last_update = Some(Instant::now());
+        let settings = context.heating.lock().unwrap().settings.clone();
+
+        let outdoor = find(readings, &settings.outdoor_sensor);
+        let flow = find(readings, &settings.flow_sensor);
+        if let Some(outdoor) = outdoor {
+            // first order low pass with the configured time constant
+            let tau = settings.damping_hours * 3600.0;
+            self.damped_outdoor = Some(match self.damped_outdoor {
+                Some(damped) if tau > 0.0 => damped + (outdoor - damped) * (dt / tau).min(1.0),
+                _ => outdoor,
+            });
+        }
+
+        let flow_setpoint = self
+            .damped_outdoor
+            .filter(|&damped| damped < settings.summer_cutoff)
+            .and_then(|damped| settings.curve.flow_setpoint(damped))
+            .map(|setpoint| setpoint + settings.shift);
+
+        (self.pid.kp, self.pid.ki, self.pid.kd) = (settings.kp, settings.ki, settings.kd);
+        let duty = match (flow_setpoint, flow) {
+            (Some(setpoint), Some(flow)) => self.pid.update(setpoint, flow, dt),
+            _ => {
+                self.pid.reset();
+                0.0
+            }
+        };
+        self.heater.set(duty);
+
+        context.heating.lock().unwrap().status = Status {
+            outdoor,
+            damped_outdoor: self.damped_outdoor,
+            flow_setpoint,
+            flow,
+            duty,
+        };
+    }
+}
+
+fn settings_example() -> serde_json::Value {
+    json!(Settings { outdoor_sensor: Some(String::new()), flow_sensor: Some(String::new()), ..Default::default() })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| {
+        let value = Some(Celsius(0.0));
+        let status = Status { outdoor: value, damped_outdoor: value, flow_setpoint: value, flow: value, duty: 0.0 };
+        json!({ "settings": settings_example(), "status": status })
+    });
+    http::route(server, "/api/heating", Method::Get, status, move |request| {
+        let state = status_context.heating.lock().unwrap();
+        let body = json!({ "settings": state.settings, "status": state.status });
+        drop(state);
+        http::write_json(request, &body)
+    })?;
+
+    let settings_context = context;
+    let settings = Api::json(settings_example).body(settings_example);
+    http::route(server, "/api/heating", Method::Post, settings, move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 2048)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        if settings.curve.points.is_empty() {
+            return http::write_error(request, 400, "the heating curve needs at least one point");
+        }
+        if let Err(error) = storage::write_json(SETTINGS_FILE, &settings) {
+            return http::write_error(request, 500, &error.to_string());
+        }
+        settings_context.heating.lock().unwrap().settings = settings.clone();
+        settings_context.events.publish(Event::ConfigChanged("heating"));
+        http::write_json(request, &json!(settings))
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde_json::json;
+
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+
+const DEFAULT_SIZE: usize = 24;
+const MAX_SIZE: usize = 64;
+// higher powers make each sensor dominate its own surroundings more
+const IDW_POWER: f32 = 2.0;
+
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub celsius: f32,
+}
+
+// inverse distance weighted grid over the unit square, rows top to bottom, sampled at cell centers
+pub fn interpolate(points: &[Point], columns: usize, rows: usize) -> Vec<Vec<f32>> {
+    (0..rows)
+        .map(|row| {
+            let y = (row as f32 + 0.5) / rows as f32;
+            (0..columns)
+                .map(|column| {
+                    let x = (column as f32 + 0.5) / columns as f32;
+                    let mut weighted = 0.0;
+                    let mut total_weight = 0.0;
+                    for point in points {
+                        let distance_squared = (point.x - x).powi(2) + (point.y - y).powi(2);
+                        if distance_squared < 1e-9 {
+                            return point.celsius;
+                        }
+                        let weight = 1.0 / distance_squared.powf(IDW_POWER / 2.0);
+                        weighted += weight * point.celsius;
+                        total_weight += weight;
+                    }
+                    weighted / total_weight
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn positioned_points(context: &Context) -> Vec<Point> {
+    let readings = context.latest_readings();
+    let registry = context.registry.lock().unwrap();
+    readings
+        .iter()
+        .filter_map(|reading| {
+            let info = registry.get(&reading.sensor)?;
+            Some(Point {
+                x: info.x?,
+                y: info.y?,
+                celsius: reading.celsius.0,
+            })
+        })
+        .collect()
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    // ?cols=&rows= select the grid resolution
+    let heatmap = Api::json(|| json!({ "columns": 0, "rows": 0, "min": 0.0, "max": 0.0, "grid": [[0.0]] }))
+        .query(&[("cols", "integer"), ("rows", "integer")]);
+    http::route(server, "/api/heatmap", Method::Get, heatmap, move |request| {
+        let size = |name| {
+            http::query_param(request.uri(), name)
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(DEFAULT_SIZE)
+                .clamp(1, MAX_SIZE)
+        };
+        let (columns, rows) = (size("cols"), size("rows"));
+
+        let points = positioned_points(&context);
+        if points.is_empty() {
+            return http::write_error(request, 404, "no sensors have a position on the floor plan");
+        }
+
+        let grid = interpolate(&points, columns, rows);
+        let (min, max) = grid
+            .iter()
+            .flatten()
+            .fold((f32::MAX, f32::MIN), |(min, max), &value| (min.min(value), max.max(value)));
+        http::write_json(
+            request,
+            &json!({ "columns": columns, "rows": rows, "min": min, "max": max, "grid": grid }),
+        )
+    })?;
+
+    Ok(())
+}
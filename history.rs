@@ -0,0 +1,621 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::clock;
+use crate::config;
+use crate::context::Context;
+use crate::gps::Location;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::signing::{self, DeviceKey};
+use crate::storage;
+
+// the log is a series of JSON-lines segments: history.0 is being appended to, higher numbers
+// are older; at 64 KiB per segment and one line every few minutes this keeps months of data
+const SEGMENT_SIZE: u64 = 64 * 1024;
+pub const MAX_SEGMENTS: usize = 24;
+// downsampled exports are built in memory
+const MAX_BUCKETS: usize = 4096;
+// ?step= and ?max_gap= are clamped to these
+const MAX_STEP_SECS: u64 = 7 * 86_400;
+const MAX_GAP_SECS: u64 = 86_400;
+// exports read this much at a time under the lock
+const PAGE_RECORDS: usize = 32;
+const RAW_PAGE_BYTES: usize = 4096;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    // location is where a mobile logger was, from the GPS
+    Readings {
+        t: u64,
+        values: BTreeMap<String, f32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<Location>,
+        // taken in maintenance mode, while probes may have been out
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        maintenance: bool,
+    },
+    // a digital input changing state, e.g. a fridge door
+    Contact { t: u64, name: String, open: bool },
+    // free text from a user, e.g. "defrost started"; source is "api" or "mqtt"
+    Annotation { t: u64, text: String, source: String },
+    // last line of a sealed segment: hash = SHA-256(prev || every byte of the segment before
+    // this line), signed with the device key; prev is the previous segment's hash
+    Seal { t: u64, prev: String, hash: String, signature: String },
+}
+
+impl Record {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Record::Readings { t, .. }
+            | Record::Contact { t, .. }
+            | Record::Annotation { t, .. }
+            | Record::Seal { t, .. } => *t,
+        }
+    }
+
+    pub fn readings(readings: &[Reading], t: u64, location: Option<Location>, maintenance: bool) -> Self {
+        Record::Readings {
+            t,
+            values: readings.iter().map(|reading| (reading.sensor.clone(), reading.celsius.0)).collect(),
+            location,
+            maintenance,
+        }
+    }
+}
+
+fn segment_name(index: usize) -> String {
+    format!("history.{}", index)
+}
+
+fn encode(record: &Record) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn hash_from_hex(hex: &str) -> Option<[u8; 32]> {
+    signing::from_hex(hex)?.try_into().ok()
+}
+
+pub struct History {
+    // segments are only sealed when a key is given
+    seal_key: Option<DeviceKey>,
+    // hash of the newest sealed segment, found lazily after boot
+    chain_head: Option<[u8; 32]>,
+    // since boot, for paged reads to find their place again
+    rotations: u64,
+    rewrites: u64,
+}
+
+// how far a paged read has got, so it can let go of the lock between pages: a segment, found
+// again after rotations by how many there have been since, and a byte offset in it
+pub struct Cursor {
+    rotations: u64,
+    rewrites: u64,
+    index: usize,
+    offset: u64,
+    since: u64,
+    // the last record returned; after a segment was rewritten it is read again from the start,
+    // past this
+    last: Option<u64>,
+    after: Option<u64>,
+    done: bool,
+}
+
+impl History {
+    pub fn new(seal_key: Option<DeviceKey>) -> Self {
+        Self {
+            seal_key,
+            chain_head: None,
+            rotations: 0,
+            rewrites: 0,
+        }
+    }
+
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        let path = storage::path(&segment_name(0));
+        if fs::metadata(&path).map(|metadata| metadata.len() >= SEGMENT_SIZE).unwrap_or(false) {
+            if self.seal_key.is_some() {
+                let seal = self.seal(&fs::read(&path)?)?;
+                OpenOptions::new().append(true).open(&path)?.write_all(&encode(&seal)?)?;
+                if let Record::Seal { hash, .. } = &seal {
+                    self.chain_head = hash_from_hex(hash);
+                }
+            }
+            self.rotate()?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(&encode(record)?)
+    }
+
+    // a record from the past, put before the first newer one so the log stays in order for
+    // cursor() to skip segments by; only the open segment is rewritten, a sealed one is covered
+    // byte for byte, so the record can't go back past its first
+    pub fn insert(&mut self, record: &Record) -> io::Result<()> {
+        let t = record.timestamp();
+        match first_timestamp(0) {
+            Some(first) if t < first => {
+                let message = format!("t must not be before {}, where the open history segment starts", first);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+            None if fs::metadata(storage::path(&segment_name(1))).is_ok() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "t must not be in the past right after the history rotated"));
+            }
+            _ => {}
+        }
+        let name = segment_name(0);
+        let data = fs::read(storage::path(&name)).unwrap_or_default();
+        let mut at = 0;
+        for line in data.split_inclusive(|&byte| byte == b'\n') {
+            if serde_json::from_slice::<Record>(line).is_ok_and(|existing| existing.timestamp() > t) {
+                let mut rewritten = Vec::with_capacity(data.len() + 256);
+                rewritten.extend_from_slice(&data[..at]);
+                rewritten.extend(encode(record)?);
+                rewritten.extend_from_slice(&data[at..]);
+                storage::write(&name, &rewritten)?;
+                self.rewrites += 1;
+                return Ok(());
+            }
+            at += line.len();
+        }
+        self.append(record)
+    }
+
+    // the previous segment's seal, or all zeros at the start of the chain
+    fn previous_hash(&mut self) -> [u8; 32] {
+        if let Some(head) = self.chain_head {
+            return head;
+        }
+        let last_seal = fs::read_to_string(storage::path(&segment_name(1)))
+            .ok()
+            .and_then(|segment| segment.lines().last().and_then(|line| serde_json::from_str::<Record>(line).ok()));
+        let head = match last_seal {
+            Some(Record::Seal { hash, .. }) => hash_from_hex(&hash).unwrap_or_default(),
+            _ => [0u8; 32],
+        };
+        self.chain_head = Some(head);
+        head
+    }
+
+    fn seal(&mut self, segment: &[u8]) -> io::Result<Record> {
+        let previous = self.previous_hash();
+        let Some(key) = &self.seal_key else {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "history signing is disabled"));
+        };
+        let hash = Sha256::new().chain_update(previous).chain_update(segment).finalize();
+        Ok(Record::Seal {
+            t: clock::now_unix(),
+            prev: signing::to_hex(&previous),
+            hash: signing::to_hex(&hash),
+            signature: key.sign_hex(&hash),
+        })
+    }
+
+    // a paged read of the records at or after `since`, starting in the oldest segment that can
+    // hold any: a segment ends before the next newer one starts
+    pub fn cursor(&self, since: u64) -> Cursor {
+        let index = (1..MAX_SEGMENTS).rev().find(|&index| !first_timestamp(index - 1).is_some_and(|t| t < since)).unwrap_or(0);
+        Cursor {
+            rotations: self.rotations,
+            rewrites: self.rewrites,
+            index,
+            offset: 0,
+            since,
+            last: None,
+            after: None,
+            done: false,
+        }
+    }
+
+    fn follow(&self, cursor: &mut Cursor) {
+        cursor.index += (self.rotations - cursor.rotations) as usize;
+        cursor.rotations = self.rotations;
+        // its segment was dropped, the oldest left is newer
+        if cursor.index >= MAX_SEGMENTS {
+            cursor.index = MAX_SEGMENTS - 1;
+            cursor.offset = 0;
+        }
+        if cursor.rewrites != self.rewrites {
+            cursor.rewrites = self.rewrites;
+            if cursor.offset > 0 {
+                cursor.offset = 0;
+                cursor.after = cursor.last;
+            }
+        }
+    }
+
+    // hands `take` the lines from the cursor on, oldest first, until it returns false or the
+    // open segment has been read to its end
+    fn advance(&self, cursor: &mut Cursor, mut take: impl FnMut(&[u8]) -> bool) -> io::Result<()> {
+        let mut line = Vec::new();
+        while !cursor.done {
+            if let Ok(file) = File::open(storage::path(&segment_name(cursor.index))) {
+                let mut reader = BufReader::new(file);
+                reader.seek(SeekFrom::Start(cursor.offset))?;
+                loop {
+                    line.clear();
+                    let read = reader.read_until(b'\n', &mut line)?;
+                    if read == 0 {
+                        break;
+                    }
+                    cursor.offset += read as u64;
+                    if !take(&line) {
+                        return Ok(());
+                    }
+                }
+            }
+            if cursor.index == 0 {
+                cursor.done = true;
+            } else {
+                cursor.index -= 1;
+                cursor.offset = 0;
+            }
+        }
+        Ok(())
+    }
+
+    // the next records of a paged read, empty once it has reached the end
+    pub fn page(&self, cursor: &mut Cursor) -> io::Result<Vec<Record>> {
+        self.follow(cursor);
+        let (since, after) = (cursor.since, cursor.after);
+        let mut records = Vec::new();
+        self.advance(cursor, |line| {
+            // a torn last line after a power cut is skipped rather than ending the export
+            if let Ok(record) = serde_json::from_slice::<Record>(line) {
+                let t = record.timestamp();
+                if t >= since && after.map_or(true, |after| t > after) {
+                    records.push(record);
+                }
+            }
+            records.len() < PAGE_RECORDS
+        })?;
+        if let Some(record) = records.last() {
+            cursor.last = Some(record.timestamp());
+        }
+        Ok(records)
+    }
+
+    // the next bytes of every segment as stored, oldest first; after the open segment a seal
+    // over it, so the whole export can be verified, then nothing
+    pub fn page_raw(&mut self, cursor: &mut Cursor) -> io::Result<Vec<u8>> {
+        if cursor.done {
+            return Ok(Vec::new());
+        }
+        self.follow(cursor);
+        let mut bytes = Vec::new();
+        self.advance(cursor, |line| {
+            bytes.extend_from_slice(line);
+            bytes.len() < RAW_PAGE_BYTES
+        })?;
+        if cursor.done && self.seal_key.is_some() {
+            let current = fs::read(storage::path(&segment_name(0))).unwrap_or_default();
+            let seal = self.seal(&current)?;
+            bytes.extend(encode(&seal)?);
+        }
+        Ok(bytes)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let _ = fs::remove_file(storage::path(&segment_name(MAX_SEGMENTS - 1)));
+        for index in (0..MAX_SEGMENTS - 1).rev() {
+            let from = storage::path(&segment_name(index));
+            if fs::metadata(&from).is_ok() {
+                fs::rename(from, storage::path(&segment_name(index + 1)))?;
+            }
+        }
+        self.rotations += 1;
+        Ok(())
+    }
+
+    // rewrites a closed segment with its readings averaged over `step` seconds, keeping every
+    // other record; the sizes before and after, or None when there was nothing to merge. Sealed
+    // segments are left alone, their seal covers them byte for byte
+    pub fn downsample_segment(&mut self, index: usize, step: u64) -> io::Result<Option<(u64, u64)>> {
+        if index == 0 {
+            return Ok(None);
+        }
+        let name = segment_name(index);
+        let Ok(file) = File::open(storage::path(&name)) else {
+            return Ok(None);
+        };
+        let before = file.metadata()?.len();
+        let mut records = Vec::new();
+        let mut lines = 0;
+        let mut sums: BTreeMap<u64, (BTreeMap<String, (f32, u32)>, Option<Location>)> = BTreeMap::new();
+        for line in BufReader::new(file).lines() {
+            let Ok(record) = serde_json::from_str::<Record>(&line?) else {
+                continue;
+            };
+            match record {
+                Record::Seal { .. } => return Ok(None),
+                // maintenance readings stay as they are rather than skewing the means
+                Record::Readings { t, values, location, maintenance: false } => {
+                    lines += 1;
+                    let (bucket, last_location) = sums.entry(t / step * step).or_default();
+                    for (sensor, celsius) in values {
+                        let (sum, count) = bucket.entry(sensor).or_insert((0.0, 0));
+                        *sum += celsius;
+                        *count += 1;
+                    }
+                    *last_location = location.or(*last_location);
+                }
+                record => records.push(record),
+            }
+        }
+        if sums.len() == lines {
+            return Ok(None);
+        }
+        records.extend(sums.into_iter().map(|(t, (sensors, location))| Record::Readings {
+            t,
+            values: sensors.into_iter().map(|(sensor, (sum, count))| (sensor, sum / count as f32)).collect(),
+            location,
+            maintenance: false,
+        }));
+        records.sort_by_key(Record::timestamp);
+        let mut data = Vec::new();
+        for record in &records {
+            data.extend(encode(record)?);
+        }
+        storage::write(&name, &data)?;
+        self.rewrites += 1;
+        Ok(Some((before, data.len() as u64)))
+    }
+
+    // every record at or after `since`, oldest first
+    pub fn for_each(&self, since: u64, mut visit: impl FnMut(Record) -> io::Result<()>) -> io::Result<()> {
+        self.scan(since, |record| visit(record).map(|()| ControlFlow::Continue(())))
+    }
+
+    // for_each that the visitor can stop early. A segment ends before the next newer one
+    // starts, so one is skipped without being read when the next starts before `since`
+    pub fn scan(&self, since: u64, mut visit: impl FnMut(Record) -> io::Result<ControlFlow<()>>) -> io::Result<()> {
+        for index in (0..MAX_SEGMENTS).rev() {
+            if index > 0 && first_timestamp(index - 1).is_some_and(|t| t < since) {
+                continue;
+            }
+            let Ok(file) = File::open(storage::path(&segment_name(index))) else {
+                continue;
+            };
+            for line in BufReader::new(file).lines() {
+                // a torn last line after a power cut is skipped rather than ending the export
+                let Ok(record) = serde_json::from_str::<Record>(&line?) else {
+                    continue;
+                };
+                if record.timestamp() >= since && visit(record)?.is_break() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// records at or after `since` a page at a time, the lock held only while a page is read and
+// never while `visit` sends it on, so a slow client doesn't hold up the sampling loop
+pub fn scan_paged(history: &Mutex<History>, since: u64, mut visit: impl FnMut(Record) -> io::Result<ControlFlow<()>>) -> io::Result<()> {
+    let mut cursor = history.lock().unwrap().cursor(since);
+    loop {
+        let page = history.lock().unwrap().page(&mut cursor)?;
+        if page.is_empty() {
+            return Ok(());
+        }
+        for record in page {
+            if visit(record)?.is_break() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn first_timestamp(index: usize) -> Option<u64> {
+    let file = File::open(storage::path(&segment_name(index))).ok()?;
+    let line = BufReader::new(file).lines().next()?.ok()?;
+    serde_json::from_str::<Record>(&line).ok().map(|record| record.timestamp())
+}
+
+// one step of a downsampled export: the mean of every sensor's readings in [t, t + step); the
+// sensors in `synthetic` had none there and were interpolated
+#[derive(Debug, Serialize)]
+struct Bucket {
+    t: u64,
+    values: BTreeMap<String, f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    synthetic: Vec<String>,
+}
+
+fn too_many_buckets() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "too many steps, use a larger step or a later since")
+}
+
+fn downsample(history: &History, since: u64, step: u64) -> io::Result<Vec<Bucket>> {
+    let mut sums: BTreeMap<u64, BTreeMap<String, (f32, u32)>> = BTreeMap::new();
+    history.for_each(since, |record| {
+        if let Record::Readings { t, values, .. } = record {
+            let bucket = sums.entry(t / step * step).or_default();
+            for (sensor, celsius) in values {
+                let (sum, count) = bucket.entry(sensor).or_insert((0.0, 0));
+                *sum += celsius;
+                *count += 1;
+            }
+            if sums.len() > MAX_BUCKETS {
+                return Err(too_many_buckets());
+            }
+        }
+        Ok(())
+    })?;
+    Ok(sums
+        .into_iter()
+        .map(|(t, sensors)| Bucket {
+            t,
+            values: sensors.into_iter().map(|(sensor, (sum, count))| (sensor, sum / count as f32)).collect(),
+            synthetic: Vec::new(),
+        })
+        .collect())
+}
+
+// linear interpolation into the steps a sensor has no reading in, between two real ones at most
+// `max_gap` seconds apart; longer gaps (an outage, the device off) stay gaps
+fn fill(buckets: &mut Vec<Bucket>, step: u64, max_gap: u64) -> io::Result<()> {
+    let mut grid = Vec::new();
+    for pair in buckets.windows(2) {
+        let (from, to) = (pair[0].t, pair[1].t);
+        if to - from <= max_gap {
+            // counted before any of them is allocated
+            let steps = ((to - from - 1) / step) as usize;
+            if buckets.len() + grid.len() + steps > MAX_BUCKETS {
+                return Err(too_many_buckets());
+            }
+            grid.extend((from + step..to).step_by(step as usize));
+        }
+    }
+    buckets.extend(grid.into_iter().map(|t| Bucket { t, values: BTreeMap::new(), synthetic: Vec::new() }));
+    buckets.sort_by_key(|bucket| bucket.t);
+
+    let sensors: BTreeSet<String> = buckets.iter().flat_map(|bucket| bucket.values.keys().cloned()).collect();
+    for sensor in sensors {
+        let mut last: Option<(usize, f32)> = None;
+        for index in 0..buckets.len() {
+            let Some(&value) = buckets[index].values.get(&sensor) else {
+                continue;
+            };
+            if let Some((from, from_value)) = last {
+                let (t0, t1) = (buckets[from].t, buckets[index].t);
+                if t1 - t0 <= max_gap {
+                    for bucket in &mut buckets[from + 1..index] {
+                        let fraction = (bucket.t - t0) as f32 / (t1 - t0) as f32;
+                        bucket.values.insert(sensor.clone(), from_value + (value - from_value) * fraction);
+                        bucket.synthetic.push(sensor.clone());
+                    }
+                }
+            }
+            last = Some((index, value));
+        }
+    }
+    buckets.retain(|bucket| !bucket.values.is_empty());
+    Ok(())
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    // JSON lines, ?since=<unix seconds> to only fetch newer records. ?step=<secs> downsamples
+    // the readings instead, and &fill=linear interpolates the steps between two readings at most
+    // max_gap seconds apart (three history intervals by default, a day at most), marking them
+    // synthetic; a step is a week at most.
+    // ?zone= keeps the readings of that zone's sensors, ?fields= the named keys of each line and
+    // ?limit= ends the export after that many lines, finishing the last one's second, so the
+    // next page starts at ?since=<its t + 1>
+    let export_context = context.clone();
+    let export = Api::other("application/x-ndjson").query(&[
+        ("since", "integer"),
+        ("step", "integer"),
+        ("fill", "string"),
+        ("max_gap", "integer"),
+        ("zone", "string"),
+        ("limit", "integer"),
+        ("fields", "string"),
+    ]);
+    http::route(server, "/api/history", Method::Get, export, move |request| {
+        let number = |name| http::query_param(request.uri(), name).and_then(|value| value.parse::<u64>().ok());
+        let query = http::ListQuery::parse(request.uri());
+        let zone: Option<BTreeSet<String>> = query.zone.as_ref().map(|zone| {
+            let registry = export_context.registry.lock().unwrap();
+            registry
+                .sensors()
+                .filter(|(_, info)| info.zone.as_ref() == Some(zone))
+                .map(|(sensor, _)| sensor.clone())
+                .collect()
+        });
+        let in_zone = |values: &mut BTreeMap<String, f32>| match &zone {
+            Some(sensors) => {
+                values.retain(|sensor, _| sensors.contains(sensor));
+                !values.is_empty()
+            }
+            None => true,
+        };
+        if let Some(step) = number("step").filter(|&step| step > 0) {
+            let step = step.min(MAX_STEP_SECS);
+            let max_gap = number("max_gap").unwrap_or(3 * config::HISTORY_INTERVAL_SECS).min(MAX_GAP_SECS);
+            let linear = http::query_param(request.uri(), "fill") == Some("linear");
+            let history = export_context.history.lock().unwrap();
+            let buckets = downsample(&history, query.since, step);
+            drop(history);
+            let buckets = buckets.and_then(|mut buckets| {
+                buckets.retain_mut(|bucket| in_zone(&mut bucket.values));
+                if linear {
+                    fill(&mut buckets, step, max_gap)?;
+                }
+                buckets.truncate(query.limit.unwrap_or(usize::MAX));
+                Ok(buckets)
+            });
+            let buckets = match buckets {
+                Ok(buckets) => buckets,
+                Err(error) if error.kind() == io::ErrorKind::InvalidInput => {
+                    return http::write_error(request, 400, &error.to_string());
+                }
+                Err(error) => return http::write_error(request, 500, &error.to_string()),
+            };
+            let mut response = request.into_response(200, None, &[("Content-Type", "application/x-ndjson")])?;
+            for bucket in buckets {
+                let line = format!("{}\n", query.select(serde_json::json!(bucket)));
+                esp_idf_hal::io::Write::write_all(&mut response, line.as_bytes())?;
+            }
+            return Ok(());
+        }
+        let mut response = request.into_response(200, None, &[("Content-Type", "application/x-ndjson")])?;
+        let (mut sent, mut last) = (0, None);
+        let result = scan_paged(&export_context.history, query.since, |mut record| {
+            if let Record::Readings { values, .. } = &mut record {
+                if !in_zone(values) {
+                    return Ok(ControlFlow::Continue(()));
+                }
+            }
+            let t = record.timestamp();
+            if query.limit.is_some_and(|limit| sent >= limit) && last != Some(t) {
+                return Ok(ControlFlow::Break(()));
+            }
+            (sent, last) = (sent + 1, Some(t));
+            let mut line = serde_json::to_vec(&query.select(serde_json::to_value(&record)?))?;
+            line.push(b'\n');
+            esp_idf_hal::io::Write::write_all(&mut response, &line)
+                .map(|()| ControlFlow::Continue(()))
+                .map_err(|error| io::Error::other(format!("{:?}", error)))
+        });
+        if let Err(error) = result {
+            log::warn!("history export failed: {}", error);
+        }
+        Ok::<(), esp_idf_svc::io::EspIOError>(())
+    })?;
+
+    // the complete log exactly as stored, for tools/verify_history.py when signing is enabled
+    http::route(server, "/api/history/raw", Method::Get, Api::other("application/x-ndjson"), move |request| {
+        let mut response = request.into_response(200, None, &[("Content-Type", "application/x-ndjson")])?;
+        let mut cursor = context.history.lock().unwrap().cursor(0);
+        let result = loop {
+            let bytes = match context.history.lock().unwrap().page_raw(&mut cursor) {
+                Ok(bytes) if bytes.is_empty() => break Ok(()),
+                Ok(bytes) => bytes,
+                Err(error) => break Err(error),
+            };
+            if let Err(error) = esp_idf_hal::io::Write::write_all(&mut response, &bytes) {
+                break Err(io::Error::other(format!("{:?}", error)));
+            }
+        };
+        if let Err(error) = result {
+            log::warn!("raw history export failed: {}", error);
+        }
+        Ok::<(), esp_idf_svc::io::EspIOError>(())
+    })?;
+
+    Ok(())
+}
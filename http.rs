@@ -0,0 +1,416 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use esp_idf_hal::io::{Read, Write};
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::EspIOError;
+use esp_idf_svc::sys::EspError;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::alarms;
+use crate::annotations;
+use crate::build_info;
+use crate::client::HttpClient;
+use crate::clock;
+use crate::coex::Coexistence;
+use crate::commands;
+use crate::compliance_api;
+use crate::config;
+use crate::contacts;
+use crate::counters;
+use crate::cycle::CycleStats;
+use crate::derived;
+use crate::drift;
+use crate::context::Context;
+use crate::events::{Event, Subscription};
+use crate::export;
+use crate::expressions;
+use crate::flash;
+use crate::fleet;
+use crate::floorplan;
+use crate::health;
+use crate::heating;
+use crate::heatmap;
+use crate::history;
+use crate::incubator;
+use crate::lifecycle;
+use crate::modbus;
+use crate::openapi::{self, Api};
+use crate::ow_trace;
+use crate::power;
+use crate::presets;
+use crate::probes;
+use crate::profiles;
+use crate::program;
+use crate::rail::Rail;
+use crate::readings::{Reading, Snapshot};
+use crate::redundancy;
+use crate::registry::SensorInfo;
+use crate::rules;
+use crate::schedule_api;
+use crate::scripting;
+use crate::service;
+use crate::soak;
+use crate::stats;
+use crate::tilt;
+use crate::topology;
+use crate::trend;
+use crate::twin;
+use crate::wifi;
+
+pub type HandlerResult = Result<(), EspIOError>;
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+// every route of the server as registered, with what it takes and answers, for /api/openapi.json
+static ROUTES: Mutex<Vec<(Method, &'static str, Api)>> = Mutex::new(Vec::new());
+
+// server.fn_handler, keeping a record of the route
+pub fn route<F, E>(server: &mut EspHttpServer<'static>, uri: &'static str, method: Method, api: Api, handler: F) -> Result<(), EspError>
+    where
+        F: for<'r> Fn(Request<&mut EspHttpConnection<'r>>) -> Result<(), E> + Send + 'static,
+        E: Debug,
+{
+    server.fn_handler(uri, method, handler)?;
+    ROUTES.lock().unwrap().push((method, uri, api));
+    Ok(())
+}
+
+pub fn routes() -> Vec<(Method, &'static str, Api)> {
+    ROUTES.lock().unwrap().clone()
+}
+
+pub fn write_json(request: Request<&mut EspHttpConnection>, body: &Value) -> HandlerResult {
+    let mut response = request.into_response(200, None, &[("Content-Type", "application/json")])?;
+    response.write_all(body.to_string().as_bytes())?;
+    Ok(())
+}
+
+// strong validator of the readings, unique across reboots (the version starts over at each)
+pub fn readings_etag(context: &Context, version: u64) -> String {
+    format!("\"{}-{}\"", context.counters.get(counters::BOOTS), version)
+}
+
+// the readings with their ETag, or a 304 when the client has them (`known`, its If-None-Match)
+pub fn write_readings(request: Request<&mut EspHttpConnection>, context: &Context, known: Option<&str>, snapshot: &Snapshot) -> HandlerResult {
+    let etag = readings_etag(context, snapshot.version);
+    if known == Some(etag.as_str()) {
+        request.into_response(304, None, &[("ETag", etag.as_str())])?;
+        return Ok(());
+    }
+    let body = readings_json(context, &snapshot.readings);
+    let mut response = request.into_response(200, None, &[("Content-Type", "application/json"), ("ETag", etag.as_str())])?;
+    response.write_all(body.to_string().as_bytes())?;
+    Ok(())
+}
+
+pub fn write_error(request: Request<&mut EspHttpConnection>, status: u16, message: &str) -> HandlerResult {
+    let mut response = request.into_response(status, None, &[("Content-Type", "application/json")])?;
+    response.write_all(json!({ "error": message }).to_string().as_bytes())?;
+    Ok(())
+}
+
+// value of `name` in the query string of `uri`, without percent-decoding
+pub fn query_param<'a>(uri: &'a str, name: &str) -> Option<&'a str> {
+    let (_, query) = uri.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// query values with %XX escapes and + for spaces, as browsers send them
+pub fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+                continue;
+            }
+            (b'+', _) => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+        rest = tail;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// paging and filtering of the list endpoints (/api/sensors, /api/history): ?zone= is a registry
+// zone, ?since= unix seconds, ?offset= and ?limit= count items and ?fields= is a comma-separated
+// list of the keys to keep in each item
+#[derive(Debug, Default)]
+pub struct ListQuery {
+    pub zone: Option<String>,
+    pub since: u64,
+    pub offset: usize,
+    pub limit: Option<usize>,
+    pub fields: Option<Vec<String>>,
+}
+
+impl ListQuery {
+    pub fn parse(uri: &str) -> ListQuery {
+        let number = |name| query_param(uri, name).and_then(|value| value.parse::<usize>().ok());
+        ListQuery {
+            zone: query_param(uri, "zone").map(percent_decode),
+            since: query_param(uri, "since").and_then(|since| since.parse().ok()).unwrap_or(0),
+            offset: number("offset").unwrap_or(0),
+            limit: number("limit"),
+            fields: query_param(uri, "fields").map(|fields| percent_decode(fields).split(',').map(str::to_string).collect()),
+        }
+    }
+
+    pub fn select(&self, item: Value) -> Value {
+        match (&self.fields, item) {
+            (Some(fields), Value::Object(item)) => Value::Object(item.into_iter().filter(|(key, _)| fields.contains(key)).collect()),
+            (_, item) => item,
+        }
+    }
+}
+
+// reads the whole request body, or None if it is larger than `limit`
+pub fn read_body(request: &mut Request<&mut EspHttpConnection>, limit: usize) -> Result<Option<Vec<u8>>, EspIOError> {
+    let mut body = Vec::new();
+    let mut buffer = [0u8; 512];
+    loop {
+        let read = request.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(Some(body));
+        }
+        if body.len() + read > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buffer[..read]);
+    }
+}
+
+// reads and parses a JSON body; the error is the status and message to answer with
+pub fn read_json<T: DeserializeOwned>(
+    request: &mut Request<&mut EspHttpConnection>,
+    limit: usize,
+) -> Result<Result<T, (u16, String)>, EspIOError> {
+    let Some(body) = read_body(request, limit)? else {
+        return Ok(Err((413, "request body too large".to_string())));
+    };
+    Ok(serde_json::from_slice(&body).map_err(|error| (400, error.to_string())))
+}
+
+#[derive(serde::Deserialize)]
+struct SensorUpdate {
+    sensor: String,
+    #[serde(flatten)]
+    info: SensorInfo,
+}
+
+// readings restored from before a reset carry "restored": true until the first conversion
+fn readings_json(context: &Context, readings: &[Reading]) -> Value {
+    let mut readings = json!(readings);
+    if context.restored.lock().unwrap().is_some() {
+        for reading in readings.as_array_mut().into_iter().flatten() {
+            reading["restored"] = json!(true);
+        }
+    }
+    readings
+}
+
+fn reading_example() -> Value {
+    json!({ "sensor": "", "celsius": 0.0, "humidity": 0.0, "restored": true })
+}
+
+fn sensor_example() -> Value {
+    json!({ "sensor": "", "celsius": 0.0, "info": SensorInfo::default() })
+}
+
+fn info_example() -> Value {
+    let mut info = build_info::info_json("");
+    info["gateway"] = json!(false);
+    info["cycle"] = CycleStats::default().to_json();
+    info["subscribers"] = json!([Subscription::example()]);
+    info["healthy"] = json!(true);
+    info["maintenance_mode"] = json!(false);
+    info["wifi"] = json!({
+        "addresses": [""],
+        "prefer": "",
+        "power_save": "",
+        "listen_interval": 0,
+        "mqtt_round_trip": { "samples": 0, "lost": 0, "last_ms": 0, "min_ms": 0, "mean_ms": 0, "max_ms": 0 },
+    });
+    info["one_wire"] = Coexistence::default().to_json();
+    info["sensor_supply"] = Rail::default().to_json();
+    info["http_client"] = HttpClient::default().to_json();
+    info["clock"] = json!({ "synced": true, "source": "" });
+    info["restored"] = json!({ "saved_at": "", "age_secs": 0 });
+    info
+}
+
+fn sensors_json(context: &Context) -> Vec<Value> {
+    let snapshot = context.readings.snapshot();
+    let readings = &snapshot.readings;
+    let registry = context.registry.lock().unwrap();
+
+    // every sensor currently on the bus, plus registered ones that are missing right now
+    let mut sensors: Vec<Value> = readings
+        .iter()
+        .map(|reading| {
+            let info = registry.get(&reading.sensor).cloned().unwrap_or_default();
+            json!({ "sensor": reading.sensor, "celsius": reading.celsius, "info": info })
+        })
+        .collect();
+    sensors.extend(
+        registry
+            .sensors()
+            .filter(|(sensor, _)| !readings.iter().any(|reading| &reading.sensor == *sensor))
+            .map(|(sensor, info)| json!({ "sensor": sensor, "celsius": null, "info": info })),
+    );
+    sensors
+}
+
+pub fn start(context: Arc<Context>) -> Result<EspHttpServer<'static>, EspError> {
+    // the default table of 32 handlers is too small for all the feature modules
+    let mut server = EspHttpServer::new(&Configuration {
+        max_uri_handlers: 96,
+        ..Default::default()
+    })?;
+
+    route(&mut server, "/", Method::Get, Api::other("text/html"), |request| {
+        let mut response = request.into_response(200, None, &[("Content-Type", "text/html")])?;
+        response.write_all(DASHBOARD.as_bytes())?;
+        Ok::<(), EspIOError>(())
+    })?;
+
+    let info_context = context.clone();
+    route(&mut server, "/api/info", Method::Get, Api::json(info_example), move |request| {
+        let mut info = build_info::info_json(&info_context.node_id);
+        info["gateway"] = json!(info_context.gateway);
+        info["cycle"] = info_context.cycle_stats.lock().unwrap().to_json();
+        info["subscribers"] = info_context.events.to_json();
+        info["healthy"] = json!(info_context.health.ok());
+        info["maintenance_mode"] = json!(info_context.service.active());
+        info["wifi"] = json!({
+            "addresses": wifi::addresses(),
+            "prefer": config::NET_PREFER,
+            "power_save": wifi::power_save(),
+            "listen_interval": config::WIFI_LISTEN_INTERVAL,
+            "mqtt_round_trip": info_context.round_trip.lock().unwrap().to_json(),
+        });
+        info["one_wire"] = info_context.coex.to_json();
+        info["sensor_supply"] = info_context.rail.lock().unwrap().to_json();
+        info["http_client"] = info_context.http_client.to_json();
+        info["clock"] = json!({ "synced": clock::is_synced(), "source": clock::source() });
+        info["restored"] = json!(info_context.restored.lock().unwrap().as_ref().map(|restored| restored.to_json()));
+        write_json(request, &info)
+    })?;
+
+    // conditional GET: readings the client has (If-None-Match) are a 304. This server answers
+    // one request at a time, so the long poll is on its own (poll.rs)
+    let temps_context = context.clone();
+    route(&mut server, "/api/temps", Method::Get, Api::json(|| json!([reading_example()])), move |request| {
+        let known = request.header("If-None-Match").map(str::to_string);
+        let snapshot = temps_context.readings.snapshot();
+        write_readings(request, &temps_context, known.as_deref(), &snapshot)
+    })?;
+
+    // on a gateway this includes every peer heard over MQTT, otherwise just this node
+    let nodes_context = context.clone();
+    let nodes = Api::json(|| json!([{ "node": "", "online": true, "last_seen_secs": 0, "readings": [reading_example()] }]));
+    route(&mut server, "/api/nodes", Method::Get, nodes, move |request| {
+        let snapshot = nodes_context.readings.snapshot();
+        write_json(request, &nodes_context.peers.nodes_json(&nodes_context.node_id, &snapshot.readings))
+    })?;
+
+    let sensors_context = context.clone();
+    // ?zone=, ?offset=, ?limit= and ?fields=; X-Total-Count is the number of sensors before paging
+    let sensors = Api::json(|| json!([sensor_example()])).query(&[
+        ("zone", "string"),
+        ("offset", "integer"),
+        ("limit", "integer"),
+        ("fields", "string"),
+    ]);
+    route(&mut server, "/api/sensors", Method::Get, sensors, move |request| {
+        let query = ListQuery::parse(request.uri());
+        let mut sensors = sensors_json(&sensors_context);
+        if let Some(zone) = &query.zone {
+            sensors.retain(|sensor| sensor["info"]["zone"].as_str() == Some(zone.as_str()));
+        }
+        let total = sensors.len().to_string();
+        let page: Vec<Value> = sensors
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|sensor| query.select(sensor))
+            .collect();
+        let headers = [("Content-Type", "application/json"), ("X-Total-Count", total.as_str())];
+        let mut response = request.into_response(200, None, &headers)?;
+        response.write_all(json!(page).to_string().as_bytes())?;
+        Ok::<(), EspIOError>(())
+    })?;
+
+    let update_context = context.clone();
+    let update = Api::json(|| json!([sensor_example()])).body(|| {
+        let mut update = json!(SensorInfo::default());
+        update["sensor"] = json!("");
+        update
+    });
+    route(&mut server, "/api/sensors", Method::Post, update, move |mut request| {
+        let update = match read_json::<SensorUpdate>(&mut request, 1024)? {
+            Ok(update) => update,
+            Err((status, message)) => return write_error(request, status, &message),
+        };
+        let mut registry = update_context.registry.lock().unwrap();
+        registry.update(&update.sensor, update.info);
+        if let Err(error) = registry.save() {
+            return write_error(request, 500, &error.to_string());
+        }
+        drop(registry);
+        update_context.events.publish(Event::ConfigChanged("sensors"));
+        write_json(request, &json!(sensors_json(&update_context)))
+    })?;
+
+    floorplan::register(&mut server)?;
+    heatmap::register(&mut server, context.clone())?;
+    program::register(&mut server, context.clone())?;
+    alarms::register(&mut server, context.clone())?;
+    tilt::register(&mut server, context.clone())?;
+    incubator::register(&mut server, context.clone())?;
+    compliance_api::register(&mut server, context.clone())?;
+    history::register(&mut server, context.clone())?;
+    contacts::register(&mut server, context.clone())?;
+    probes::register(&mut server, context.clone())?;
+    heating::register(&mut server, context.clone())?;
+    derived::register(&mut server, context.clone())?;
+    stats::register(&mut server, context.clone())?;
+    trend::register(&mut server, context.clone())?;
+    annotations::register(&mut server, context.clone())?;
+    redundancy::register(&mut server, context.clone())?;
+    drift::register(&mut server, context.clone())?;
+    topology::register(&mut server, context.clone())?;
+    lifecycle::register(&mut server, context.clone())?;
+    health::register(&mut server, context.clone())?;
+    export::register(&mut server, context.clone())?;
+    modbus::register(&mut server, context.clone())?;
+    soak::register(&mut server, context.clone())?;
+    ow_trace::register(&mut server, context.clone())?;
+    schedule_api::register(&mut server, context.clone())?;
+    fleet::register(&mut server, context.clone())?;
+    twin::register(&mut server, context.clone())?;
+    expressions::register(&mut server, context.clone())?;
+    scripting::register(&mut server, context.clone())?;
+    rules::register(&mut server, context.clone())?;
+    profiles::register(&mut server, context.clone())?;
+    presets::register(&mut server, context.clone())?;
+    counters::register(&mut server, context.clone())?;
+    power::register(&mut server, context.clone())?;
+    flash::register(&mut server, context.clone())?;
+    service::register(&mut server, context.clone())?;
+    wifi::register(&mut server, context.clone())?;
+    commands::register(&mut server, context)?;
+    openapi::register(&mut server)?;
+
+    Ok(server)
+}
@@ -0,0 +1,109 @@
+use embedded_hal::blocking::i2c::WriteRead;
+
+use crate::config;
+
+// A0 and A1 to ground on either chip
+pub const DEFAULT_ADDRESS: u8 = 0x40;
+const INA219_CONFIG_REGISTER: u8 = 0x00;
+// 32 V range, ±320 mV, 12 bits, shunt and bus continuously: what it starts with and is left at
+const INA219_CONFIG_DEFAULT: u16 = 0x399f;
+const INA219_SHUNT_REGISTER: u8 = 0x01;
+const INA219_BUS_REGISTER: u8 = 0x02;
+const INA3221_DIE_ID_REGISTER: u8 = 0xff;
+const INA3221_DIE_ID: u16 = 0x3220;
+// a channel with less than this on its bus input isn't wired
+const UNUSED_VOLTS: f32 = 0.5;
+
+// an INA219, or the three channels of an INA3221, measuring the current into the sensor supply
+// through a shunt of config::SHUNT_MILLIOHMS
+#[derive(Clone, Debug)]
+pub enum Ina {
+    Ina219 { address: u8 },
+    // the channels in use, 1 to 3
+    Ina3221 { address: u8, channels: Vec<u8> },
+}
+
+pub struct Sample {
+    pub channel: String,
+    pub volts: f32,
+    pub milliamps: f32,
+}
+
+fn read_register<I, E>(i2c: &mut I, address: u8, register: u8) -> Result<u16, E>
+    where
+        I: WriteRead<Error=E>,
+{
+    let mut data = [0u8; 2];
+    i2c.write_read(address, &[register], &mut data)?;
+    Ok(u16::from_be_bytes(data))
+}
+
+fn milliamps(shunt_millivolts: f32) -> f32 {
+    shunt_millivolts * 1000.0 / config::SHUNT_MILLIOHMS as f32
+}
+
+impl Ina {
+    // the INA3221 has a die ID to tell it by, the INA219 only its power-on configuration
+    pub fn detect<I, E>(i2c: &mut I, address: u8) -> Option<Self>
+        where
+            I: WriteRead<Error=E>,
+    {
+        if read_register(i2c, address, INA3221_DIE_ID_REGISTER).ok() == Some(INA3221_DIE_ID) {
+            let samples = Ina::Ina3221 { address, channels: vec![1, 2, 3] }.read(i2c).ok()?;
+            let channels: Vec<u8> =
+                samples.iter().zip(1..).filter(|(sample, _)| sample.volts >= UNUSED_VOLTS).map(|(_, channel)| channel).collect();
+            return (!channels.is_empty()).then_some(Ina::Ina3221 { address, channels });
+        }
+        if read_register(i2c, address, INA219_CONFIG_REGISTER).ok() == Some(INA219_CONFIG_DEFAULT) {
+            return Some(Ina::Ina219 { address });
+        }
+        None
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ina::Ina219 { .. } => "INA219",
+            Ina::Ina3221 { .. } => "INA3221",
+        }
+    }
+
+    pub fn channels(&self) -> Vec<String> {
+        match self {
+            Ina::Ina219 { .. } => vec!["ina219".to_string()],
+            Ina::Ina3221 { channels, .. } => channels.iter().map(|channel| format!("ina3221_ch{}", channel)).collect(),
+        }
+    }
+
+    pub fn read<I, E>(&self, i2c: &mut I) -> Result<Vec<Sample>, E>
+        where
+            I: WriteRead<Error=E>,
+    {
+        match self {
+            Ina::Ina219 { address } => {
+                // shunt: 10 µV a step; bus: bits 15..3, 4 mV a step
+                let shunt = read_register(i2c, *address, INA219_SHUNT_REGISTER)? as i16;
+                let bus = read_register(i2c, *address, INA219_BUS_REGISTER)?;
+                Ok(vec![Sample {
+                    channel: "ina219".to_string(),
+                    volts: f32::from(bus >> 3) * 0.004,
+                    milliamps: milliamps(f32::from(shunt) * 0.01),
+                }])
+            }
+            Ina::Ina3221 { address, channels } => {
+                // each channel a shunt and a bus register, both bits 15..3: 40 µV and 8 mV a step
+                let mut samples = Vec::new();
+                for &channel in channels {
+                    let register = (channel - 1) * 2 + 1;
+                    let shunt = (read_register(i2c, *address, register)? as i16) >> 3;
+                    let bus = read_register(i2c, *address, register + 1)? >> 3;
+                    samples.push(Sample {
+                        channel: format!("ina3221_ch{}", channel),
+                        volts: f32::from(bus) * 0.008,
+                        milliamps: milliamps(f32::from(shunt) * 0.04),
+                    });
+                }
+                Ok(samples)
+            }
+        }
+    }
+}
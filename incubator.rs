@@ -0,0 +1,200 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::v2::OutputPin;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::alarms::BandWatch;
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::pid::Pid;
+use crate::pwm::TimeProportional;
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+const SETTINGS_FILE: &str = "incubator.json";
+const TEMPERATURE_ALARM: &str = "incubator_temperature";
+const HUMIDITY_ALARM: &str = "incubator_humidity";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // temperature probe, defaults to the humidity sensor's own temperature
+    pub sensor: Option<String>,
+    pub celsius: Celsius,
+    pub humidity: f32,
+    // the humidifier switches on below humidity - hysteresis and off again at the target
+    pub humidity_hysteresis: f32,
+    // safe band around each target, alarms fire after being outside for alarm_minutes
+    pub celsius_band: Degrees,
+    pub humidity_band: f32,
+    pub alarm_minutes: u32,
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl Default for Settings {
+    // typical for chicken eggs
+    fn default() -> Self {
+        Self {
+            sensor: None,
+            celsius: Celsius(37.5),
+            humidity: 55.0,
+            humidity_hysteresis: 3.0,
+            celsius_band: Degrees(0.5),
+            humidity_band: 10.0,
+            alarm_minutes: 15,
+            kp: 0.5,
+            ki: 0.002,
+            kd: 10.0,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct Status {
+    pub celsius: Option<Celsius>,
+    pub humidity: Option<f32>,
+    pub heater_duty: f32,
+    pub humidifying: bool,
+}
+
+#[derive(Default)]
+pub struct IncubatorState {
+    pub settings: Settings,
+    pub status: Status,
+}
+
+impl IncubatorState {
+    pub fn load() -> Self {
+        Self {
+            settings: storage::read_json(SETTINGS_FILE).unwrap_or_default(),
+            status: Status::default(),
+        }
+    }
+}
+
+pub struct Incubator<H> {
+    pid: Pid,
+    heater: TimeProportional,
+    humidifier: H,
+    humidifying: bool,
+    temperature_watch: BandWatch,
+    humidity_watch: BandWatch,
+    last_update: Option<Instant>,
+}
+
+impl<H: OutputPin> Incubator<H> {
+    pub fn new(heater: TimeProportional, mut humidifier: H) -> Self {
+        let _ = humidifier.set_low();
+        let defaults = Settings::default();
+        Self {
+            pid: Pid::new(defaults.kp, defaults.ki, defaults.kd),
+            heater,
+            humidifier,
+            humidifying: false,
+            temperature_watch: BandWatch::default(),
+            humidity_watch: BandWatch::default(),
+            last_update: None,
+        }
+    }
+
+    pub fn update(&mut self, context: &Context, readings: &[Reading]) {
+        let dt = self.last_update.map_or(0.0, |last| last.elapsed().as_secs_f32());
+        self.last_update = Some(Instant::now());
+        let settings = context.incubator.lock().unwrap().settings.clone();
+
+        let humidity_reading = readings.iter().find(|reading| reading.humidity.is_some());
+        let humidity = humidity_reading.and_then(|reading| reading.humidity);
+        let celsius = match &settings.sensor {
+            Some(sensor) => readings.iter().find(|reading| &reading.sensor == sensor),
+            None => humidity_reading,
+        }
+        .map(|reading| reading.celsius);
+
+        (self.pid.kp, self.pid.ki, self.pid.kd) = (settings.kp, settings.ki, settings.kd);
+        let heater_duty = match celsius {
+            Some(celsius) => self.pid.update(settings.celsius, celsius, dt),
+            None => {
+                self.pid.reset();
+                0.0
+            }
+        };
+        self.heater.set(heater_duty);
+
+        self.humidifying = match humidity {
+            Some(humidity) if humidity < settings.humidity - settings.humidity_hysteresis => true,
+            Some(humidity) if humidity >= settings.humidity => false,
+            Some(_) => self.humidifying,
+            None => false,
+        };
+        let _ = if self.humidifying { self.humidifier.set_high() } else { self.humidifier.set_low() };
+
+        let delay = Duration::from_secs(u64::from(settings.alarm_minutes) * 60);
+        let (low, high) = (settings.celsius - settings.celsius_band, settings.celsius + settings.celsius_band);
+        if self.temperature_watch.update(celsius, low, high, delay) {
+            context.alarms.raise(
+                TEMPERATURE_ALARM,
+                format!("incubator temperature outside {:.1}..{:.1}", low, high),
+            );
+        } else {
+            context.alarms.clear(TEMPERATURE_ALARM);
+        }
+        let (low, high) = (settings.humidity - settings.humidity_band, settings.humidity + settings.humidity_band);
+        if self.humidity_watch.update(humidity, low, high, delay) {
+            context.alarms.raise(HUMIDITY_ALARM, format!("incubator humidity outside {:.0}..{:.0} %", low, high));
+        } else {
+            context.alarms.clear(HUMIDITY_ALARM);
+        }
+
+        context.incubator.lock().unwrap().status = Status {
+            celsius,
+            humidity,
+            heater_duty,
+            humidifying: self.humidifying,
+        };
+    }
+}
+
+fn settings_example() -> serde_json::Value {
+    json!(Settings { sensor: Some(String::new()), ..Default::default() })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| {
+        let status = Status { celsius: Some(Celsius(0.0)), humidity: Some(0.0), heater_duty: 0.0, humidifying: false };
+        json!({ "settings": settings_example(), "status": status })
+    });
+    http::route(server, "/api/incubator", Method::Get, status, move |request| {
+        let state = status_context.incubator.lock().unwrap();
+        let body = json!({ "settings": state.settings, "status": state.status });
+        drop(state);
+        http::write_json(request, &body)
+    })?;
+
+    let settings_context = context;
+    let settings = Api::json(settings_example).body(settings_example);
+    http::route(server, "/api/incubator", Method::Post, settings, move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 1024)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        if let Err(error) = storage::write_json(SETTINGS_FILE, &settings) {
+            return http::write_error(request, 500, &error.to_string());
+        }
+        settings_context.incubator.lock().unwrap().settings = settings.clone();
+        settings_context.events.publish(Event::ConfigChanged("incubator"));
+        http::write_json(request, &json!(settings))
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+
+use crate::config;
+use crate::context::Context;
+use crate::events::Policy;
+use crate::export::{Exporter, Measurement, Rejected};
+
+// InfluxDB v2 write API, one point per sensor and cycle in line protocol
+pub struct Influx {
+    context: Arc<Context>,
+    authorization: Option<String>,
+}
+
+impl Influx {
+    pub fn new(context: Arc<Context>) -> Self {
+        Self {
+            context,
+            authorization: (!config::INFLUX_TOKEN.is_empty()).then(|| format!("Token {}", config::INFLUX_TOKEN)),
+        }
+    }
+
+    // tag values can't contain unescaped commas, spaces or equals signs
+    fn tag(value: &str) -> String {
+        value.replace(',', "\\,").replace(' ', "\\ ").replace('=', "\\=")
+    }
+
+    fn body(&self, batch: &[Measurement]) -> String {
+        let mut body = String::new();
+        // restored readings were already sent before the reset
+        for measurement in batch.iter().filter(|measurement| !measurement.restored) {
+            for reading in &measurement.readings {
+                let (node, sensor) = (Self::tag(&self.context.node_id), Self::tag(&reading.sensor));
+                let _ = write!(body, "temperature,node={},sensor={} celsius={}", node, sensor, reading.celsius.0);
+                if let Some(humidity) = reading.humidity {
+                    let _ = write!(body, ",humidity={}", humidity);
+                }
+                if let Some(location) = measurement.location {
+                    let _ = write!(body, ",lat={},lon={}", location.lat, location.lon);
+                }
+                // seconds, the write URL needs precision=s
+                let _ = writeln!(body, " {}", measurement.t);
+            }
+        }
+        body
+    }
+}
+
+pub fn exporter(context: &Arc<Context>) -> Result<Option<Box<dyn Exporter>>, EspError> {
+    if config::INFLUX_URL.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Box::new(Influx::new(context.clone()))))
+}
+
+impl Exporter for Influx {
+    fn name(&self) -> &'static str {
+        "influx"
+    }
+
+    // about an hour of 30 s samples
+    fn capacity(&self) -> usize {
+        120
+    }
+
+    // the series stays gap-free up to where the queue filled rather than losing its start
+    fn policy(&self) -> Policy {
+        Policy::DropNewest
+    }
+
+    fn backfills(&self) -> bool {
+        true
+    }
+
+    fn connected(&self) -> Option<bool> {
+        Some(self.context.http_client.connected(config::INFLUX_URL))
+    }
+
+    fn export(&mut self, batch: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        let body = self.body(batch);
+        if body.is_empty() {
+            return Ok(());
+        }
+        let length = body.len().to_string();
+        let mut headers = vec![("Content-Type", "text/plain; charset=utf-8"), ("Content-Length", length.as_str())];
+        if let Some(authorization) = &self.authorization {
+            headers.push(("Authorization", authorization.as_str()));
+        }
+
+        let response = self.context.http_client.request(Method::Post, config::INFLUX_URL, &headers, body.as_bytes())?;
+        let message = format!("InfluxDB answered {}: {}", response.status, String::from_utf8_lossy(&response.body));
+        match response.status {
+            200..=299 => Ok(()),
+            // credentials and rate limits aren't about the batch, it goes through once they're sorted
+            401 | 403 | 429 => Err(message.into()),
+            // the body says which line it didn't like; sending it again won't change that
+            400..=499 => Err(Box::new(Rejected(message))),
+            _ => Err(message.into()),
+        }
+    }
+}
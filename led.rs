@@ -0,0 +1,53 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::v2::OutputPin;
+use esp_idf_hal::delay::FreeRtos;
+
+use crate::context::Context;
+use crate::lifecycle::State;
+
+const STEP_MS: u32 = 250;
+// the identify blink, faster than any status pattern so it can't be mistaken for one
+const IDENTIFY_STEP_MS: u32 = 100;
+
+// until when the identify command blinks the LED
+static IDENTIFY_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+
+// blinks the LED for `duration` to find the device among others
+pub fn identify(duration: Duration) {
+    *IDENTIFY_UNTIL.lock().unwrap() = Some(Instant::now() + duration);
+}
+
+fn identifying() -> bool {
+    IDENTIFY_UNTIL.lock().unwrap().is_some_and(|until| Instant::now() < until)
+}
+
+// the on-board LED (GPIO2 on most devkits) shows what the device is waiting for: off while
+// running normally, fast blinking while no sensors answer, slow blinking in safe mode and
+// steady on while starting up; the identify command overrides it with a quick blink
+pub fn start<P: OutputPin + Send + 'static>(context: Arc<Context>, mut pin: P) {
+    let spawned = thread::Builder::new()
+        .name("led".into())
+        .stack_size(2048)
+        .spawn(move || {
+            let mut step: u32 = 0;
+            loop {
+                let identifying = identifying();
+                let on = match context.lifecycle.state() {
+                    _ if identifying => step % 2 == 0,
+                    State::Running => false,
+                    State::WaitingForSensors => step % 2 == 0,
+                    State::SafeMode => step % 8 < 4,
+                    _ => true,
+                };
+                let _ = if on { pin.set_high() } else { pin.set_low() };
+                step = step.wrapping_add(1);
+                FreeRtos::delay_ms(if identifying { IDENTIFY_STEP_MS } else { STEP_MS });
+            }
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the status LED: {}", error);
+    }
+}
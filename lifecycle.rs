@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::{esp_timer_get_time, EspError};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+
+// the transitions kept for /api/state, enough to see how the device got where it is
+const KEEP_TRANSITIONS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Boot,
+    // checking for Wi-Fi credentials, without them the device keeps reporting over serial
+    Provisioning,
+    Connecting,
+    Running,
+    // running, but no sensors answered the last search of the 1-Wire bus
+    WaitingForSensors,
+    // sampling, alarms and control keep running but the network side failed to start
+    SafeMode,
+    // entered by firmware updates, which don't exist yet
+    #[allow(dead_code)]
+    Updating,
+}
+
+impl State {
+    fn can_become(self, next: State) -> bool {
+        use State::*;
+        matches!(
+            (self, next),
+            (Boot, Provisioning | Connecting | SafeMode)
+                | (Provisioning, Connecting | Running | SafeMode)
+                | (Connecting, Running | SafeMode)
+                | (Running, Connecting | WaitingForSensors | SafeMode | Updating)
+                | (WaitingForSensors, Running | SafeMode)
+                | (SafeMode, Running | Updating)
+                | (Updating, Running | SafeMode)
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct Transition {
+    from: State,
+    to: State,
+    reason: String,
+    uptime_secs: u64,
+}
+
+struct Inner {
+    state: State,
+    since: Instant,
+    transitions: VecDeque<Transition>,
+    // ms since the chip started when each startup step first completed
+    milestones: Vec<(String, u64)>,
+    // what keeps the device in safe mode, e.g. "Wi-Fi" or "the HTTP server", with the error
+    failed: Vec<(String, String)>,
+}
+
+// where the device is in its startup sequence; every change goes through `enter`, so a startup
+// ordering problem shows up as a refused transition in the log
+pub struct Lifecycle {
+    boot: Instant,
+    inner: Mutex<Inner>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            boot: now,
+            inner: Mutex::new(Inner {
+                state: State::Boot,
+                since: now,
+                transitions: VecDeque::new(),
+                milestones: Vec::new(),
+                failed: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl Lifecycle {
+    pub fn state(&self) -> State {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.boot.elapsed()
+    }
+
+    // false, and nothing changes, when the current state can't lead to `next`
+    pub fn enter(&self, next: State, reason: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let from = inner.state;
+        if from == next {
+            return true;
+        }
+        if !from.can_become(next) {
+            log::warn!("refused lifecycle transition {:?} -> {:?} ({})", from, next, reason);
+            return false;
+        }
+        log::info!("lifecycle {:?} -> {:?}: {}", from, next, reason);
+        inner.state = next;
+        inner.since = Instant::now();
+        if inner.transitions.len() >= KEEP_TRANSITIONS {
+            inner.transitions.pop_front();
+        }
+        inner.transitions.push_back(Transition {
+            from,
+            to: next,
+            reason: reason.to_string(),
+            uptime_secs: self.boot.elapsed().as_secs(),
+        });
+        true
+    }
+
+    // a part of the network side that failed to start: the device stays in safe mode until every
+    // failed part has recovered, and /api/state lists them
+    pub fn fail(&self, what: &str, error: &str) {
+        let reason = format!("{} failed: {}", what, error);
+        log::error!("{}", reason);
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.failed.retain(|(failed, _)| failed != what);
+            inner.failed.push((what.to_string(), error.to_string()));
+        }
+        self.enter(State::SafeMode, &reason);
+    }
+
+    pub fn recovered(&self, what: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.failed.len();
+        inner.failed.retain(|(failed, _)| failed != what);
+        let cleared = inner.failed.is_empty() && inner.failed.len() < before;
+        drop(inner);
+        if cleared && self.state() == State::SafeMode {
+            self.enter(State::Running, &format!("{} recovered", what));
+        }
+    }
+
+    // only the first time counts, so it can be called from every cycle or export; the clock
+    // starts with the chip, so after a deep sleep wake this is the time since the wake
+    pub fn milestone(&self, name: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.milestones.iter().any(|(reached, _)| reached == name) {
+            return;
+        }
+        let ms = unsafe { esp_timer_get_time() } as u64 / 1000;
+        log::info!("startup: {} at {} ms", name, ms);
+        inner.milestones.push((name.to_string(), ms));
+    }
+
+    pub fn to_json(&self) -> Value {
+        let inner = self.inner.lock().unwrap();
+        let startup: Map<String, Value> = inner.milestones.iter().map(|(name, ms)| (name.clone(), json!(ms))).collect();
+        let failed: Map<String, Value> = inner.failed.iter().map(|(what, error)| (what.clone(), json!(error))).collect();
+        json!({
+            "state": inner.state,
+            "since_secs": inner.since.elapsed().as_secs(),
+            "uptime_secs": self.uptime().as_secs(),
+            "transitions": inner.transitions,
+            "startup_ms": startup,
+            "failed": failed,
+        })
+    }
+}
+
+// one transition, one milestone and one failure, for the API description
+fn state_example() -> Value {
+    let lifecycle = Lifecycle::default();
+    let mut inner = lifecycle.inner.lock().unwrap();
+    inner.transitions.push_back(Transition { from: State::Boot, to: State::Connecting, reason: String::new(), uptime_secs: 0 });
+    inner.milestones.push((String::new(), 0));
+    inner.failed.push((String::new(), String::new()));
+    drop(inner);
+    lifecycle.to_json()
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    http::route(server, "/api/state", Method::Get, Api::json(state_example), move |request| {
+        http::write_json(request, &context.lifecycle.to_json())
+    })?;
+
+    Ok(())
+}
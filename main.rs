@@ -1,112 +1,711 @@
 use embedded_hal::digital::v2::{OutputPin, InputPin};
 use embedded_hal::blocking::delay::{DelayUs, DelayMs};
-use esp_idf_hal::gpio::PinDriver;
-use esp_idf_hal::delay::Ets;
+use esp_idf_hal::adc::attenuation::DB_11;
+use esp_idf_hal::adc::oneshot::config::AdcChannelConfig;
+use esp_idf_hal::adc::oneshot::{AdcChannelDriver, AdcDriver};
+use esp_idf_hal::gpio::{AnyIOPin, IOPin, PinDriver, Pull};
+use esp_idf_hal::i2c::{I2cConfig, I2cDriver};
+use esp_idf_hal::uart::config::Config as UartConfig;
+use esp_idf_hal::uart::UartDriver;
+use esp_idf_hal::units::{FromValueType, Hertz};
+use esp_idf_hal::delay::{Ets, FreeRtos};
 use esp_idf_hal::prelude::Peripherals;
-use esp_idf_hal::io::Write;
-use esp_idf_hal::sys::link_patches;
+use esp_idf_hal::sys::{esp_deep_sleep, link_patches, EspError};
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::log::EspLogger;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sntp::SyncStatus;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use one_wire_bus::{OneWire, OneWireError, OneWireResult};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use one_wire_bus::{Address, OneWire, OneWireError, OneWireResult};
 use ds18b20::Resolution;
 use ds18b20::Ds18b20;
 
+mod alarms;
+mod annotations;
+mod build_info;
+mod client;
+mod clock;
+mod coex;
+mod commands;
+mod compliance;
+mod compliance_api;
+mod config;
+mod contacts;
+mod counters;
+mod context;
+mod cycle;
+mod degree_days;
+mod derived;
+mod display;
+mod drift;
+mod ds3231;
+mod eeprom;
+mod events;
+mod export;
+mod expressions;
+mod fermentation;
+mod flash;
+mod fleet;
+mod gps;
+mod grpc;
+mod floorplan;
+mod health;
+mod heating;
+mod heatmap;
+mod history;
+mod http;
+mod ina;
+mod incubator;
+mod influx;
+mod led;
+mod lifecycle;
+mod maintenance;
+mod modbus;
+mod mqtt;
+mod mux;
+mod openapi;
+mod output;
+mod ow_trace;
+mod peers;
+mod power;
+mod presets;
+mod pid;
+mod poll;
+mod probes;
+mod profiles;
+mod program;
+mod pwm;
+mod rail;
+mod readings;
+mod redundancy;
+mod registry;
+mod remote;
+mod roughtime;
+mod rules;
+mod runner;
+mod scan;
+mod schedule;
+mod schedule_api;
+mod scripting;
+mod service;
+mod shadow;
+mod sht31;
+mod signing;
+mod soak;
+mod ssd1306;
+mod stats;
+mod storage;
+mod systime;
+mod thermostat;
+mod tilt;
+mod topology;
+mod trend;
+mod twin;
+mod units;
+mod wifi;
+
+use context::Context;
+use lifecycle::State;
+use output::Output;
+use readings::Reading;
+use registry::Target;
+use units::Celsius;
+
 fn get_temperature<P, E>(
     delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
-    tx: &mut impl Write,
+    tx: &mut Output<impl std::io::Write>,
     one_wire_bus: &mut OneWire<P>,
-) -> OneWireResult<(), E>
+    addresses: &[Address],
+    limits: &BTreeMap<String, eeprom::Limits>,
+    converting_since: Option<Instant>,
+    mid_conversion: &mut impl FnMut(),
+) -> OneWireResult<Vec<Reading>, E>
     where
         P: OutputPin<Error=E> + InputPin<Error=E>,
         E: Debug
 {
-    // initiate a temperature measurement for all connected devices
-    ds18b20::start_simultaneous_temp_measurement(one_wire_bus, delay)?;
+    // a conversion started at boot only has to be waited out; one from long ago (Wi-Fi took
+    // its time) is stale and done again
+    match converting_since.filter(|since| since.elapsed() < Duration::from_secs(10)) {
+        Some(since) => {
+            let conversion = Duration::from_millis(u64::from(Resolution::Bits12.max_measurement_time_millis()));
+            // the supply is only worth measuring while the sensors still convert
+            if since.elapsed() < conversion {
+                mid_conversion();
+            }
+            FreeRtos::delay_ms(conversion.saturating_sub(since.elapsed()).as_millis() as u32);
+        }
+        None => {
+            // initiate a temperature measurement for all connected devices
+            ds18b20::start_simultaneous_temp_measurement(one_wire_bus, delay)?;
 
-    // wait until the measurement is done. This depends on the resolution you specified
-    // If you don't know the resolution, you can obtain it from reading the sensor data,
-    // or just wait the longest time, which is the 12-bit resolution (750ms)
-    Resolution::Bits12.delay_for_measurement_time(delay);
+            // wait until the measurement is done. This depends on the resolution you specified
+            // If you don't know the resolution, you can obtain it from reading the sensor data,
+            // or just wait the longest time, which is the 12-bit resolution (750ms); halfway
+            // through, while the sensors draw their conversion current, the supply is measured
+            let conversion = Resolution::Bits12.max_measurement_time_millis();
+            delay.delay_ms(conversion / 2);
+            mid_conversion();
+            delay.delay_ms(conversion - conversion / 2);
+        }
+    }
 
-    // iterate over all the devices, and report their temperature
-    let mut search_state = None;
-    loop {
-        if let Some((device_address, state)) = one_wire_bus.device_search(search_state.as_ref(), false, delay)? {
-            search_state = Some(state);
-            if device_address.family_code() != ds18b20::FAMILY_CODE {
-                // skip other devices
+    // read every device found by the last scan, and report their temperature
+    let mut readings = Vec::new();
+    for &device_address in addresses {
+        let sensor = Ds18b20::new(device_address)?;
+
+        // contains the read temperature, as well as config info such as the resolution used;
+        // a sensor that doesn't answer is just missing from this cycle
+        let sensor_data = match sensor.read_data(one_wire_bus, delay) {
+            Ok(sensor_data) => sensor_data,
+            Err(error) => {
+                writeln!(tx, "Device at {:?} didn't answer: {:?}", device_address, error);
                 continue;
             }
-            // You will generally create the sensor once, and save it for later
-            let sensor = Ds18b20::new(device_address)?;
+        };
+        writeln!(tx, "Device at {:?} is {}°C", device_address, sensor_data.temperature);
+        let sensor_id = readings::sensor_id(&device_address);
 
-            // contains the read temperature, as well as config info such as the resolution used
-            let sensor_data = sensor.read_data(one_wire_bus, delay)?;
-            writeln!(tx, "Device at {:?} is {}°C", device_address, sensor_data.temperature);
-        } else {
-            break;
+        // keep the TL/TH alarm bytes in EEPROM of registered sensors in line with their thresholds
+        if let Some(&wanted) = limits.get(&sensor_id) {
+            match eeprom::sync(&sensor, &sensor_data, wanted, one_wire_bus, delay) {
+                Ok(true) => {
+                    writeln!(tx, "Device at {:?} alarm limits set to {}..{}°C", device_address, wanted.low, wanted.high);
+                }
+                Ok(false) => {}
+                Err(error) => {
+                    writeln!(tx, "Device at {:?} alarm limits not saved: {:?}", device_address, error);
+                }
+            }
         }
+        readings.push(Reading {
+            sensor: sensor_id,
+            celsius: Celsius(sensor_data.temperature),
+            humidity: None,
+        });
     }
-    Ok(())
+    Ok(readings)
 }
 
-fn test_config<P, E>(
-    delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
-    tx: &mut impl Write,
-    one_wire_bus: &mut OneWire<P>,
-) -> OneWireResult<(), E>
-    where
-        P: OutputPin<Error=E> + InputPin<Error=E>,
-        E: Debug
-{
+// a network service that fails to start is left out and the device goes to safe mode; the rest,
+// and sampling, go on
+fn started<T>(context: &Context, name: &str, result: Result<T, EspError>) -> Option<T> {
+    result.map_err(|error| context.lifecycle.fail(name, &error.to_string())).ok()
+}
 
-    // Find the first device on the bus (assuming they are all Ds18b20's)
-    if let Some(device_address) = one_wire_bus.devices(false, delay).next() {
-        let device_address = device_address?;
-        let device = Ds18b20::new(device_address)?;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    link_patches();
+    EspLogger::initialize_default();
 
-        // read the initial config values (read from EEPROM by the device when it was first powered)
-        let initial_data = device.read_data(one_wire_bus, delay)?;
-        writeln!(tx, "Initial data: {:?}", initial_data);
+    let peripherals = Peripherals::take().unwrap();
+    let pins = peripherals.pins;
+    let sysloop = EspSystemEventLoop::take()?;
+    let nvs = EspDefaultNvsPartition::take()?;
 
-        let resolution = initial_data.resolution;
+    let mut delay = Ets;
 
-        // set new alarm values, but keep the resolution the same
-        device.set_config(18, 24, resolution, one_wire_bus, delay)?;
+    // recorded for /api/debug/ow-trace while a trace is on
+    let mut pin = ow_trace::Traced(PinDriver::input_output(pins.gpio4)?);
+    let mut one_wire_bus = OneWire::new(pin)?;
+    // with MUX_CHANNELS a 74HC4051 puts one sensor cable at a time on GPIO4
+    let mux_select = if config::MUX_CHANNELS > 0 {
+        vec![
+            PinDriver::output(pins.gpio5.downgrade())?,
+            PinDriver::output(pins.gpio13.downgrade())?,
+            PinDriver::output(pins.gpio14.downgrade())?,
+        ]
+    } else {
+        Vec::new()
+    };
+    let mut mux = mux::Mux::new(mux_select, config::MUX_CHANNELS.min(8) as u8);
 
-        // confirm the new config is now in the scratchpad memory
-        let new_data = device.read_data(one_wire_bus, delay)?;
-        writeln!(tx, "New data: {:?}", new_data);
+    // with the addresses from the last boot the first conversion can start right away and run
+    // while the settings load and Wi-Fi comes up
+    let mut scan_cache = scan::ScanCache::load(nvs.clone())?;
+    let mut converting_since = None;
+    if !scan_cache.addresses().is_empty()
+        && mux::start_conversions(&mut mux, &mut one_wire_bus, &mut delay, &scan_cache.groups()).is_ok()
+    {
+        converting_since = Some(Instant::now());
+    }
 
-        // save the config to EEPROM to save it permanently
-        device.save_to_eeprom(one_wire_bus, delay)?;
+    flash::mount()?;
 
-        // read the values from EEPROM back to the scratchpad to verify it was saved correctly
-        device.recall_from_eeprom(one_wire_bus, delay)?;
-        let eeprom_data = device.read_data(one_wire_bus, delay)?;
-        writeln!(tx, "EEPROM data: {:?}", eeprom_data);
+    let node_id = wifi::node_id()?;
+    let device_key = signing::DeviceKey::load_or_create(nvs.clone())?;
+    let counters = counters::Counters::load(nvs.clone())?;
+    let context = Context::new(node_id, config::is_gateway(), device_key, counters);
+    // written right away, a boot loop would never reach the first flush
+    context.counters.add(counters::BOOTS, 1);
+    if let Some(counter) = context.power.lock().unwrap().reset_counter() {
+        context.counters.add(counter, 1);
+    }
+    context.counters.flush();
+    counters::start(context.clone());
+    maintenance::start(context.clone());
+    context.lifecycle.milestone("context");
+    let mut tx = output::serial(context.clone());
+    for line in build_info::banner(&context.node_id, scan_cache.addresses().len()) {
+        writeln!(tx, "{}", line);
+    }
+    commands::start_console(context.clone());
+    // what the sensors read before a software reset, until the first conversion is done
+    if let Some((readings, restored)) = shadow::restore() {
+        writeln!(tx, "Restored {} readings from before the reset", readings.len());
+        context.readings.publish(readings);
+        *context.restored.lock().unwrap() = Some(restored);
     }
-    Ok(())
-}
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    link_patches();
+    // an SHT31 on the default I2C pins adds temperature and humidity, if it answers at boot; an
+    // SSD1306 display on the same bus is driven from its own thread, from before the network
+    // comes up so it shows the connection too
+    let i2c = Arc::new(Mutex::new(I2cDriver::new(
+        peripherals.i2c0,
+        pins.gpio21,
+        pins.gpio22,
+        &I2cConfig::new().baudrate(100.kHz().into()),
+    )?));
+    let sht31 = Some(sht31::Sht31::new(sht31::DEFAULT_ADDRESS))
+        .filter(|sht31| sht31.measure(&mut *i2c.lock().unwrap(), &mut delay).is_ok());
+    // a DS3231 on the same bus keeps the time through power cuts and outages: it sets the clock
+    // until SNTP does, and every SNTP sync sets it in turn
+    let rtc = Some(ds3231::Ds3231).filter(|rtc| rtc.temperature(&mut *i2c.lock().unwrap()).is_ok());
+    if let Some(rtc) = &rtc {
+        match rtc.read_time(&mut *i2c.lock().unwrap()) {
+            Ok(Some(unix)) => {
+                systime::set_unix(unix, "rtc");
+                writeln!(tx, "Clock set from the RTC to {}", clock::format_iso8601(unix));
+            }
+            Ok(None) => {
+                writeln!(tx, "The RTC lost the time, waiting for SNTP");
+            }
+            Err(error) => {
+                writeln!(tx, "RTC read failed: {:?}", error);
+            }
+        }
+    }
+    // an INA219 or INA3221 on the same bus measures the sensor supply during each conversion
+    let ina = ina::Ina::detect(&mut *i2c.lock().unwrap(), ina::DEFAULT_ADDRESS);
+    if let Some(ina) = &ina {
+        context.rail.lock().unwrap().start(ina);
+        writeln!(tx, "Sensor supply measured by an {} on {}", ina.name(), ina.channels().join(", "));
+    }
+    let mut display_button = PinDriver::input(pins.gpio0)?;
+    display_button.set_pull(Pull::Up)?;
+    // without a display the button is only there for maintenance mode
+    if let Some(button) = display::start(context.clone(), i2c.clone(), display_button) {
+        service::watch_button(context.clone(), button);
+    }
 
-    let peripherals = Peripherals::take().unwrap();
-    let pins = peripherals.pins;
+    // the 1-Wire readings of the conversion started at boot are taken before the network comes
+    // up: they go out as soon as an exporter is running, and are the first cycle's readings
+    let mut first_reading = None;
+    if converting_since.is_some() {
+        let limits = eeprom::wanted(&context.registry.lock().unwrap());
+        let mut readings = Vec::new();
+        for (channel, addresses) in &scan_cache.groups() {
+            mux.select(*channel, &mut delay);
+            match get_temperature(&mut delay, &mut tx, &mut one_wire_bus, addresses, &limits, converting_since, &mut || {}) {
+                Ok(read) => readings.extend(read),
+                Err(error) => {
+                    writeln!(tx, "First read on {} failed: {:?}", mux::bus_name(*channel), error);
+                }
+            }
+        }
+        converting_since = None;
+        if !readings.is_empty() {
+            let registry = context.registry.lock().unwrap();
+            let (shown, exported) = (registry.adjusted(&readings, Target::Api), registry.adjusted(&readings, Target::Export));
+            drop(registry);
+            context.set_readings(shown);
+            context.lifecycle.milestone("first_reading");
+            first_reading = Some((readings, exported));
+        }
+    }
 
-    let mut delay = Ets;
-    let mut tx = std::io::stdout();
+    // without Wi-Fi credentials the firmware only reports over serial
+    let online = !config::WIFI_SSID.is_empty();
+    if online {
+        context.lifecycle.enter(State::Connecting, "joining Wi-Fi");
+    } else {
+        context.lifecycle.enter(State::Provisioning, "no Wi-Fi credentials");
+    }
+    // a network that doesn't come up shouldn't stop the sensors from being watched: the
+    // driver is kept and the loop tries again, the services start anyway and serve once it
+    // is up
+    let mut wifi_retry_at: Option<u64> = None;
+    let mut wifi = if online {
+        match wifi::connect(peripherals.modem, sysloop, nvs) {
+            Ok((wifi, Ok(()))) => {
+                context.lifecycle.milestone("wifi");
+                context.events.publish(events::Event::Network(events::Network::WifiUp));
+                Some(wifi)
+            }
+            Ok((wifi, Err(error))) => {
+                context.lifecycle.fail("Wi-Fi", &error.to_string());
+                wifi_retry_at = Some(clock::now_unix() + config::WIFI_RETRY_SECS);
+                Some(wifi)
+            }
+            Err(error) => {
+                context.lifecycle.fail("the Wi-Fi driver", &error.to_string());
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let online = wifi.is_some();
+    // every configured exporter runs on its own thread behind its own event bus subscription;
+    // they go first so the first reading isn't held up by the servers starting
+    let exporting = online && started(&context, "the exporters", export::start(&context)).is_some();
+    if exporting {
+        // readings restored from before a reset go out flagged, for the backends that show
+        // the current state
+        if let Some((_, exported)) = first_reading.as_ref().filter(|_| !context.service.active()) {
+            let first = export::Measurement { t: clock::now_unix(), readings: exported.clone(), location: None, restored: false };
+            context.events.publish(events::Event::Readings(first));
+        } else if context.restored.lock().unwrap().is_some() && !context.service.active() {
+            let restored = export::Measurement { t: clock::now_unix(), readings: context.latest_readings(), location: None, restored: true };
+            context.events.publish(events::Event::Readings(restored));
+        }
+    }
+    let sntp = online.then(systime::start_sntp).and_then(|sntp| started(&context, "SNTP", sntp));
+    let _server = online.then(|| http::start(context.clone())).and_then(|server| started(&context, "the HTTP server", server));
+    let _remote = online.then(|| remote::start(context.clone())).and_then(|remote| started(&context, "the remote console", remote)).flatten();
+    let _grpc = online.then(|| grpc::start(context.clone())).and_then(|grpc| started(&context, "gRPC-Web", grpc)).flatten();
+    let _poll = online.then(|| poll::start(context.clone())).and_then(|poll| started(&context, "the long poll server", poll)).flatten();
+    if online {
+        modbus::start(context.clone());
+    }
 
-    let mut pin = PinDriver::input_output(pins.gpio4)?;
-    let mut one_wire_bus = OneWire::new(pin)?;
+    // reed switches (door contacts) between GPIO32/GPIO33 and ground
+    let mut contact_pins = Vec::new();
+    for pin in [pins.gpio32.downgrade(), pins.gpio33.downgrade()] {
+        let mut input = PinDriver::input(pin)?;
+        input.set_pull(Pull::Up)?;
+        contact_pins.push(input);
+    }
+    contacts::start(context.clone(), contact_pins);
+
+    // the supply voltage through a divider on GPIO34, see config::VIN_DIVIDER
+    if config::VIN_DIVIDER > 0 {
+        let adc = AdcDriver::new(peripherals.adc1)?;
+        let channel_config = AdcChannelConfig {
+            attenuation: DB_11,
+            calibration: true,
+            ..Default::default()
+        };
+        let mut vin = AdcChannelDriver::new(adc, pins.gpio34, &channel_config)?;
+        power::start_monitor(context.clone(), move || vin.read());
+    }
+
+    if config::GPS_BAUD > 0 {
+        let uart_config = UartConfig::new().baudrate(Hertz(config::GPS_BAUD));
+        let uart = UartDriver::new(
+            peripherals.uart2,
+            pins.gpio17,
+            pins.gpio16,
+            Option::<AnyIOPin>::None,
+            Option::<AnyIOPin>::None,
+            &uart_config,
+        )?;
+        gps::start(context.clone(), uart);
+    }
+
+    let mut buzzer = PinDriver::output(pins.gpio25)?;
+    // the spare pins the rules drive, in the order of rules::OUTPUTS
+    let rule_outputs = [
+        pwm::TimeProportional::start(PinDriver::output(pins.gpio18)?),
+        pwm::TimeProportional::start(PinDriver::output(pins.gpio19)?),
+        pwm::TimeProportional::start(PinDriver::output(pins.gpio23)?),
+    ];
+    led::start(context.clone(), PinDriver::output(pins.gpio2)?);
+    let mut sensor_alarms = alarms::SensorAlarms::default();
+    let mut last_history: Option<u64> = None;
+    // the first reading went out already, the first cycle doesn't send it again
+    let mut exported_first = first_reading.is_some() && exporting && !context.service.active();
+    // with a mux the soak test takes one cable a cycle
+    let mut soak_turn = 0;
+    let mut fermenter = None;
+    let mut runner = None;
+    let mut incubator = None;
+    let mut compensator = None;
+    match config::PROFILE {
+        "fermentation" => {
+            tilt::start_scanner(context.clone());
+            fermenter = Some(fermentation::Controller::new(
+                PinDriver::output(pins.gpio26)?,
+                PinDriver::output(pins.gpio27)?,
+            ));
+        }
+        "sous_vide" => {
+            let heater = pwm::TimeProportional::start(PinDriver::output(pins.gpio26)?);
+            runner = Some(runner::Runner::new(heater));
+        }
+        "incubator" => {
+            let heater = pwm::TimeProportional::start(PinDriver::output(pins.gpio26)?);
+            incubator = Some(incubator::Incubator::new(heater, PinDriver::output(pins.gpio27)?));
+        }
+        "heating" => {
+            let heater = pwm::TimeProportional::start(PinDriver::output(pins.gpio26)?);
+            compensator = Some(heating::Compensator::new(heater));
+        }
+        _ => {}
+    }
+
+    if context.lifecycle.state() != State::SafeMode {
+        context.lifecycle.enter(State::Running, if online { "online" } else { "reporting over serial only" });
+    }
 
-    writeln!(tx, "Testing DS18B20 sensor").unwrap();
+    loop {
+        let cycle = cycle::Cycle::start(context.fleet.lock().unwrap().sample_interval_ms());
 
-    // Test the sensor configuration
-    test_config(&mut delay, &mut tx, &mut one_wire_bus)?;
+        // a connect that failed is tried again a while later rather than every cycle
+        if let Some(wifi) = wifi.as_mut().filter(|_| wifi_retry_at.map_or(true, |at| clock::now_unix() >= at)) {
+            let enabled = context.schedule.lock().unwrap().enabled("wifi", &clock::SystemClock);
+            wifi_retry_at = None;
+            match wifi::set_enabled(wifi, enabled) {
+                Ok(true) => {
+                    if enabled {
+                        context.lifecycle.recovered("Wi-Fi");
+                    }
+                    let network = if enabled { events::Network::WifiUp } else { events::Network::WifiDown };
+                    context.events.publish(events::Event::Network(network));
+                }
+                Ok(false) => {}
+                Err(error) => {
+                    writeln!(tx, "Failed to switch Wi-Fi {}: {}", if enabled { "on" } else { "off" }, error);
+                    wifi_retry_at = Some(clock::now_unix() + config::WIFI_RETRY_SECS);
+                }
+            }
+        }
 
-    // Get the temperature from the sensor
-    get_temperature(&mut delay, &mut tx, &mut one_wire_bus)?;
+        // every SNTP sync (hourly) also sets the RTC
+        if sntp.as_ref().is_some_and(|sntp| sntp.get_sync_status() == SyncStatus::Completed) {
+            clock::set_source("sntp");
+            if let Some(rtc) = &rtc {
+                if let Err(error) = rtc.set_time(&mut *i2c.lock().unwrap(), clock::now_unix()) {
+                    writeln!(tx, "Failed to set the RTC: {:?}", error);
+                }
+            }
+        }
 
-    Ok(())
+        // the bus is left alone while a BLE scan runs
+        let overlapped = context.coex.bus_begin();
+        if context.rescan.swap(false, Ordering::Relaxed) {
+            scan_cache.invalidate();
+        }
+        if scan_cache.search_due() {
+            let mut found = Vec::new();
+            for channel in 0..mux.channels() {
+                mux.select(channel, &mut delay);
+                // a bad cable (shorted, unplugged) only loses its own sensors; with one bus the
+                // cycle goes on without any, so alarms, the rail check and exports keep running
+                // while the search backs off
+                match scan::search(&mut one_wire_bus, &mut delay) {
+                    Ok(addresses) => {
+                        context.health.record("one_wire", Ok::<(), String>(()));
+                        found.extend(addresses.into_iter().map(|address| (channel, address)));
+                    }
+                    Err(error) => {
+                        let bus = if mux.channels() > 1 { mux::bus_name(channel) } else { "GPIO4".to_string() };
+                        writeln!(tx, "Search on {} failed: {:?}", bus, error);
+                        context.health.record("one_wire", Err(format!("search on {} failed: {:?}", bus, error)));
+                    }
+                }
+            }
+            writeln!(tx, "Found {} sensors on the bus", found.len());
+            if mux.channels() > 1 {
+                scan::label_buses(&context, &found);
+            }
+            scan_cache.store(found);
+            if scan_cache.waiting() {
+                writeln!(tx, "No sensors, searching again in {} s", scan_cache.retry_after().as_secs());
+            }
+        }
+        scan::report(&scan_cache, &context);
+
+        // Get the temperature from the sensor
+        let limits = eeprom::wanted(&context.registry.lock().unwrap());
+        let addresses = scan_cache.addresses().to_vec();
+        let mut measure_rail = || {
+            if let Some(ina) = &ina {
+                rail::measure(&context, ina, &mut *i2c.lock().unwrap());
+            }
+        };
+        let groups = scan_cache.groups();
+        let mut readings = match first_reading.take() {
+            // read before the network came up
+            Some((readings, _)) => readings,
+            None => {
+                // with more than one cable every cable starts converting before the first is read;
+                // if one of them fails, each converts on its own when it is read
+                if groups.len() > 1 && converting_since.is_none() {
+                    match mux::start_conversions(&mut mux, &mut one_wire_bus, &mut delay, &groups) {
+                        Ok(()) => converting_since = Some(Instant::now()),
+                        Err(error) => writeln!(tx, "Starting the conversions failed: {:?}", error),
+                    }
+                }
+                // a cable's bus error (one shorted low) only loses its sensors, which count as
+                // read failures below
+                let mut readings = Vec::new();
+                for (channel, addresses) in &groups {
+                    mux.select(*channel, &mut delay);
+                    match get_temperature(
+                        &mut delay,
+                        &mut tx,
+                        &mut one_wire_bus,
+                        addresses,
+                        &limits,
+                        converting_since,
+                        &mut measure_rail,
+                    ) {
+                        Ok(group) => readings.extend(group),
+                        Err(error) if mux.channels() > 1 => {
+                            writeln!(tx, "Reading {} failed: {:?}", mux::bus_name(*channel), error)
+                        }
+                        Err(error) => writeln!(tx, "Reading the sensors failed: {:?}", error),
+                    }
+                }
+                readings
+            }
+        };
+        converting_since = None;
+        context.coex.bus_end(overlapped, addresses.len(), addresses.len().saturating_sub(readings.len()));
+        if readings.len() < addresses.len() {
+            context.counters.add(counters::READ_FAILURES, (addresses.len() - readings.len()) as u64);
+            scan_cache.invalidate();
+        }
+        if let Some(sht31) = &sht31 {
+            match sht31.measure(&mut *i2c.lock().unwrap(), &mut delay) {
+                Ok(measurement) => {
+                    writeln!(tx, "{} is {}, {}%RH", sht31.sensor_id(), measurement.celsius, measurement.humidity);
+                    readings.push(Reading {
+                        sensor: sht31.sensor_id(),
+                        celsius: measurement.celsius,
+                        humidity: Some(measurement.humidity),
+                    });
+                }
+                Err(error) => {
+                    writeln!(tx, "{} read failed: {:?}", sht31.sensor_id(), error);
+                }
+            }
+        }
+        if let Some(rtc) = &rtc {
+            match rtc.temperature(&mut *i2c.lock().unwrap()) {
+                Ok(celsius) => readings.push(Reading {
+                    sensor: rtc.sensor_id(),
+                    celsius,
+                    humidity: None,
+                }),
+                Err(error) => {
+                    writeln!(tx, "{} read failed: {:?}", rtc.sensor_id(), error);
+                }
+            }
+        }
+        // mounting offsets, each for the outputs it is configured for; topology discovery looks
+        // for a rise and takes the readings as they are
+        let raw = readings;
+        let registry = context.registry.lock().unwrap();
+        let mut readings = registry.adjusted(&raw, Target::Control);
+        let mut shown = registry.adjusted(&raw, Target::Api);
+        let mut logged = registry.adjusted(&raw, Target::History);
+        let mut exported = registry.adjusted(&raw, Target::Export);
+        drop(registry);
+
+        // the computed sensors work from the corrected values the controller sees, and every
+        // output gets them as they came out
+        derived::add_virtual_sensors(&context.derived.lock().unwrap(), &mut readings);
+        redundancy::add_logical_sensors(&context, &mut readings);
+        expressions::add_channels(&context, &mut readings);
+        scripting::run(&context, &mut readings);
+        let computed = &readings[raw.len()..];
+        shown.extend_from_slice(computed);
+        logged.extend_from_slice(computed);
+        exported.extend_from_slice(computed);
+        context.set_readings(shown.clone());
+        context.lifecycle.milestone("first_reading");
+        shadow::save(&shown);
+        context.topology.lock().unwrap().update(&raw);
+        context.extremes.lock().unwrap().update(&shown);
+        let now = clock::now_unix();
+        let location = context.gps.lock().unwrap().location();
+        context.compliance.lock().unwrap().update(&readings, &clock::SystemClock);
+        context.degree_days.lock().unwrap().update(&readings, &clock::SystemClock);
+        service::update(&context);
+        let in_maintenance = context.service.active();
+        sensor_alarms.update(&context, &readings);
+
+        // a slow bus already used up the cycle: analysis can wait, the trend window and the
+        // drift average just get one sample less
+        let skipped = cycle.over_budget();
+        if !skipped {
+            context.trends.lock().unwrap().update(&readings);
+            trend::update_warnings(&context);
+            context.drift.lock().unwrap().update(&context, &readings);
+        }
+
+        if last_history.map_or(true, |last| now.saturating_sub(last) >= config::HISTORY_INTERVAL_SECS) {
+            last_history = Some(now);
+            let record = history::Record::readings(&logged, now, location, in_maintenance);
+            if let Err(error) = context.history.lock().unwrap().append(&record) {
+                writeln!(tx, "Failed to write history: {}", error);
+            }
+        }
+
+        if let Some(fermenter) = fermenter.as_mut() {
+            fermenter.update(&context, &readings);
+        }
+        if let Some(runner) = runner.as_mut() {
+            runner.update(&context, &readings);
+        }
+        if let Some(incubator) = incubator.as_mut() {
+            incubator.update(&context, &readings);
+        }
+        if let Some(compensator) = compensator.as_mut() {
+            compensator.update(&context, &readings);
+        }
+        rules::update(&context, &rule_outputs, &readings);
+        flash::check(&context);
+
+        context.alarms.end_carry_over();
+        if context.alarms.sounding() {
+            buzzer.set_high()?;
+        } else {
+            buzzer.set_low()?;
+        }
+
+        // readings taken with probes out or being cleaned would only mislead the backends; only
+        // the first cycle is skipped for the reading that went out at startup, maintenance or not
+        let first = std::mem::take(&mut exported_first);
+        if !in_maintenance && !first {
+            context.events.publish(events::Event::Readings(export::Measurement { t: now, readings: exported, location, restored: false }));
+        }
+
+        context.cycle_stats.lock().unwrap().record(&cycle, skipped);
+        context.counters.add(counters::CYCLES, 1);
+        let remaining = cycle.remaining_ms();
+        if context.soak.lock().unwrap().active() {
+            let groups = scan_cache.groups();
+            let (channel, addresses) = groups.get(soak_turn % groups.len().max(1)).cloned().unwrap_or_default();
+            soak_turn += 1;
+            mux.select(channel, &mut delay);
+            soak::hammer(&context, &mut one_wire_bus, &mut delay, channel, &addresses, Duration::from_millis(u64::from(remaining)));
+        } else if config::DEEP_SLEEP_SECS > 0 {
+            // once the exporters have sent this cycle's readings, or 10 s went by trying; RTC
+            // memory keeps the readings and NVS the sensors, so the wake converts right away
+            context.exporters.wait_idle(Duration::from_secs(10));
+            counters::flush(&context);
+            writeln!(tx, "Sleeping for {} s", config::DEEP_SLEEP_SECS);
+            unsafe { esp_deep_sleep(config::DEEP_SLEEP_SECS * 1_000_000) };
+        } else {
+            context.maintenance.idle(Duration::from_millis(u64::from(remaining)));
+            FreeRtos::delay_ms(remaining);
+        }
+    }
 }
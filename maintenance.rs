@@ -0,0 +1,159 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::task::thread::ThreadSpawnConfiguration;
+use esp_idf_svc::sys::{esp, esp_err_t, esp_spiffs_gc, ESP_ERR_NOT_FINISHED};
+use serde_json::{json, Value};
+
+use crate::context::Context;
+use crate::history;
+use crate::storage;
+
+// a job starts only with at least this much of the idle time between cycles left, none of
+// them takes more than a few seconds
+const MIN_WINDOW: Duration = Duration::from_secs(5);
+// SPIFFS collects garbage when a write finds no free page, which can stall the history append
+// inside a cycle for seconds; keeping a segment's worth clean moves that here
+const GC_INTERVAL: Duration = Duration::from_secs(600);
+const GC_BYTES: usize = 64 * 1024;
+// segments from this one on (a few weeks back at 5 minute lines) are kept as hourly means
+const DOWNSAMPLE_FROM_SEGMENT: usize = 6;
+const DOWNSAMPLE_STEP_SECS: u64 = 3600;
+const DOWNSAMPLE_INTERVAL: Duration = Duration::from_secs(3600);
+// that of the main task running the sampling loop, below the default 5 of every other thread
+const PRIORITY: u8 = 1;
+
+#[derive(Default)]
+struct State {
+    // end of the idle time the sampling loop announced last
+    idle_until: Option<Instant>,
+    last_gc: Option<Instant>,
+    gc_runs: u64,
+    last_downsample: Option<Instant>,
+    // the next segment to look at, one per run
+    next_segment: usize,
+    downsampled: u64,
+    bytes_saved: u64,
+    last_error: Option<String>,
+}
+
+// storage housekeeping (SPIFFS garbage collection, downsampling old history) in its own
+// low-priority thread, only while the sampling loop sleeps between cycles
+#[derive(Default)]
+pub struct Maintenance {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+enum Job {
+    CollectGarbage,
+    Downsample,
+}
+
+impl Maintenance {
+    // from the sampling loop, about to sleep for `duration`
+    pub fn idle(&self, duration: Duration) {
+        self.state.lock().unwrap().idle_until = Some(Instant::now() + duration);
+        self.changed.notify_all();
+    }
+
+    fn due(state: &State) -> Option<Job> {
+        let elapsed = |last: Option<Instant>, interval: Duration| last.map_or(true, |last| last.elapsed() >= interval);
+        if elapsed(state.last_gc, GC_INTERVAL) {
+            Some(Job::CollectGarbage)
+        } else if elapsed(state.last_downsample, DOWNSAMPLE_INTERVAL) {
+            Some(Job::Downsample)
+        } else {
+            None
+        }
+    }
+
+    // blocks until a job is due and the loop is idle long enough for it
+    fn next_job(&self) -> Job {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let window = state.idle_until.map(|until| until.saturating_duration_since(Instant::now()));
+            if let Some(job) = window.filter(|window| *window >= MIN_WINDOW).and_then(|_| Self::due(&state)) {
+                return job;
+            }
+            // woken by the next idle() or rechecked once a minute for due jobs
+            state = self.changed.wait_timeout(state, Duration::from_secs(60)).unwrap().0;
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "gc_runs": state.gc_runs,
+            "segments_downsampled": state.downsampled,
+            "bytes_saved": state.bytes_saved,
+            "last_error": state.last_error,
+        })
+    }
+}
+
+fn collect_garbage(context: &Context) -> Result<(), String> {
+    let mut state = context.maintenance.state.lock().unwrap();
+    state.gc_runs += 1;
+    state.last_gc = Some(Instant::now());
+    drop(state);
+    let result = unsafe { esp_spiffs_gc(storage::PARTITION.as_ptr(), GC_BYTES) };
+    // it did what it could in one go, the next run continues
+    if result == ESP_ERR_NOT_FINISHED as esp_err_t {
+        return Ok(());
+    }
+    esp!(result).map_err(|error| format!("SPIFFS garbage collection failed: {}", error))
+}
+
+fn downsample(context: &Context) -> Result<(), String> {
+    let index = {
+        let mut state = context.maintenance.state.lock().unwrap();
+        if state.next_segment < DOWNSAMPLE_FROM_SEGMENT || state.next_segment >= history::MAX_SEGMENTS {
+            state.next_segment = DOWNSAMPLE_FROM_SEGMENT;
+        }
+        let index = state.next_segment;
+        state.next_segment += 1;
+        // a pass over all old segments, then a pause
+        if state.next_segment >= history::MAX_SEGMENTS {
+            state.last_downsample = Some(Instant::now());
+        }
+        index
+    };
+    // appends wait, the segment can't be rotated away while it is rewritten
+    let result = context.history.lock().unwrap().downsample_segment(index, DOWNSAMPLE_STEP_SECS);
+    match result {
+        Ok(Some((before, after))) => {
+            log::info!("history segment {} downsampled from {} to {} bytes", index, before, after);
+            let mut state = context.maintenance.state.lock().unwrap();
+            state.downsampled += 1;
+            state.bytes_saved += before.saturating_sub(after);
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(error) => Err(format!("downsampling history segment {} failed: {}", index, error)),
+    }
+}
+
+pub fn start(context: Arc<Context>) {
+    let configured = ThreadSpawnConfiguration { priority: PRIORITY, ..Default::default() }.set();
+    let spawned = thread::Builder::new()
+        .name("maintenance".into())
+        .stack_size(6144)
+        .spawn(move || loop {
+            let result = match context.maintenance.next_job() {
+                Job::CollectGarbage => collect_garbage(&context),
+                Job::Downsample => downsample(&context),
+            };
+            if let Err(error) = result {
+                log::warn!("{}", error);
+                context.maintenance.state.lock().unwrap().last_error = Some(error);
+            }
+        });
+    if configured.is_ok() {
+        let _ = ThreadSpawnConfiguration::default().set();
+    }
+    if let Err(error) = spawned {
+        log::warn!("failed to start the maintenance thread: {}", error);
+    }
+}
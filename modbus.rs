@@ -0,0 +1,219 @@
+use std::io::{self, Read, Write as _};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_hal::io::Write;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config;
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::registry::Target;
+
+// registers per sensor: temperature, humidity, status
+const BLOCK: u16 = 3;
+// most registers one request may read, as the Modbus spec allows
+const MAX_COUNT: u16 = 125;
+// closes a connection that has been quiet this long, the server takes one at a time
+const IDLE: Duration = Duration::from_secs(30);
+// sent for a value the sensor has no reading of
+const NO_TEMPERATURE: i16 = i16::MIN;
+const NO_HUMIDITY: u16 = u16::MAX;
+
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+// exception codes
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+
+// one register of the map, as /api/register-map lists it
+#[derive(Serialize)]
+struct Row {
+    address: u16,
+    sensor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    quantity: &'static str,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    // the register value times this is the value in `unit`
+    scale: f32,
+    unit: &'static str,
+    // the value sent while there is no reading, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missing: Option<i32>,
+}
+
+// every sensor in the registry or the current readings, in the order of their ids; a block of
+// BLOCK registers each, so the addresses move when a sensor with a lower id is added
+fn sensors(context: &Context) -> Vec<String> {
+    let mut sensors: Vec<String> = context.registry.lock().unwrap().sensors().map(|(sensor, _)| sensor.clone()).collect();
+    sensors.extend(context.readings.snapshot().readings.iter().map(|reading| reading.sensor.clone()));
+    sensors.sort();
+    sensors.dedup();
+    sensors
+}
+
+fn map(context: &Context) -> Vec<Row> {
+    let sensors = sensors(context);
+    let registry = context.registry.lock().unwrap();
+    let mut rows = Vec::with_capacity(sensors.len() * usize::from(BLOCK));
+    for (index, sensor) in sensors.into_iter().enumerate() {
+        let base = index as u16 * BLOCK;
+        let name = registry.get(&sensor).and_then(|info| info.name.clone());
+        let row = |offset: u16, quantity, kind, scale, unit, missing| Row {
+            address: base + offset,
+            sensor: sensor.clone(),
+            name: name.clone(),
+            quantity,
+            kind,
+            scale,
+            unit,
+            missing,
+        };
+        rows.push(row(0, "temperature", "int16", 0.01, "°C", Some(i32::from(NO_TEMPERATURE))));
+        rows.push(row(1, "humidity", "uint16", 0.01, "%RH", Some(i32::from(NO_HUMIDITY))));
+        // 1 while the sensor is in the current readings, 0 while it is missing
+        rows.push(row(2, "status", "uint16", 1.0, "", None));
+    }
+    rows
+}
+
+// the register values in the order of map(), with the offsets configured for exports
+fn registers(context: &Context) -> Vec<u16> {
+    let sensors = sensors(context);
+    let snapshot = context.readings.snapshot();
+    let readings = context.registry.lock().unwrap().adjusted(&snapshot.readings, Target::Export);
+    let mut registers = Vec::with_capacity(sensors.len() * usize::from(BLOCK));
+    for sensor in &sensors {
+        match readings.iter().find(|reading| &reading.sensor == sensor) {
+            Some(reading) => {
+                let celsius = (reading.celsius.0 * 100.0).round().clamp(f32::from(i16::MIN + 1), f32::from(i16::MAX));
+                let humidity = reading.humidity.map_or(NO_HUMIDITY, |humidity| (humidity * 100.0).round().clamp(0.0, 10000.0) as u16);
+                registers.extend([celsius as i16 as u16, humidity, 1]);
+            }
+            None => registers.extend([NO_TEMPERATURE as u16, NO_HUMIDITY, 0]),
+        }
+    }
+    registers
+}
+
+fn csv(rows: &[Row]) -> String {
+    let mut csv = String::from("address,sensor,name,quantity,type,scale,unit,missing\n");
+    for row in rows {
+        // names are free text, quoted with their quotes doubled
+        let name = row.name.as_deref().map_or(String::new(), |name| format!("\"{}\"", name.replace('"', "\"\"")));
+        let missing = row.missing.map_or(String::new(), |missing| missing.to_string());
+        csv.push_str(&format!("{},{},{},{},{},{},{},{}\n", row.address, row.sensor, name, row.quantity, row.kind, row.scale, row.unit, missing));
+    }
+    csv
+}
+
+// the PDU of the answer to one request PDU
+fn answer(context: &Context, request: &[u8]) -> Vec<u8> {
+    let function = request.first().copied().unwrap_or(0);
+    let exception = |code: u8| vec![function | 0x80, code];
+    if function != READ_HOLDING_REGISTERS && function != READ_INPUT_REGISTERS {
+        return exception(ILLEGAL_FUNCTION);
+    }
+    let [_, address_high, address_low, count_high, count_low] = request else {
+        return exception(ILLEGAL_DATA_VALUE);
+    };
+    let (address, count) = (u16::from_be_bytes([*address_high, *address_low]), u16::from_be_bytes([*count_high, *count_low]));
+    if count == 0 || count > MAX_COUNT {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let registers = registers(context);
+    let Some(values) = registers.get(usize::from(address)..usize::from(address) + usize::from(count)) else {
+        return exception(ILLEGAL_DATA_ADDRESS);
+    };
+    let mut pdu = vec![function, (count * 2) as u8];
+    for value in values {
+        pdu.extend_from_slice(&value.to_be_bytes());
+    }
+    pdu
+}
+
+// Modbus TCP frames: transaction id, protocol id (0), length of what follows, unit id, PDU
+fn serve(context: &Context, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(IDLE))?;
+    loop {
+        let mut header = [0u8; 7];
+        stream.read_exact(&mut header)?;
+        let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        if header[2..4] != [0, 0] || !(2..=256).contains(&length) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a Modbus TCP frame"));
+        }
+        let mut request = vec![0u8; length - 1];
+        stream.read_exact(&mut request)?;
+
+        let pdu = answer(context, &request);
+        let mut frame = Vec::with_capacity(7 + pdu.len());
+        frame.extend_from_slice(&header[0..4]);
+        frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend_from_slice(&pdu);
+        stream.write_all(&frame)?;
+    }
+}
+
+// read-only Modbus TCP on MODBUS_PORT, the registers as /api/register-map describes them; any
+// unit id is answered, function 3 and 4 read the same registers
+pub fn start(context: Arc<Context>) {
+    let Ok(port) = u16::try_from(config::MODBUS_PORT) else {
+        return;
+    };
+    if port == 0 {
+        return;
+    }
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(error) => {
+            context.lifecycle.fail("Modbus", &error.to_string());
+            return;
+        }
+    };
+    let spawned = thread::Builder::new()
+        .name("modbus".into())
+        .stack_size(4096)
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let served = stream.and_then(|stream| serve(&context, stream));
+                if let Err(error) = served {
+                    if error.kind() != io::ErrorKind::UnexpectedEof {
+                        log::debug!("Modbus connection ended: {}", error);
+                    }
+                }
+            }
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the Modbus server: {}", error);
+    }
+}
+
+// /api/register-map: the Modbus registers of the current sensor set, ?format=csv to download
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let example = || {
+        json!([{ "address": 0, "sensor": "", "name": "", "quantity": "temperature", "type": "int16", "scale": 0.01, "unit": "°C", "missing": -32768 }])
+    };
+    let api = Api::json(example).query(&[("format", "string")]);
+    http::route(server, "/api/register-map", Method::Get, api, move |request| {
+        let rows = map(&context);
+        if http::query_param(request.uri(), "format") == Some("csv") {
+            let headers = [("Content-Type", "text/csv"), ("Content-Disposition", "attachment; filename=\"register-map.csv\"")];
+            let mut response = request.into_response(200, None, &headers)?;
+            response.write_all(csv(&rows).as_bytes())?;
+            return Ok(());
+        }
+        http::write_json(request, &json!({ "port": config::MODBUS_PORT, "registers": rows }))
+    })?;
+    Ok(())
+}
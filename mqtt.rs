@@ -0,0 +1,314 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::mqtt::client::{EspMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS};
+use esp_idf_svc::sys::EspError;
+use serde_json::{json, Map, Value};
+
+use crate::annotations;
+use crate::build_info;
+use crate::commands::{self, Source};
+use crate::config;
+use crate::context::Context;
+use crate::events::{Event, Network, Topic};
+use crate::export::{Exporter, Measurement};
+use crate::fleet;
+use crate::readings::Reading;
+use crate::twin;
+
+pub struct Mqtt {
+    client: EspMqttClient<'static>,
+    context: Arc<Context>,
+    // set by the event callback on every (re)connect, subscriptions are renewed on the next publish
+    session_started: Arc<AtomicBool>,
+    // between the callback's Connected and Disconnected
+    connected: Arc<AtomicBool>,
+    // sensors we already published a Home Assistant discovery config for
+    announced: HashSet<String>,
+    // command results, the callback can't publish so they go out with the next state
+    replies: Arc<Mutex<Vec<String>>>,
+    // the ping on temp/<node>/ping still on its way back, sent with every state
+    ping: Arc<Mutex<Option<(u32, Instant)>>>,
+    next_ping: u32,
+    // the twin's reported document as last published, it is retained so only changes go out
+    last_reported: Option<String>,
+}
+
+// a ping that hasn't come back by then counts as lost
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+
+// time for a message to the broker to come back to us, which is what Wi-Fi power save stretches:
+// the broker has to wait for the station to wake up before it can deliver
+#[derive(Debug, Default)]
+pub struct RoundTrip {
+    samples: u64,
+    lost: u64,
+    last_ms: u64,
+    min_ms: Option<u64>,
+    max_ms: u64,
+    total_ms: u64,
+}
+
+impl RoundTrip {
+    fn record(&mut self, elapsed: Duration) {
+        let elapsed = elapsed.as_millis() as u64;
+        self.samples += 1;
+        self.last_ms = elapsed;
+        self.min_ms = Some(self.min_ms.map_or(elapsed, |min| min.min(elapsed)));
+        self.max_ms = self.max_ms.max(elapsed);
+        self.total_ms += elapsed;
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "samples": self.samples,
+            "lost": self.lost,
+            "last_ms": self.last_ms,
+            "min_ms": self.min_ms,
+            "mean_ms": self.total_ms.checked_div(self.samples),
+            "max_ms": self.max_ms,
+        })
+    }
+}
+
+fn node_topic(node_id: &str, kind: &str) -> String {
+    format!("{}/{}/{}", config::MQTT_TOPIC_PREFIX, node_id, kind)
+}
+
+impl Mqtt {
+    pub fn connect(context: Arc<Context>) -> Result<Self, EspError> {
+        let status_topic = node_topic(&context.node_id, "status");
+        let conf = MqttClientConfiguration {
+            client_id: Some(&context.node_id),
+            lwt: Some(LwtConfiguration {
+                topic: &status_topic,
+                payload: b"offline",
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+            ..Default::default()
+        };
+
+        let session_started = Arc::new(AtomicBool::new(false));
+        let callback_session = session_started.clone();
+        let connected = Arc::new(AtomicBool::new(false));
+        let callback_connected = connected.clone();
+        let callback_context = context.clone();
+        let replies = Arc::new(Mutex::new(Vec::new()));
+        let callback_replies = replies.clone();
+        let ping = Arc::new(Mutex::new(None));
+        let callback_ping = ping.clone();
+        let client = EspMqttClient::new_cb(config::MQTT_URL, &conf, move |event| match event.payload() {
+            EventPayload::Connected(_) => {
+                callback_session.store(true, Ordering::Relaxed);
+                callback_connected.store(true, Ordering::Relaxed);
+                callback_context.events.publish(Event::Network(Network::MqttConnected));
+            }
+            EventPayload::Disconnected => {
+                callback_connected.store(false, Ordering::Relaxed);
+                callback_context.events.publish(Event::Network(Network::MqttDisconnected));
+            }
+            EventPayload::Received { topic: Some(topic), data, .. } => {
+                if topic == node_topic(&callback_context.node_id, "annotate") {
+                    annotations::from_mqtt(&callback_context, data);
+                } else if topic == node_topic(&callback_context.node_id, "ping") {
+                    let mut ping = callback_ping.lock().unwrap();
+                    let id = std::str::from_utf8(data).ok().and_then(|id| id.parse::<u32>().ok());
+                    if let Some((sent, at)) = *ping {
+                        if id == Some(sent) {
+                            callback_context.round_trip.lock().unwrap().record(at.elapsed());
+                            *ping = None;
+                        }
+                    }
+                } else if topic == node_topic(&callback_context.node_id, "command") {
+                    let reply = commands::run_json(&callback_context, data, Source::Mqtt);
+                    callback_replies.lock().unwrap().push(reply.to_string());
+                } else if topic == node_topic(&callback_context.node_id, "desired") {
+                    twin::handle_desired(&callback_context, data);
+                } else if topic == fleet::topic() {
+                    if let Some(reply) = fleet::handle(&callback_context, data) {
+                        callback_replies.lock().unwrap().push(reply.to_string());
+                    }
+                } else {
+                    callback_context.peers.handle_message(&callback_context.node_id, topic, data);
+                }
+            }
+            _ => {}
+        })?;
+
+        Ok(Self {
+            client,
+            context,
+            session_started,
+            connected,
+            announced: HashSet::new(),
+            replies,
+            ping,
+            next_ping: 0,
+            last_reported: None,
+        })
+    }
+
+    fn start_session(&mut self) -> Result<(), EspError> {
+        let status_topic = node_topic(&self.context.node_id, "status");
+        self.client.publish(&status_topic, QoS::AtLeastOnce, true, b"online")?;
+        self.client.subscribe(&node_topic(&self.context.node_id, "annotate"), QoS::AtLeastOnce)?;
+        self.client.subscribe(&node_topic(&self.context.node_id, "command"), QoS::AtLeastOnce)?;
+        self.client.subscribe(&node_topic(&self.context.node_id, "ping"), QoS::AtMostOnce)?;
+        self.client.subscribe(&fleet::topic(), QoS::AtLeastOnce)?;
+        self.client.subscribe(&node_topic(&self.context.node_id, "desired"), QoS::AtLeastOnce)?;
+
+        if self.context.gateway {
+            let wildcard = format!("{}/+/", config::MQTT_TOPIC_PREFIX);
+            self.client.subscribe(&format!("{}state", wildcard), QoS::AtMostOnce)?;
+            self.client.subscribe(&format!("{}status", wildcard), QoS::AtLeastOnce)?;
+        }
+        Ok(())
+    }
+
+    fn send_ping(&mut self) -> Result<(), EspError> {
+        let mut ping = self.ping.lock().unwrap();
+        match *ping {
+            Some((_, at)) if at.elapsed() < PING_TIMEOUT => return Ok(()),
+            Some(_) => self.context.round_trip.lock().unwrap().lost += 1,
+            None => {}
+        }
+        self.next_ping = self.next_ping.wrapping_add(1);
+        *ping = Some((self.next_ping, Instant::now()));
+        drop(ping);
+        let topic = node_topic(&self.context.node_id, "ping");
+        self.client.publish(&topic, QoS::AtMostOnce, false, self.next_ping.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    fn announce(&mut self, sensor: &str) -> Result<(), EspError> {
+        let node_id = &self.context.node_id;
+        let unique_id = format!("{}_{}", node_id, sensor);
+        let topic = format!("{}/sensor/{}/config", config::MQTT_DISCOVERY_PREFIX, unique_id);
+        let payload = json!({
+            "name": sensor,
+            "unique_id": unique_id,
+            "state_topic": node_topic(node_id, "state"),
+            "availability_topic": node_topic(node_id, "status"),
+            "value_template": format!("{{{{ value_json['{}'] }}}}", sensor),
+            "unit_of_measurement": "°C",
+            "device_class": "temperature",
+            "device": {
+                "identifiers": [node_id],
+                "name": node_id,
+                "sw_version": build_info::version_tag(),
+            },
+        });
+        self.client.publish(&topic, QoS::AtLeastOnce, true, payload.to_string().as_bytes())?;
+        self.announced.insert(sensor.to_string());
+        Ok(())
+    }
+
+    // restored readings are from before a reset, flagged with "restored": true in the state
+    pub fn publish(&mut self, readings: &[Reading], restored: bool) -> Result<(), EspError> {
+        if self.session_started.swap(false, Ordering::Relaxed) {
+            if let Err(error) = self.start_session() {
+                // try again on the next publish
+                self.session_started.store(true, Ordering::Relaxed);
+                return Err(error);
+            }
+        }
+
+        for reading in readings {
+            if !self.announced.contains(&reading.sensor) {
+                self.announce(&reading.sensor)?;
+            }
+        }
+
+        let mut state: Map<String, Value> = readings
+            .iter()
+            .map(|reading| (reading.sensor.clone(), json!(reading.celsius)))
+            .collect();
+        if restored {
+            state.insert("restored".to_string(), json!(true));
+        }
+        let topic = node_topic(&self.context.node_id, "state");
+        self.client.publish(&topic, QoS::AtMostOnce, false, Value::Object(state).to_string().as_bytes())?;
+
+        self.send_ping()?;
+
+        let reported = self.context.twin.lock().unwrap().reported(&self.context).to_string();
+        if self.last_reported.as_ref() != Some(&reported) {
+            self.client.publish(&node_topic(&self.context.node_id, "reported"), QoS::AtLeastOnce, true, reported.as_bytes())?;
+            self.last_reported = Some(reported);
+        }
+
+        let outbox = std::mem::take(&mut self.context.script.lock().unwrap().outbox);
+        for (suffix, payload) in outbox {
+            let topic = node_topic(&self.context.node_id, &format!("script/{}", suffix));
+            self.client.publish(&topic, QoS::AtMostOnce, false, payload.as_bytes())?;
+        }
+
+        let replies = std::mem::take(&mut *self.replies.lock().unwrap());
+        for reply in replies {
+            self.client.publish(&node_topic(&self.context.node_id, "command/result"), QoS::AtLeastOnce, false, reply.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn exporter(context: &Arc<Context>) -> Result<Option<Box<dyn Exporter>>, EspError> {
+    if config::MQTT_URL.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Box::new(Mqtt::connect(context.clone())?)))
+}
+
+impl Exporter for Mqtt {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn topics(&self) -> &'static [Topic] {
+        &[Topic::Readings, Topic::Alarms, Topic::Config]
+    }
+
+    fn connected(&self) -> Option<bool> {
+        Some(self.connected.load(Ordering::Relaxed))
+    }
+
+    // the broker only keeps the current state so a backlog of readings isn't worth sending,
+    // the room is for alarm changes queued between them
+    fn capacity(&self) -> usize {
+        16
+    }
+
+    fn export(&mut self, batch: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        if let Some(measurement) = batch.last() {
+            self.publish(&measurement.readings, measurement.restored)?;
+            if let Some(location) = measurement.location {
+                let topic = node_topic(&self.context.node_id, "location");
+                self.client.publish(&topic, QoS::AtMostOnce, true, json!(location).to_string().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // alarm changes on temp/<node>/alarm, with the active alarms retained on temp/<node>/alarms
+    // so every client shows the same ones acknowledged; settings changes on temp/<node>/config
+    // so other nodes or a dashboard can reload them
+    fn notify(&mut self, event: &Event) -> Result<(), Box<dyn Error>> {
+        let (kind, payload) = match event {
+            Event::AlarmRaised { id, message } => ("alarm", json!({ "id": id, "active": true, "message": message })),
+            Event::AlarmCleared { id } => ("alarm", json!({ "id": id, "active": false })),
+            Event::AlarmAcknowledged { id, by } => ("alarm", json!({ "id": id, "active": true, "acknowledged_by": by })),
+            Event::ConfigChanged(module) => ("config", json!({ "changed": module })),
+            _ => return Ok(()),
+        };
+        let topic = node_topic(&self.context.node_id, kind);
+        self.client.publish(&topic, QoS::AtLeastOnce, false, payload.to_string().as_bytes())?;
+        if kind == "alarm" {
+            let topic = node_topic(&self.context.node_id, "alarms");
+            self.client.publish(&topic, QoS::AtLeastOnce, true, self.context.alarms.to_json().to_string().as_bytes())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt::Debug;
+
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use one_wire_bus::{Address, OneWire, OneWireResult};
+
+// the cable just switched in charges up through its pull-up before the first slot
+const SETTLE_US: u16 = 500;
+
+// a 74HC4051, or any analog switch with binary select lines, between GPIO4 and up to eight
+// sensor cables, for star topologies that ring when the cables are joined: one cable is on the
+// bus at a time. Without one there is a single channel and selecting does nothing.
+pub struct Mux<S> {
+    select: Vec<S>,
+    channels: u8,
+    selected: Option<u8>,
+}
+
+impl<S: OutputPin> Mux<S> {
+    pub fn new(select: Vec<S>, channels: u8) -> Self {
+        Self { select, channels: channels.max(1), selected: None }
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    pub fn select(&mut self, channel: u8, delay: &mut impl DelayUs<u16>) {
+        if self.select.is_empty() || self.selected == Some(channel) {
+            return;
+        }
+        for (bit, pin) in self.select.iter_mut().enumerate() {
+            // a GPIO output doesn't fail to switch
+            let _ = if (channel >> bit) & 1 == 1 { pin.set_high() } else { pin.set_low() };
+        }
+        self.selected = Some(channel);
+        delay.delay_us(SETTLE_US);
+    }
+}
+
+// the logical bus of a channel as the registry shows it
+pub fn bus_name(channel: u8) -> String {
+    format!("mux{}", channel)
+}
+
+// a conversion command on each cable in turn; powered sensors convert on their own once told,
+// so every cable converts at once and the reads follow one cable after the other
+pub fn start_conversions<S, P, E>(
+    mux: &mut Mux<S>,
+    one_wire_bus: &mut OneWire<P>,
+    delay: &mut impl DelayUs<u16>,
+    groups: &[(u8, Vec<Address>)],
+) -> OneWireResult<(), E>
+    where
+        S: OutputPin,
+        P: OutputPin<Error=E> + InputPin<Error=E>,
+        E: Debug
+{
+    for (channel, _) in groups {
+        mux.select(*channel, delay);
+        ds18b20::start_simultaneous_temp_measurement(one_wire_bus, delay)?;
+    }
+    Ok(())
+}
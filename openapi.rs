@@ -0,0 +1,170 @@
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::build_info;
+use crate::http;
+
+// what a route takes and answers, declared with `http::route` so the document can't drift from
+// the handlers. JSON bodies are given as a function returning an example of the shape (a
+// struct's defaults, or a `json!` with one value of each field's type, see `schema`); the
+// schema is derived from it when the document is built, so the routes only keep function
+// pointers
+#[derive(Clone, Copy)]
+pub struct Api {
+    query: &'static [(&'static str, &'static str)],
+    body: Option<Content>,
+    response: Content,
+}
+
+#[derive(Clone, Copy)]
+enum Content {
+    Json(fn() -> Value),
+    Other(&'static str),
+}
+
+impl Api {
+    // answers with JSON shaped like `example()`
+    pub const fn json(example: fn() -> Value) -> Api {
+        Api { query: &[], body: None, response: Content::Json(example) }
+    }
+
+    // answers with a body of `content_type`
+    pub const fn other(content_type: &'static str) -> Api {
+        Api { query: &[], body: None, response: Content::Other(content_type) }
+    }
+
+    // query parameters the handler reads, with their schema type
+    pub const fn query(self, query: &'static [(&'static str, &'static str)]) -> Api {
+        Api { query, ..self }
+    }
+
+    // takes a JSON body shaped like `example()`
+    pub const fn body(self, example: fn() -> Value) -> Api {
+        Api { body: Some(Content::Json(example)), ..self }
+    }
+
+    // takes a body of `content_type`
+    pub const fn other_body(self, content_type: &'static str) -> Api {
+        Api { body: Some(Content::Other(content_type)), ..self }
+    }
+
+    // a settings endpoint: takes the struct it saves and answers with it
+    pub fn settings<T: Default + Serialize>() -> Api {
+        Api::json(defaults::<T>).body(defaults::<T>)
+    }
+}
+
+// a struct's defaults, as an example; for a settings struct that has every field
+pub fn defaults<T: Default + Serialize>() -> Value {
+    serde_json::to_value(T::default()).unwrap_or_default()
+}
+
+impl Content {
+    fn to_json(self) -> Value {
+        let (content_type, schema) = match self {
+            Content::Json(example) => ("application/json", schema(&example())),
+            Content::Other(content_type) if content_type.starts_with("image/") => {
+                (content_type, json!({ "type": "string", "format": "binary" }))
+            }
+            Content::Other(content_type) => (content_type, json!({ "type": "string" })),
+        };
+        json!({ content_type: { "schema": schema } })
+    }
+}
+
+// a schema from what a value serializes to; an object with "" as its only key is a map, e.g. by
+// sensor id, of values like that key's. The items of an array are merged, so a list of enum
+// variants, or of structs with optional fields, describes every field
+fn schema(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({}),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(number) if number.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(items) => json!({ "type": "array", "items": items.iter().map(schema).fold(json!({}), merge) }),
+        Value::Object(fields) if fields.len() == 1 && fields.contains_key("") => {
+            json!({ "type": "object", "additionalProperties": schema(&fields[""]) })
+        }
+        Value::Object(fields) => {
+            let properties: Map<String, Value> = fields.iter().map(|(name, value)| (name.clone(), schema(value))).collect();
+            json!({ "type": "object", "properties": properties })
+        }
+    }
+}
+
+// the properties, or the items, of both schemas; otherwise the first, unless it is the empty
+// one of a null or []
+fn merge(first: Value, second: Value) -> Value {
+    let (Value::Object(mut first), Value::Object(mut second)) = (first, second) else {
+        return json!({});
+    };
+    if first.is_empty() {
+        return Value::Object(second);
+    }
+    let properties = (first.get_mut("properties"), second.remove("properties"));
+    if let (Some(Value::Object(properties)), Some(Value::Object(more))) = properties {
+        for (name, value) in more {
+            let merged = merge(properties.remove(&name).unwrap_or_else(|| json!({})), value);
+            properties.insert(name, merged);
+        }
+    }
+    if let (Some(items), Some(more)) = (first.get_mut("items"), second.remove("items")) {
+        *items = merge(items.take(), more);
+    }
+    Value::Object(first)
+}
+
+fn operation(api: &Api) -> Value {
+    let mut operation = json!({
+        "responses": {
+            "200": { "description": "OK", "content": api.response.to_json() },
+            "400": {
+                "description": "invalid request",
+                "content": Content::Json(|| json!({ "error": "" })).to_json(),
+            },
+        },
+    });
+    let parameters: Vec<Value> = api
+        .query
+        .iter()
+        .map(|(name, kind)| json!({ "name": name, "in": "query", "schema": { "type": kind } }))
+        .collect();
+    if !parameters.is_empty() {
+        operation["parameters"] = json!(parameters);
+    }
+    if let Some(body) = api.body {
+        operation["requestBody"] = json!({ "required": true, "content": body.to_json() });
+    }
+    operation
+}
+
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for (method, path, api) in http::routes() {
+        let name = match method {
+            Method::Get => "get",
+            Method::Post => "post",
+            Method::Put => "put",
+            Method::Delete => "delete",
+            _ => continue,
+        };
+        let item = paths.entry(path.to_string()).or_insert_with(|| json!({}));
+        item[name] = operation(&api);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "temp", "version": build_info::version_tag() },
+        "paths": paths,
+    })
+}
+
+// built from the routes as registered, so it always matches the running firmware
+pub fn register(server: &mut EspHttpServer<'static>) -> Result<(), EspError> {
+    let openapi = Api::json(|| json!({ "openapi": "", "info": { "title": "", "version": "" }, "paths": {} }));
+    http::route(server, "/api/openapi.json", Method::Get, openapi, |request| http::write_json(request, &document()))?;
+    Ok(())
+}
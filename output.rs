@@ -0,0 +1,28 @@
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::context::Context;
+
+// a line-oriented writer whose failures go to the health subsystem instead of being dropped;
+// `writeln!(out, ...)` works as with any writer, there is just no Result left to ignore
+pub struct Output<W: Write> {
+    inner: W,
+    context: Arc<Context>,
+    channel: &'static str,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(inner: W, context: Arc<Context>, channel: &'static str) -> Self {
+        Self { inner, context, channel }
+    }
+
+    pub fn write_fmt(&mut self, args: fmt::Arguments) {
+        let result = self.inner.write_fmt(args);
+        self.context.health.record(self.channel, result);
+    }
+}
+
+pub fn serial(context: Arc<Context>) -> Output<std::io::Stdout> {
+    Output::new(std::io::stdout(), context, "serial")
+}
@@ -0,0 +1,438 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use esp_idf_hal::io::Write;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::EspIOError;
+use esp_idf_svc::sys::{esp_timer_get_time, EspError};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+
+// 16 KiB, about ten sensor reads; the oldest events are overwritten
+const CAPACITY: usize = 4096;
+pub const MAX_SECS: f64 = 3600.0;
+// an event is its kind in the top three bits and the time in µs, modulo 2^29 (about 9 minutes),
+// below
+const TIME_BITS: u32 = 29;
+const TIME_MASK: u32 = (1 << TIME_BITS) - 1;
+// the kind of an event written before one that comes more than TIME_MASK µs after the event
+// before it, with the time between them in ms, so that long waits between reads add up
+const GAP: u32 = 4;
+// a low pulse this long is a reset, a write slot shorter than this is a 1
+const RESET_US: u32 = 400;
+const WRITE_ONE_US: u32 = 15;
+// the decoded download goes out in pieces this big
+const CHUNK: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Low,
+    Release,
+    Read(bool),
+}
+
+impl Action {
+    fn code(self) -> u32 {
+        match self {
+            Action::Low => 0,
+            Action::Release => 1,
+            Action::Read(false) => 2,
+            Action::Read(true) => 3,
+        }
+    }
+
+    fn from_code(code: u32) -> Self {
+        match code {
+            0 => Action::Low,
+            1 => Action::Release,
+            2 => Action::Read(false),
+            3 => Action::Read(true),
+            _ => unreachable!("not a pin action: {}", code),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::Low => "low",
+            Action::Release => "release",
+            Action::Read(false) => "read 0",
+            Action::Read(true) => "read 1",
+        }
+    }
+}
+
+// written from the sampling thread alone, between its pin accesses, so without a lock
+struct Trace {
+    events: [AtomicU32; CAPACITY],
+    // events written since the boot
+    written: AtomicUsize,
+    // where the current recording starts
+    first: AtomicUsize,
+    recording: AtomicBool,
+    until_us: AtomicI64,
+    // when the last event was written
+    last_us: AtomicI64,
+}
+
+static TRACE: Trace = Trace {
+    events: [const { AtomicU32::new(0) }; CAPACITY],
+    written: AtomicUsize::new(0),
+    first: AtomicUsize::new(0),
+    recording: AtomicBool::new(false),
+    until_us: AtomicI64::new(0),
+    last_us: AtomicI64::new(0),
+};
+
+fn now_us() -> i64 {
+    unsafe { esp_timer_get_time() }
+}
+
+// a flag check while not recording; while recording about a microsecond per pin access, which
+// is within the slack of the 1-Wire timing
+fn record(action: Action) {
+    if !TRACE.recording.load(Ordering::Relaxed) {
+        return;
+    }
+    let now = now_us();
+    if now >= TRACE.until_us.load(Ordering::Relaxed) {
+        TRACE.recording.store(false, Ordering::Relaxed);
+        return;
+    }
+    let mut index = TRACE.written.load(Ordering::Relaxed);
+    let since = now - TRACE.last_us.swap(now, Ordering::Relaxed);
+    if since > i64::from(TIME_MASK) {
+        let ms = (since / 1000).min(i64::from(TIME_MASK)) as u32;
+        TRACE.events[index % CAPACITY].store((GAP << TIME_BITS) | ms, Ordering::Relaxed);
+        index += 1;
+    }
+    TRACE.events[index % CAPACITY].store((action.code() << TIME_BITS) | (now as u32 & TIME_MASK), Ordering::Relaxed);
+    TRACE.written.store(index + 1, Ordering::Release);
+}
+
+// starts a new recording for `duration`; a zero duration stops it, what it recorded stays
+pub fn start(duration: Duration) {
+    if duration.is_zero() {
+        TRACE.recording.store(false, Ordering::Relaxed);
+        return;
+    }
+    TRACE.first.store(TRACE.written.load(Ordering::Acquire), Ordering::Relaxed);
+    let now = now_us();
+    TRACE.last_us.store(now, Ordering::Relaxed);
+    TRACE.until_us.store(now + duration.as_micros() as i64, Ordering::Relaxed);
+    TRACE.recording.store(true, Ordering::Relaxed);
+}
+
+fn recording() -> bool {
+    TRACE.recording.load(Ordering::Relaxed) && now_us() < TRACE.until_us.load(Ordering::Relaxed)
+}
+
+// the events of the current recording as stored, oldest first, and how many were overwritten
+// before they could be downloaded
+fn snapshot() -> (Vec<u32>, usize) {
+    let written = TRACE.written.load(Ordering::Acquire);
+    let first = TRACE.first.load(Ordering::Relaxed);
+    let mut from = first.max(written.saturating_sub(CAPACITY));
+    let mut raw: Vec<u32> = (from..written).map(|index| TRACE.events[index % CAPACITY].load(Ordering::Relaxed)).collect();
+    // the sampling thread may have gone round while these were copied
+    let overwritten = TRACE.written.load(Ordering::Acquire).saturating_sub(CAPACITY).saturating_sub(from).min(raw.len());
+    raw.drain(..overwritten);
+    from += overwritten;
+    (raw, from - first)
+}
+
+// the stored events as (µs since the first, action), decoded as they are written out rather
+// than into a second, four times larger copy
+fn timed(raw: &[u32]) -> impl Iterator<Item = (u64, Action)> + '_ {
+    let modulus = u64::from(TIME_MASK) + 1;
+    let (mut t, mut last, mut gap) = (0u64, None, 0u64);
+    raw.iter().filter_map(move |&event| {
+        let (kind, time) = (event >> TIME_BITS, event & TIME_MASK);
+        if kind == GAP {
+            gap = u64::from(time) * 1000;
+            return None;
+        }
+        if let Some(last) = last {
+            // the time since the event before is known modulo 2^29, and after a gap to the ms
+            let since = u64::from(time.wrapping_sub(last) & TIME_MASK);
+            t += gap + (since + modulus - gap % modulus) % modulus;
+        }
+        (last, gap) = (Some(time), 0);
+        Some((t, Action::from_code(kind)))
+    })
+}
+
+fn pin_events(raw: &[u32]) -> usize {
+    raw.iter().filter(|&&event| event >> TIME_BITS != GAP).count()
+}
+
+pub fn to_json() -> Value {
+    let written = TRACE.written.load(Ordering::Acquire);
+    let first = TRACE.first.load(Ordering::Relaxed);
+    let recording = recording();
+    json!({
+        "recording": recording,
+        "remaining_secs": recording.then(|| (TRACE.until_us.load(Ordering::Relaxed) - now_us()) / 1_000_000),
+        "events": (written - first).min(CAPACITY),
+        "dropped": (written - first).saturating_sub(CAPACITY),
+        "capacity": CAPACITY,
+    })
+}
+
+// the 1-Wire pin, with every access recorded while a trace is on
+pub struct Traced<P>(pub P);
+
+impl<P: OutputPin> OutputPin for Traced<P> {
+    type Error = P::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let result = self.0.set_low();
+        record(Action::Low);
+        result
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let result = self.0.set_high();
+        record(Action::Release);
+        result
+    }
+}
+
+impl<P: InputPin> InputPin for Traced<P> {
+    type Error = P::Error;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        let high = self.0.is_high()?;
+        record(Action::Read(high));
+        Ok(high)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        let low = self.0.is_low()?;
+        record(Action::Read(!low));
+        Ok(low)
+    }
+}
+
+fn rom_command(byte: u8) -> Option<&'static str> {
+    match byte {
+        0x33 => Some("read ROM"),
+        0x55 => Some("match ROM"),
+        0xCC => Some("skip ROM"),
+        0xF0 => Some("search ROM"),
+        0xEC => Some("alarm search"),
+        _ => None,
+    }
+}
+
+fn function_command(byte: u8) -> Option<&'static str> {
+    match byte {
+        0x44 => Some("convert T"),
+        0xBE => Some("read scratchpad"),
+        0x4E => Some("write scratchpad"),
+        0x48 => Some("copy scratchpad"),
+        0xB8 => Some("recall EEPROM"),
+        0xB4 => Some("read power supply"),
+        _ => None,
+    }
+}
+
+enum Pending {
+    None,
+    // released after a long low, the next read is the presence pulse
+    Reset(u64),
+    // a short low released: a read if a read follows, a write otherwise
+    Slot { at: u64, low_us: u64 },
+}
+
+// the slots the pin events make up: resets with their presence pulse, and the bits written and
+// read in runs, as bytes least significant bit first the way they go over the wire
+struct Decoder {
+    out: String,
+    low_at: Option<u64>,
+    pending: Pending,
+    // (when the run started, written rather than read, bits)
+    run: Option<(u64, bool, Vec<bool>)>,
+    // written since the last reset, to name the commands
+    written: Vec<u8>,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self { out: String::new(), low_at: None, pending: Pending::None, run: None, written: Vec::new() }
+    }
+
+    fn event(&mut self, t: u64, action: Action) {
+        match action {
+            Action::Low => {
+                self.finish_slot();
+                self.low_at = Some(t);
+            }
+            Action::Release => {
+                if let Some(at) = self.low_at.take() {
+                    let low_us = t - at;
+                    self.pending = if low_us >= u64::from(RESET_US) { Pending::Reset(at) } else { Pending::Slot { at, low_us } };
+                }
+            }
+            Action::Read(high) => match std::mem::replace(&mut self.pending, Pending::None) {
+                Pending::Reset(at) => self.reset(at, Some(!high)),
+                Pending::Slot { at, .. } => self.bit(at, false, high),
+                // waiting for the bus to go idle before a reset
+                Pending::None => {}
+            },
+        }
+    }
+
+    fn finish_slot(&mut self) {
+        match std::mem::replace(&mut self.pending, Pending::None) {
+            Pending::Reset(at) => self.reset(at, None),
+            Pending::Slot { at, low_us } => self.bit(at, true, low_us < u64::from(WRITE_ONE_US)),
+            Pending::None => {}
+        }
+    }
+
+    fn reset(&mut self, at: u64, presence: Option<bool>) {
+        self.flush();
+        self.written.clear();
+        let presence = match presence {
+            Some(true) => "presence",
+            Some(false) => "no presence",
+            None => "not sampled",
+        };
+        let _ = writeln!(self.out, "{:>10} reset, {}", at, presence);
+    }
+
+    fn bit(&mut self, at: u64, write: bool, bit: bool) {
+        if self.run.as_ref().is_some_and(|(_, run_write, _)| *run_write != write) {
+            self.flush();
+        }
+        self.run.get_or_insert_with(|| (at, write, Vec::new())).2.push(bit);
+    }
+
+    fn flush(&mut self) {
+        let Some((at, write, bits)) = self.run.take() else {
+            return;
+        };
+        let bytes: Vec<u8> = bits
+            .chunks_exact(8)
+            .map(|byte| byte.iter().enumerate().fold(0, |value, (index, bit)| value | (u8::from(*bit) << index)))
+            .collect();
+        let _ = write!(self.out, "{:>10} {}", at, if write { "write" } else { "read" });
+        for byte in &bytes {
+            let _ = write!(self.out, " {:02X}", byte);
+        }
+        let rest = &bits[bytes.len() * 8..];
+        if !rest.is_empty() {
+            let _ = write!(self.out, " bits ");
+            for bit in rest {
+                self.out.push(if *bit { '1' } else { '0' });
+            }
+        }
+        if write {
+            let start = self.written.len();
+            self.written.extend(&bytes);
+            let names: Vec<&str> = (start..self.written.len()).filter_map(|index| self.command(index)).collect();
+            if !names.is_empty() {
+                let _ = write!(self.out, "  # {}", names.join(", "));
+            }
+        }
+        self.out.push('\n');
+    }
+
+    // the name of the index-th byte written since the reset if it is a command
+    fn command(&self, index: usize) -> Option<&'static str> {
+        let byte = self.written[index];
+        match (index, self.written[0]) {
+            (0, _) => rom_command(byte),
+            (1, 0xCC) | (9, 0x55) => function_command(byte),
+            _ => None,
+        }
+    }
+}
+
+fn write_decoded(
+    response: &mut impl Write<Error = EspIOError>,
+    events: impl Iterator<Item = (u64, Action)>,
+) -> Result<(), EspIOError> {
+    let mut decoder = Decoder::new();
+    for (t, action) in events {
+        decoder.event(t, action);
+        if decoder.out.len() >= CHUNK {
+            response.write_all(decoder.out.as_bytes())?;
+            decoder.out.clear();
+        }
+    }
+    decoder.finish_slot();
+    decoder.flush();
+    response.write_all(decoder.out.as_bytes())
+}
+
+fn write_raw(
+    response: &mut impl Write<Error = EspIOError>,
+    events: impl Iterator<Item = (u64, Action)>,
+) -> Result<(), EspIOError> {
+    let mut out = String::new();
+    for (t, action) in events {
+        let _ = writeln!(out, "{} {}", t, action.name());
+        if out.len() >= CHUNK {
+            response.write_all(out.as_bytes())?;
+            out.clear();
+        }
+    }
+    response.write_all(out.as_bytes())
+}
+
+#[derive(Deserialize)]
+struct TraceRequest {
+    seconds: Option<f64>,
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    // the recording as text, times in µs since its first event: decoded into resets, bytes and
+    // commands, or with ?format=raw every pin access for a closer look at the timing
+    let trace = Api::other("text/plain").query(&[("format", "string")]);
+    http::route(server, "/api/debug/ow-trace", Method::Get, trace, |request| {
+        let raw = http::query_param(request.uri(), "format") == Some("raw");
+        let (events, dropped) = snapshot();
+        let mut response = request.into_response(200, None, &[("Content-Type", "text/plain; charset=utf-8")])?;
+        let header = format!(
+            "# 1-Wire trace: {} events over {} us, {} dropped{}\n",
+            pin_events(&events),
+            timed(&events).last().map_or(0, |(t, _)| t),
+            dropped,
+            if recording() { ", still recording" } else { "" },
+        );
+        response.write_all(header.as_bytes())?;
+        if raw {
+            write_raw(&mut response, timed(&events))?;
+        } else {
+            write_decoded(&mut response, timed(&events))?;
+        }
+        Ok::<(), EspIOError>(())
+    })?;
+
+    // {"seconds": 60} starts a new recording, {"seconds": 0} stops it
+    let start = Api::json(|| json!({ "recording": true, "remaining_secs": 0, "events": 0, "dropped": 0, "capacity": 0 }))
+        .body(|| json!({ "seconds": 0.0 }));
+    http::route(server, "/api/debug/ow-trace", Method::Post, start, move |mut request| {
+        let trace = match http::read_json::<TraceRequest>(&mut request, 256)? {
+            Ok(trace) => trace,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let args: Map<String, Value> = trace.seconds.map(|seconds| ("seconds".to_string(), json!(seconds))).into_iter().collect();
+        match commands::run(&context, "ow_trace", &args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}
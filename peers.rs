@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::readings::Reading;
+use crate::units::Celsius;
+
+// a node that hasn't published state for this many sample intervals is shown offline,
+// even if its last will hasn't been delivered yet
+const STALE_INTERVALS: u32 = 3;
+
+#[derive(Default)]
+struct Peer {
+    readings: Vec<Reading>,
+    online: bool,
+    last_seen: Option<Instant>,
+}
+
+impl Peer {
+    fn is_online(&self) -> bool {
+        let stale_after = Duration::from_millis(u64::from(config::SAMPLE_INTERVAL_MS * STALE_INTERVALS));
+        self.online && self.last_seen.is_some_and(|seen| seen.elapsed() < stale_after)
+    }
+}
+
+// readings of other nodes, collected by a gateway from their temp/<node>/state topics
+#[derive(Default)]
+pub struct Peers {
+    nodes: Mutex<BTreeMap<String, Peer>>,
+}
+
+impl Peers {
+    pub fn handle_message(&self, own_node: &str, topic: &str, data: &[u8]) {
+        let mut parts = topic.split('/');
+        let (Some(prefix), Some(node), Some(kind), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return;
+        };
+        if prefix != config::MQTT_TOPIC_PREFIX || node == own_node {
+            return;
+        }
+
+        let mut nodes = self.nodes.lock().unwrap();
+        match kind {
+            "state" => {
+                let Ok(Value::Object(state)) = serde_json::from_slice::<Value>(data) else {
+                    return;
+                };
+                let peer = nodes.entry(node.to_string()).or_default();
+                peer.readings = state
+                    .iter()
+                    .filter_map(|(sensor, value)| {
+                        Some(Reading {
+                            sensor: sensor.clone(),
+                            celsius: Celsius(value.as_f64()? as f32),
+                            humidity: None,
+                        })
+                    })
+                    .collect();
+                peer.online = true;
+                peer.last_seen = Some(Instant::now());
+            }
+            "status" => {
+                let peer = nodes.entry(node.to_string()).or_default();
+                peer.online = data == b"online";
+            }
+            _ => {}
+        }
+    }
+
+    // every known node including this one, grouped for the dashboard
+    pub fn nodes_json(&self, own_node: &str, own_readings: &[Reading]) -> Value {
+        let nodes = self.nodes.lock().unwrap();
+        let mut result = vec![json!({
+            "node": own_node,
+            "online": true,
+            "readings": own_readings,
+        })];
+        result.extend(nodes.iter().map(|(node, peer)| {
+            json!({
+                "node": node,
+                "online": peer.is_online(),
+                "last_seen_secs": peer.last_seen.map(|seen| seen.elapsed().as_secs()),
+                "readings": peer.readings,
+            })
+        }));
+        Value::Array(result)
+    }
+}
@@ -0,0 +1,46 @@
+use crate::units::Celsius;
+
+// PID controller with output clamped to 0..1 and conditional integration against windup
+pub struct Pid {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    integral: f32,
+    last_celsius: Option<Celsius>,
+}
+
+impl Pid {
+    pub fn new(kp: f32, ki: f32, kd: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            integral: 0.0,
+            last_celsius: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_celsius = None;
+    }
+
+    // `dt` is the time since the previous update in seconds
+    pub fn update(&mut self, setpoint: Celsius, celsius: Celsius, dt: f32) -> f32 {
+        let error = (setpoint - celsius).0;
+        // derivative on measurement, so setpoint changes (ramps) don't kick the output
+        let derivative = match self.last_celsius {
+            Some(last) if dt > 0.0 => -(celsius - last).0 / dt,
+            _ => 0.0,
+        };
+        self.last_celsius = Some(celsius);
+
+        let unclamped = self.kp * error + self.ki * (self.integral + error * dt) + self.kd * derivative;
+        let output = unclamped.clamp(0.0, 1.0);
+        // only integrate while not saturated, or while the error drives the output out of saturation
+        if unclamped == output || (unclamped > 1.0 && error < 0.0) || (unclamped < 0.0 && error > 0.0) {
+            self.integral += error * dt;
+        }
+        output
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+
+use crate::config;
+use crate::context::Context;
+use crate::http;
+
+// the dashboard server, the remote console and gRPC-Web have the control ports below
+const CTRL_PORT: u16 = 32771;
+// longest hold of a request; the server answers one at a time, so a second client waits for
+// the first one's readings before its own wait starts
+const MAX_WAIT_SECS: u64 = 30;
+
+// GET /api/temps as on the dashboard server, and with ?wait=<secs> and an If-None-Match of the
+// current readings it is held until the sampling loop publishes new ones
+pub fn start(context: Arc<Context>) -> Result<Option<EspHttpServer<'static>>, EspError> {
+    let Ok(port) = u16::try_from(config::LONG_POLL_PORT) else {
+        return Ok(None);
+    };
+    if port == 0 {
+        return Ok(None);
+    }
+    let mut server = EspHttpServer::new(&Configuration {
+        http_port: port,
+        ctrl_port: CTRL_PORT,
+        ..Default::default()
+    })?;
+
+    server.fn_handler("/api/temps", Method::Get, move |request| {
+        let wait = http::query_param(request.uri(), "wait").and_then(|secs| secs.parse::<u64>().ok()).unwrap_or(0);
+        let known = request.header("If-None-Match").map(str::to_string);
+        let mut snapshot = context.readings.snapshot();
+        if wait > 0 && known.as_deref() == Some(http::readings_etag(&context, snapshot.version).as_str()) {
+            snapshot = context.readings.wait_past(snapshot.version, Duration::from_secs(wait.min(MAX_WAIT_SECS)));
+        }
+        http::write_readings(request, &context, known.as_deref(), &snapshot)
+    })?;
+
+    Ok(Some(server))
+}
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::{
+    esp_reset_reason, esp_reset_reason_t_ESP_RST_BROWNOUT, esp_reset_reason_t_ESP_RST_DEEPSLEEP,
+    esp_reset_reason_t_ESP_RST_EXT, esp_reset_reason_t_ESP_RST_INT_WDT, esp_reset_reason_t_ESP_RST_PANIC,
+    esp_reset_reason_t_ESP_RST_POWERON, esp_reset_reason_t_ESP_RST_SW, esp_reset_reason_t_ESP_RST_TASK_WDT,
+    esp_reset_reason_t_ESP_RST_WDT, EspError,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::clock;
+use crate::config;
+use crate::context::Context;
+use crate::counters;
+use crate::http;
+use crate::openapi::Api;
+use crate::storage;
+
+const LOG_FILE: &str = "power.json";
+const MAX_EVENTS: usize = 32;
+// brownouts proper reset the chip faster than this, it catches the sags that don't
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+// why the chip started, grouped by what to look at: the supply or the firmware
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    Power,
+    Firmware,
+    // the reboot command, a reset button or a deep sleep wake
+    Intended,
+    Unknown,
+}
+
+fn reset_reason() -> (&'static str, Cause) {
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_POWERON => ("power_on", Cause::Power),
+        esp_reset_reason_t_ESP_RST_BROWNOUT => ("brownout", Cause::Power),
+        esp_reset_reason_t_ESP_RST_PANIC => ("panic", Cause::Firmware),
+        esp_reset_reason_t_ESP_RST_INT_WDT => ("interrupt_watchdog", Cause::Firmware),
+        esp_reset_reason_t_ESP_RST_TASK_WDT => ("task_watchdog", Cause::Firmware),
+        esp_reset_reason_t_ESP_RST_WDT => ("watchdog", Cause::Firmware),
+        esp_reset_reason_t_ESP_RST_SW => ("software", Cause::Intended),
+        esp_reset_reason_t_ESP_RST_EXT => ("reset_pin", Cause::Intended),
+        esp_reset_reason_t_ESP_RST_DEEPSLEEP => ("deep_sleep", Cause::Intended),
+        _ => ("unknown", Cause::Unknown),
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PowerEvent {
+    // t is null when the clock wasn't set yet, which at boot it usually isn't
+    Reset { t: Option<u64>, reason: String, cause: Cause },
+    // VIN below VIN_DIP_MV
+    Dip { t: Option<u64>, min_mv: u32, duration_ms: u64 },
+}
+
+fn now() -> Option<u64> {
+    clock::is_synced().then(clock::now_unix)
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+struct Vin {
+    mv: u32,
+    min_mv: u32,
+    max_mv: u32,
+}
+
+// the reset reason of every boot and the supply dips, kept across reboots so a device that
+// "crashes" in the field can be told apart from one on a bad supply
+pub struct Power {
+    events: Vec<PowerEvent>,
+    reason: &'static str,
+    cause: Cause,
+    // since boot, None without a divider on VIN
+    vin: Option<Vin>,
+}
+
+impl Power {
+    pub fn load() -> Self {
+        let (reason, cause) = reset_reason();
+        if cause != Cause::Intended {
+            log::warn!("reset by {}", reason);
+        }
+        let mut power = Self {
+            events: storage::read_json(LOG_FILE).unwrap_or_default(),
+            reason,
+            cause,
+            vin: None,
+        };
+        power.log(PowerEvent::Reset { t: now(), reason: reason.to_string(), cause });
+        power
+    }
+
+    fn log(&mut self, event: PowerEvent) {
+        self.events.push(event);
+        let excess = self.events.len().saturating_sub(MAX_EVENTS);
+        self.events.drain(..excess);
+        if let Err(error) = storage::write_json(LOG_FILE, &self.events) {
+            log::warn!("failed to save the power log: {}", error);
+        }
+    }
+
+    // the lifetime counter this boot's reset adds to, if any
+    pub fn reset_counter(&self) -> Option<&'static str> {
+        match (self.reason, self.cause) {
+            ("brownout", _) => Some(counters::BROWNOUTS),
+            (_, Cause::Firmware) => Some(counters::CRASHES),
+            _ => None,
+        }
+    }
+
+    fn sample(&mut self, mv: u32) {
+        self.vin = Some(match self.vin {
+            Some(vin) => Vin { mv, min_mv: vin.min_mv.min(mv), max_mv: vin.max_mv.max(mv) },
+            None => Vin { mv, min_mv: mv, max_mv: mv },
+        });
+    }
+}
+
+// samples VIN through `read_mv`, the calibrated voltage at the pin
+pub fn start_monitor<F>(context: Arc<Context>, mut read_mv: F)
+    where
+        F: FnMut() -> Result<u16, EspError> + Send + 'static
+{
+    let spawned = thread::Builder::new()
+        .name("power".into())
+        .stack_size(3072)
+        .spawn(move || {
+            // start and lowest voltage of the dip in progress
+            let mut dip: Option<(Instant, u32)> = None;
+            let mut failing = false;
+            loop {
+                match read_mv() {
+                    Ok(pin_mv) => {
+                        failing = false;
+                        let mv = u32::from(pin_mv) * config::VIN_DIVIDER / 1000;
+                        let mut power = context.power.lock().unwrap();
+                        power.sample(mv);
+                        if mv < config::VIN_DIP_MV {
+                            dip = Some(dip.map_or((Instant::now(), mv), |(since, min)| (since, min.min(mv))));
+                        } else if let Some((since, min_mv)) = dip.take() {
+                            let duration_ms = since.elapsed().as_millis() as u64;
+                            log::warn!("VIN dipped to {} mV for {} ms", min_mv, duration_ms);
+                            power.log(PowerEvent::Dip { t: now(), min_mv, duration_ms });
+                        }
+                    }
+                    Err(error) => {
+                        if !failing {
+                            log::warn!("VIN read failed: {}", error);
+                        }
+                        failing = true;
+                    }
+                }
+                thread::sleep(SAMPLE_INTERVAL);
+            }
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the VIN monitor: {}", error);
+    }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let power = Api::json(|| {
+        let events = [
+            PowerEvent::Reset { t: Some(0), reason: String::new(), cause: Cause::Power },
+            PowerEvent::Dip { t: Some(0), min_mv: 0, duration_ms: 0 },
+        ];
+        json!({
+            "reset": { "reason": "", "cause": Cause::Power },
+            "vin": Vin { mv: 0, min_mv: 0, max_mv: 0 },
+            "brownouts": 0,
+            "crashes": 0,
+            "events": events,
+        })
+    });
+    http::route(server, "/api/power", Method::Get, power, move |request| {
+        let power = context.power.lock().unwrap();
+        let body = json!({
+            "reset": { "reason": power.reason, "cause": power.cause },
+            "vin": power.vin,
+            "brownouts": context.counters.get(counters::BROWNOUTS),
+            "crashes": context.counters.get(counters::CRASHES),
+            "events": power.events,
+        });
+        drop(power);
+        http::write_json(request, &body)
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::clock;
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::events::Event;
+use crate::fleet::Thresholds;
+use crate::http;
+use crate::openapi::Api;
+use crate::profiles::{self, Profile};
+use crate::rules::{self, Action, Condition, Rule};
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+const SETTINGS_FILE: &str = "preset.json";
+
+// an output rule of a preset, on the first sensor of its zone
+#[derive(Debug, Serialize)]
+struct PresetRule {
+    name: &'static str,
+    output: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    above: Option<Celsius>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    below: Option<Celsius>,
+    hysteresis: Degrees,
+    // only between these local times
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<(&'static str, &'static str)>,
+}
+
+impl PresetRule {
+    fn build(&self, preset: &str, sensor: &str) -> Rule {
+        let mut when = Vec::new();
+        if self.above.is_some() || self.below.is_some() {
+            when.push(Condition::Sensor {
+                sensor: sensor.to_string(),
+                above: self.above,
+                below: self.below,
+                hysteresis: self.hysteresis,
+                mean_hours: 0,
+            });
+        }
+        if let Some((from, to)) = self.schedule {
+            when.push(Condition::Schedule { from: from.to_string(), to: to.to_string() });
+        }
+        Rule {
+            name: self.name.to_string(),
+            when,
+            output: self.output.to_string(),
+            action: Action::Set,
+            preset: Some(preset.to_string()),
+        }
+    }
+}
+
+// a complete starting setup for one kind of install: the sensors go into its zone, with its
+// alarm limits and sample interval, and its output rules replace those of the preset before
+#[derive(Debug, Serialize)]
+pub struct Preset {
+    name: &'static str,
+    description: &'static str,
+    zone: &'static str,
+    sample_interval_secs: u32,
+    alarm_low: Celsius,
+    alarm_high: Celsius,
+    alarm_delay_minutes: u32,
+    rules: &'static [PresetRule],
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "home_heating",
+        description: "room temperature with the heating on GPIO18 below 20 °C",
+        zone: "rooms",
+        sample_interval_secs: 60,
+        alarm_low: Celsius(10.0),
+        alarm_high: Celsius(28.0),
+        alarm_delay_minutes: 30,
+        rules: &[PresetRule { name: "heating", output: "gpio18", above: None, below: Some(Celsius(20.0)), hysteresis: Degrees(0.5), schedule: None }],
+    },
+    Preset {
+        name: "aquarium",
+        description: "tank heater on GPIO18 holding 25 °C, light on GPIO19 from 10:00 to 20:00",
+        zone: "tank",
+        sample_interval_secs: 60,
+        alarm_low: Celsius(23.0),
+        alarm_high: Celsius(29.0),
+        alarm_delay_minutes: 10,
+        rules: &[
+            PresetRule { name: "heater", output: "gpio18", above: None, below: Some(Celsius(25.0)), hysteresis: Degrees(0.5), schedule: None },
+            PresetRule { name: "light", output: "gpio19", above: None, below: None, hysteresis: Degrees(0.0), schedule: Some(("10:00", "20:00")) },
+        ],
+    },
+    Preset {
+        name: "greenhouse",
+        description: "vent fan on GPIO18 above 28 °C, frost heater on GPIO19 below 5 °C",
+        zone: "greenhouse",
+        sample_interval_secs: 120,
+        alarm_low: Celsius(2.0),
+        alarm_high: Celsius(38.0),
+        alarm_delay_minutes: 15,
+        rules: &[
+            PresetRule { name: "vent", output: "gpio18", above: Some(Celsius(28.0)), below: None, hysteresis: Degrees(2.0), schedule: None },
+            PresetRule { name: "frost_heater", output: "gpio19", above: None, below: Some(Celsius(5.0)), hysteresis: Degrees(1.0), schedule: None },
+        ],
+    },
+    Preset {
+        name: "freezer",
+        description: "freezer monitor, alarms outside -30 to -15 °C that outlast a defrost cycle",
+        zone: "freezer",
+        sample_interval_secs: 60,
+        alarm_low: Celsius(-30.0),
+        alarm_high: Celsius(-15.0),
+        alarm_delay_minutes: 45,
+        rules: &[],
+    },
+];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Presets {
+    // the preset last applied; the settings it made can have been changed since
+    pub active: Option<String>,
+    pub since: Option<u64>,
+    pub by: Option<String>,
+}
+
+impl Presets {
+    pub fn load() -> Self {
+        storage::read_json(SETTINGS_FILE).unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        storage::write_json(SETTINGS_FILE, self).map_err(|error| error.to_string())
+    }
+}
+
+// the DS18B20s, by their bare address; the I2C sensors are named after their chip
+fn is_one_wire(sensor: &str) -> bool {
+    sensor.len() == 16 && sensor.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// the 1-Wire sensors without a zone, or in the zone of the preset applied before, join the
+// preset's, then its limits, sample interval and rules are applied like a configuration
+// profile; nothing changes if there are no such sensors
+pub fn apply(context: &Context, name: &str, by: &str) -> Result<(), String> {
+    let preset = PRESETS.iter().find(|preset| preset.name == name).ok_or_else(|| {
+        let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+        format!("no preset {}, one of {}", name, names.join(", "))
+    })?;
+    let previous_zone = {
+        let presets = context.presets.lock().unwrap();
+        PRESETS.iter().find(|previous| presets.active.as_deref() == Some(previous.name)).map(|previous| previous.zone)
+    };
+
+    let mut registry = context.registry.lock().unwrap();
+    let mut sensors: Vec<String> = registry.sensors().map(|(sensor, _)| sensor.clone()).collect();
+    for reading in context.latest_readings() {
+        if !sensors.contains(&reading.sensor) {
+            sensors.push(reading.sensor);
+        }
+    }
+    sensors.retain(|sensor| is_one_wire(sensor));
+    let found = !sensors.is_empty();
+    sensors.retain(|sensor| {
+        let zone = registry.get(sensor).and_then(|info| info.zone.as_deref());
+        zone.map_or(true, |zone| zone == preset.zone || Some(zone) == previous_zone)
+    });
+    if sensors.is_empty() {
+        return Err(if found {
+            format!("every sensor is in a zone of its own, clear the zone of those meant for {}", preset.zone)
+        } else {
+            "no sensors found yet, connect them first".to_string()
+        });
+    }
+    sensors.sort();
+    let rules: Vec<Rule> = preset.rules.iter().map(|rule| rule.build(preset.name, &sensors[0])).collect();
+    for sensor in &sensors {
+        let mut info = registry.get(sensor).cloned().unwrap_or_default();
+        info.zone = Some(preset.zone.to_string());
+        registry.update(sensor, info);
+    }
+    registry.save().map_err(|error| error.to_string())?;
+    drop(registry);
+
+    let profile = Profile {
+        sample_interval_secs: Some(preset.sample_interval_secs),
+        thresholds: vec![Thresholds {
+            zone: Some(preset.zone.to_string()),
+            alarm_low: Some(Some(preset.alarm_low)),
+            alarm_high: Some(Some(preset.alarm_high)),
+            alarm_delay_minutes: Some(Some(preset.alarm_delay_minutes)),
+        }],
+        sections: Map::new(),
+    };
+    profiles::apply_profile(context, &profile)?;
+    // the rules set up by hand stay, the last preset's go even when this one has none
+    let mut kept: Vec<Rule> = context.rules.lock().unwrap().settings.rules.clone();
+    kept.retain(|rule| rule.preset.is_none());
+    kept.extend(rules);
+    rules::apply(context, rules::Settings { rules: kept }).map_err(|(_, error)| format!("rules: {}", error))?;
+
+    let mut presets = context.presets.lock().unwrap();
+    presets.active = Some(name.to_string());
+    presets.since = Some(clock::now_unix());
+    presets.by = Some(by.to_string());
+    presets.save()?;
+    drop(presets);
+    log::info!("applied the {} preset over {} to {} sensors", name, by, sensors.len());
+    context.events.publish(Event::ConfigChanged("presets"));
+    Ok(())
+}
+
+pub fn to_json(context: &Context) -> Value {
+    let presets = context.presets.lock().unwrap();
+    json!({
+        "active": presets.active,
+        "since": presets.since,
+        "by": presets.by,
+        "presets": PRESETS,
+    })
+}
+
+#[derive(Deserialize)]
+struct Select {
+    preset: String,
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let list_context = context.clone();
+    let list = || json!({ "active": "", "since": 0, "by": "", "presets": PRESETS });
+    http::route(server, "/api/presets", Method::Get, Api::json(list), move |request| {
+        http::write_json(request, &to_json(&list_context))
+    })?;
+
+    // {"preset": "aquarium"}
+    let select = Api::json(list).body(|| json!({ "preset": "" }));
+    http::route(server, "/api/presets/apply", Method::Post, select, move |mut request| {
+        let select = match http::read_json::<Select>(&mut request, 256)? {
+            Ok(select) => select,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let mut args = Map::new();
+        args.insert("preset".to_string(), json!(select.preset));
+        match commands::run(&context, "preset", &args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}
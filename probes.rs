@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::registry::Registry;
+use crate::units::Celsius;
+
+#[derive(Serialize)]
+pub struct DepthPoint {
+    pub sensor: String,
+    pub depth_cm: f32,
+    // None if the sensor didn't answer this cycle
+    pub celsius: Option<Celsius>,
+}
+
+#[derive(Serialize)]
+pub struct Gradient {
+    pub from_cm: f32,
+    pub to_cm: f32,
+    // positive when it gets warmer with depth
+    pub celsius_per_m: f32,
+}
+
+#[derive(Serialize)]
+pub struct Profile {
+    pub probe: String,
+    pub points: Vec<DepthPoint>,
+    pub gradients: Vec<Gradient>,
+}
+
+// every probe in the registry with its sensors ordered from shallowest to deepest
+pub fn profiles(registry: &Registry, readings: &[Reading]) -> Vec<Profile> {
+    let mut probes: BTreeMap<&str, Vec<DepthPoint>> = BTreeMap::new();
+    for (sensor, info) in registry.sensors() {
+        let (Some(probe), Some(depth_cm)) = (&info.probe, info.depth_cm) else {
+            continue;
+        };
+        probes.entry(probe).or_default().push(DepthPoint {
+            sensor: sensor.clone(),
+            depth_cm,
+            celsius: readings.iter().find(|reading| &reading.sensor == sensor).map(|reading| reading.celsius),
+        });
+    }
+
+    probes
+        .into_iter()
+        .map(|(probe, mut points)| {
+            points.sort_by(|a, b| a.depth_cm.total_cmp(&b.depth_cm));
+            // between neighbouring sensors that both answered, skipping missing ones
+            let answered: Vec<(f32, Celsius)> = points
+                .iter()
+                .filter_map(|point| Some((point.depth_cm, point.celsius?)))
+                .collect();
+            let gradients = answered
+                .windows(2)
+                .filter(|pair| pair[1].0 > pair[0].0)
+                .map(|pair| Gradient {
+                    from_cm: pair[0].0,
+                    to_cm: pair[1].0,
+                    celsius_per_m: (pair[1].1 - pair[0].1).0 / ((pair[1].0 - pair[0].0) / 100.0),
+                })
+                .collect();
+            Profile {
+                probe: probe.to_string(),
+                points,
+                gradients,
+            }
+        })
+        .collect()
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    // ?probe=<name> for a single probe
+    let probes = Api::json(|| {
+        json!([Profile {
+            probe: String::new(),
+            points: vec![DepthPoint { sensor: String::new(), depth_cm: 0.0, celsius: Some(Celsius(0.0)) }],
+            gradients: vec![Gradient { from_cm: 0.0, to_cm: 0.0, celsius_per_m: 0.0 }],
+        }])
+    });
+    http::route(server, "/api/probes", Method::Get, probes.query(&[("probe", "string")]), move |request| {
+        let readings = context.latest_readings();
+        let mut profiles = profiles(&context.registry.lock().unwrap(), &readings);
+        if let Some(probe) = http::query_param(request.uri(), "probe") {
+            profiles.retain(|profile| profile.probe == probe);
+            if profiles.is_empty() {
+                return http::write_error(request, 404, "no such probe");
+            }
+        }
+        http::write_json(request, &json!(profiles))
+    })?;
+
+    Ok(())
+}
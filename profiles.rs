@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::clock;
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::events::Event;
+use crate::fleet::{self, Thresholds};
+use crate::http;
+use crate::openapi::Api;
+use crate::storage;
+use crate::twin;
+
+const SETTINGS_FILE: &str = "profiles.json";
+
+// a named set of changes made together, e.g. a winter profile with tighter freeze alarms and a
+// shorter sample interval; what a profile leaves out stays as it is
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub sample_interval_secs: Option<u32>,
+    // as in a fleet message, one entry per zone
+    pub thresholds: Vec<Thresholds>,
+    // device twin sections in the form their POST endpoints take, e.g. {"schedule": {...}}
+    pub sections: Map<String, Value>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Settings {
+    fn validate(&self) -> Result<(), String> {
+        for (name, profile) in &self.profiles {
+            for section in profile.sections.keys() {
+                // a rule switching profiles could otherwise replace itself
+                if section == "rules" {
+                    return Err(format!("{}: a profile can't change the rules", name));
+                }
+                if twin::section_names().all(|known| known != section) {
+                    return Err(format!("{}: {} is not a twin section", name, section));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profiles {
+    pub settings: Settings,
+    // the profile last switched to, kept over a reset
+    pub active: Option<String>,
+    pub since: Option<u64>,
+    pub by: Option<String>,
+}
+
+impl Profiles {
+    pub fn load() -> Self {
+        storage::read_json(SETTINGS_FILE).unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        storage::write_json(SETTINGS_FILE, self).map_err(|error| error.to_string())
+    }
+}
+
+pub fn switch(context: &Context, name: &str, by: &str) -> Result<(), String> {
+    let profile = context
+        .profiles
+        .lock()
+        .unwrap()
+        .settings
+        .profiles
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("no profile {}", name))?;
+    apply_profile(context, &profile)?;
+
+    let mut profiles = context.profiles.lock().unwrap();
+    profiles.active = Some(name.to_string());
+    profiles.since = Some(clock::now_unix());
+    profiles.by = Some(by.to_string());
+    profiles.save()?;
+    drop(profiles);
+    log::info!("switched to the {} profile over {}", name, by);
+    context.events.publish(Event::ConfigChanged("profiles"));
+    Ok(())
+}
+
+// applies the profile's changes, each like its own endpoint would; the first that fails stops
+// it, with what came before it in effect
+pub fn apply_profile(context: &Context, profile: &Profile) -> Result<(), String> {
+    if let Some(secs) = profile.sample_interval_secs {
+        context.fleet.lock().unwrap().set_sample_interval(secs).map_err(|error| format!("sample interval: {}", error))?;
+    }
+    for thresholds in &profile.thresholds {
+        fleet::apply_thresholds(context, thresholds)?;
+    }
+    if !profile.thresholds.is_empty() {
+        context.events.publish(Event::ConfigChanged("sensors"));
+    }
+    for (section, value) in &profile.sections {
+        twin::apply_section(context, section, value).map_err(|(_, error)| format!("{}: {}", section, error))?;
+    }
+    Ok(())
+}
+
+// validated and saved, the active profile isn't applied again
+pub fn apply(context: &Context, settings: Settings) -> Result<(), (u16, String)> {
+    settings.validate().map_err(|message| (400, message))?;
+    let mut profiles = context.profiles.lock().unwrap();
+    profiles.settings = settings;
+    profiles.save().map_err(|error| (500, error))?;
+    drop(profiles);
+    context.events.publish(Event::ConfigChanged("profiles"));
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Activate {
+    profile: String,
+}
+
+fn settings_example() -> Settings {
+    let profile = Profile { sample_interval_secs: Some(0), thresholds: vec![Thresholds::example()], sections: Map::new() };
+    Settings { profiles: BTreeMap::from([(String::new(), profile)]) }
+}
+
+fn profiles_example() -> Value {
+    json!(Profiles { settings: settings_example(), active: Some(String::new()), since: Some(0), by: Some(String::new()) })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/profiles", Method::Get, Api::json(profiles_example), move |request| {
+        let body = json!(*status_context.profiles.lock().unwrap());
+        http::write_json(request, &body)
+    })?;
+
+    // {"profiles": {"winter": {"sample_interval_secs": 60, "thresholds": [{"zone": "pipes",
+    //  "alarm_low": 4}]}, "summer": {"sample_interval_secs": 300, "thresholds": [{"zone":
+    //  "pipes", "alarm_low": 1}]}}}
+    let settings_context = context.clone();
+    let settings = Api::json(|| json!(settings_example())).body(|| json!(settings_example()));
+    http::route(server, "/api/profiles", Method::Post, settings, move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 4096)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let body = json!(settings);
+        match apply(&settings_context, settings) {
+            Ok(()) => http::write_json(request, &body),
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    // {"profile": "winter"}
+    let activate = Api::json(profiles_example).body(|| json!({ "profile": "" }));
+    http::route(server, "/api/profiles/active", Method::Post, activate, move |mut request| {
+        let activate = match http::read_json::<Activate>(&mut request, 256)? {
+            Ok(activate) => activate,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let mut args = Map::new();
+        args.insert("profile".to_string(), json!(activate.profile));
+        match commands::run(&context, "profile", &args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map};
+
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+// progress is persisted on every step change, in between with the counters
+const STATE_FILE: &str = "program.json";
+
+// one step of a temperature program, ramps start from wherever the previous step ended
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Step {
+    Hold { celsius: Celsius, minutes: u32 },
+    Ramp { celsius: Celsius, minutes: u32 },
+    // like hold, but the time only starts counting once the temperature is within tolerance
+    Soak { celsius: Celsius, minutes: u32, tolerance: Degrees },
+}
+
+impl Step {
+    fn duration_secs(&self) -> u64 {
+        match self {
+            Step::Hold { minutes, .. } | Step::Ramp { minutes, .. } | Step::Soak { minutes, .. } => {
+                u64::from(*minutes) * 60
+            }
+        }
+    }
+
+    fn target(&self) -> Celsius {
+        match self {
+            Step::Hold { celsius, .. } | Step::Ramp { celsius, .. } | Step::Soak { celsius, .. } => *celsius,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Program {
+    pub steps: Vec<Step>,
+}
+
+// progress through a program, small enough to persist so a reboot resumes where it left off
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Run {
+    pub step: usize,
+    pub step_elapsed_secs: u64,
+    // setpoint at the start of the current step, where a ramp starts from
+    pub step_start_celsius: Celsius,
+    // a soak step has reached its temperature and is counting down
+    #[serde(default)]
+    pub soaking: bool,
+}
+
+impl Run {
+    pub fn start(start_celsius: Celsius) -> Self {
+        Self {
+            step: 0,
+            step_elapsed_secs: 0,
+            step_start_celsius: start_celsius,
+            soaking: false,
+        }
+    }
+
+    pub fn finished(&self, program: &Program) -> bool {
+        self.step >= program.steps.len()
+    }
+
+    // the setpoint for the current position, None once the program is finished
+    pub fn setpoint(&self, program: &Program) -> Option<Celsius> {
+        let step = program.steps.get(self.step)?;
+        Some(match step {
+            Step::Hold { celsius, .. } | Step::Soak { celsius, .. } => *celsius,
+            Step::Ramp { celsius, .. } => {
+                let duration = step.duration_secs();
+                if duration == 0 {
+                    *celsius
+                } else {
+                    let progress = self.step_elapsed_secs.min(duration) as f32 / duration as f32;
+                    self.step_start_celsius + (*celsius - self.step_start_celsius) * progress
+                }
+            }
+        })
+    }
+
+    // moves the run forward by `secs`, returns true if that changed the current step
+    pub fn advance(&mut self, program: &Program, secs: u64, celsius: Option<Celsius>) -> bool {
+        let start_step = self.step;
+        let mut remaining = secs;
+        while let Some(step) = program.steps.get(self.step) {
+            if let Step::Soak { celsius: target, tolerance, .. } = step {
+                if !self.soaking {
+                    match celsius {
+                        Some(celsius) if (celsius - *target).abs() <= *tolerance => self.soaking = true,
+                        _ => break,
+                    }
+                }
+            }
+
+            let left = step.duration_secs() - self.step_elapsed_secs.min(step.duration_secs());
+            if remaining < left {
+                self.step_elapsed_secs += remaining;
+                break;
+            }
+            remaining -= left;
+            self.step += 1;
+            self.step_elapsed_secs = 0;
+            self.step_start_celsius = step.target();
+            self.soaking = false;
+        }
+        self.step != start_step
+    }
+}
+
+// what the active controller is doing with its outputs
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(untagged)]
+pub enum ControlOutput {
+    Thermostat(crate::thermostat::Output),
+    // heater duty cycle, 0..1
+    Duty(f32),
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ProgramState {
+    // the probe whose temperature the program controls
+    pub sensor: Option<String>,
+    pub program: Program,
+    pub run: Option<Run>,
+    #[serde(skip)]
+    pub setpoint: Option<Celsius>,
+    #[serde(skip)]
+    pub output: Option<ControlOutput>,
+    // the run moved on since the last save
+    #[serde(skip)]
+    dirty: bool,
+}
+
+// result of moving the program forward for one control cycle
+pub struct Tick {
+    pub setpoint: Option<Celsius>,
+    pub celsius: Option<Celsius>,
+    // the program ended during this tick
+    pub finished: bool,
+}
+
+impl ProgramState {
+    pub fn load() -> Self {
+        storage::read_json(STATE_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&mut self) {
+        self.dirty = false;
+        if let Err(error) = storage::write_json(STATE_FILE, self) {
+            log::warn!("failed to save program state: {}", error);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.save();
+        }
+    }
+
+    pub fn sensor_celsius(&self, readings: &[Reading]) -> Option<Celsius> {
+        let sensor = self.sensor.as_ref()?;
+        readings
+            .iter()
+            .find(|reading| &reading.sensor == sensor)
+            .map(|reading| reading.celsius)
+    }
+}
+
+// advances the shared program state by wall time, for use by the profile controllers
+#[derive(Default)]
+pub struct Ticker {
+    last_update: Option<Instant>,
+}
+
+impl Ticker {
+    pub fn tick(&mut self, state: &mut ProgramState, readings: &[Reading]) -> Tick {
+        // the program moves in whole seconds, the remainder is carried over to the next update
+        let now = Instant::now();
+        let elapsed = self.last_update.map_or(0, |last| (now - last).as_secs());
+        self.last_update = Some(self.last_update.map_or(now, |last| last + Duration::from_secs(elapsed)));
+
+        let celsius = state.sensor_celsius(readings);
+        let ProgramState { program, run, .. } = state;
+        let Some(active) = run.as_mut() else {
+            return Tick {
+                setpoint: None,
+                celsius,
+                finished: false,
+            };
+        };
+
+        let step_changed = active.advance(program, elapsed, celsius);
+        let setpoint = active.setpoint(program);
+        let finished = active.finished(program);
+        if finished {
+            *run = None;
+        }
+        state.setpoint = setpoint;
+
+        if step_changed || finished {
+            state.save();
+        } else if elapsed > 0 {
+            state.dirty = true;
+        }
+        Tick {
+            setpoint,
+            celsius,
+            finished,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StartRequest {
+    sensor: String,
+    program: Program,
+}
+
+// a program with a step of each type
+fn program_example() -> Program {
+    let (celsius, minutes) = (Celsius(0.0), 0);
+    Program {
+        steps: vec![
+            Step::Hold { celsius, minutes },
+            Step::Ramp { celsius, minutes },
+            Step::Soak { celsius, minutes, tolerance: Degrees(0.0) },
+        ],
+    }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    // the output is the thermostat's state ("heating", ...) or a heater duty cycle
+    let status = Api::json(|| {
+        json!({
+            "sensor": "",
+            "program": program_example(),
+            "run": Run::start(Celsius(0.0)),
+            "setpoint": Celsius(0.0),
+            "output": null,
+        })
+    });
+    http::route(server, "/api/program", Method::Get, status, move |request| {
+        let state = status_context.program.lock().unwrap().clone();
+        http::write_json(
+            request,
+            &json!({
+                "sensor": state.sensor,
+                "program": state.program,
+                "run": state.run,
+                "setpoint": state.setpoint,
+                "output": state.output,
+            }),
+        )
+    })?;
+
+    // starts (or restarts) a program; ramps in the first step start from the current temperature
+    let start_context = context.clone();
+    let start = Api::json(|| json!({ "started": true })).body(|| json!({ "sensor": "", "program": program_example() }));
+    http::route(server, "/api/program", Method::Post, start, move |mut request| {
+        let start = match http::read_json::<StartRequest>(&mut request, 4096)? {
+            Ok(start) => start,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        if start.program.steps.is_empty() {
+            return http::write_error(request, 400, "program has no steps");
+        }
+        let current = start_context
+            .latest_readings()
+            .iter()
+            .find(|reading| reading.sensor == start.sensor)
+            .map(|reading| reading.celsius);
+        let Some(current) = current else {
+            return http::write_error(request, 404, "sensor not found on the bus");
+        };
+
+        let mut state = start_context.program.lock().unwrap();
+        state.sensor = Some(start.sensor);
+        state.program = start.program;
+        state.run = Some(Run::start(current));
+        state.save();
+        drop(state);
+        start_context.alarms.clear(crate::runner::PROGRAM_DONE_ALARM);
+        http::write_json(request, &json!({ "started": true }))
+    })?;
+
+    let stop_context = context;
+    http::route(server, "/api/program", Method::Delete, Api::json(|| json!({ "stopped": true })), move |request| {
+        match commands::run(&stop_context, "stop_program", &Map::new(), Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 500, &error),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use embedded_hal::digital::v2::OutputPin;
+use esp_idf_hal::delay::FreeRtos;
+
+const WINDOW_MS: u32 = 2_000;
+const RESOLUTION_MS: u32 = 100;
+
+// slow software PWM for relays and SSRs, switching at most once per window
+#[derive(Clone)]
+pub struct TimeProportional {
+    // duty cycle as f32 bits, written by the controller and read by the output thread
+    duty: Arc<AtomicU32>,
+    // a one-off high pulse in ms, taken by the output thread at the start of the next window
+    pulse_ms: Arc<AtomicU32>,
+}
+
+impl TimeProportional {
+    pub fn start<P: OutputPin + Send + 'static>(mut pin: P) -> Self {
+        let duty = Arc::new(AtomicU32::new(0f32.to_bits()));
+        let thread_duty = duty.clone();
+        let pulse_ms = Arc::new(AtomicU32::new(0));
+        let thread_pulse = pulse_ms.clone();
+        thread::Builder::new()
+            .name("pwm".into())
+            .stack_size(2048)
+            .spawn(move || loop {
+                let pulse = thread_pulse.swap(0, Ordering::Relaxed);
+                if pulse > 0 {
+                    let _ = pin.set_high();
+                    FreeRtos::delay_ms(pulse);
+                    let _ = pin.set_low();
+                }
+                let on_ms = (f32::from_bits(thread_duty.load(Ordering::Relaxed)) * WINDOW_MS as f32) as u32;
+                let on_ms = on_ms / RESOLUTION_MS * RESOLUTION_MS;
+                if on_ms > 0 {
+                    let _ = pin.set_high();
+                    FreeRtos::delay_ms(on_ms);
+                }
+                let _ = pin.set_low();
+                if on_ms < WINDOW_MS {
+                    FreeRtos::delay_ms(WINDOW_MS - on_ms);
+                }
+            })
+            .expect("failed to start the pwm output");
+        Self { duty, pulse_ms }
+    }
+
+    pub fn set(&self, duty: f32) {
+        self.duty.store(duty.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn pulse(&self, ms: u32) {
+        self.pulse_ms.store(ms, Ordering::Relaxed);
+    }
+}
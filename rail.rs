@@ -0,0 +1,190 @@
+use std::fmt::Debug;
+
+use embedded_hal::blocking::i2c::WriteRead;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::config;
+use crate::context::Context;
+use crate::ina::{Ina, Sample};
+
+// a short: the current jumps to this many times the normal draw, and by at least the margin
+const SHORT_FACTOR: f32 = 3.0;
+const SHORT_MARGIN_MA: f32 = 20.0;
+// an open line: it falls below this share of a normal draw of at least OPEN_MIN_BASELINE_MA;
+// the sensors draw about 1 mA each while converting, which is when the rail is measured
+const OPEN_FRACTION: f32 = 0.25;
+const OPEN_MIN_BASELINE_MA: f32 = 0.5;
+// the normal draw is learnt over this many conversions before it alarms, then follows slowly
+const LEARN_SAMPLES: u32 = 5;
+const BASELINE_WEIGHT: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Health {
+    Learning,
+    Ok,
+    Short,
+    Open,
+}
+
+#[derive(Debug)]
+struct Channel {
+    name: String,
+    volts: f32,
+    milliamps: f32,
+    baseline_ma: Option<f32>,
+    samples: u32,
+    min_ma: f32,
+    max_ma: f32,
+    health: Health,
+    shorts: u64,
+    opens: u64,
+}
+
+impl Channel {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            volts: 0.0,
+            milliamps: 0.0,
+            baseline_ma: None,
+            samples: 0,
+            min_ma: f32::MAX,
+            max_ma: 0.0,
+            health: Health::Learning,
+            shorts: 0,
+            opens: 0,
+        }
+    }
+
+    // above and below what the current is a short and an open line, None while unknown
+    fn limits(&self) -> (Option<f32>, Option<f32>) {
+        let learnt = self.baseline_ma.filter(|_| self.samples >= LEARN_SAMPLES);
+        let short = match config::RAIL_MAX_MA {
+            0 => learnt.map(|baseline| (baseline * SHORT_FACTOR).max(baseline + SHORT_MARGIN_MA)),
+            max => Some(max as f32),
+        };
+        let open = match config::RAIL_MIN_MA {
+            0 => learnt.filter(|baseline| *baseline >= OPEN_MIN_BASELINE_MA).map(|baseline| baseline * OPEN_FRACTION),
+            min => Some(min as f32),
+        };
+        (short, open)
+    }
+
+    fn update(&mut self, sample: &Sample) {
+        let (short, open) = self.limits();
+        let milliamps = sample.milliamps;
+        let health = match (short, open) {
+            (Some(short), _) if milliamps > short => Health::Short,
+            (_, Some(open)) if milliamps < open => Health::Open,
+            _ if self.samples < LEARN_SAMPLES => Health::Learning,
+            _ => Health::Ok,
+        };
+        if health != self.health {
+            match health {
+                Health::Short => self.shorts += 1,
+                Health::Open => self.opens += 1,
+                _ => {}
+            }
+        }
+        // a fault isn't what the rail normally draws
+        if matches!(health, Health::Learning | Health::Ok) {
+            let weight = if self.samples < LEARN_SAMPLES { 1.0 / (self.samples + 1) as f32 } else { BASELINE_WEIGHT };
+            self.baseline_ma = Some(self.baseline_ma.map_or(milliamps, |baseline| baseline + (milliamps - baseline) * weight));
+            self.samples += 1;
+        }
+        self.health = health;
+        self.volts = sample.volts;
+        self.milliamps = milliamps;
+        self.min_ma = self.min_ma.min(milliamps);
+        self.max_ma = self.max_ma.max(milliamps);
+    }
+
+    fn to_json(&self) -> Value {
+        let (short, open) = self.limits();
+        json!({
+            "name": self.name,
+            "volts": self.volts,
+            "milliamps": self.milliamps,
+            "baseline_ma": self.baseline_ma,
+            "short_above_ma": short,
+            "open_below_ma": open,
+            "min_ma": (self.min_ma <= self.max_ma).then_some(self.min_ma),
+            "max_ma": self.max_ma,
+            "health": self.health,
+            "shorts": self.shorts,
+            "opens": self.opens,
+        })
+    }
+}
+
+// the sensor supply rail, measured in the middle of every conversion
+#[derive(Debug, Default)]
+pub struct Rail {
+    chip: Option<&'static str>,
+    channels: Vec<Channel>,
+    samples: u64,
+    read_failures: u64,
+}
+
+impl Rail {
+    pub fn start(&mut self, ina: &Ina) {
+        self.chip = Some(ina.name());
+        self.channels = ina.channels().into_iter().map(Channel::new).collect();
+    }
+
+    pub fn to_json(&self) -> Value {
+        let channels: Vec<Value> = self.channels.iter().map(Channel::to_json).collect();
+        json!({
+            "chip": self.chip,
+            "samples": self.samples,
+            "read_failures": self.read_failures,
+            "channels": channels,
+        })
+    }
+}
+
+pub fn rail_alarm_id(channel: &str) -> String {
+    format!("rail_{}", channel)
+}
+
+pub fn measure<I, E>(context: &Context, ina: &Ina, i2c: &mut I)
+    where
+        I: WriteRead<Error=E>,
+        E: Debug,
+{
+    let samples = ina.read(i2c);
+    let mut rail = context.rail.lock().unwrap();
+    let samples = match samples {
+        Ok(samples) => samples,
+        Err(error) => {
+            rail.read_failures += 1;
+            log::warn!("{} read failed: {:?}", ina.name(), error);
+            return;
+        }
+    };
+    rail.samples += 1;
+    for sample in &samples {
+        let Some(channel) = rail.channels.iter_mut().find(|channel| channel.name == sample.channel) else {
+            continue;
+        };
+        let was = channel.health;
+        channel.update(sample);
+        let id = rail_alarm_id(&channel.name);
+        match channel.health {
+            Health::Short => context.alarms.raise(
+                &id,
+                format!("sensor supply {} draws {:.1} mA, normally {:.1} mA: short?", channel.name, sample.milliamps, channel.baseline_ma.unwrap_or(0.0)),
+            ),
+            Health::Open => context.alarms.raise(
+                &id,
+                format!("sensor supply {} draws {:.2} mA, normally {:.1} mA: open line?", channel.name, sample.milliamps, channel.baseline_ma.unwrap_or(0.0)),
+            ),
+            _ => context.alarms.clear(&id),
+        }
+        if channel.health != was {
+            log::info!("sensor supply {} {:?} at {:.2} V, {:.2} mA", channel.name, channel.health, sample.volts, sample.milliamps);
+        }
+    }
+}
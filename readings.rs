@@ -0,0 +1,59 @@
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use one_wire_bus::Address;
+use serde::Serialize;
+
+use crate::units::Celsius;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Reading {
+    pub sensor: String,
+    pub celsius: Celsius,
+    // relative humidity in %, for sensors that measure it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f32>,
+}
+
+// stable text form of a ROM address, used as the sensor id in topics and APIs
+pub fn sensor_id(address: &Address) -> String {
+    format!("{:016X}", address.0)
+}
+
+// one set of readings as published, numbered from 1 at boot
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub version: u64,
+    pub readings: Vec<Reading>,
+}
+
+// the latest readings, swapped in whole: a reader only holds the lock to clone the Arc and the
+// sampler only to swap it, so neither ever waits on the other's work
+#[derive(Default)]
+pub struct ReadingsCache {
+    current: Mutex<Arc<Snapshot>>,
+    changed: Condvar,
+}
+
+impl ReadingsCache {
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.current.lock().unwrap().clone()
+    }
+
+    // only the sampling loop publishes; the previous snapshot is freed, if no reader still has
+    // it, after the lock is released
+    pub fn publish(&self, readings: Vec<Reading>) {
+        let next = Arc::new(Snapshot { version: self.snapshot().version + 1, readings });
+        let previous = mem::replace(&mut *self.current.lock().unwrap(), next);
+        self.changed.notify_all();
+        drop(previous);
+    }
+
+    // the snapshot after version `seen`, or the current one once `timeout` passes
+    pub fn wait_past(&self, seen: u64, timeout: Duration) -> Arc<Snapshot> {
+        let current = self.current.lock().unwrap();
+        let (current, _) = self.changed.wait_timeout_while(current, timeout, |current| current.version == seen).unwrap();
+        current.clone()
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+const SETTINGS_FILE: &str = "redundancy.json";
+
+// two or three probes measuring the same point, published as one logical sensor named after
+// the group that controllers and alarms can use instead of any single probe
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Group {
+    pub name: String,
+    pub sensors: Vec<String>,
+    // a probe further than this from the median is outvoted
+    #[serde(default = "default_max_divergence")]
+    pub max_divergence: Degrees,
+}
+
+fn default_max_divergence() -> Degrees {
+    Degrees(1.0)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub groups: Vec<Group>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        storage::read_json(SETTINGS_FILE).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Vote {
+    // None when the probes disagree without a majority, e.g. two probes that diverged: a
+    // missing sensor puts the controllers in their safe state and raises its alarms
+    pub celsius: Option<Celsius>,
+    pub outvoted: Vec<String>,
+    pub missing: Vec<String>,
+}
+
+fn median(values: &mut [Celsius]) -> Celsius {
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        values[middle - 1] + (values[middle] - values[middle - 1]) * 0.5
+    } else {
+        values[middle]
+    }
+}
+
+pub fn vote(group: &Group, readings: &[Reading]) -> Vote {
+    let mut present = Vec::new();
+    let mut missing = Vec::new();
+    for sensor in &group.sensors {
+        match readings.iter().find(|reading| &reading.sensor == sensor) {
+            Some(reading) => present.push((sensor.clone(), reading.celsius)),
+            None => missing.push(sensor.clone()),
+        }
+    }
+    if present.is_empty() {
+        return Vote { celsius: None, outvoted: Vec::new(), missing };
+    }
+
+    let center = median(&mut present.iter().map(|(_, celsius)| *celsius).collect::<Vec<_>>());
+    let (mut agreeing, mut outvoted) = (Vec::new(), Vec::new());
+    for (sensor, celsius) in present.iter() {
+        if (*celsius - center).abs() <= group.max_divergence {
+            agreeing.push(*celsius);
+        } else {
+            outvoted.push(sensor.clone());
+        }
+    }
+    let celsius = (agreeing.len() * 2 > present.len()).then(|| median(&mut agreeing));
+    Vote { celsius, outvoted, missing }
+}
+
+fn alarm_id(group: &Group) -> String {
+    format!("divergence_{}", group.name)
+}
+
+fn degraded_alarm_id(group: &Group) -> String {
+    format!("degraded_{}", group.name)
+}
+
+// adds a reading per group and raises a divergence alarm while any of its probes is outvoted,
+// and a degraded alarm while any doesn't answer: the others carry on without a cross-check
+pub fn add_logical_sensors(context: &Context, readings: &mut Vec<Reading>) {
+    let settings = context.redundancy.lock().unwrap();
+    let mut logical = Vec::new();
+    for group in &settings.groups {
+        let vote = vote(group, readings);
+        if vote.outvoted.is_empty() {
+            context.alarms.clear(&alarm_id(group));
+        } else {
+            let message = match vote.celsius {
+                Some(celsius) => format!("{} outvoted, {} uses {:.2}", vote.outvoted.join(", "), group.name, celsius),
+                None => format!("probes of {} disagree, no majority", group.name),
+            };
+            context.alarms.raise(&alarm_id(group), message);
+        }
+        if vote.missing.is_empty() {
+            context.alarms.clear(&degraded_alarm_id(group));
+        } else {
+            let reporting = group.sensors.len() - vote.missing.len();
+            context.alarms.raise(
+                &degraded_alarm_id(group),
+                format!("{} of {} probes of {} report, {} missing", reporting, group.sensors.len(), group.name, vote.missing.join(", ")),
+            );
+        }
+        if let Some(celsius) = vote.celsius {
+            logical.push(Reading {
+                sensor: group.name.clone(),
+                celsius,
+                humidity: None,
+            });
+        }
+    }
+    readings.extend(logical);
+}
+
+pub fn apply(context: &Context, settings: Settings) -> Result<(), (u16, String)> {
+    if let Some(group) = settings.groups.iter().find(|group| group.sensors.len() < 2) {
+        return Err((400, format!("{} needs at least two sensors", group.name)));
+    }
+    storage::write_json(SETTINGS_FILE, &settings).map_err(|error| (500, error.to_string()))?;
+    let mut current = context.redundancy.lock().unwrap();
+    // alarms of removed groups would otherwise stay active forever
+    for group in &current.groups {
+        if !settings.groups.iter().any(|kept| kept.name == group.name) {
+            context.alarms.clear(&alarm_id(group));
+            context.alarms.clear(&degraded_alarm_id(group));
+        }
+    }
+    *current = settings;
+    drop(current);
+    context.events.publish(Event::ConfigChanged("redundancy"));
+    Ok(())
+}
+
+fn group_example() -> Group {
+    Group { name: String::new(), sensors: vec![String::new()], max_divergence: default_max_divergence() }
+}
+
+pub fn settings_example() -> Settings {
+    Settings { groups: vec![group_example()] }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| {
+        let vote = Vote { celsius: Some(Celsius(0.0)), outvoted: vec![String::new()], missing: vec![String::new()] };
+        json!([{ "group": group_example(), "vote": vote }])
+    });
+    http::route(server, "/api/redundancy", Method::Get, status, move |request| {
+        let readings = status_context.latest_readings();
+        let settings = status_context.redundancy.lock().unwrap().clone();
+        let groups: Vec<_> = settings
+            .groups
+            .iter()
+            .map(|group| json!({ "group": group, "vote": vote(group, &readings) }))
+            .collect();
+        http::write_json(request, &json!(groups))
+    })?;
+
+    // {"groups": [{"name": "tank", "sensors": ["28...", "28...", "28..."], "max_divergence": 0.5}]}
+    let settings_context = context;
+    let settings = || json!(settings_example());
+    http::route(server, "/api/redundancy", Method::Post, Api::json(settings).body(settings), move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 2048)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let body = json!(settings);
+        match apply(&settings_context, settings) {
+            Ok(()) => http::write_json(request, &body),
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+const REGISTRY_FILE: &str = "registry.json";
+
+// user-provided metadata about a sensor, keyed by its sensor id
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SensorInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // position on the floor plan, as a fraction (0..1) of the image width and height
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<f32>,
+    // sensors on one multi-sensor probe (soil spike, immersion string) share a probe name and
+    // are ordered by their depth below the probe's reference point
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth_cm: Option<f32>,
+    // sensors in one zone (a room, a cold store) are expected to read alike and are
+    // cross-checked for drift
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    // the cable behind the bus multiplexer it was last found on, "mux0" to "mux7", from the search
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bus: Option<String>,
+    // index along the cable, 1 nearest the controller, from the guided topology discovery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<u32>,
+    // alarm when the reading stays below/above these for alarm_delay_minutes (default 0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alarm_low: Option<Celsius>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alarm_high: Option<Celsius>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alarm_delay_minutes: Option<u32>,
+    // warn when the trend predicts crossing alarm_low/alarm_high within this many minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trend_warning_minutes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mounting: Option<Mounting>,
+}
+
+// where a mounting offset is applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    // /api/temps, the dashboard and the display
+    Api,
+    // controllers, alarms, compliance, trends, drift and degree days
+    Control,
+    History,
+    // MQTT, InfluxDB and Modbus
+    Export,
+}
+
+// a known, static difference between what the sensor reads and what it is meant to measure,
+// because of how it is mounted (on the pipe surface rather than in the liquid), as opposed to
+// an error of the sensor itself
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mounting {
+    // added to the reading
+    pub offset: Degrees,
+    // why, e.g. "strapped to the outside of the flow pipe, under insulation"
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+    // where the offset is applied, all of them when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub apply_to: Vec<Target>,
+}
+
+impl Mounting {
+    fn offset_for(&self, target: Target) -> Degrees {
+        if self.apply_to.is_empty() || self.apply_to.contains(&target) {
+            self.offset
+        } else {
+            Degrees(0.0)
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Registry {
+    sensors: BTreeMap<String, SensorInfo>,
+}
+
+impl Registry {
+    pub fn load() -> Self {
+        storage::read_json(REGISTRY_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        storage::write_json(REGISTRY_FILE, self)
+    }
+
+    pub fn get(&self, sensor: &str) -> Option<&SensorInfo> {
+        self.sensors.get(sensor)
+    }
+
+    pub fn sensors(&self) -> impl Iterator<Item = (&String, &SensorInfo)> {
+        self.sensors.iter()
+    }
+
+    pub fn update(&mut self, sensor: &str, info: SensorInfo) {
+        self.sensors.insert(sensor.to_string(), info);
+    }
+
+    // the readings as one output sees them, with the mounting offsets configured for it
+    pub fn adjusted(&self, readings: &[Reading], target: Target) -> Vec<Reading> {
+        readings
+            .iter()
+            .map(|reading| {
+                let mounting = self.get(&reading.sensor).and_then(|info| info.mounting.as_ref());
+                let mut reading = reading.clone();
+                reading.celsius = reading.celsius + mounting.map_or(Degrees(0.0), |mounting| mounting.offset_for(target));
+                reading
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use esp_idf_svc::http::server::ws::EspHttpWsConnection;
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use esp_idf_svc::sys::EspError;
+use esp_idf_svc::tls::X509;
+use esp_idf_svc::ws::FrameType;
+
+use crate::commands::{self, Source};
+use crate::config;
+use crate::context::Context;
+
+// one command line, like the serial console would take
+const MAX_LINE: usize = 512;
+// the dashboard server already has the default control port
+const CTRL_PORT: u16 = 32769;
+
+// the certificate and key are handed to the TLS stack for as long as the server runs
+fn pem(text: &str) -> X509<'static> {
+    X509::pem_until_nul(Box::leak(format!("{}\0", text).into_bytes().into_boxed_slice()))
+}
+
+// compares every byte so the time taken doesn't tell how much of a guess was right
+fn token_matches(given: &str) -> bool {
+    let (given, expected) = (given.as_bytes(), config::CONSOLE_TOKEN.as_bytes());
+    given.len() == expected.len() && given.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn send(ws: &mut EspHttpWsConnection, text: &str) -> Result<(), EspError> {
+    ws.send(FrameType::Text(false), text.as_bytes())
+}
+
+// the serial console over TLS: every session starts with the token as its first message,
+// then each text message is a command line, answered with what the serial console would print
+pub fn start(context: Arc<Context>) -> Result<Option<EspHttpServer<'static>>, EspError> {
+    if config::CONSOLE_TOKEN.is_empty() || config::CONSOLE_CERT.is_empty() || config::CONSOLE_KEY.is_empty() {
+        return Ok(None);
+    }
+    let mut server = EspHttpServer::new(&Configuration {
+        ctrl_port: CTRL_PORT,
+        server_certificate: Some(pem(config::CONSOLE_CERT)),
+        private_key: Some(pem(config::CONSOLE_KEY)),
+        ..Default::default()
+    })?;
+
+    let authenticated = Mutex::new(HashSet::new());
+    server.ws_handler("/console", move |ws| {
+        let session = ws.session();
+        if ws.is_new() {
+            return send(ws, "token?");
+        }
+        if ws.is_closed() {
+            authenticated.lock().unwrap().remove(&session);
+            return Ok(());
+        }
+
+        let (_, len) = ws.recv(&mut [])?;
+        if len > MAX_LINE {
+            send(ws, "error: line too long")?;
+            return ws.send(FrameType::Close, &[]);
+        }
+        let mut buffer = [0; MAX_LINE];
+        ws.recv(&mut buffer)?;
+        let line = String::from_utf8_lossy(&buffer[..len]);
+        let line = line.trim_matches(char::from(0)).trim();
+
+        if !authenticated.lock().unwrap().contains(&session) {
+            if !token_matches(line) {
+                log::warn!("remote console: wrong token");
+                send(ws, "error: not authorized")?;
+                return ws.send(FrameType::Close, &[]);
+            }
+            authenticated.lock().unwrap().insert(session);
+            log::info!("remote console session opened");
+            return send(ws, "ok, try help");
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+        log::info!("remote console: {}", line);
+        send(ws, &commands::answer(&context, line, Source::Remote))
+    })?;
+
+    Ok(Some(server))
+}
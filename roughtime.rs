@@ -0,0 +1,176 @@
+use std::io;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha2::{Digest, Sha512};
+
+use crate::config;
+use crate::signing;
+use crate::wifi;
+
+// Google-style Roughtime: the server signs (midpoint, radius) together with our nonce, so a
+// response proves the nonce, and whatever it was derived from, existed before that time
+const REQUEST_SIZE: usize = 1024;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const DELEGATION_CONTEXT: &[u8] = b"RoughTime v1 delegation signature--\0";
+const RESPONSE_CONTEXT: &[u8] = b"RoughTime v1 response signature\0";
+
+const TAG_NONC: u32 = tag(b"NONC");
+const TAG_PAD: u32 = tag(b"PAD\xff");
+const TAG_SIG: u32 = tag(b"SIG\0");
+const TAG_SREP: u32 = tag(b"SREP");
+const TAG_CERT: u32 = tag(b"CERT");
+const TAG_INDX: u32 = tag(b"INDX");
+const TAG_PATH: u32 = tag(b"PATH");
+const TAG_ROOT: u32 = tag(b"ROOT");
+const TAG_MIDP: u32 = tag(b"MIDP");
+const TAG_RADI: u32 = tag(b"RADI");
+const TAG_DELE: u32 = tag(b"DELE");
+const TAG_PUBK: u32 = tag(b"PUBK");
+const TAG_MINT: u32 = tag(b"MINT");
+const TAG_MAXT: u32 = tag(b"MAXT");
+
+const fn tag(name: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*name)
+}
+
+pub struct Attestation {
+    pub server: &'static str,
+    // microseconds since the epoch, the true time is within midpoint ± radius
+    pub midpoint: u64,
+    pub radius: u32,
+    // the complete signed response, for anyone wanting to check it themselves
+    pub response: Vec<u8>,
+}
+
+pub fn enabled() -> bool {
+    !config::ROUGHTIME_SERVER.is_empty() && !config::ROUGHTIME_PUBLIC_KEY.is_empty()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// tag-value map: tag count, offsets of all values but the first, the tags, then the values
+fn parse(message: &[u8]) -> io::Result<Vec<(u32, &[u8])>> {
+    let word = |index: usize| -> io::Result<u32> {
+        let bytes = message.get(index * 4..index * 4 + 4).ok_or_else(|| invalid("truncated message"))?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let count = word(0)? as usize;
+    if count == 0 || count > 64 {
+        return Err(invalid("bad tag count"));
+    }
+    let values = 4 * (2 * count);
+    let body = message.get(values..).ok_or_else(|| invalid("truncated message"))?;
+    let mut fields = Vec::with_capacity(count);
+    for index in 0..count {
+        let start = if index == 0 { 0 } else { word(index)? as usize };
+        let end = if index + 1 == count { body.len() } else { word(index + 1)? as usize };
+        let value = body.get(start..end).ok_or_else(|| invalid("bad offset"))?;
+        fields.push((word(count + index)?, value));
+    }
+    Ok(fields)
+}
+
+fn field<'a>(fields: &[(u32, &'a [u8])], tag: u32) -> io::Result<&'a [u8]> {
+    fields
+        .iter()
+        .find(|(candidate, _)| *candidate == tag)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| invalid("missing tag"))
+}
+
+fn fixed<const N: usize>(bytes: &[u8]) -> io::Result<[u8; N]> {
+    bytes.try_into().map_err(|_| invalid("bad field length"))
+}
+
+fn verify(key: &VerifyingKey, context: &[u8], data: &[u8], signature: &[u8]) -> io::Result<()> {
+    let signature = Signature::from_bytes(&fixed(signature)?);
+    key.verify(&[context, data].concat(), &signature).map_err(|_| invalid("bad signature"))
+}
+
+fn tree_hash(prefix: u8, parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = Sha512::new().chain_update([prefix]);
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut hash = [0u8; 64];
+    hash.copy_from_slice(&hasher.finalize());
+    hash
+}
+
+fn request(nonce: &[u8; 64]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(REQUEST_SIZE);
+    message.extend_from_slice(&2u32.to_le_bytes());
+    message.extend_from_slice(&64u32.to_le_bytes());
+    message.extend_from_slice(&TAG_NONC.to_le_bytes());
+    message.extend_from_slice(&TAG_PAD.to_le_bytes());
+    message.extend_from_slice(nonce);
+    // servers ignore requests smaller than this so they can't be used as amplifiers
+    message.resize(REQUEST_SIZE, 0);
+    message
+}
+
+fn check(response: &[u8], nonce: &[u8; 64], server_key: &VerifyingKey) -> io::Result<(u64, u32)> {
+    let fields = parse(response)?;
+    let signed = field(&fields, TAG_SREP)?;
+    let cert = parse(field(&fields, TAG_CERT)?)?;
+
+    // the long-term key delegates to an online key for a limited time span
+    let delegation = field(&cert, TAG_DELE)?;
+    verify(server_key, DELEGATION_CONTEXT, delegation, field(&cert, TAG_SIG)?)?;
+    let delegation = parse(delegation)?;
+    let online_key = VerifyingKey::from_bytes(&fixed(field(&delegation, TAG_PUBK)?)?)
+        .map_err(|_| invalid("bad delegated key"))?;
+    verify(&online_key, RESPONSE_CONTEXT, signed, field(&fields, TAG_SIG)?)?;
+
+    // the server signs the root of a Merkle tree over a batch of nonces, PATH leads from ours to it
+    let mut hash = tree_hash(0, &[&nonce[..]]);
+    let mut index = u32::from_le_bytes(fixed(field(&fields, TAG_INDX)?)?);
+    for sibling in field(&fields, TAG_PATH)?.chunks(64) {
+        let (left, right) = if index & 1 == 0 { (&hash[..], sibling) } else { (sibling, &hash[..]) };
+        hash = tree_hash(1, &[left, right]);
+        index >>= 1;
+    }
+    let signed = parse(signed)?;
+    if field(&signed, TAG_ROOT)? != hash {
+        return Err(invalid("nonce not covered by the signed root"));
+    }
+
+    let midpoint = u64::from_le_bytes(fixed(field(&signed, TAG_MIDP)?)?);
+    let radius = u32::from_le_bytes(fixed(field(&signed, TAG_RADI)?)?);
+    let valid_from = u64::from_le_bytes(fixed(field(&delegation, TAG_MINT)?)?);
+    let valid_until = u64::from_le_bytes(fixed(field(&delegation, TAG_MAXT)?)?);
+    if midpoint < valid_from || midpoint > valid_until {
+        return Err(invalid("time outside of the delegation"));
+    }
+    Ok((midpoint, radius))
+}
+
+// one round trip to the configured server, fully verified; the nonce is usually a hash of the
+// data being timestamped
+pub fn attest(nonce: [u8; 64]) -> io::Result<Attestation> {
+    let server_key = signing::from_hex(config::ROUGHTIME_PUBLIC_KEY)
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .ok_or_else(|| invalid("bad ROUGHTIME_PUBLIC_KEY"))?;
+
+    let server = wifi::resolve(config::ROUGHTIME_SERVER)?;
+    let socket = UdpSocket::bind(if server.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" })?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(&request(&nonce))?;
+    let mut buffer = vec![0u8; 1500];
+    let length = socket.recv(&mut buffer)?;
+    buffer.truncate(length);
+
+    let (midpoint, radius) = check(&buffer, &nonce, &server_key)?;
+    Ok(Attestation {
+        server: config::ROUGHTIME_SERVER,
+        midpoint,
+        radius,
+        response: buffer,
+    })
+}
@@ -0,0 +1,423 @@
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::context::Context;
+use crate::events::Event;
+use crate::history::{self, History, Record};
+use crate::http;
+use crate::openapi::Api;
+use crate::profiles;
+use crate::pwm::TimeProportional;
+use crate::readings::Reading;
+use crate::schedule::Window;
+use crate::storage;
+use crate::units::{Celsius, Degrees};
+
+const SETTINGS_FILE: &str = "rules.json";
+// the spare pins rules can drive, started in main
+pub const OUTPUTS: &[&str] = &["gpio18", "gpio19", "gpio23"];
+const MAX_PULSE_MS: u32 = 60_000;
+const MAX_MEAN_HOURS: u32 = 7 * 24;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    // {"type": "sensor", "sensor": "t1", "above": 30, "hysteresis": 0.5}; a missing reading is false
+    Sensor {
+        sensor: String,
+        above: Option<Celsius>,
+        below: Option<Celsius>,
+        // once the rule is on, the value has to come back this far past the threshold
+        #[serde(default)]
+        hysteresis: Degrees,
+        // compare the mean over this many hours rather than the reading, false until there are
+        // readings going back that long, in the history log or since the start
+        #[serde(default)]
+        mean_hours: u32,
+    },
+    // a specific alarm, or any without an id
+    Alarm { id: Option<String> },
+    // local time of day as in the schedule, false while the clock isn't synced
+    Schedule { from: String, to: String },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    // high while the conditions hold
+    Set,
+    // one pulse each time the conditions become true
+    Pulse { ms: u32 },
+    // a duty cycle on the 2 s software PWM while the conditions hold
+    Pwm { duty: f32 },
+    // switch to a configuration profile each time the conditions become true; takes no output
+    Profile { profile: String },
+}
+
+// when all of `when` hold, `action` drives `output`; an output no rule is driving is low, and
+// of two rules on the same output the later one wins
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub when: Vec<Condition>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub output: String,
+    pub action: Action,
+    // the preset that set the rule up, the next preset replaces it; rules set up by hand have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub rules: Vec<Rule>,
+}
+
+impl Settings {
+    fn validate(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            match &rule.action {
+                Action::Profile { .. } if !rule.output.is_empty() => {
+                    return Err(format!("{}: a profile switch takes no output", rule.name));
+                }
+                Action::Profile { .. } => {}
+                _ if !OUTPUTS.contains(&rule.output.as_str()) => {
+                    return Err(format!("{}: unknown output {}, one of {}", rule.name, rule.output, OUTPUTS.join(", ")));
+                }
+                _ => {}
+            }
+            if rule.when.is_empty() {
+                return Err(format!("{}: needs at least one condition", rule.name));
+            }
+            for condition in &rule.when {
+                match condition {
+                    Condition::Sensor { above: None, below: None, .. } => {
+                        return Err(format!("{}: a sensor condition needs above or below", rule.name));
+                    }
+                    Condition::Sensor { mean_hours, .. } if *mean_hours > MAX_MEAN_HOURS => {
+                        return Err(format!("{}: a mean is over at most {} hours", rule.name, MAX_MEAN_HOURS));
+                    }
+                    Condition::Schedule { from, to } if !(Window { from: from.clone(), to: to.clone() }).is_valid() => {
+                        return Err(format!("{}: times are HH:MM", rule.name));
+                    }
+                    _ => {}
+                }
+            }
+            match rule.action {
+                Action::Pulse { ms } if ms == 0 || ms > MAX_PULSE_MS => {
+                    return Err(format!("{}: a pulse is 1 to {} ms", rule.name, MAX_PULSE_MS));
+                }
+                Action::Pwm { duty } if !(0.0..=1.0).contains(&duty) => {
+                    return Err(format!("{}: duty is between 0 and 1", rule.name));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// hourly sums of one sensor's readings, for the conditions on its mean
+#[derive(Debug)]
+struct Mean {
+    sensor: String,
+    hours: u32,
+    // (hour since the epoch, sum, count), oldest first
+    buckets: VecDeque<(u64, f32, u32)>,
+}
+
+impl Mean {
+    fn add(&mut self, celsius: Celsius, now: u64) {
+        let hour = now / 3600;
+        match self.buckets.back_mut() {
+            Some((last, sum, count)) if *last == hour => {
+                *sum += celsius.0;
+                *count += 1;
+            }
+            _ => self.buckets.push_back((hour, celsius.0, 1)),
+        }
+        while self.buckets.front().is_some_and(|(first, _, _)| first + u64::from(self.hours) < hour) {
+            self.buckets.pop_front();
+        }
+    }
+
+    // the window's readings from the history log, so a reboot doesn't start it over; it has
+    // fewer lines an hour than there are cycles
+    fn seed(&mut self, history: &Mutex<History>, now: u64) {
+        let since = now.saturating_sub(u64::from(self.hours) * 3600);
+        let seeded = history::scan_paged(history, since, |record| {
+            if let Record::Readings { t, values, maintenance: false, .. } = record {
+                if let Some(celsius) = values.get(&self.sensor) {
+                    self.add(Celsius(*celsius), t);
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        });
+        if let Err(error) = seeded {
+            log::warn!("failed to read the {} h mean of {} from the history: {}", self.hours, self.sensor, error);
+        }
+    }
+
+    // None until the readings go back the whole window; each hour counts the same however
+    // many readings it has, seeded hours have fewer
+    fn value(&self, now: u64) -> Option<Celsius> {
+        let (first, _, _) = self.buckets.front()?;
+        if first + u64::from(self.hours) > now / 3600 {
+            return None;
+        }
+        let sum: f32 = self.buckets.iter().map(|(_, sum, count)| sum / *count as f32).sum();
+        Some(Celsius(sum / self.buckets.len() as f32))
+    }
+}
+
+#[derive(Default)]
+pub struct Rules {
+    pub settings: Settings,
+    // which rules held on the last cycle, for pulses and hysteresis
+    active: Vec<bool>,
+    // the level each output was last set to
+    levels: Vec<f32>,
+    // for every sensor and window a condition takes the mean of, seeded from the history
+    means: Vec<Mean>,
+}
+
+impl Rules {
+    pub fn load() -> Self {
+        Self {
+            settings: storage::read_json(SETTINGS_FILE).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let rules: Vec<Value> = self
+            .settings
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| json!({ "name": rule.name, "active": self.active.get(index).copied().unwrap_or(false) }))
+            .collect();
+        let outputs: Vec<Value> = OUTPUTS
+            .iter()
+            .enumerate()
+            .map(|(index, output)| json!({ "output": output, "level": self.levels.get(index).copied().unwrap_or(0.0) }))
+            .collect();
+        let now = clock::now_unix();
+        let means: Vec<Value> = self
+            .means
+            .iter()
+            .map(|mean| json!({ "sensor": mean.sensor, "hours": mean.hours, "celsius": mean.value(now) }))
+            .collect();
+        json!({ "settings": self.settings, "rules": rules, "outputs": outputs, "means": means })
+    }
+
+    // every sensor and window a condition takes the mean of
+    fn wanted_means(&self) -> Vec<(&String, u32)> {
+        let mut wanted: Vec<(&String, u32)> = Vec::new();
+        for condition in self.settings.rules.iter().flat_map(|rule| &rule.when) {
+            if let Condition::Sensor { sensor, mean_hours, .. } = condition {
+                if *mean_hours > 0 && !wanted.contains(&(sensor, *mean_hours)) {
+                    wanted.push((sensor, *mean_hours));
+                }
+            }
+        }
+        wanted
+    }
+
+    // the means update_means would start this cycle, empty, to be seeded without the lock
+    fn new_means(&self, readings: &[Reading], now: u64) -> Vec<Mean> {
+        if !clock::is_plausible(now) {
+            return Vec::new();
+        }
+        self.wanted_means()
+            .into_iter()
+            .filter(|(sensor, _)| readings.iter().any(|reading| &reading.sensor == *sensor))
+            .filter(|(sensor, hours)| !self.means.iter().any(|mean| &mean.sensor == *sensor && mean.hours == *hours))
+            .map(|(sensor, hours)| Mean { sensor: sensor.clone(), hours, buckets: VecDeque::new() })
+            .collect()
+    }
+
+    // feeds the readings to a mean for every condition that takes one, starting with the
+    // `seeded` ones new_means gave, and drops those no condition uses any more. Hours need the
+    // real time, before SNTP there are none
+    fn update_means(&mut self, seeded: Vec<Mean>, readings: &[Reading], now: u64) {
+        let wanted: Vec<(String, u32)> = self.wanted_means().into_iter().map(|(sensor, hours)| (sensor.clone(), hours)).collect();
+        let wants = |mean: &Mean| wanted.iter().any(|(sensor, hours)| *sensor == mean.sensor && *hours == mean.hours);
+        self.means.retain(wants);
+        if !clock::is_plausible(now) {
+            return;
+        }
+        for mean in seeded {
+            // the rules may have changed while it was seeded
+            if wants(&mean) && !self.means.iter().any(|other| other.sensor == mean.sensor && other.hours == mean.hours) {
+                self.means.push(mean);
+            }
+        }
+        for mean in &mut self.means {
+            if let Some(reading) = readings.iter().find(|reading| reading.sensor == mean.sensor) {
+                mean.add(reading.celsius, now);
+            }
+        }
+    }
+}
+
+fn holds(context: &Context, means: &[Mean], condition: &Condition, readings: &[Reading], was_active: bool, now: u64) -> bool {
+    match condition {
+        Condition::Sensor { sensor, above, below, hysteresis, mean_hours } => {
+            let celsius = match mean_hours {
+                0 => readings.iter().find(|reading| &reading.sensor == sensor).map(|reading| reading.celsius),
+                hours => means.iter().find(|mean| &mean.sensor == sensor && mean.hours == *hours).and_then(|mean| mean.value(now)),
+            };
+            let Some(celsius) = celsius else {
+                return false;
+            };
+            let margin = if was_active { *hysteresis } else { Degrees(0.0) };
+            above.map_or(true, |above| celsius > above - margin) && below.map_or(true, |below| celsius < below + margin)
+        }
+        Condition::Alarm { id } => context.alarms.is_active(id.as_deref()),
+        Condition::Schedule { from, to } => {
+            let window = Window { from: from.clone(), to: to.clone() };
+            clock::is_synced() && context.schedule.lock().unwrap().within(&window, now)
+        }
+    }
+}
+
+// evaluated once per cycle after the controllers, `outputs` in the order of OUTPUTS
+pub fn update(context: &Context, outputs: &[TimeProportional], readings: &[Reading]) {
+    let now = clock::now_unix();
+    // a new mean reads up to its whole window from the history, which takes a while; the cycle
+    // does that without holding the rules, so the API and the twin don't wait on it
+    let mut seeded = context.rules.lock().unwrap().new_means(readings, now);
+    for mean in &mut seeded {
+        mean.seed(&context.history, now);
+    }
+    let mut guard = context.rules.lock().unwrap();
+    let rules = &mut *guard;
+    rules.update_means(seeded, readings, now);
+    rules.active.resize(rules.settings.rules.len(), false);
+    let mut levels = vec![0.0; OUTPUTS.len()];
+    // switched once the lock is let go, a profile goes through other settings; the rule only
+    // counts as active once its switch went through, until then it is tried every cycle
+    let mut switches = Vec::new();
+    for (index, rule) in rules.settings.rules.iter().enumerate() {
+        let was_active = rules.active[index];
+        let active = rule.when.iter().all(|condition| holds(context, &rules.means, condition, readings, was_active, now));
+        rules.active[index] = active;
+        if let Action::Profile { profile } = &rule.action {
+            if active && !was_active {
+                rules.active[index] = false;
+                switches.push((index, profile.clone(), format!("rule {}", rule.name)));
+            }
+        }
+        let Some(output) = OUTPUTS.iter().position(|output| *output == rule.output) else {
+            continue;
+        };
+        match rule.action {
+            Action::Set if active => levels[output] = 1.0,
+            Action::Pwm { duty } if active => levels[output] = duty,
+            Action::Pulse { ms } if active && !was_active => outputs[output].pulse(ms),
+            _ => {}
+        }
+    }
+    for (output, level) in outputs.iter().zip(&levels) {
+        output.set(*level);
+    }
+    rules.levels = levels;
+    drop(guard);
+
+    let active_profile = context.profiles.lock().unwrap().active.clone();
+    let mut switched = Vec::new();
+    for (index, profile, by) in switches {
+        if active_profile.as_ref() != Some(&profile) {
+            if let Err(error) = profiles::switch(context, &profile, &by) {
+                log::warn!("{} failed to switch to the {} profile: {}", by, profile, error);
+                continue;
+            }
+        }
+        switched.push(index);
+    }
+    if !switched.is_empty() {
+        // rules applied in the meantime start over inactive
+        let mut rules = context.rules.lock().unwrap();
+        for index in switched {
+            if let Some(active) = rules.active.get_mut(index) {
+                *active = true;
+            }
+        }
+    }
+}
+
+// validated, saved and in effect, from POST /api/rules or the device twin
+pub fn apply(context: &Context, settings: Settings) -> Result<(), (u16, String)> {
+    settings.validate().map_err(|message| (400, message))?;
+    storage::write_json(SETTINGS_FILE, &settings).map_err(|error| (500, error.to_string()))?;
+    let mut rules = context.rules.lock().unwrap();
+    rules.settings = settings;
+    rules.active.clear();
+    drop(rules);
+    context.events.publish(Event::ConfigChanged("rules"));
+    Ok(())
+}
+
+// a rule with every condition for each action
+pub fn settings_example() -> Settings {
+    let sensor = Condition::Sensor {
+        sensor: String::new(),
+        above: Some(Celsius(0.0)),
+        below: Some(Celsius(0.0)),
+        hysteresis: Degrees(0.0),
+        mean_hours: 0,
+    };
+    let when = vec![
+        sensor,
+        Condition::Alarm { id: Some(String::new()) },
+        Condition::Schedule { from: String::new(), to: String::new() },
+    ];
+    let actions = [Action::Set, Action::Pulse { ms: 0 }, Action::Pwm { duty: 0.0 }, Action::Profile { profile: String::new() }];
+    let rules = actions
+        .into_iter()
+        .map(|action| Rule { name: String::new(), when: when.clone(), output: OUTPUTS[0].to_string(), action, preset: None })
+        .collect();
+    Settings { rules }
+}
+
+fn status_example() -> Value {
+    let mut status = Rules { settings: settings_example(), ..Default::default() }.to_json();
+    status["means"] = json!([{ "sensor": "", "hours": 0, "celsius": 0.0 }]);
+    status
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/rules", Method::Get, Api::json(status_example), move |request| {
+        let body = status_context.rules.lock().unwrap().to_json();
+        http::write_json(request, &body)
+    })?;
+
+    // {"rules": [{"name": "fan", "when": [{"type": "sensor", "sensor": "attic", "above": 30,
+    //  "hysteresis": 1}], "output": "gpio18", "action": {"type": "set"}}]}
+    let settings_context = context;
+    let settings = || json!(settings_example());
+    http::route(server, "/api/rules", Method::Post, Api::json(settings).body(settings), move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 4096)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let body = json!(settings);
+        match apply(&settings_context, settings) {
+            Ok(()) => http::write_json(request, &body),
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::time::Instant;
+
+use crate::config;
+use crate::context::Context;
+use crate::pid::Pid;
+use crate::program::{ControlOutput, Ticker};
+use crate::pwm::TimeProportional;
+use crate::readings::Reading;
+
+pub const PROGRAM_DONE_ALARM: &str = "program_done";
+
+// follows the program's time/temperature curve with a PID-driven heater, e.g. a sous-vide bath
+pub struct Runner {
+    pid: Pid,
+    heater: TimeProportional,
+    ticker: Ticker,
+    last_update: Option<Instant>,
+}
+
+impl Runner {
+    pub fn new(heater: TimeProportional) -> Self {
+        Self {
+            pid: Pid::new(config::PID_KP, config::PID_KI, config::PID_KD),
+            heater,
+            ticker: Ticker::default(),
+            last_update: None,
+        }
+    }
+
+    pub fn update(&mut self, context: &Context, readings: &[Reading]) {
+        let dt = self.last_update.map_or(0.0, |last| last.elapsed().as_secs_f32());
+        self.last_update = Some(Instant::now());
+
+        let mut state = context.program.lock().unwrap();
+        let tick = self.ticker.tick(&mut state, readings);
+        if tick.finished {
+            context.alarms.raise(PROGRAM_DONE_ALARM, "temperature program finished".to_string());
+        }
+
+        state.output = match (tick.setpoint, tick.celsius) {
+            (Some(setpoint), Some(celsius)) => {
+                let duty = self.pid.update(setpoint, celsius, dt);
+                self.heater.set(duty);
+                Some(ControlOutput::Duty(duty))
+            }
+            // finished, or the probe is missing: never heat blindly
+            _ => {
+                self.pid.reset();
+                self.heater.set(0.0);
+                None
+            }
+        };
+    }
+}
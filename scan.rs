@@ -0,0 +1,193 @@
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::EspError;
+use one_wire_bus::{Address, OneWire, OneWireResult};
+
+use crate::config;
+use crate::context::Context;
+use crate::events::Event;
+use crate::lifecycle::State;
+use crate::mux;
+use crate::readings;
+
+const NAMESPACE: &str = "onewire";
+const KEY_NAME: &str = "scan";
+// the mux channel of each address, in the same order
+const CHANNELS_KEY: &str = "channels";
+// NVS blobs of this size are fine, and no bus carries that many sensors
+const MAX_SENSORS: usize = 128;
+// an empty bus is searched again after this, doubling up to RESCAN_MINUTES
+const EMPTY_RETRY: Duration = Duration::from_secs(15);
+const NO_SENSORS_ALARM: &str = "no_sensors";
+
+// the addresses found by the last full ROM search, kept in NVS so a boot can start reading
+// right away; a full search only runs after RESCAN_MINUTES or when a sensor stops answering
+pub struct ScanCache {
+    nvs: EspNvs<NvsDefault>,
+    addresses: Vec<Address>,
+    channels: Vec<u8>,
+    last_search: Option<Instant>,
+    stale: bool,
+    // searches in a row that found nothing
+    empty_searches: u32,
+}
+
+impl ScanCache {
+    pub fn load(partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        let mut buffer = [0u8; MAX_SENSORS * 8];
+        let addresses = match nvs.get_raw(KEY_NAME, &mut buffer)? {
+            Some(stored) => stored
+                .chunks_exact(8)
+                .map(|bytes| Address(u64::from_le_bytes(bytes.try_into().unwrap())))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut channel_buffer = [0u8; MAX_SENSORS];
+        let channels = match nvs.get_raw(CHANNELS_KEY, &mut channel_buffer)? {
+            Some(stored) if stored.len() == addresses.len() => stored.to_vec(),
+            _ => vec![0; addresses.len()],
+        };
+        Ok(Self {
+            nvs,
+            addresses,
+            channels,
+            last_search: None,
+            stale: false,
+            empty_searches: 0,
+        })
+    }
+
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    // the addresses by mux channel, the channels without sensors left out
+    pub fn groups(&self) -> Vec<(u8, Vec<Address>)> {
+        let mut groups: Vec<(u8, Vec<Address>)> = Vec::new();
+        for (&channel, &address) in self.channels.iter().zip(&self.addresses) {
+            match groups.iter_mut().find(|(group, _)| *group == channel) {
+                Some((_, addresses)) => addresses.push(address),
+                None => groups.push((channel, vec![address])),
+            }
+        }
+        groups.sort_by_key(|(channel, _)| *channel);
+        groups
+    }
+
+    // without a cache the first cycle searches; with one, the cache stands in for the boot search
+    pub fn search_due(&mut self) -> bool {
+        if self.stale {
+            return true;
+        }
+        let interval = Duration::from_secs(u64::from(config::RESCAN_MINUTES) * 60);
+        match self.last_search {
+            Some(last) if self.waiting() => last.elapsed() >= self.retry_after(),
+            Some(last) => last.elapsed() >= interval,
+            None if self.addresses.is_empty() => true,
+            None => {
+                self.last_search = Some(Instant::now());
+                false
+            }
+        }
+    }
+
+    // a search ran and found no sensors
+    pub fn waiting(&self) -> bool {
+        self.last_search.is_some() && self.addresses.is_empty()
+    }
+
+    pub fn retry_after(&self) -> Duration {
+        let interval = Duration::from_secs(u64::from(config::RESCAN_MINUTES) * 60);
+        (EMPTY_RETRY * 2u32.pow(self.empty_searches.saturating_sub(1).min(8))).min(interval)
+    }
+
+    // a cached sensor didn't answer: search again on the next cycle
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    // what a search found, with the mux channel of each address
+    pub fn store(&mut self, mut found: Vec<(u8, Address)>) {
+        self.last_search = Some(Instant::now());
+        self.stale = false;
+        self.empty_searches = if found.is_empty() { self.empty_searches + 1 } else { 0 };
+        found.truncate(MAX_SENSORS);
+        let (channels, addresses): (Vec<u8>, Vec<Address>) = found.into_iter().unzip();
+        if addresses == self.addresses && channels == self.channels {
+            return;
+        }
+        let bytes: Vec<u8> = addresses.iter().flat_map(|address| address.0.to_le_bytes()).collect();
+        if let Err(error) = self.nvs.set_raw(KEY_NAME, &bytes).and_then(|_| self.nvs.set_raw(CHANNELS_KEY, &channels)) {
+            log::warn!("failed to save the 1-Wire scan: {}", error);
+        }
+        self.addresses = addresses;
+        self.channels = channels;
+    }
+}
+
+// an empty bus is a wiring or power problem more often than not: say so with an alarm (which
+// also goes out over MQTT) and the lifecycle state the status LED shows, until a search finds
+// sensors again
+pub fn report(scan_cache: &ScanCache, context: &Context) {
+    if scan_cache.waiting() {
+        let message = format!(
+            "no sensors found on the 1-Wire bus (GPIO4), searching again within {} s",
+            scan_cache.retry_after().as_secs()
+        );
+        context.alarms.raise(NO_SENSORS_ALARM, message);
+        if context.lifecycle.state() == State::Running {
+            context.lifecycle.enter(State::WaitingForSensors, "no sensors on the bus");
+        }
+    } else {
+        context.alarms.clear(NO_SENSORS_ALARM);
+        if context.lifecycle.state() == State::WaitingForSensors {
+            context.lifecycle.enter(State::Running, "sensors found");
+        }
+    }
+}
+
+// with a mux, the registry shows every sensor found on the cable it was found on
+pub fn label_buses(context: &Context, found: &[(u8, Address)]) {
+    let mut registry = context.registry.lock().unwrap();
+    let mut changed = false;
+    for (channel, address) in found {
+        let sensor = readings::sensor_id(address);
+        let bus = Some(mux::bus_name(*channel));
+        let mut info = registry.get(&sensor).cloned().unwrap_or_default();
+        if info.bus != bus {
+            info.bus = bus;
+            registry.update(&sensor, info);
+            changed = true;
+        }
+    }
+    if !changed {
+        return;
+    }
+    if let Err(error) = registry.save() {
+        log::warn!("failed to save the sensor buses: {}", error);
+    }
+    drop(registry);
+    context.events.publish(Event::ConfigChanged("sensors"));
+}
+
+// full ROM search for DS18B20s
+pub fn search<P, E>(one_wire_bus: &mut OneWire<P>, delay: &mut impl DelayUs<u16>) -> OneWireResult<Vec<Address>, E>
+    where
+        P: OutputPin<Error=E> + InputPin<Error=E>,
+        E: Debug
+{
+    let mut addresses = Vec::new();
+    let mut search_state = None;
+    while let Some((address, state)) = one_wire_bus.device_search(search_state.as_ref(), false, delay)? {
+        search_state = Some(state);
+        if address.family_code() == ds18b20::FAMILY_CODE {
+            addresses.push(address);
+        }
+    }
+    Ok(addresses)
+}
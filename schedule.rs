@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::clock::{Clock, SimulatedClock};
+use crate::storage;
+
+const SETTINGS_FILE: &str = "schedule.json";
+// the outputs that can be scheduled: the radio itself and each exporter
+pub const OUTPUTS: &[&str] = &["wifi", "mqtt", "influx"];
+
+// local time of day, "from" inclusive and "to" exclusive; a window past midnight ("22:00" to
+// "06:00") wraps, equal times mean the whole day
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Window {
+    pub from: String,
+    pub to: String,
+}
+
+fn minute_of_day(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+impl Window {
+    pub fn is_valid(&self) -> bool {
+        minute_of_day(&self.from).is_some() && minute_of_day(&self.to).is_some()
+    }
+
+    fn contains(&self, minute: u32) -> bool {
+        let (Some(from), Some(to)) = (minute_of_day(&self.from), minute_of_day(&self.to)) else {
+            return true;
+        };
+        match from.cmp(&to) {
+            std::cmp::Ordering::Less => (from..to).contains(&minute),
+            std::cmp::Ordering::Greater => minute >= from || minute < to,
+            std::cmp::Ordering::Equal => true,
+        }
+    }
+}
+
+// when each output is on, to keep the radio quiet at night or publish only in working hours;
+// outputs without windows are always on. Readings taken while an exporter is off wait in its
+// queue, and the exporter sends what overflowed it from the history log once back on.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub utc_offset_minutes: i32,
+    pub outputs: BTreeMap<String, Vec<Window>>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        storage::read_json(SETTINGS_FILE).unwrap_or_default()
+    }
+
+    pub fn enabled(&self, output: &str, clock: &dyn Clock) -> bool {
+        let Some(windows) = self.outputs.get(output).filter(|windows| !windows.is_empty()) else {
+            return true;
+        };
+        // without the time of day there is nothing to go by, better to stay on
+        if !clock.is_synced() {
+            return true;
+        }
+        windows.iter().any(|window| self.within(window, clock.now_unix()))
+    }
+
+    // whether `unix` is inside the window in local time
+    pub fn within(&self, window: &Window, unix: u64) -> bool {
+        let local = unix as i64 + i64::from(self.utc_offset_minutes) * 60;
+        window.contains((local.rem_euclid(86_400) / 60) as u32)
+    }
+
+    // an exporter also needs the radio
+    pub fn exporting(&self, exporter: &str, clock: &dyn Clock) -> bool {
+        self.enabled("wifi", clock) && self.enabled(exporter, clock)
+    }
+
+    // what is on at `from` and every switch in the `hours` after, walked a minute at a time
+    pub fn preview(&self, from: u64, hours: u64) -> Value {
+        let clock = SimulatedClock::at(from);
+        let mut state: Vec<bool> = OUTPUTS.iter().map(|output| self.enabled(output, &clock)).collect();
+        let enabled: Map<String, Value> = OUTPUTS.iter().zip(&state).map(|(output, on)| (output.to_string(), json!(on))).collect();
+        let mut switches = Vec::new();
+        for _ in 0..hours * 60 {
+            clock.advance(60);
+            for (output, enabled) in OUTPUTS.iter().zip(state.iter_mut()) {
+                let now = self.enabled(output, &clock);
+                if now != *enabled {
+                    *enabled = now;
+                    switches.push(json!({ "t": clock.now_unix(), "output": output, "enabled": now }));
+                }
+            }
+        }
+        json!({ "from": from, "enabled": enabled, "switches": switches })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        storage::write_json(SETTINGS_FILE, self)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (output, windows) in &self.outputs {
+            if !OUTPUTS.contains(&output.as_str()) {
+                return Err(format!("unknown output {}, one of {}", output, OUTPUTS.join(", ")));
+            }
+            for window in windows {
+                if !window.is_valid() {
+                    return Err(format!("{}: times are HH:MM", output));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn settings_example() -> Settings {
+    let windows = vec![Window { from: String::new(), to: String::new() }];
+    Settings { utc_offset_minutes: 0, outputs: BTreeMap::from([(String::new(), windows)]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01T00:00:00Z
+    const MIDNIGHT: u64 = 1_704_067_200;
+
+    fn window(from: &str, to: &str) -> Window {
+        Window { from: from.to_string(), to: to.to_string() }
+    }
+
+    fn wifi(utc_offset_minutes: i32, windows: Vec<Window>) -> Settings {
+        Settings { utc_offset_minutes, outputs: BTreeMap::from([("wifi".to_string(), windows)]) }
+    }
+
+    #[test]
+    fn window_past_midnight_wraps() {
+        let night = window("22:00", "06:00");
+        assert!(night.contains(23 * 60));
+        assert!(night.contains(5 * 60 + 59));
+        assert!(!night.contains(6 * 60));
+        assert!(!night.contains(12 * 60));
+        assert!(window("08:00", "08:00").contains(3 * 60));
+    }
+
+    #[test]
+    fn window_is_in_local_time() {
+        let settings = wifi(60, vec![window("07:00", "23:00")]);
+        let clock = SimulatedClock::at(MIDNIGHT);
+        assert!(!settings.enabled("wifi", &clock));
+        // 06:00 UTC is 07:00 local
+        clock.advance(6 * 3600);
+        assert!(settings.enabled("wifi", &clock));
+        clock.advance(16 * 3600 - 1);
+        assert!(settings.enabled("wifi", &clock));
+        clock.advance(1);
+        assert!(!settings.enabled("wifi", &clock));
+    }
+
+    #[test]
+    fn unscheduled_or_unsynced_stays_on() {
+        let settings = wifi(0, vec![window("07:00", "08:00")]);
+        assert!(settings.enabled("mqtt", &SimulatedClock::at(MIDNIGHT)));
+        assert!(settings.enabled("wifi", &SimulatedClock::at(3600)));
+        assert!(!settings.exporting("mqtt", &SimulatedClock::at(MIDNIGHT)));
+    }
+
+    #[test]
+    fn preview_lists_each_switch() {
+        let settings = wifi(0, vec![window("07:00", "23:00")]);
+        let preview = settings.preview(MIDNIGHT, 24);
+        assert_eq!(preview["enabled"]["wifi"], json!(false));
+        assert_eq!(preview["enabled"]["mqtt"], json!(true));
+        let switches: Vec<(u64, bool)> = preview["switches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|switch| (switch["t"].as_u64().unwrap(), switch["enabled"].as_bool().unwrap()))
+            .collect();
+        assert_eq!(switches, [(MIDNIGHT + 7 * 3600, true), (MIDNIGHT + 23 * 3600, false)]);
+    }
+
+    #[test]
+    fn validate_rejects_bad_times_and_outputs() {
+        assert!(wifi(0, vec![window("07:00", "24:00")]).validate().is_err());
+        assert!(wifi(0, vec![window("7", "08:00")]).validate().is_err());
+        let mut settings = wifi(0, vec![window("07:00", "08:00")]);
+        assert!(settings.validate().is_ok());
+        settings.outputs.insert("ftp".to_string(), Vec::new());
+        assert!(settings.validate().is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde_json::{json, Map, Value};
+
+use crate::clock::SystemClock;
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::schedule::{self, Settings, OUTPUTS};
+
+// the settings and what they have on right now; the windows themselves are in schedule.rs
+fn schedule_json(settings: &Settings) -> Value {
+    let state: Map<String, Value> = OUTPUTS.iter().map(|output| (output.to_string(), json!(settings.enabled(output, &SystemClock)))).collect();
+    json!({ "settings": settings, "enabled": state })
+}
+
+// validated, saved and in effect, from POST /api/schedule or the device twin
+pub fn apply(context: &Context, settings: Settings) -> Result<(), (u16, String)> {
+    settings.validate().map_err(|message| (400, message))?;
+    settings.save().map_err(|error| (500, error.to_string()))?;
+    *context.schedule.lock().unwrap() = settings;
+    context.events.publish(Event::ConfigChanged("schedule"));
+    Ok(())
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    let status = Api::json(|| schedule_json(&schedule::settings_example()));
+    http::route(server, "/api/schedule", Method::Get, status, move |request| {
+        let body = schedule_json(&status_context.schedule.lock().unwrap());
+        http::write_json(request, &body)
+    })?;
+
+    // {"utc_offset_minutes": 60, "outputs": {"wifi": [{"from": "07:00", "to": "23:00"}],
+    //  "mqtt": [{"from": "08:00", "to": "20:00"}]}}
+    let settings_context = context;
+    let settings = Api::json(|| schedule_json(&schedule::settings_example())).body(|| json!(schedule::settings_example()));
+    http::route(server, "/api/schedule", Method::Post, settings, move |mut request| {
+        let settings = match http::read_json::<Settings>(&mut request, 2048)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let body = schedule_json(&settings);
+        match apply(&settings_context, settings) {
+            Ok(()) => http::write_json(request, &body),
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    Ok(())
+}
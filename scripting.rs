@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::{esp_get_free_heap_size, EspError};
+use rhai::{Dynamic, Engine, Map, Scope, AST, FLOAT};
+use serde_json::{json, Value};
+
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::storage;
+use crate::units::Celsius;
+
+const SCRIPT_FILE: &str = "script.rhai";
+const MAX_SCRIPT_SIZE: usize = 16 * 1024;
+// the work a script gets per cycle; a runaway loop is stopped rather than starving the
+// sampling loop, the same for the sizes below
+const MAX_OPERATIONS: u64 = 50_000;
+// a script that leaves less free heap than this is stopped, checked every HEAP_CHECK_OPERATIONS;
+// Wi-Fi and the HTTP server need the rest
+const MIN_FREE_HEAP: u32 = 48 * 1024;
+const HEAP_CHECK_OPERATIONS: u64 = 64;
+// messages waiting for MQTT, the oldest are dropped while it is down
+const MAX_OUTBOX: usize = 16;
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(16);
+    engine.set_max_expr_depths(32, 16);
+    engine.set_max_string_size(1024);
+    engine.set_max_array_size(256);
+    engine.set_max_map_size(256);
+    // rhai has no heap limit of its own
+    engine.on_progress(|operations| {
+        let low = operations % HEAP_CHECK_OPERATIONS == 0 && unsafe { esp_get_free_heap_size() } < MIN_FREE_HEAP;
+        low.then(|| Dynamic::from("out of memory"))
+    });
+    engine.on_print(|text| log::info!("script: {}", text));
+    engine
+}
+
+fn alarm_id(id: &str) -> String {
+    format!("script_{}", id)
+}
+
+// topic suffixes are kept to what can't turn a publish into a wildcard or escape the node
+fn valid_suffix(suffix: &str) -> bool {
+    !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_alphanumeric() || "_-/".contains(c)) && !suffix.contains("..")
+}
+
+fn number(value: &Dynamic) -> Option<f32> {
+    let number = value.as_float().ok().or_else(|| value.as_int().ok().map(|int| int as FLOAT))?;
+    Some(number as f32).filter(|number| number.is_finite())
+}
+
+// a user script run once per cycle, after the mounting offsets, virtual sensors and
+// expressions. It sees `readings` (sensor to °C) and `state`, a map it keeps between cycles,
+// and fills in any of
+//   values  - new readings: values.lift = readings.supply - readings["return"]; one named like
+//             a sensor is left out
+//   alarms  - alarm id to message; an alarm it stops returning is cleared
+//   publish - topic suffix to payload, sent on temp/<node>/script/<suffix>
+// rhai is built with the `sync` feature so the engine and the compiled script can sit in the
+// context.
+pub struct Script {
+    engine: Engine,
+    source: String,
+    ast: Option<AST>,
+    state: Map,
+    // why the last run failed; a failing script leaves the readings alone
+    error: Option<String>,
+    // ids the script has raised, without the prefix
+    alarms: BTreeSet<String>,
+    // values of the last run left out for being named like a sensor
+    shadowing: BTreeSet<String>,
+    runs: u64,
+    last_run_us: u64,
+    pub outbox: Vec<(String, String)>,
+}
+
+impl Script {
+    pub fn load() -> Self {
+        let mut script = Self {
+            engine: engine(),
+            source: String::new(),
+            ast: None,
+            state: Map::new(),
+            error: None,
+            alarms: BTreeSet::new(),
+            shadowing: BTreeSet::new(),
+            runs: 0,
+            last_run_us: 0,
+            outbox: Vec::new(),
+        };
+        let source = storage::read(SCRIPT_FILE).ok().and_then(|source| String::from_utf8(source).ok()).unwrap_or_default();
+        if let Err(error) = script.set_source(source) {
+            log::warn!("script not loaded: {}", error);
+        }
+        script
+    }
+
+    // an empty source is no script
+    fn compile(&self, source: &str) -> Result<Option<AST>, String> {
+        if source.trim().is_empty() {
+            return Ok(None);
+        }
+        self.engine.compile(source).map(Some).map_err(|error| error.to_string())
+    }
+
+    fn set_source(&mut self, source: String) -> Result<(), String> {
+        let ast = self.compile(&source)?;
+        self.install(source, ast);
+        Ok(())
+    }
+
+    fn install(&mut self, source: String, ast: Option<AST>) {
+        self.ast = ast;
+        self.source = source;
+        self.state = Map::new();
+        self.error = None;
+        self.shadowing.clear();
+        self.runs = 0;
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "source": self.source,
+            "loaded": self.ast.is_some(),
+            "error": self.error,
+            "runs": self.runs,
+            "last_run_us": self.last_run_us,
+            "alarms": self.alarms,
+            "shadowing": self.shadowing,
+            "state": serde_json::from_str::<Value>(&rhai::format_map_as_json(&self.state)).unwrap_or(Value::Null),
+        })
+    }
+}
+
+pub fn run(context: &Context, readings: &mut Vec<Reading>) {
+    let mut script = context.script.lock().unwrap();
+    let script = &mut *script;
+    let Some(ast) = &script.ast else {
+        return;
+    };
+
+    let sensors: Map = readings
+        .iter()
+        .map(|reading| (reading.sensor.as_str().into(), Dynamic::from_float(reading.celsius.0 as FLOAT)))
+        .collect();
+    let mut scope = Scope::new();
+    scope.push("readings", sensors);
+    scope.push("state", std::mem::take(&mut script.state));
+    scope.push("values", Map::new());
+    scope.push("alarms", Map::new());
+    scope.push("publish", Map::new());
+
+    let started = Instant::now();
+    let result = script.engine.run_ast_with_scope(&mut scope, ast);
+    script.last_run_us = started.elapsed().as_micros() as u64;
+    script.runs += 1;
+    script.state = scope.get_value::<Map>("state").unwrap_or_default();
+    if let Err(error) = result {
+        let error = error.to_string();
+        if script.error.as_ref() != Some(&error) {
+            log::warn!("script failed: {}", error);
+        }
+        script.error = Some(error);
+        return;
+    }
+    script.error = None;
+
+    let mut shadowing = BTreeSet::new();
+    for (name, value) in scope.get_value::<Map>("values").unwrap_or_default() {
+        if readings.iter().any(|reading| reading.sensor == name.as_str()) {
+            shadowing.insert(name.to_string());
+            continue;
+        }
+        match number(&value) {
+            Some(celsius) => readings.push(Reading {
+                sensor: name.to_string(),
+                celsius: Celsius(celsius),
+                humidity: None,
+            }),
+            None => log::warn!("script value {} is not a number", name),
+        }
+    }
+    if shadowing != script.shadowing && !shadowing.is_empty() {
+        log::warn!("script values {:?} are named like sensors, left out", shadowing);
+    }
+    script.shadowing = shadowing;
+
+    let raised: BTreeMap<String, String> = scope
+        .get_value::<Map>("alarms")
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, message)| (id.to_string(), message.to_string()))
+        .collect();
+    for id in script.alarms.iter().filter(|id| !raised.contains_key(*id)) {
+        context.alarms.clear(&alarm_id(id));
+    }
+    for (id, message) in &raised {
+        context.alarms.raise(&alarm_id(id), message.clone());
+    }
+    script.alarms = raised.into_keys().collect();
+
+    for (suffix, payload) in scope.get_value::<Map>("publish").unwrap_or_default() {
+        if !valid_suffix(&suffix) {
+            log::warn!("script topic {} is not allowed", suffix);
+            continue;
+        }
+        let payload = match payload.read_lock::<Map>() {
+            Some(map) => rhai::format_map_as_json(&map),
+            None => payload.to_string(),
+        };
+        script.outbox.push((suffix.to_string(), payload));
+    }
+    let excess = script.outbox.len().saturating_sub(MAX_OUTBOX);
+    script.outbox.drain(..excess);
+}
+
+// compiled, saved, and only then run in place of the old script
+fn apply(context: &Context, source: String) -> Result<(), (u16, String)> {
+    let mut script = context.script.lock().unwrap();
+    let ast = script.compile(&source).map_err(|error| (400, error))?;
+    storage::write(SCRIPT_FILE, source.as_bytes()).map_err(|error| (500, error.to_string()))?;
+    script.install(source, ast);
+    for id in std::mem::take(&mut script.alarms) {
+        context.alarms.clear(&alarm_id(&id));
+    }
+    drop(script);
+    context.events.publish(Event::ConfigChanged("script"));
+    Ok(())
+}
+
+// "state" is whatever the script keeps there
+fn status_example() -> Value {
+    json!({
+        "source": "",
+        "loaded": true,
+        "error": "",
+        "runs": 0,
+        "last_run_us": 0,
+        "alarms": [""],
+        "shadowing": [""],
+        "state": {},
+    })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/script", Method::Get, Api::json(status_example), move |request| {
+        let body = status_context.script.lock().unwrap().to_json();
+        http::write_json(request, &body)
+    })?;
+
+    // the script source as the body, an empty body removes it
+    let upload_context = context;
+    let upload = Api::json(status_example).other_body("text/plain");
+    http::route(server, "/api/script", Method::Post, upload, move |mut request| {
+        let Some(source) = http::read_body(&mut request, MAX_SCRIPT_SIZE)? else {
+            return http::write_error(request, 413, "script too large");
+        };
+        let Ok(source) = String::from_utf8(source) else {
+            return http::write_error(request, 400, "script is not UTF-8");
+        };
+        match apply(&upload_context, source) {
+            Ok(()) => {
+                let body = upload_context.script.lock().unwrap().to_json();
+                http::write_json(request, &body)
+            }
+            Err((status, message)) => http::write_error(request, status, &message),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::v2::InputPin;
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::clock;
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+
+pub const DEFAULT_MINUTES: u64 = 30;
+pub const MAX_MINUTES: u64 = 8 * 60;
+// holding the button this long toggles maintenance mode; shorter presses are the display's
+pub const BUTTON_HOLD: Duration = Duration::from_secs(5);
+const POLL_MS: u32 = 50;
+
+struct Window {
+    until: Instant,
+    since_unix: u64,
+    by: String,
+}
+
+// maintenance mode, for probe swaps and cleaning: readings are still logged, tagged, but new
+// alarms stay quiet and nothing is exported until it is left or runs out
+#[derive(Default)]
+pub struct ServiceMode {
+    window: Mutex<Option<Window>>,
+}
+
+impl ServiceMode {
+    // whether it is on; running out is noticed here, on the next look
+    pub fn active(&self) -> bool {
+        let mut window = self.window.lock().unwrap();
+        if window.as_ref().is_some_and(|window| Instant::now() >= window.until) {
+            *window = None;
+            log::info!("maintenance mode ran out");
+        }
+        window.is_some()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let active = self.active();
+        let window = self.window.lock().unwrap();
+        json!({
+            "active": active,
+            "since": window.as_ref().map(|window| window.since_unix),
+            "remaining_secs": window.as_ref().map(|window| window.until.saturating_duration_since(Instant::now()).as_secs()),
+            "by": window.as_ref().map(|window| window.by.clone()),
+        })
+    }
+}
+
+// into maintenance mode for `minutes`, or for longer if it is already on
+pub fn enter(context: &Context, minutes: u64, by: &str) {
+    let until = Instant::now() + Duration::from_secs(minutes * 60);
+    let mut window = context.service.window.lock().unwrap();
+    match window.as_mut() {
+        Some(window) => window.until = until,
+        None => {
+            *window = Some(Window { until, since_unix: clock::now_unix(), by: by.to_string() });
+            log::info!("maintenance mode on for {} min over {}", minutes, by);
+        }
+    }
+    drop(window);
+    update(context);
+    context.events.publish(Event::ConfigChanged("maintenance"));
+}
+
+pub fn leave(context: &Context) -> bool {
+    let left = context.service.window.lock().unwrap().take().is_some();
+    if left {
+        log::info!("maintenance mode off");
+        update(context);
+        context.events.publish(Event::ConfigChanged("maintenance"));
+    }
+    left
+}
+
+// from every cycle: alarms follow the mode, including when it runs out
+pub fn update(context: &Context) {
+    context.alarms.set_suppressed(context.service.active());
+}
+
+pub fn toggle(context: &Context, by: &str) {
+    if !leave(context) {
+        enter(context, DEFAULT_MINUTES, by);
+    }
+}
+
+// the button on its own, for devices without a display to share it with
+pub fn watch_button<P: InputPin + Send + 'static>(context: Arc<Context>, button: P) {
+    let spawned = thread::Builder::new()
+        .name("button".into())
+        .stack_size(2048)
+        .spawn(move || {
+            let mut pressed_at: Option<Instant> = None;
+            loop {
+                // active low, with the pull-up
+                match (button.is_low().unwrap_or(false), pressed_at) {
+                    (true, None) => pressed_at = Some(Instant::now()),
+                    (false, Some(since)) => {
+                        pressed_at = None;
+                        if since.elapsed() >= BUTTON_HOLD {
+                            toggle(&context, "button");
+                        }
+                    }
+                    _ => {}
+                }
+                FreeRtos::delay_ms(POLL_MS);
+            }
+        });
+    if let Err(error) = spawned {
+        log::warn!("failed to start the button thread: {}", error);
+    }
+}
+
+#[derive(Deserialize)]
+struct ServiceRequest {
+    // 0 leaves maintenance mode
+    minutes: Option<u64>,
+}
+
+fn status_example() -> Value {
+    json!({ "active": true, "since": 0, "remaining_secs": 0, "by": "" })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/maintenance", Method::Get, Api::json(status_example), move |request| {
+        http::write_json(request, &status_context.service.to_json())
+    })?;
+
+    // {"minutes": 45} enters or extends it, {"minutes": 0} leaves it
+    let enter = Api::json(status_example).body(|| json!({ "minutes": 0 }));
+    http::route(server, "/api/maintenance", Method::Post, enter, move |mut request| {
+        let service = match http::read_json::<ServiceRequest>(&mut request, 256)? {
+            Ok(service) => service,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let mut args = Map::new();
+        if let Some(minutes) = service.minutes {
+            args.insert("minutes".to_string(), json!(minutes));
+        }
+        match commands::run(&context, "maintenance", &args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::mem::MaybeUninit;
+use std::ptr::{addr_of, addr_of_mut};
+
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::readings::Reading;
+use crate::units::Celsius;
+
+const MAGIC: u32 = 0x5348_4457;
+const SLOTS: usize = 16;
+// the longest sensor id, "SHT31-44" or a ROM address in hex
+const ID_LEN: usize = 16;
+
+// laid out without padding so every field is covered by the checksum
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Slot {
+    // zero padded
+    id: [u8; ID_LEN],
+    celsius: f32,
+    // NaN for sensors without humidity
+    humidity: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Shadow {
+    magic: u32,
+    count: u32,
+    // 0 when the clock wasn't synced yet
+    saved_unix: u64,
+    slots: [Slot; SLOTS],
+    checksum: u32,
+}
+
+impl Shadow {
+    fn checksum(&self) -> u32 {
+        // FNV-1a over the words, anything that isn't what save() wrote fails it
+        let mut hash = 0x811c_9dc5u32;
+        let mut add = |word: u32| hash = (hash ^ word).wrapping_mul(0x0100_0193);
+        add(self.magic);
+        add(self.count);
+        add(self.saved_unix as u32);
+        add((self.saved_unix >> 32) as u32);
+        for slot in &self.slots {
+            for chunk in slot.id.chunks_exact(4) {
+                add(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+            }
+            add(slot.celsius.to_bits());
+            add(slot.humidity.to_bits());
+        }
+        hash
+    }
+}
+
+// RTC slow memory that a software reset, panic or watchdog reset leaves alone; after power-on
+// it holds whatever the cells came up with, which the magic and checksum reject
+#[link_section = ".rtc_noinit"]
+static mut SHADOW: MaybeUninit<Shadow> = MaybeUninit::uninit();
+
+// readings restored at boot, shown until the first conversion replaces them
+#[derive(Debug)]
+pub struct Restored {
+    saved_unix: Option<u64>,
+}
+
+impl Restored {
+    pub fn to_json(&self) -> Value {
+        // the clock survives a software reset too, once it has been synced
+        let age = self.saved_unix.filter(|_| clock::is_synced()).map(|saved| clock::now_unix().saturating_sub(saved));
+        json!({ "saved_at": self.saved_unix.map(clock::format_iso8601), "age_secs": age })
+    }
+}
+
+// called with every new set of readings, beyond SLOTS sensors the rest aren't kept
+pub fn save(readings: &[Reading]) {
+    let empty = Slot { id: [0; ID_LEN], celsius: 0.0, humidity: f32::NAN };
+    let mut shadow = Shadow {
+        magic: MAGIC,
+        count: 0,
+        saved_unix: if clock::is_synced() { clock::now_unix() } else { 0 },
+        slots: [empty; SLOTS],
+        checksum: 0,
+    };
+    for reading in readings.iter().filter(|reading| reading.sensor.len() <= ID_LEN).take(SLOTS) {
+        let slot = &mut shadow.slots[shadow.count as usize];
+        slot.id[..reading.sensor.len()].copy_from_slice(reading.sensor.as_bytes());
+        slot.celsius = reading.celsius.0;
+        slot.humidity = reading.humidity.unwrap_or(f32::NAN);
+        shadow.count += 1;
+    }
+    shadow.checksum = shadow.checksum();
+    // SAFETY: only the sampling loop touches the shadow, and every bit pattern is a valid Shadow
+    unsafe { addr_of_mut!(SHADOW).cast::<Shadow>().write_volatile(shadow) };
+}
+
+// the readings from before the reset, if the memory survived it
+pub fn restore() -> Option<(Vec<Reading>, Restored)> {
+    // SAFETY: as in save(), and this runs once at boot before the sampling loop starts
+    let shadow = unsafe { addr_of!(SHADOW).cast::<Shadow>().read_volatile() };
+    if shadow.magic != MAGIC || shadow.count as usize > SLOTS || shadow.checksum != shadow.checksum() {
+        return None;
+    }
+    let readings: Vec<Reading> = shadow.slots[..shadow.count as usize]
+        .iter()
+        .filter_map(|slot| {
+            let len = slot.id.iter().position(|&byte| byte == 0).unwrap_or(ID_LEN);
+            Some(Reading {
+                sensor: String::from_utf8(slot.id[..len].to_vec()).ok()?,
+                celsius: Celsius(slot.celsius),
+                humidity: Some(slot.humidity).filter(|humidity| !humidity.is_nan()),
+            })
+        })
+        .collect();
+    (!readings.is_empty()).then_some((readings, Restored { saved_unix: Some(shadow.saved_unix).filter(|&saved| saved != 0) }))
+}
@@ -0,0 +1,67 @@
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::i2c::{Read, Write};
+
+use crate::units::Celsius;
+
+pub const DEFAULT_ADDRESS: u8 = 0x44;
+// single shot, high repeatability, no clock stretching
+const MEASURE_COMMAND: [u8; 2] = [0x24, 0x00];
+const MEASURE_TIME_MS: u16 = 16;
+
+#[derive(Debug)]
+pub enum Sht31Error<E> {
+    I2c(E),
+    Crc,
+}
+
+pub struct Sht31 {
+    address: u8,
+}
+
+pub struct Measurement {
+    pub celsius: Celsius,
+    pub humidity: f32,
+}
+
+// CRC-8, polynomial 0x31, initial value 0xff, as in the datasheet
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xffu8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+impl Sht31 {
+    pub fn new(address: u8) -> Self {
+        Self { address }
+    }
+
+    pub fn sensor_id(&self) -> String {
+        format!("SHT31-{:02X}", self.address)
+    }
+
+    pub fn measure<I, E>(&self, i2c: &mut I, delay: &mut impl DelayMs<u16>) -> Result<Measurement, Sht31Error<E>>
+        where
+            I: Write<Error=E> + Read<Error=E>,
+    {
+        i2c.write(self.address, &MEASURE_COMMAND).map_err(Sht31Error::I2c)?;
+        delay.delay_ms(MEASURE_TIME_MS);
+
+        let mut data = [0u8; 6];
+        i2c.read(self.address, &mut data).map_err(Sht31Error::I2c)?;
+        if crc8(&data[0..2]) != data[2] || crc8(&data[3..5]) != data[5] {
+            return Err(Sht31Error::Crc);
+        }
+
+        let raw_temperature = f32::from(u16::from_be_bytes([data[0], data[1]]));
+        let raw_humidity = f32::from(u16::from_be_bytes([data[3], data[4]]));
+        Ok(Measurement {
+            celsius: Celsius(-45.0 + 175.0 * raw_temperature / 65535.0),
+            humidity: 100.0 * raw_humidity / 65535.0,
+        })
+    }
+}
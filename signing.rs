@@ -0,0 +1,56 @@
+use ed25519_dalek::{Signer, SigningKey};
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs};
+use esp_idf_svc::sys::{bootloader_random_disable, bootloader_random_enable, esp_fill_random, EspError};
+
+const NAMESPACE: &str = "security";
+const KEY_NAME: &str = "device_key";
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+// Ed25519 key generated on first boot and kept in NVS; the public half is published so
+// exported reports and logs can be verified off-device
+#[derive(Clone)]
+pub struct DeviceKey {
+    signing_key: SigningKey,
+}
+
+impl DeviceKey {
+    // must run before Wi-Fi/BT start: the RNG entropy source used here shares the ADC with the radio
+    pub fn load_or_create(partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        let mut nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        let mut seed = [0u8; 32];
+        let stored = matches!(nvs.get_raw(KEY_NAME, &mut seed)?, Some(stored) if stored.len() == 32);
+        if !stored {
+            // without the radio running the RNG needs the bootloader's entropy source to be truly random
+            unsafe {
+                bootloader_random_enable();
+                esp_fill_random(seed.as_mut_ptr().cast(), seed.len());
+                bootloader_random_disable();
+            }
+            nvs.set_raw(KEY_NAME, &seed)?;
+        }
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&seed),
+        })
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        to_hex(self.signing_key.verifying_key().as_bytes())
+    }
+
+    pub fn sign_hex(&self, data: &[u8]) -> String {
+        to_hex(&self.signing_key.sign(data).to_bytes())
+    }
+}
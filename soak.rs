@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use one_wire_bus::{Address, OneWire, OneWireError};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::commands::{self, Source};
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings;
+use crate::scan;
+
+// a full ROM search every this many passes, to catch sensors that vanish from the bus entirely
+const SEARCH_EVERY: u64 = 20;
+// 12-bit conversion time; waited with the scheduler so the idle task can run
+const CONVERSION_MS: u32 = 750;
+
+#[derive(Debug, Default)]
+struct SensorStats {
+    reads: u64,
+    failures: u64,
+    crc_errors: u64,
+    // answering, then not
+    dropouts: u64,
+    failing: bool,
+    min_read_us: Option<u64>,
+    max_read_us: u64,
+    total_read_us: u64,
+}
+
+impl SensorStats {
+    fn record<E>(&mut self, result: &Result<(), OneWireError<E>>, read: Duration) {
+        let read_us = read.as_micros() as u64;
+        self.reads += 1;
+        self.min_read_us = Some(self.min_read_us.map_or(read_us, |min| min.min(read_us)));
+        self.max_read_us = self.max_read_us.max(read_us);
+        self.total_read_us += read_us;
+        match result {
+            Ok(()) => self.failing = false,
+            Err(error) => {
+                self.failures += 1;
+                if matches!(error, OneWireError::CrcMismatch) {
+                    self.crc_errors += 1;
+                }
+                if !self.failing {
+                    self.dropouts += 1;
+                }
+                self.failing = true;
+            }
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let rate = |count: u64| if self.reads == 0 { 0.0 } else { count as f64 / self.reads as f64 };
+        json!({
+            "reads": self.reads,
+            "failures": self.failures,
+            "failure_rate": rate(self.failures),
+            "crc_errors": self.crc_errors,
+            "crc_error_rate": rate(self.crc_errors),
+            "dropouts": self.dropouts,
+            "read_us": {
+                "min": self.min_read_us,
+                "mean": self.total_read_us.checked_div(self.reads),
+                "max": self.max_read_us,
+            },
+        })
+    }
+}
+
+// burn-in test of a cable installation: while it runs, the time the sampling loop would sleep
+// is spent converting and reading every sensor back to back instead
+#[derive(Debug, Default)]
+pub struct Soak {
+    started: Option<Instant>,
+    duration: Duration,
+    passes: u64,
+    // conversions the bus didn't accept, e.g. held low
+    bus_errors: u64,
+    searches: u64,
+    // searches that found a different number of sensors than the scan the test started with,
+    // each mux channel against its own
+    search_mismatches: u64,
+    expected: BTreeMap<u8, usize>,
+    sensors: BTreeMap<String, SensorStats>,
+}
+
+impl Soak {
+    // a zero duration stops a running test, the results stay
+    pub fn start(&mut self, duration: Duration) {
+        if duration.is_zero() {
+            self.duration = self.started.map_or(Duration::ZERO, |started| started.elapsed());
+            return;
+        }
+        *self = Self {
+            started: Some(Instant::now()),
+            duration,
+            ..Self::default()
+        };
+    }
+
+    pub fn active(&self) -> bool {
+        self.started.is_some_and(|started| started.elapsed() < self.duration)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let sensors: Map<String, Value> =
+            self.sensors.iter().map(|(sensor, stats)| (sensor.clone(), stats.to_json())).collect();
+        json!({
+            "active": self.active(),
+            "elapsed_secs": self.started.map(|started| started.elapsed().min(self.duration).as_secs()),
+            "duration_secs": self.duration.as_secs(),
+            "passes": self.passes,
+            "bus_errors": self.bus_errors,
+            "searches": self.searches,
+            "search_mismatches": self.search_mismatches,
+            "expected_sensors": (!self.expected.is_empty()).then(|| self.expected.values().sum::<usize>()),
+            "sensors": sensors,
+        })
+    }
+}
+
+// converts and reads every sensor on the selected mux channel over and over until `budget` is
+// used up
+pub fn hammer<P, E>(
+    context: &Context,
+    one_wire_bus: &mut OneWire<P>,
+    delay: &mut (impl DelayUs<u16> + DelayMs<u16>),
+    channel: u8,
+    addresses: &[Address],
+    budget: Duration,
+)
+    where
+        P: OutputPin<Error=E> + InputPin<Error=E>,
+        E: Debug
+{
+    let deadline = Instant::now() + budget;
+    // a pass needs the conversion time plus a little for the reads
+    while Instant::now() + Duration::from_millis(u64::from(CONVERSION_MS) + 100) < deadline {
+        if !context.soak.lock().unwrap().active() {
+            break;
+        }
+        let overlapped = context.coex.bus_begin();
+        if ds18b20::start_simultaneous_temp_measurement(one_wire_bus, delay).is_err() {
+            context.coex.bus_end(overlapped, 0, 0);
+            context.soak.lock().unwrap().bus_errors += 1;
+            FreeRtos::delay_ms(CONVERSION_MS);
+            continue;
+        }
+        FreeRtos::delay_ms(CONVERSION_MS);
+
+        let mut results = Vec::new();
+        for &address in addresses {
+            let started = Instant::now();
+            let result = ds18b20::Ds18b20::new::<E>(address)
+                .and_then(|sensor| sensor.read_data(one_wire_bus, delay))
+                .map(|_| ());
+            results.push((readings::sensor_id(&address), result, started.elapsed()));
+        }
+
+        let search = (context.soak.lock().unwrap().passes + 1) % SEARCH_EVERY == 0;
+        let found = search.then(|| scan::search(one_wire_bus, delay).map(|found| found.len()));
+        let failures = results.iter().filter(|(_, result, _)| result.is_err()).count();
+        context.coex.bus_end(overlapped, results.len(), failures);
+
+        let mut soak = context.soak.lock().unwrap();
+        let expected = *soak.expected.entry(channel).or_insert(addresses.len());
+        soak.passes += 1;
+        for (sensor, result, read) in results {
+            soak.sensors.entry(sensor).or_default().record(&result, read);
+        }
+        if let Some(found) = found {
+            soak.searches += 1;
+            if found.map_or(true, |found| found != expected) {
+                soak.search_mismatches += 1;
+            }
+        }
+    }
+    // whatever is left of the budget, or all of it once the test is over
+    let left = deadline.saturating_duration_since(Instant::now());
+    FreeRtos::delay_ms(u32::try_from(left.as_millis()).unwrap_or(u32::MAX));
+}
+
+#[derive(Deserialize)]
+struct SoakRequest {
+    minutes: Option<f64>,
+}
+
+// a running test with one sensor, for the API description
+fn status_example() -> Value {
+    let stats = SensorStats { reads: 1, min_read_us: Some(0), ..Default::default() };
+    let soak = Soak {
+        started: Some(Instant::now()),
+        expected: BTreeMap::from([(0, 0)]),
+        sensors: BTreeMap::from([(String::new(), stats)]),
+        ..Default::default()
+    };
+    soak.to_json()
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/soak", Method::Get, Api::json(status_example), move |request| {
+        let body = status_context.soak.lock().unwrap().to_json();
+        http::write_json(request, &body)
+    })?;
+
+    // {"minutes": 60} starts a test, {"minutes": 0} stops it
+    let start_context = context;
+    let start = Api::json(status_example).body(|| json!({ "minutes": 0.0 }));
+    http::route(server, "/api/soak", Method::Post, start, move |mut request| {
+        let soak = match http::read_json::<SoakRequest>(&mut request, 256)? {
+            Ok(soak) => soak,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let args: Map<String, Value> = soak.minutes.map(|minutes| ("minutes".to_string(), json!(minutes))).into_iter().collect();
+        match commands::run(&start_context, "soak", &args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use embedded_hal::blocking::i2c::Write;
+
+pub const DEFAULT_ADDRESS: u8 = 0x3C;
+// 5x7 glyphs with a blank column after each, in the 8 pixel high pages of the controller
+pub const COLUMNS: usize = 21;
+pub const LINES: usize = 8;
+
+const WIDTH: usize = 128;
+const PAGES: usize = 8;
+// 128x64 panel with the internal charge pump, horizontal addressing, rotated so the pins are
+// at the top
+const INIT: [u8; 25] = [
+    0xAE, 0xD5, 0x80, 0xA8, 0x3F, 0xD3, 0x00, 0x40, 0x8D, 0x14, 0x20, 0x00, 0xA1, 0xC8, 0xDA, 0x12, 0x81, 0xCF,
+    0xD9, 0xF1, 0xDB, 0x40, 0xA4, 0xA6, 0xAF,
+];
+const COMMAND: u8 = 0x00;
+const DATA: u8 = 0x40;
+
+// ASCII 0x20 to 0x5F, lowercase is shown as uppercase
+const FONT: [[u8; 5]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5F, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50],
+    [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00],
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08],
+    [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E],
+    [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46],
+    [0x21, 0x41, 0x45, 0x4B, 0x31],
+    [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3C, 0x4A, 0x49, 0x49, 0x30],
+    [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x06, 0x49, 0x49, 0x29, 0x1E],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x00, 0x08, 0x14, 0x22, 0x41],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x41, 0x22, 0x14, 0x08, 0x00],
+    [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3E],
+    [0x7E, 0x11, 0x11, 0x11, 0x7E],
+    [0x7F, 0x49, 0x49, 0x49, 0x36],
+    [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x22, 0x1C],
+    [0x7F, 0x49, 0x49, 0x49, 0x41],
+    [0x7F, 0x09, 0x09, 0x01, 0x01],
+    [0x3E, 0x41, 0x41, 0x51, 0x32],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F],
+    [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01],
+    [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x04, 0x02, 0x7F],
+    [0x7F, 0x04, 0x08, 0x10, 0x7F],
+    [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06],
+    [0x3E, 0x41, 0x51, 0x21, 0x5E],
+    [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7F, 0x01, 0x01],
+    [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F],
+    [0x7F, 0x20, 0x18, 0x20, 0x7F],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03],
+    [0x61, 0x51, 0x49, 0x45, 0x43],
+    [0x00, 0x00, 0x7F, 0x41, 0x41],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x41, 0x41, 0x7F, 0x00, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+];
+const DEGREE: [u8; 5] = [0x00, 0x06, 0x09, 0x09, 0x06];
+const UNKNOWN: [u8; 5] = [0x7F, 0x41, 0x41, 0x41, 0x7F];
+
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '°' => DEGREE,
+        c @ ' '..='_' => FONT[c as usize - 0x20],
+        _ => UNKNOWN,
+    }
+}
+
+// text-only driver for the common 0.96" I2C OLED modules
+pub struct Ssd1306 {
+    address: u8,
+    buffer: [u8; WIDTH * PAGES],
+}
+
+impl Ssd1306 {
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            buffer: [0; WIDTH * PAGES],
+        }
+    }
+
+    pub fn init<I, E>(&self, i2c: &mut I) -> Result<(), E>
+        where
+            I: Write<Error=E>,
+    {
+        let mut command = [COMMAND; INIT.len() + 1];
+        command[1..].copy_from_slice(&INIT);
+        i2c.write(self.address, &command)
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill(0);
+    }
+
+    // one line of text, cut off at the edge of the panel
+    pub fn text(&mut self, line: usize, text: &str) {
+        if line >= LINES {
+            return;
+        }
+        let row = &mut self.buffer[line * WIDTH..(line + 1) * WIDTH];
+        for (index, c) in text.chars().take(COLUMNS).enumerate() {
+            row[index * 6..index * 6 + 5].copy_from_slice(&glyph(c));
+        }
+    }
+
+    pub fn flush<I, E>(&self, i2c: &mut I) -> Result<(), E>
+        where
+            I: Write<Error=E>,
+    {
+        i2c.write(self.address, &[COMMAND, 0x21, 0, (WIDTH - 1) as u8, 0x22, 0, (PAGES - 1) as u8])?;
+        let mut data = [DATA; WIDTH + 1];
+        for page in self.buffer.chunks_exact(WIDTH) {
+            data[1..].copy_from_slice(page);
+            i2c.write(self.address, &data)?;
+        }
+        Ok(())
+    }
+}
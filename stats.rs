@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde_json::{json, Value};
+
+use crate::context::Context;
+use crate::degree_days::{self, Day};
+use crate::events::Event;
+use crate::http;
+use crate::openapi::Api;
+
+const DEFAULT_DAYS: usize = 31;
+
+fn settings_example() -> Value {
+    json!(degree_days::Settings { sensor: Some(String::new()), ..Default::default() })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    // ?days= limits the daily totals to the most recent days
+    let stats_context = context.clone();
+    let stats = Api::json(|| {
+        let day = Day { date: String::new(), heating: 0.0, cooling: 0.0, hours: 0.0 };
+        let degree_days = json!({ "settings": settings_example(), "days": [day], "total_heating": 0.0, "total_cooling": 0.0 });
+        json!({ "degree_days": degree_days })
+    });
+    http::route(server, "/api/stats", Method::Get, stats.query(&[("days", "integer")]), move |request| {
+        let days = http::query_param(request.uri(), "days")
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(DEFAULT_DAYS);
+        let degree_days = stats_context.degree_days.lock().unwrap();
+        let recent = &degree_days.days()[degree_days.days().len().saturating_sub(days)..];
+        let body = json!({
+            "degree_days": {
+                "settings": degree_days.settings,
+                "days": recent,
+                "total_heating": recent.iter().map(|day| day.heating).sum::<f64>(),
+                "total_cooling": recent.iter().map(|day| day.cooling).sum::<f64>(),
+            },
+        });
+        drop(degree_days);
+        http::write_json(request, &body)
+    })?;
+
+    let settings_context = context;
+    let settings = Api::json(settings_example).body(settings_example);
+    http::route(server, "/api/stats/degree_days", Method::Post, settings, move |mut request| {
+        let settings = match http::read_json::<degree_days::Settings>(&mut request, 512)? {
+            Ok(settings) => settings,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let mut degree_days = settings_context.degree_days.lock().unwrap();
+        degree_days.settings = settings;
+        degree_days.save();
+        let body = json!(degree_days.settings);
+        drop(degree_days);
+        settings_context.events.publish(Event::ConfigChanged("degree_days"));
+        http::write_json(request, &body)
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// the "storage" SPIFFS partition from partitions.csv is mounted here (flash::mount); the rest
+// is plain std::fs, so modules that save through it still build on the host
+pub const BASE_PATH: &str = "/storage";
+pub const PARTITION: &CStr = c"storage";
+
+pub fn path(name: &str) -> String {
+    format!("{}/{}", BASE_PATH, name)
+}
+
+pub fn read(name: &str) -> io::Result<Vec<u8>> {
+    recover(&path(name));
+    fs::read(path(name))
+}
+
+pub fn write(name: &str, data: &[u8]) -> io::Result<()> {
+    replace(name, |file| file.write_all(data))
+}
+
+// `fill` writes a temporary file first so a reset mid-write never leaves a truncated file
+// behind; if it fails the old file stays. SPIFFS can't rename onto an existing file, so the old
+// one is moved aside to .bak first and only removed once the new one is in place
+pub fn replace(name: &str, fill: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let target = path(name);
+    let temporary = format!("{}.tmp", target);
+    let backup = format!("{}.bak", target);
+    let mut file = File::create(&temporary)?;
+    let filled = fill(&mut file);
+    drop(file);
+    if let Err(error) = filled {
+        let _ = fs::remove_file(&temporary);
+        return Err(error);
+    }
+    let _ = fs::remove_file(&backup);
+    if fs::metadata(&target).is_ok() {
+        fs::rename(&target, &backup)?;
+    }
+    fs::rename(&temporary, &target)?;
+    let _ = fs::remove_file(&backup);
+    Ok(())
+}
+
+// a reset between moving the old file aside and renaming the new one into place leaves only
+// the .bak; it is moved back
+fn recover(target: &str) {
+    let backup = format!("{}.bak", target);
+    if fs::metadata(target).is_err() && fs::metadata(&backup).is_ok() {
+        if let Err(error) = fs::rename(&backup, target) {
+            log::warn!("failed to recover {}: {}", target, error);
+        }
+    }
+}
+
+// at mount, for the readers that open their files directly (history segments)
+pub fn recover_all() {
+    let Ok(entries) = fs::read_dir(BASE_PATH) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if let Some(target) = entry.path().to_str().and_then(|path| path.strip_suffix(".bak")) {
+            recover(target);
+        }
+    }
+}
+
+pub fn read_json<T: DeserializeOwned>(name: &str) -> Option<T> {
+    serde_json::from_slice(&read(name).ok()?).ok()
+}
+
+pub fn write_json<T: Serialize>(name: &str, value: &T) -> io::Result<()> {
+    write(name, &serde_json::to_vec(value)?)
+}
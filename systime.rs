@@ -0,0 +1,22 @@
+use esp_idf_svc::sntp::EspSntp;
+use esp_idf_svc::sys::{settimeofday, timeval, EspError};
+
+use crate::clock;
+
+// setting the system time, which clock::SystemClock then reads
+pub fn start_sntp() -> Result<EspSntp<'static>, EspError> {
+    EspSntp::new_default()
+}
+
+// from the RTC or the GPS; `source` is what /api/info reports the clock was set by
+pub fn set_unix(unix: u64, source: &'static str) {
+    let time = timeval {
+        tv_sec: unix as _,
+        tv_usec: 0,
+    };
+    if unsafe { settimeofday(&time, std::ptr::null()) } != 0 {
+        log::warn!("failed to set the clock from the {}", source);
+        return;
+    }
+    clock::set_source(source);
+}
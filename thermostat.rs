@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::v2::OutputPin;
+use serde::Serialize;
+
+use crate::units::{Celsius, Degrees};
+
+// two-output (heating and cooling) bang-bang controller with a dead band around the setpoint
+pub struct Thermostat<H, C> {
+    heat: H,
+    cool: C,
+    // outputs switch on when the temperature leaves setpoint ± band and off once it is back at the setpoint
+    pub band: Degrees,
+    // compressors must not be restarted right after stopping
+    pub cool_min_off: Duration,
+    state: Output,
+    cool_stopped: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Output {
+    Idle,
+    Heating,
+    Cooling,
+}
+
+impl<H: OutputPin, C: OutputPin> Thermostat<H, C> {
+    pub fn new(heat: H, cool: C, band: Degrees) -> Self {
+        let mut thermostat = Self {
+            heat,
+            cool,
+            band,
+            cool_min_off: Duration::from_secs(300),
+            state: Output::Idle,
+            cool_stopped: None,
+        };
+        thermostat.apply(Output::Idle);
+        thermostat
+    }
+
+    pub fn state(&self) -> Output {
+        self.state
+    }
+
+    fn apply(&mut self, state: Output) {
+        if self.state == Output::Cooling && state != Output::Cooling {
+            self.cool_stopped = Some(Instant::now());
+        }
+        // pin errors can't be handled meaningfully here, the next update retries anyway
+        let _ = if state == Output::Heating { self.heat.set_high() } else { self.heat.set_low() };
+        let _ = if state == Output::Cooling { self.cool.set_high() } else { self.cool.set_low() };
+        self.state = state;
+    }
+
+    pub fn update(&mut self, celsius: Celsius, setpoint: Celsius) -> Output {
+        let cool_allowed = self.cool_stopped.map_or(true, |stopped| stopped.elapsed() >= self.cool_min_off);
+        let next = match self.state {
+            Output::Heating if celsius >= setpoint => Output::Idle,
+            Output::Cooling if celsius <= setpoint => Output::Idle,
+            Output::Idle if celsius < setpoint - self.band => Output::Heating,
+            Output::Idle if celsius > setpoint + self.band && cool_allowed => Output::Cooling,
+            current => current,
+        };
+        if next != self.state {
+            self.apply(next);
+        }
+        self.state
+    }
+
+    // both outputs off, e.g. when no program is running or the sensor is missing
+    pub fn stop(&mut self) {
+        if self.state != Output::Idle {
+            self.apply(Output::Idle);
+        }
+    }
+}
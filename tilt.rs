@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use esp32_nimble::BLEDevice;
+use esp_idf_hal::task::block_on;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config;
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::units::{Celsius, Fahrenheit};
+
+const APPLE_COMPANY_ID: [u8; 2] = [0x4c, 0x00];
+const IBEACON_TYPE: [u8; 2] = [0x02, 0x15];
+// Tilt hydrometers advertise A495BBx0-C5B1-4B44-B512-1370F02D74DE, x being the color
+const TILT_UUID_PREFIX: [u8; 3] = [0xa4, 0x95, 0xbb];
+const TILT_UUID_SUFFIX: [u8; 12] = [0xc5, 0xb1, 0x4b, 0x44, 0xb5, 0x12, 0x13, 0x70, 0xf0, 0x2d, 0x74, 0xde];
+const COLORS: [&str; 8] = ["red", "green", "black", "purple", "orange", "blue", "yellow", "pink"];
+
+// one scan per sampling cycle, started right after the bus pass
+const SCAN_MS: i32 = 5_000;
+// scan window per 100 ms interval: nearly all of it when BLE has the radio to itself, under a
+// third when it has to share it with Wi-Fi so MQTT and HTTP traffic still get through
+const SCAN_WINDOW_ALONE: u16 = 99;
+const SCAN_WINDOW_SHARED: u16 = 30;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TiltReading {
+    pub color: &'static str,
+    pub gravity: f32,
+    pub celsius: Celsius,
+    #[serde(skip)]
+    pub received: Instant,
+}
+
+// decodes the iBeacon manufacturer data of a Tilt; major is °F and minor is gravity * 1000
+// (the Tilt Pro sends ten times the resolution for both)
+pub fn parse(data: &[u8]) -> Option<TiltReading> {
+    if data.len() < 25 || data[0..2] != APPLE_COMPANY_ID || data[2..4] != IBEACON_TYPE {
+        return None;
+    }
+    let uuid = &data[4..20];
+    if uuid[0..3] != TILT_UUID_PREFIX || uuid[4..16] != TILT_UUID_SUFFIX || uuid[3] & 0x0f != 0 {
+        return None;
+    }
+    let color = *COLORS.get(usize::from(uuid[3] >> 4).checked_sub(1)?)?;
+
+    let major = f32::from(u16::from_be_bytes([data[20], data[21]]));
+    let minor = f32::from(u16::from_be_bytes([data[22], data[23]]));
+    let (fahrenheit, gravity) = if minor > 5000.0 {
+        (major / 10.0, minor / 10000.0)
+    } else {
+        (major, minor / 1000.0)
+    };
+
+    Some(TiltReading {
+        color,
+        gravity,
+        celsius: Fahrenheit(fahrenheit).into(),
+        received: Instant::now(),
+    })
+}
+
+// passive BLE scans in the background, the latest Tilt seen is kept in the context
+pub fn start_scanner(context: Arc<Context>) {
+    let window = if config::WIFI_SSID.is_empty() { SCAN_WINDOW_ALONE } else { SCAN_WINDOW_SHARED };
+    thread::Builder::new()
+        .name("tilt".into())
+        .stack_size(8 * 1024)
+        .spawn(move || {
+            let device = BLEDevice::take();
+            let scan = device.get_scan();
+            let callback_context = context.clone();
+            scan.active_scan(false).interval(100).window(window).on_result(move |_scan, advertised| {
+                if let Some(reading) = advertised.get_manufacture_data().and_then(parse) {
+                    *callback_context.hydrometer.lock().unwrap() = Some(reading);
+                }
+            });
+            loop {
+                context.coex.scan_begin();
+                if let Err(error) = block_on(scan.start(SCAN_MS)) {
+                    log::warn!("BLE scan failed: {:?}", error);
+                }
+                context.coex.scan_end();
+            }
+        })
+        .expect("failed to start the tilt scanner");
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let hydrometer = Api::json(|| json!({ "color": "", "gravity": 0.0, "celsius": 0.0, "age_secs": 0 }));
+    http::route(server, "/api/hydrometer", Method::Get, hydrometer, move |request| {
+        let hydrometer = context.hydrometer.lock().unwrap().clone();
+        let Some(tilt) = hydrometer else {
+            return http::write_error(request, 404, "no hydrometer seen yet");
+        };
+        http::write_json(
+            request,
+            &json!({
+                "color": tilt.color,
+                "gravity": tilt.gravity,
+                "celsius": tilt.celsius,
+                "age_secs": tilt.received.elapsed().as_secs(),
+            }),
+        )
+    })?;
+
+    Ok(())
+}
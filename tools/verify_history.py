@@ -0,0 +1,54 @@
+#!/usr/bin/env python3
+# checks a GET /api/history/raw export against the device's public key
+# (GET /api/compliance/key); needs the "cryptography" package
+#
+#   curl -s http://<node>/api/history/raw > history.ndjson
+#   ./tools/verify_history.py history.ndjson <public key hex>
+import hashlib
+import json
+import sys
+
+from cryptography.exceptions import InvalidSignature
+from cryptography.hazmat.primitives.asymmetric.ed25519 import Ed25519PublicKey
+
+
+def verify(data, public_key):
+    key = Ed25519PublicKey.from_public_bytes(bytes.fromhex(public_key))
+    head = None
+    segment = b""
+    seals = 0
+    for line in data.splitlines(keepends=True):
+        try:
+            record = json.loads(line)
+        except ValueError:
+            record = None
+        if not isinstance(record, dict) or record.get("type") != "seal":
+            segment += line
+            continue
+        prev = bytes.fromhex(record["prev"])
+        # the oldest segments are dropped on rotation, so the first seal's prev is taken as is
+        if head is not None and prev != head:
+            raise ValueError("seal %d doesn't continue the chain" % (seals + 1))
+        digest = hashlib.sha256(prev + segment).digest()
+        if digest.hex() != record["hash"]:
+            raise ValueError("segment %d was modified" % (seals + 1))
+        try:
+            key.verify(bytes.fromhex(record["signature"]), digest)
+        except InvalidSignature:
+            raise ValueError("seal %d has an invalid signature" % (seals + 1))
+        head = digest
+        segment = b""
+        seals += 1
+    if segment:
+        raise ValueError("%d bytes after the last seal are not covered" % len(segment))
+    return seals
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 3:
+        sys.exit("usage: verify_history.py <export> <public key hex>")
+    with open(sys.argv[1], "rb") as export:
+        try:
+            print("ok, %d sealed segments" % verify(export.read(), sys.argv[2]))
+        except ValueError as error:
+            sys.exit("FAILED: %s" % error)
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde_json::json;
+
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::units::{Celsius, Degrees};
+
+// warming a probe in a hand or with breath gives well over this within a few samples
+const RISE: Degrees = Degrees(1.0);
+
+// guided discovery of the order of sensors along a cable: the user warms one sensor after
+// the other, starting at the controller end, and each one that rises is given the next index
+#[derive(Debug, Default)]
+pub struct Discovery {
+    // sensor readings to detect a rise against, for the sensors not placed yet
+    baseline: BTreeMap<String, Celsius>,
+    order: Vec<String>,
+    active: bool,
+}
+
+impl Discovery {
+    pub fn start(&mut self, readings: &[Reading]) {
+        self.baseline = readings
+            .iter()
+            // only DS18B20s hang off the cable, their ids end in the family code
+            .filter(|reading| reading.sensor.len() == 16 && reading.sensor.ends_with("28"))
+            .map(|reading| (reading.sensor.clone(), reading.celsius))
+            .collect();
+        self.order.clear();
+        self.active = true;
+    }
+
+    pub fn cancel(&mut self) {
+        self.baseline.clear();
+        self.active = false;
+    }
+
+    pub fn update(&mut self, readings: &[Reading]) {
+        if !self.active {
+            return;
+        }
+        let rise = |reading: &Reading| self.baseline.get(&reading.sensor).map(|baseline| reading.celsius - *baseline);
+        let warmed = readings
+            .iter()
+            .filter_map(|reading| Some((reading, rise(reading)?)))
+            .filter(|(_, rise)| *rise >= RISE)
+            .max_by(|a, b| a.1 .0.total_cmp(&b.1 .0))
+            .map(|(reading, _)| reading.sensor.clone());
+        let Some(sensor) = warmed else {
+            // follow slow ambient changes so they don't add up to a rise
+            for reading in readings {
+                if let Some(baseline) = self.baseline.get_mut(&reading.sensor) {
+                    *baseline = baseline.min(reading.celsius);
+                }
+            }
+            return;
+        };
+        self.baseline.remove(&sensor);
+        self.order.push(sensor);
+        // everything else starts over from where it is now, the warmed sensor may have
+        // heated its neighbours a little
+        for reading in readings {
+            if let Some(baseline) = self.baseline.get_mut(&reading.sensor) {
+                *baseline = reading.celsius;
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "active": self.active,
+            "order": self.order,
+            "remaining": self.baseline.keys().collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn status_example() -> serde_json::Value {
+    json!({ "active": true, "order": [""], "remaining": [""] })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    let status_context = context.clone();
+    http::route(server, "/api/topology", Method::Get, Api::json(status_example), move |request| {
+        let body = status_context.topology.lock().unwrap().to_json();
+        http::write_json(request, &body)
+    })?;
+
+    // takes the current readings as the baseline, then warm the sensors one by one
+    let start_context = context.clone();
+    http::route(server, "/api/topology/start", Method::Post, Api::json(status_example), move |request| {
+        let readings = start_context.latest_readings();
+        let mut discovery = start_context.topology.lock().unwrap();
+        discovery.start(&readings);
+        let body = discovery.to_json();
+        drop(discovery);
+        http::write_json(request, &body)
+    })?;
+
+    // stores the discovered order as the registry "position" (1 for the first sensor on the
+    // cable); sensors that weren't warmed keep theirs
+    let finish_context = context.clone();
+    let finish = Api::json(|| json!({ "order": [""] }));
+    http::route(server, "/api/topology/finish", Method::Post, finish, move |request| {
+        let mut discovery = finish_context.topology.lock().unwrap();
+        if !discovery.active {
+            drop(discovery);
+            return http::write_error(request, 409, "no discovery running");
+        }
+        let order = discovery.order.clone();
+        discovery.cancel();
+        drop(discovery);
+
+        let mut registry = finish_context.registry.lock().unwrap();
+        for (index, sensor) in order.iter().enumerate() {
+            let mut info = registry.get(sensor).cloned().unwrap_or_default();
+            info.position = Some(index as u32 + 1);
+            registry.update(sensor, info);
+        }
+        let saved = registry.save();
+        drop(registry);
+        if let Err(error) = saved {
+            return http::write_error(request, 500, &error.to_string());
+        }
+        http::write_json(request, &json!({ "order": order }))
+    })?;
+
+    let cancel_context = context;
+    http::route(server, "/api/topology", Method::Delete, Api::json(|| json!({ "active": false })), move |request| {
+        cancel_context.topology.lock().unwrap().cancel();
+        http::write_json(request, &json!({ "active": false }))
+    })?;
+
+    Ok(())
+}
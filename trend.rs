@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+use crate::readings::Reading;
+use crate::units::{Celsius, DegreesPerMinute};
+
+// trends are fitted over this much recent history
+const WINDOW_SECS: f32 = 30.0 * 60.0;
+// and only once there is enough of it to mean something
+const MIN_SPAN_SECS: f32 = 5.0 * 60.0;
+const MIN_SAMPLES: usize = 6;
+// predictions further out than this aren't reported
+const MAX_HORIZON_MINUTES: f32 = 24.0 * 60.0;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum Fit {
+    Linear {
+        celsius: f32,
+        celsius_per_minute: f32,
+    },
+    // approaching `asymptote` like a freezer warming towards room temperature
+    Exponential {
+        celsius: f32,
+        asymptote: f32,
+        time_constant_minutes: f32,
+    },
+}
+
+impl Fit {
+    fn value_at(&self, minutes: f32) -> f32 {
+        match *self {
+            Fit::Linear { celsius, celsius_per_minute } => celsius + celsius_per_minute * minutes,
+            Fit::Exponential { celsius, asymptote, time_constant_minutes } => {
+                asymptote + (celsius - asymptote) * (-minutes / time_constant_minutes).exp()
+            }
+        }
+    }
+
+    pub fn rate(&self) -> DegreesPerMinute {
+        match *self {
+            Fit::Linear { celsius_per_minute, .. } => DegreesPerMinute(celsius_per_minute),
+            Fit::Exponential { celsius, asymptote, time_constant_minutes } => {
+                (Celsius(asymptote) - Celsius(celsius)).per_minutes(time_constant_minutes)
+            }
+        }
+    }
+
+    // estimated minutes until the temperature crosses `threshold`, None if it isn't heading there
+    pub fn minutes_until(&self, threshold: Celsius) -> Option<f32> {
+        let Celsius(threshold) = threshold;
+        let minutes = match *self {
+            Fit::Linear { celsius, celsius_per_minute } => {
+                if celsius_per_minute == 0.0 {
+                    return None;
+                }
+                (threshold - celsius) / celsius_per_minute
+            }
+            Fit::Exponential { celsius, asymptote, time_constant_minutes } => {
+                let ratio = (threshold - asymptote) / (celsius - asymptote);
+                // the curve only ever gets between the current value and the asymptote
+                if !(ratio > 0.0 && ratio <= 1.0) {
+                    return None;
+                }
+                -time_constant_minutes * ratio.ln()
+            }
+        };
+        (0.0..=MAX_HORIZON_MINUTES).contains(&minutes).then_some(minutes)
+    }
+}
+
+// least squares line and, if the data curves, a three-segment exponential fit; whichever
+// explains the samples better wins. Times are minutes relative to the newest sample.
+pub fn fit(samples: &[(f32, f32)]) -> Option<Fit> {
+    let n = samples.len() as f32;
+    let mean_t = samples.iter().map(|sample| sample.0).sum::<f32>() / n;
+    let mean_c = samples.iter().map(|sample| sample.1).sum::<f32>() / n;
+    let covariance: f32 = samples.iter().map(|(t, c)| (t - mean_t) * (c - mean_c)).sum();
+    let variance: f32 = samples.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+    let slope = covariance / variance;
+    let linear = Fit::Linear {
+        celsius: mean_c - slope * mean_t,
+        celsius_per_minute: slope,
+    };
+
+    let squared_error = |fit: &Fit| -> f32 { samples.iter().map(|(t, c)| (fit.value_at(*t) - c).powi(2)).sum() };
+    let Some(exponential) = fit_exponential(samples) else {
+        return Some(linear);
+    };
+    if squared_error(&exponential) < squared_error(&linear) {
+        Some(exponential)
+    } else {
+        Some(linear)
+    }
+}
+
+// means of three equally long time segments a, b, c; for an exponential approach
+// (c - b) / (b - a) is the decay over one segment length
+fn fit_exponential(samples: &[(f32, f32)]) -> Option<Fit> {
+    let start = samples.first()?.0;
+    let segment = (samples.last()?.0 - start) / 3.0;
+    let mean_of = |index: usize| -> Option<(f32, f32)> {
+        let from = start + segment * index as f32;
+        let inside: Vec<_> = samples
+            .iter()
+            .filter(|(t, _)| *t >= from && (*t < from + segment || index == 2))
+            .collect();
+        if inside.is_empty() {
+            return None;
+        }
+        let count = inside.len() as f32;
+        Some((
+            inside.iter().map(|(t, _)| t).sum::<f32>() / count,
+            inside.iter().map(|(_, c)| c).sum::<f32>() / count,
+        ))
+    };
+    let ((_, a), (_, b), (t_c, c)) = (mean_of(0)?, mean_of(1)?, mean_of(2)?);
+
+    let ratio = (c - b) / (b - a);
+    if !(0.05..0.95).contains(&ratio) || segment <= 0.0 {
+        return None;
+    }
+    let asymptote = (a * c - b * b) / (a + c - 2.0 * b);
+    let time_constant_minutes = -segment / ratio.ln();
+    // segment mean c sits at t_c (minutes before now), extrapolate it to now
+    let celsius = asymptote + (c - asymptote) * (t_c / time_constant_minutes).exp();
+    Some(Fit::Exponential {
+        celsius,
+        asymptote,
+        time_constant_minutes,
+    })
+}
+
+#[derive(Default)]
+pub struct Trends {
+    samples: BTreeMap<String, VecDeque<(Instant, f32)>>,
+}
+
+impl Trends {
+    pub fn update(&mut self, readings: &[Reading]) {
+        let now = Instant::now();
+        for reading in readings {
+            let samples = self.samples.entry(reading.sensor.clone()).or_default();
+            samples.push_back((now, reading.celsius.0));
+            while samples.front().is_some_and(|(time, _)| now.duration_since(*time).as_secs_f32() > WINDOW_SECS) {
+                samples.pop_front();
+            }
+        }
+        self.samples.retain(|sensor, _| readings.iter().any(|reading| &reading.sensor == sensor));
+    }
+
+    pub fn fit(&self, sensor: &str) -> Option<Fit> {
+        let samples = self.samples.get(sensor)?;
+        let newest = samples.back()?.0;
+        let relative: Vec<(f32, f32)> = samples
+            .iter()
+            .map(|(time, celsius)| (-newest.duration_since(*time).as_secs_f32() / 60.0, *celsius))
+            .collect();
+        if relative.len() < MIN_SAMPLES || -relative[0].0 * 60.0 < MIN_SPAN_SECS {
+            return None;
+        }
+        fit(&relative)
+    }
+
+    pub fn sensors(&self) -> impl Iterator<Item = &String> {
+        self.samples.keys()
+    }
+}
+
+pub fn trend_alarm_id(sensor: &str) -> String {
+    format!("trend_{}", sensor)
+}
+
+// warns ahead of the threshold alarm when a sensor is predicted to cross alarm_low/alarm_high
+// within its trend_warning_minutes
+pub fn update_warnings(context: &Context) {
+    let trends = context.trends.lock().unwrap();
+    let registry = context.registry.lock().unwrap();
+    for (sensor, info) in registry.sensors() {
+        let id = trend_alarm_id(sensor);
+        let Some(warning_minutes) = info.trend_warning_minutes else {
+            context.alarms.clear(&id);
+            continue;
+        };
+        let Some(fit) = trends.fit(sensor) else {
+            context.alarms.clear(&id);
+            continue;
+        };
+        let eta = [info.alarm_low, info.alarm_high]
+            .into_iter()
+            .flatten()
+            .filter_map(|threshold| Some((threshold, fit.minutes_until(threshold)?)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        match eta {
+            // already past it is the threshold alarm's job
+            Some((threshold, minutes)) if minutes > 0.0 && minutes <= warning_minutes as f32 => {
+                let name = info.name.as_deref().unwrap_or(sensor);
+                context.alarms.raise(&id, format!("{} will reach {} in about {:.0} min", name, threshold, minutes));
+            }
+            _ => context.alarms.clear(&id),
+        }
+    }
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    // per sensor: fitted model, rate and minutes until its alarm thresholds;
+    // ?threshold=<°C> adds the estimate for an arbitrary temperature
+    let trends = Api::json(|| {
+        let fits = [
+            Fit::Linear { celsius: 0.0, celsius_per_minute: 0.0 },
+            Fit::Exponential { celsius: 0.0, asymptote: 0.0, time_constant_minutes: 0.0 },
+        ];
+        let sensor = |fit| {
+            json!({
+                "sensor": "",
+                "fit": fit,
+                "celsius_per_minute": 0.0,
+                "minutes_to_alarm_low": 0.0,
+                "minutes_to_alarm_high": 0.0,
+                "minutes_to_threshold": 0.0,
+            })
+        };
+        json!(fits.map(sensor))
+    });
+    http::route(server, "/api/trends", Method::Get, trends.query(&[("threshold", "number")]), move |request| {
+        let threshold = http::query_param(request.uri(), "threshold").and_then(|value| value.parse().ok()).map(Celsius);
+        let trends = context.trends.lock().unwrap();
+        let registry = context.registry.lock().unwrap();
+        let body: Vec<_> = trends
+            .sensors()
+            .map(|sensor| {
+                let fit = trends.fit(sensor);
+                let info = registry.get(sensor);
+                let until = |limit: Option<Celsius>| fit.zip(limit).and_then(|(fit, limit)| fit.minutes_until(limit));
+                json!({
+                    "sensor": sensor,
+                    "fit": fit,
+                    "celsius_per_minute": fit.map(|fit| fit.rate()),
+                    "minutes_to_alarm_low": until(info.and_then(|info| info.alarm_low)),
+                    "minutes_to_alarm_high": until(info.and_then(|info| info.alarm_high)),
+                    "minutes_to_threshold": until(threshold),
+                })
+            })
+            .collect();
+        drop(registry);
+        drop(trends);
+        http::write_json(request, &json!(body))
+    })?;
+
+    Ok(())
+}
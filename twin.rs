@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::sys::EspError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::context::Context;
+use crate::derived;
+use crate::drift;
+use crate::expressions;
+use crate::http;
+use crate::openapi::{self, Api};
+use crate::redundancy;
+use crate::rules;
+use crate::schedule;
+use crate::schedule_api;
+use crate::storage;
+
+// the desired document last applied and what failed of it, to tell it from a new one after a
+// restart
+const DESIRED_FILE: &str = "twin.json";
+
+// a part of the configuration the twin can set, with the same validation as its POST endpoint
+struct Section {
+    name: &'static str,
+    reported: fn(&Context) -> Value,
+    // the desired value as the module would store it, with defaults filled in, to compare
+    normalize: fn(&Value) -> Result<Value, String>,
+    apply: fn(&Context, &Value) -> Result<(), (u16, String)>,
+    // for the API description
+    example: fn() -> Value,
+}
+
+fn normalize<T: DeserializeOwned + Serialize>(desired: &Value) -> Result<Value, String> {
+    let settings: T = serde_json::from_value(desired.clone()).map_err(|error| error.to_string())?;
+    Ok(json!(settings))
+}
+
+fn parse<T: DeserializeOwned>(desired: &Value) -> Result<T, (u16, String)> {
+    serde_json::from_value(desired.clone()).map_err(|error| (400, error.to_string()))
+}
+
+const SECTIONS: &[Section] = &[
+    Section {
+        name: "schedule",
+        reported: |context| json!(*context.schedule.lock().unwrap()),
+        normalize: normalize::<schedule::Settings>,
+        apply: |context, desired| schedule_api::apply(context, parse(desired)?),
+        example: || json!(schedule::settings_example()),
+    },
+    Section {
+        name: "derived",
+        reported: |context| json!(*context.derived.lock().unwrap()),
+        normalize: normalize::<derived::Settings>,
+        apply: |context, desired| derived::apply(context, parse(desired)?),
+        example: openapi::defaults::<derived::Settings>,
+    },
+    Section {
+        name: "redundancy",
+        reported: |context| json!(*context.redundancy.lock().unwrap()),
+        normalize: normalize::<redundancy::Settings>,
+        apply: |context, desired| redundancy::apply(context, parse(desired)?),
+        example: || json!(redundancy::settings_example()),
+    },
+    Section {
+        name: "expressions",
+        reported: |context| json!(*context.expressions.lock().unwrap()),
+        normalize: normalize::<expressions::Settings>,
+        apply: |context, desired| expressions::apply(context, parse(desired)?),
+        example: expressions::settings_example,
+    },
+    Section {
+        name: "drift",
+        reported: |context| json!(context.drift.lock().unwrap().settings),
+        normalize: normalize::<drift::Settings>,
+        apply: |context, desired| drift::apply(context, parse(desired)?),
+        example: openapi::defaults::<drift::Settings>,
+    },
+    Section {
+        name: "rules",
+        reported: |context| json!(context.rules.lock().unwrap().settings),
+        normalize: normalize::<rules::Settings>,
+        apply: |context, desired| rules::apply(context, parse(desired)?),
+        example: || json!(rules::settings_example()),
+    },
+];
+
+// desired configuration from a retained message on temp/<node>/desired, reported back on
+// temp/<node>/reported; a section changed locally afterwards (over the API) is reported as
+// divergent rather than overwritten, until a new desired document comes in
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Twin {
+    desired: Map<String, Value>,
+    // why a desired section couldn't be applied
+    errors: BTreeMap<String, String>,
+}
+
+impl Twin {
+    pub fn load() -> Self {
+        storage::read_json(DESIRED_FILE).unwrap_or_default()
+    }
+
+    // applies every section that differs from the running configuration. The retained
+    // document arrives again with every reconnect: the one already applied changes nothing,
+    // unless a section of it failed and is worth another try
+    pub fn set_desired(&mut self, context: &Context, desired: Map<String, Value>) {
+        if desired == self.desired && self.errors.is_empty() {
+            return;
+        }
+        self.errors.clear();
+        for (name, value) in &desired {
+            let Some(section) = SECTIONS.iter().find(|section| section.name == name) else {
+                self.errors.insert(name.clone(), "not a twin section".to_string());
+                continue;
+            };
+            let result = (section.normalize)(value).map_err(|error| (400, error)).and_then(|normalized| {
+                if normalized == (section.reported)(context) {
+                    return Ok(());
+                }
+                (section.apply)(context, value)
+            });
+            if let Err((_, error)) = result {
+                log::warn!("desired {} not applied: {}", name, error);
+                self.errors.insert(name.clone(), error);
+            }
+        }
+        self.desired = desired;
+        if let Err(error) = storage::write_json(DESIRED_FILE, self) {
+            log::warn!("failed to save the desired configuration: {}", error);
+        }
+    }
+
+    pub fn reported(&self, context: &Context) -> Value {
+        let mut reported = Map::new();
+        let mut divergent = Vec::new();
+        for section in SECTIONS {
+            let value = (section.reported)(context);
+            if let Some(desired) = self.desired.get(section.name) {
+                if (section.normalize)(desired).map_or(true, |desired| desired != value) {
+                    divergent.push(section.name);
+                }
+            }
+            reported.insert(section.name.to_string(), value);
+        }
+        json!({ "config": reported, "divergent": divergent, "errors": self.errors })
+    }
+
+    pub fn to_json(&self, context: &Context) -> Value {
+        let mut twin = self.reported(context);
+        twin["desired"] = Value::Object(self.desired.clone());
+        twin
+    }
+}
+
+pub fn section_names() -> impl Iterator<Item = &'static str> {
+    SECTIONS.iter().map(|section| section.name)
+}
+
+// the running value of one section, None for a name that isn't one
+pub fn section(context: &Context, name: &str) -> Option<Value> {
+    SECTIONS.iter().find(|section| section.name == name).map(|section| (section.reported)(context))
+}
+
+// one section, validated and saved like its POST endpoint does it
+pub fn apply_section(context: &Context, name: &str, value: &Value) -> Result<(), (u16, String)> {
+    let section = SECTIONS
+        .iter()
+        .find(|section| section.name == name)
+        .ok_or_else(|| (404, format!("{} is not a twin section", name)))?;
+    (section.apply)(context, value)
+}
+
+// the MQTT callback's entry point, a payload that isn't a JSON object is ignored
+pub fn handle_desired(context: &Context, payload: &[u8]) {
+    match serde_json::from_slice::<Map<String, Value>>(payload) {
+        Ok(desired) => context.twin.lock().unwrap().set_desired(context, desired),
+        Err(error) => log::warn!("ignoring desired configuration: {}", error),
+    }
+}
+
+fn twin_example() -> Value {
+    let config: Map<String, Value> = SECTIONS.iter().map(|section| (section.name.to_string(), (section.example)())).collect();
+    json!({ "config": config, "divergent": [""], "errors": { "": "" }, "desired": config })
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    http::route(server, "/api/twin", Method::Get, Api::json(twin_example), move |request| {
+        let body = context.twin.lock().unwrap().to_json(&context);
+        http::write_json(request, &body)
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use serde::{Deserialize, Serialize};
+
+// a temperature in degrees Celsius. Thresholds, setpoints, readings and the controllers take
+// this instead of a bare f32, so a Fahrenheit value (Tilt hydrometers and the heat index formula
+// work in it), a difference or a rate can't be passed where a temperature goes. It is a plain
+// number in JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Celsius(pub f32);
+
+// a difference of two temperatures, e.g. a band, a hysteresis or a calibration offset
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Degrees(pub f32);
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Fahrenheit(pub f32);
+
+// how fast a temperature changes, e.g. the slope of a trend
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DegreesPerMinute(pub f32);
+
+impl Celsius {
+    pub fn min(self, other: Celsius) -> Celsius {
+        Celsius(self.0.min(other.0))
+    }
+
+    pub fn max(self, other: Celsius) -> Celsius {
+        Celsius(self.0.max(other.0))
+    }
+}
+
+impl Degrees {
+    pub fn abs(self) -> Self {
+        Degrees(self.0.abs())
+    }
+
+    // the rate of a change of this size over `minutes`
+    pub fn per_minutes(self, minutes: f32) -> DegreesPerMinute {
+        DegreesPerMinute(self.0 / minutes)
+    }
+}
+
+impl Sub for Celsius {
+    type Output = Degrees;
+
+    fn sub(self, other: Celsius) -> Degrees {
+        Degrees(self.0 - other.0)
+    }
+}
+
+impl Add<Degrees> for Celsius {
+    type Output = Celsius;
+
+    fn add(self, degrees: Degrees) -> Celsius {
+        Celsius(self.0 + degrees.0)
+    }
+}
+
+impl Sub<Degrees> for Celsius {
+    type Output = Celsius;
+
+    fn sub(self, degrees: Degrees) -> Celsius {
+        Celsius(self.0 - degrees.0)
+    }
+}
+
+impl Add for Degrees {
+    type Output = Degrees;
+
+    fn add(self, other: Degrees) -> Degrees {
+        Degrees(self.0 + other.0)
+    }
+}
+
+impl Sub for Degrees {
+    type Output = Degrees;
+
+    fn sub(self, other: Degrees) -> Degrees {
+        Degrees(self.0 - other.0)
+    }
+}
+
+impl Neg for Degrees {
+    type Output = Degrees;
+
+    fn neg(self) -> Degrees {
+        Degrees(-self.0)
+    }
+}
+
+// scales a difference, e.g. the part of a ramp already done
+impl Mul<f32> for Degrees {
+    type Output = Degrees;
+
+    fn mul(self, factor: f32) -> Degrees {
+        Degrees(self.0 * factor)
+    }
+}
+
+impl From<Fahrenheit> for Celsius {
+    fn from(fahrenheit: Fahrenheit) -> Self {
+        Celsius((fahrenheit.0 - 32.0) * 5.0 / 9.0)
+    }
+}
+
+impl From<Celsius> for Fahrenheit {
+    fn from(celsius: Celsius) -> Self {
+        Fahrenheit(celsius.0 * 9.0 / 5.0 + 32.0)
+    }
+}
+
+// with the unit, and the precision applied to the number: format!("{:.1}", Celsius(4.26)) is
+// "4.3 °C"
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match f.precision() {
+            Some(precision) => write!(f, "{:.*} °C", precision, self.0),
+            None => write!(f, "{} °C", self.0),
+        }
+    }
+}
+
+// in °C as well, as a band is usually written: "0.5 °C"
+impl fmt::Display for Degrees {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match f.precision() {
+            Some(precision) => write!(f, "{:.*} °C", precision, self.0),
+            None => write!(f, "{} °C", self.0),
+        }
+    }
+}
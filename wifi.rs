@@ -0,0 +1,293 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+use esp_idf_hal::modem::Modem;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::http::Method;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{
+    esp, esp_efuse_mac_get_default, esp_ip6_addr_t, esp_netif_create_ip6_linklocal, esp_netif_get_all_ip6,
+    esp_netif_get_handle_from_ifkey, esp_netif_get_ip_info, esp_netif_ip_info_t, esp_netif_t, esp_wifi_get_config,
+    esp_wifi_set_config, esp_wifi_set_ps, esp_wifi_sta_get_ap_info, wifi_ap_record_t, wifi_config_t,
+    wifi_interface_t_WIFI_IF_STA, wifi_ps_type_t_WIFI_PS_MAX_MODEM, wifi_ps_type_t_WIFI_PS_MIN_MODEM,
+    wifi_ps_type_t, wifi_ps_type_t_WIFI_PS_NONE, EspError,
+};
+use esp_idf_svc::wifi::{BlockingWifi, ClientConfiguration, Configuration, EspWifi};
+use serde::Deserialize;
+use serde_json::{json, Map};
+
+use crate::commands::{self, Source};
+use crate::config;
+use crate::context::Context;
+use crate::http;
+use crate::openapi::Api;
+
+const NAMESPACE: &str = "wifi";
+const AP_KEY: &str = "ap";
+// the key esp-idf gives the default station interface
+const STA_IFKEY: &std::ffi::CStr = c"WIFI_STA_DEF";
+// more than lwIP keeps per interface (CONFIG_LWIP_IPV6_NUM_ADDRESSES, 3 by default)
+const MAX_IPV6_ADDRESSES: usize = 8;
+const POWER_SAVE_MODES: &[(&str, wifi_ps_type_t)] = &[
+    ("none", wifi_ps_type_t_WIFI_PS_NONE),
+    ("min", wifi_ps_type_t_WIFI_PS_MIN_MODEM),
+    ("max", wifi_ps_type_t_WIFI_PS_MAX_MODEM),
+];
+
+// WIFI_POWER_SAVE until POST /api/wifi/power switches it, to compare the modes without a
+// rebuild; back to the build setting after a restart
+static POWER_SAVE: Mutex<&str> = Mutex::new(config::WIFI_POWER_SAVE);
+
+// BSSID and channel of the access point
+type AccessPoint = ([u8; 6], u8);
+
+// the access point of the last connection; joining it directly skips the scan of every
+// channel, which is most of the time it takes to come up
+fn remembered_ap(nvs: &EspNvs<NvsDefault>) -> Option<AccessPoint> {
+    let mut buffer = [0u8; 7];
+    let stored = nvs.get_raw(AP_KEY, &mut buffer).ok()??;
+    (stored.len() == 7).then(|| (stored[..6].try_into().unwrap(), stored[6]))
+}
+
+fn current_ap() -> Option<AccessPoint> {
+    let mut record: wifi_ap_record_t = unsafe { std::mem::zeroed() };
+    esp!(unsafe { esp_wifi_sta_get_ap_info(&mut record) }).ok()?;
+    Some((record.bssid, record.primary))
+}
+
+fn configure(wifi: &mut BlockingWifi<EspWifi<'static>>, ap: Option<AccessPoint>) -> Result<(), EspError> {
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        ssid: config::WIFI_SSID.try_into().unwrap(),
+        password: config::WIFI_PASSWORD.try_into().unwrap(),
+        bssid: ap.map(|(bssid, _)| bssid),
+        channel: ap.map(|(_, channel)| channel),
+        ..Default::default()
+    }))?;
+    set_listen_interval()
+}
+
+// the driver, which owns the modem, comes back even when the connect failed so the caller can
+// try again with set_enabled; only a driver that can't be set up at all is an error
+pub fn connect(
+    modem: Modem,
+    sysloop: EspSystemEventLoop,
+    nvs: EspDefaultNvsPartition,
+) -> Result<(BlockingWifi<EspWifi<'static>>, Result<(), EspError>), EspError> {
+    let mut ap_nvs = EspNvs::new(nvs.clone(), NAMESPACE, true)?;
+    let mut wifi = BlockingWifi::wrap(EspWifi::new(modem, sysloop.clone(), Some(nvs))?, sysloop)?;
+
+    let remembered = remembered_ap(&ap_nvs);
+    configure(&mut wifi, remembered)?;
+    wifi.start()?;
+    set_power_save()?;
+    let connected = join(&mut wifi, remembered, &mut ap_nvs);
+    Ok((wifi, connected))
+}
+
+fn join(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    remembered: Option<AccessPoint>,
+    ap_nvs: &mut EspNvs<NvsDefault>,
+) -> Result<(), EspError> {
+    if let Err(error) = wifi.connect().and_then(|()| wait_up(wifi)) {
+        if remembered.is_none() {
+            return Err(error);
+        }
+        // the access point was replaced or moved to another channel
+        log::warn!("remembered access point failed ({}), scanning", error);
+        let _ = wifi.disconnect();
+        configure(wifi, None)?;
+        wifi.connect()?;
+        wait_up(wifi)?;
+    }
+    log::info!("addresses: {:?}", addresses());
+
+    if let Some((bssid, channel)) = current_ap().filter(|ap| Some(*ap) != remembered) {
+        let mut stored = [0u8; 7];
+        stored[..6].copy_from_slice(&bssid);
+        stored[6] = channel;
+        if let Err(error) = ap_nvs.set_raw(AP_KEY, &stored) {
+            log::warn!("failed to remember the access point: {}", error);
+        }
+    }
+
+    Ok(())
+}
+
+fn sta_netif() -> Option<*mut esp_netif_t> {
+    let netif = unsafe { esp_netif_get_handle_from_ifkey(STA_IFKEY.as_ptr()) };
+    (!netif.is_null()).then_some(netif)
+}
+
+fn ipv6_addresses(netif: *mut esp_netif_t) -> Vec<Ipv6Addr> {
+    let mut found: [esp_ip6_addr_t; MAX_IPV6_ADDRESSES] = unsafe { std::mem::zeroed() };
+    let count = unsafe { esp_netif_get_all_ip6(netif, found.as_mut_ptr()) };
+    found
+        .iter()
+        .take(usize::try_from(count).unwrap_or(0))
+        .map(|address| {
+            // lwIP keeps the words in network byte order
+            let mut octets = [0u8; 16];
+            for (chunk, word) in octets.chunks_exact_mut(4).zip(address.addr) {
+                chunk.copy_from_slice(&word.to_ne_bytes());
+            }
+            Ipv6Addr::from(octets)
+        })
+        .collect()
+}
+
+fn is_link_local(address: &Ipv6Addr) -> bool {
+    address.segments()[0] & 0xffc0 == 0xfe80
+}
+
+// after the association: the link-local address needs no router, the IPv4 lease a DHCP server;
+// with NET_PREFER=v6 the link-local address is enough
+fn wait_up(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<(), EspError> {
+    let netif = wifi.wifi().sta_netif().handle();
+    if let Err(error) = esp!(unsafe { esp_netif_create_ip6_linklocal(netif) }) {
+        log::warn!("no IPv6 link-local address: {}", error);
+    }
+    match wifi.wait_netif_up() {
+        Err(error) if config::NET_PREFER == "v6" && ipv6_addresses(netif).iter().any(is_link_local) => {
+            log::warn!("no IPv4 lease ({}), up on IPv6 link-local only", error);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// the station's addresses, the preferred family first; link-local IPv6 ones only reach the same
+// network segment and need the zone (`%st1`) to be used from another host
+pub fn addresses() -> Vec<IpAddr> {
+    let Some(netif) = sta_netif() else {
+        return Vec::new();
+    };
+    let mut ip_info: esp_netif_ip_info_t = unsafe { std::mem::zeroed() };
+    let ipv4 = esp!(unsafe { esp_netif_get_ip_info(netif, &mut ip_info) })
+        .ok()
+        .map(|()| Ipv4Addr::from(ip_info.ip.addr.to_ne_bytes()))
+        .filter(|ip| !ip.is_unspecified());
+    let ipv6 = ipv6_addresses(netif);
+    let mut addresses: Vec<IpAddr> = ipv4.into_iter().map(IpAddr::V4).chain(ipv6.into_iter().map(IpAddr::V6)).collect();
+    sort_preferred(&mut addresses, IpAddr::is_ipv6);
+    addresses
+}
+
+// stable, so the order of each family is kept
+fn sort_preferred<T>(items: &mut [T], is_ipv6: impl Fn(&T) -> bool) {
+    let prefer_v6 = config::NET_PREFER == "v6";
+    items.sort_by_key(|item| is_ipv6(item) != prefer_v6);
+}
+
+// "host:port" to the address of the preferred family, or of the other one if it only has that;
+// for the sockets the firmware opens itself, the esp-idf clients (MQTT, HTTP) take what the
+// resolver gives first
+pub fn resolve(host_port: &str) -> io::Result<SocketAddr> {
+    let mut candidates: Vec<SocketAddr> = host_port.to_socket_addrs()?.collect();
+    sort_preferred(&mut candidates, SocketAddr::is_ipv6);
+    candidates
+        .into_iter()
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", host_port)))
+}
+
+// the station config of esp-idf-svc has no listen interval, it is patched into the driver's
+fn set_listen_interval() -> Result<(), EspError> {
+    let Ok(interval) = u16::try_from(config::WIFI_LISTEN_INTERVAL) else {
+        return Ok(());
+    };
+    if interval == 0 {
+        return Ok(());
+    }
+    let mut conf: wifi_config_t = unsafe { std::mem::zeroed() };
+    esp!(unsafe { esp_wifi_get_config(wifi_interface_t_WIFI_IF_STA, &mut conf) })?;
+    unsafe { conf.sta.listen_interval = interval };
+    esp!(unsafe { esp_wifi_set_config(wifi_interface_t_WIFI_IF_STA, &mut conf) })
+}
+
+fn power_save_mode(name: &str) -> Option<(&'static str, wifi_ps_type_t)> {
+    POWER_SAVE_MODES.iter().copied().find(|(mode, _)| *mode == name)
+}
+
+// config.rs only builds with a known WIFI_POWER_SAVE, POWER_SAVE only takes one of the modes
+fn set_power_save() -> Result<(), EspError> {
+    let (_, mode) = power_save_mode(&POWER_SAVE.lock().unwrap()).unwrap();
+    esp!(unsafe { esp_wifi_set_ps(mode) })
+}
+
+pub fn power_save() -> &'static str {
+    *POWER_SAVE.lock().unwrap()
+}
+
+// switches power save on the running radio; an unknown mode is an error
+pub fn change_power_save(name: &str) -> Result<(), String> {
+    let Some((name, mode)) = power_save_mode(name) else {
+        let names: Vec<&str> = POWER_SAVE_MODES.iter().map(|(mode, _)| *mode).collect();
+        return Err(format!("unknown power save mode {}, one of {}", name, names.join(", ")));
+    };
+    esp!(unsafe { esp_wifi_set_ps(mode) }).map_err(|error| error.to_string())?;
+    *POWER_SAVE.lock().unwrap() = name;
+    log::info!("Wi-Fi power save {}", name);
+    Ok(())
+}
+
+// switches the radio off and on again for the Wi-Fi schedule; true when something changed.
+// A radio that is on but not connected, after a connect that failed, connects again
+pub fn set_enabled(wifi: &mut BlockingWifi<EspWifi<'static>>, enabled: bool) -> Result<bool, EspError> {
+    if enabled {
+        if wifi.is_connected()? {
+            return Ok(false);
+        }
+        if !wifi.is_started()? {
+            wifi.start()?;
+            set_power_save()?;
+        }
+        wifi.connect()?;
+        wait_up(wifi)?;
+    } else {
+        if !wifi.is_started()? {
+            return Ok(false);
+        }
+        wifi.stop()?;
+    }
+    Ok(true)
+}
+
+// the factory MAC from eFuse, the station's address unless one was set
+pub fn mac() -> Result<[u8; 6], EspError> {
+    let mut mac = [0u8; 6];
+    esp!(unsafe { esp_efuse_mac_get_default(mac.as_mut_ptr()) })?;
+    Ok(mac)
+}
+
+// "temp-" followed by the last three bytes of the factory MAC, so it is known before Wi-Fi is up
+pub fn node_id() -> Result<String, EspError> {
+    let mac = mac()?;
+    Ok(format!("temp-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5]))
+}
+
+#[derive(Deserialize)]
+struct PowerRequest {
+    power_save: String,
+}
+
+pub fn register(server: &mut EspHttpServer<'static>, context: Arc<Context>) -> Result<(), EspError> {
+    // {"power_save": "max"}
+    let power = Api::json(|| json!({ "power_save": "" })).body(|| json!({ "power_save": "" }));
+    http::route(server, "/api/wifi/power", Method::Post, power, move |mut request| {
+        let power = match http::read_json::<PowerRequest>(&mut request, 256)? {
+            Ok(power) => power,
+            Err((status, message)) => return http::write_error(request, status, &message),
+        };
+        let mut args = Map::new();
+        args.insert("mode".to_string(), json!(power.power_save));
+        match commands::run(&context, "power_save", &args, Source::Api) {
+            Ok(result) => http::write_json(request, &result),
+            Err(error) => http::write_error(request, 400, &error),
+        }
+    })?;
+
+    Ok(())
+}